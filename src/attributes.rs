@@ -0,0 +1,236 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::Context;
+
+use crate::{repository::Repository, wildmatch::wildmatch};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum AttributeValue {
+    Set,
+    Unset,
+    Value(String),
+}
+
+/// One line of a `.gitattributes` file.
+#[derive(Debug)]
+struct AttributeRule {
+    pattern: String,
+    /// `None` for `!attr`, which makes the attribute unspecified again.
+    attributes: Vec<(String, Option<AttributeValue>)>,
+}
+
+fn parse_rules(contents: &str) -> Vec<AttributeRule> {
+    let mut macros: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    macros.insert(
+        "binary".into(),
+        vec!["-diff".into(), "-merge".into(), "-text".into()],
+    );
+    let mut rules = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let Some(pattern) = words.next() else {
+            continue;
+        };
+        let words: Vec<String> = words.map(str::to_string).collect();
+        if let Some(name) = pattern.strip_prefix("[attr]") {
+            macros.insert(name.to_string(), words);
+            continue;
+        }
+        let mut attributes = Vec::new();
+        for word in &words {
+            if let Some(expansion) = macros.get(word.as_str()) {
+                attributes.push((word.clone(), Some(AttributeValue::Set)));
+                attributes.extend(expansion.iter().map(|word| parse_attribute(word)));
+            } else {
+                attributes.push(parse_attribute(word));
+            }
+        }
+        rules.push(AttributeRule {
+            pattern: pattern.to_string(),
+            attributes,
+        });
+    }
+    rules
+}
+
+fn parse_attribute(word: &str) -> (String, Option<AttributeValue>) {
+    if let Some(name) = word.strip_prefix('-') {
+        (name.to_string(), Some(AttributeValue::Unset))
+    } else if let Some(name) = word.strip_prefix('!') {
+        (name.to_string(), None)
+    } else if let Some((name, value)) = word.split_once('=') {
+        (
+            name.to_string(),
+            Some(AttributeValue::Value(value.to_string())),
+        )
+    } else {
+        (word.to_string(), Some(AttributeValue::Set))
+    }
+}
+
+/// Whether a rule from the attributes file in directory `base` applies to `path`.
+fn rule_matches(rule: &AttributeRule, base: &str, path: &str) -> bool {
+    let Some(relative) = path.strip_prefix(base) else {
+        return false;
+    };
+    // directory patterns never match files
+    if rule.pattern.ends_with('/') {
+        return false;
+    }
+    let pattern = rule.pattern.strip_prefix('/').unwrap_or(&rule.pattern);
+    if rule.pattern.contains('/') {
+        wildmatch(pattern.as_bytes(), relative.as_bytes(), true)
+    } else {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        wildmatch(pattern.as_bytes(), name.as_bytes(), true)
+    }
+}
+
+/// The attributes of `path` (relative to `worktree`, using `/`), from the `.gitattributes` of
+/// each directory leading to it and then `info/attributes`, later files taking precedence.
+pub(crate) fn attributes_for(
+    repo: &Repository,
+    worktree: &Path,
+    path: &str,
+) -> anyhow::Result<BTreeMap<String, AttributeValue>> {
+    let mut sources = Vec::new();
+    let mut base = String::new();
+    let mut components = path.split('/').peekable();
+    loop {
+        let file = worktree.join(&base).join(".gitattributes");
+        if let Ok(contents) = fs::read(&file) {
+            sources.push((base.clone(), String::from_utf8_lossy(&contents).to_string()));
+        }
+        let Some(component) = components.next() else {
+            break;
+        };
+        if components.peek().is_none() {
+            break;
+        }
+        base.push_str(component);
+        base.push('/');
+    }
+    if let Some(contents) = repo.files.read("info/attributes")? {
+        sources.push((
+            String::new(),
+            String::from_utf8_lossy(&contents).to_string(),
+        ));
+    }
+
+    let mut attributes = BTreeMap::new();
+    for (base, contents) in sources {
+        for rule in parse_rules(&contents) {
+            if !rule_matches(&rule, &base, path) {
+                continue;
+            }
+            for (name, value) in rule.attributes {
+                match value {
+                    Some(value) => attributes.insert(name, value),
+                    None => attributes.remove(&name),
+                };
+            }
+        }
+    }
+    Ok(attributes)
+}
+
+/// The heuristic git uses for `text=auto`: a NUL byte early on means binary.
+pub(crate) fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+/// Converts the worktree contents of `path` into what gets stored in a blob: text files have
+/// their CRLF line endings normalized to LF according to `text`, `eol` and `core.autocrlf`.
+pub(crate) fn clean(
+    repo: &Repository,
+    worktree: &Path,
+    path: &str,
+    data: Vec<u8>,
+) -> anyhow::Result<Vec<u8>> {
+    let attributes = attributes_for(repo, worktree, path).context("read attributes")?;
+    let auto = match (attributes.get("text"), attributes.get("eol")) {
+        (Some(AttributeValue::Unset), _) => return Ok(data),
+        (Some(AttributeValue::Set), _) => false,
+        (Some(AttributeValue::Value(value)), _) if value == "auto" => true,
+        (_, Some(_)) => false,
+        _ => {
+            let config = repo.config()?;
+            match config.get("core.autocrlf") {
+                Some("input") => true,
+                _ if config.get_bool("core.autocrlf")?.unwrap_or(false) => true,
+                _ => return Ok(data),
+            }
+        }
+    };
+    if auto && is_binary(&data) {
+        return Ok(data);
+    }
+    let mut converted = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        if b == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        converted.push(b);
+    }
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes_for() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        fs::write(
+            worktree.join(".gitattributes"),
+            "# defaults\n*.txt text\n*.png binary\n/docs/*.md eol=crlf\n",
+        )?;
+        fs::create_dir_all(worktree.join("vendor"))?;
+        fs::write(worktree.join("vendor/.gitattributes"), "*.txt -text\n")?;
+        let repo = Repository::in_memory();
+        repo.init()?;
+        repo.files.write("info/attributes", b"notes.txt !text\n")?;
+
+        let attributes = attributes_for(&repo, worktree, "a/b.txt")?;
+        assert_eq!(attributes.get("text"), Some(&AttributeValue::Set));
+        let attributes = attributes_for(&repo, worktree, "vendor/lib.txt")?;
+        assert_eq!(attributes.get("text"), Some(&AttributeValue::Unset));
+        let attributes = attributes_for(&repo, worktree, "logo.png")?;
+        assert_eq!(attributes.get("binary"), Some(&AttributeValue::Set));
+        assert_eq!(attributes.get("diff"), Some(&AttributeValue::Unset));
+        let attributes = attributes_for(&repo, worktree, "docs/guide.md")?;
+        assert_eq!(
+            attributes.get("eol"),
+            Some(&AttributeValue::Value("crlf".into()))
+        );
+        assert!(attributes_for(&repo, worktree, "docs/sub/guide.md")?.is_empty());
+        assert!(attributes_for(&repo, worktree, "notes.txt")?.is_empty());
+
+        assert_eq!(
+            clean(&repo, worktree, "a.txt", b"one\r\ntwo\r\n".to_vec())?,
+            b"one\ntwo\n"
+        );
+        assert_eq!(
+            clean(&repo, worktree, "a.rs", b"one\r\n".to_vec())?,
+            b"one\r\n"
+        );
+        let mut config = repo.config()?;
+        config.set("core.autocrlf", "input");
+        repo.write_config(&config)?;
+        assert_eq!(
+            clean(&repo, worktree, "a.rs", b"one\r\n".to_vec())?,
+            b"one\n"
+        );
+        assert_eq!(
+            clean(&repo, worktree, "a.bin", b"\0one\r\n".to_vec())?,
+            b"\0one\r\n"
+        );
+        Ok(())
+    }
+}
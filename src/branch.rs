@@ -0,0 +1,446 @@
+//! Branches: the refs under `refs/heads`, the upstreams they track, and the reflog entries
+//! kept as they are created, renamed and deleted.
+
+use std::{io::Write, path::Path};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    commit::Commit,
+    config::GitConfig,
+    hash::ObjectId,
+    log::split_message,
+    object::{Object, ObjectType},
+    reflog,
+    repository::{Head, Repository},
+    tag::Tag,
+    walk::{ahead_behind, ancestors},
+};
+
+/// The branch a branch is set to track, from `branch.<name>.remote` and `.merge`.
+#[derive(Debug)]
+pub(crate) struct Upstream {
+    /// Shortened, like `origin/master`.
+    pub(crate) name: String,
+    /// In full, like `refs/remotes/origin/master`.
+    pub(crate) reference: String,
+    /// Commits only on the branch and only on the upstream, `None` if the upstream is gone.
+    pub(crate) ahead_behind: Option<(usize, usize)>,
+}
+
+/// The upstream the branch `branch` (without `refs/heads/`), at `commit`, tracks, if any.
+pub(crate) fn upstream(
+    repo: &Repository,
+    config: &GitConfig,
+    branch: &str,
+    commit: &ObjectId,
+) -> anyhow::Result<Option<Upstream>> {
+    let remote = config.get(&format!("branch.{branch}.remote"));
+    let merge = config.get(&format!("branch.{branch}.merge"));
+    let (Some(remote), Some(merge)) = (remote, merge) else {
+        return Ok(None);
+    };
+    let merge_branch = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+    let (reference, name) = if remote == "." {
+        (merge.to_string(), merge_branch.to_string())
+    } else {
+        (
+            format!("refs/remotes/{remote}/{merge_branch}"),
+            format!("{remote}/{merge_branch}"),
+        )
+    };
+    let ahead_behind = match repo.resolve_ref(&reference)? {
+        Some(upstream) => Some(ahead_behind(repo, commit, &upstream.parse()?)?),
+        None => None,
+    };
+    Ok(Some(Upstream {
+        name,
+        reference,
+        ahead_behind,
+    }))
+}
+
+/// Refuses names git wouldn't take for a branch: those that aren't valid ref names, like
+/// `a..b` or `topic.lock`, `HEAD`, and names that would read as an option.
+pub(crate) fn check_name(name: &str) -> anyhow::Result<()> {
+    let valid = name != "HEAD"
+        && name != "@"
+        && !name.starts_with('-')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name
+            .bytes()
+            .any(|b| b < 0x20 || b == 0x7f || b" ~^:?*[\\".contains(&b))
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && !part.starts_with('.') && !part.ends_with(".lock"));
+    if !valid {
+        bail!("'{name}' is not a valid branch name");
+    }
+    Ok(())
+}
+
+/// The branch `HEAD` is on, without `refs/heads/`, `None` when it is detached.
+fn current(repo: &Repository) -> anyhow::Result<Option<String>> {
+    Ok(match repo.head()? {
+        Head::Branch(name) => Some(
+            name.strip_prefix("refs/heads/")
+                .unwrap_or(&name)
+                .to_string(),
+        ),
+        Head::Detached(_) => None,
+    })
+}
+
+/// The commit `revision` names, peeling tags.
+pub(crate) fn resolve_commit(repo: &Repository, revision: &str) -> anyhow::Result<ObjectId> {
+    let mut id = repo
+        .resolve_revision(revision)?
+        .with_context(|| format!("not a valid object name: '{revision}'"))?;
+    loop {
+        match Object::read(repo.objects.as_ref(), &id.to_string())?.object_type {
+            ObjectType::Commit => return Ok(id),
+            ObjectType::Tag => {
+                id = Tag::read(repo.objects.as_ref(), &id.to_string())?
+                    .object
+                    .parse()?
+            }
+            _ => bail!("not a valid branch point: '{revision}'"),
+        }
+    }
+}
+
+/// Refuses to create the branch ref `reference` where a branch below it, or above it, exists:
+/// `topic` and `topic/a` can't both be files. `renaming` is the ref about to make way.
+fn check_conflicts(
+    repo: &Repository,
+    reference: &str,
+    renaming: Option<&str>,
+) -> anyhow::Result<()> {
+    for (existing, _) in repo.refs()? {
+        if Some(existing.as_str()) != renaming
+            && (existing.starts_with(&format!("{reference}/"))
+                || reference.starts_with(&format!("{existing}/")))
+        {
+            bail!(
+                "cannot lock ref '{reference}': '{existing}' exists; cannot create '{reference}'"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Creates the branch `name` at `start`, the current branch or `HEAD` if not given, and
+/// replacing an existing branch of that name with `force`, unless it is the one checked out
+/// in `worktree`. Starting from a remote-tracking branch sets the new branch up to track it,
+/// and the upstream's short name is returned.
+pub(crate) fn create(
+    repo: &Repository,
+    worktree: &Path,
+    name: &str,
+    start: Option<&str>,
+    force: bool,
+) -> anyhow::Result<Option<String>> {
+    check_name(name)?;
+    let reference = format!("refs/heads/{name}");
+    let old = repo.resolve_ref(&reference)?;
+    let current = current(repo)?;
+    if old.is_some() && !force {
+        bail!("a branch named '{name}' already exists");
+    }
+    if old.is_some() && current.as_deref() == Some(name) {
+        bail!(
+            "cannot force update the branch '{name}' checked out at '{}'",
+            worktree.display()
+        );
+    }
+    check_conflicts(repo, &reference, None)?;
+    let start = match start {
+        Some(start) => start.to_string(),
+        None => current.unwrap_or_else(|| "HEAD".to_string()),
+    };
+    let commit = resolve_commit(repo, &start)?.to_string();
+
+    let mut config = repo.config()?;
+    let remote_branch = match start.strip_prefix("refs/remotes/") {
+        Some(remote_branch) => Some(remote_branch),
+        None => repo
+            .read_ref(&format!("refs/remotes/{start}"))?
+            .map(|_| start.as_str()),
+    };
+    let tracking = remote_branch
+        .and_then(|remote_branch| remote_branch.split_once('/'))
+        .filter(|(remote, branch)| {
+            *branch != "HEAD" && config.subsections("remote").contains(remote)
+        })
+        .map(|(remote, branch)| (remote.to_string(), branch.to_string()));
+
+    repo.update_ref(&reference, &commit)?;
+    let message = match old {
+        Some(_) => format!("branch: Reset to {start}"),
+        None => format!("branch: Created from {start}"),
+    };
+    reflog::append(repo, &reference, old.as_deref(), Some(&commit), &message)?;
+    let Some((remote, branch)) = tracking else {
+        return Ok(None);
+    };
+    config.set(&format!("branch.{name}.remote"), &remote);
+    config.set(
+        &format!("branch.{name}.merge"),
+        &format!("refs/heads/{branch}"),
+    );
+    repo.write_config(&config)?;
+    Ok(Some(format!("{remote}/{branch}")))
+}
+
+/// A branch [`delete`] removed.
+#[derive(Debug)]
+pub(crate) struct Deleted {
+    pub(crate) commit: ObjectId,
+    /// The upstream the branch was merged into, when `HEAD` doesn't have it.
+    pub(crate) only_merged_to: Option<String>,
+}
+
+/// Deletes the branch `name` with its reflog and config. Unless `force` is set its commits
+/// have to be in its upstream, or in `HEAD` when it doesn't track one. The branch checked
+/// out in `worktree` can't go.
+pub(crate) fn delete(
+    repo: &Repository,
+    worktree: &Path,
+    name: &str,
+    force: bool,
+) -> anyhow::Result<Deleted> {
+    let reference = format!("refs/heads/{name}");
+    let Some(commit) = repo.resolve_ref(&reference)? else {
+        bail!("branch '{name}' not found.");
+    };
+    let commit: ObjectId = commit.parse()?;
+    if current(repo)?.as_deref() == Some(name) {
+        bail!(
+            "Cannot delete branch '{name}' checked out at '{}'",
+            worktree.display()
+        );
+    }
+    let head = repo.resolve_name("HEAD")?;
+    let mut only_merged_to = None;
+    if !force {
+        let upstream = upstream(repo, &repo.config()?, name, &commit)?
+            .filter(|upstream| upstream.ahead_behind.is_some());
+        let in_head = match head {
+            Some(head) => ancestors(repo, &head)?.contains(&commit),
+            None => false,
+        };
+        let merged = match &upstream {
+            Some(upstream) => upstream.ahead_behind.is_some_and(|(ahead, _)| ahead == 0),
+            None => in_head,
+        };
+        if !merged {
+            bail!(
+                "The branch '{name}' is not fully merged.\n\
+                 If you are sure you want to delete it, run 'git branch -D {name}'."
+            );
+        }
+        if head.is_some() && !in_head {
+            only_merged_to = upstream.map(|upstream| upstream.reference);
+        }
+    }
+    repo.delete_ref(&reference)?;
+    reflog::remove(repo, &reference)?;
+    let mut config = repo.config()?;
+    config.remove_section(&format!("branch.{name}"));
+    repo.write_config(&config)?;
+    Ok(Deleted {
+        commit,
+        only_merged_to,
+    })
+}
+
+/// Renames the branch `old`, the current one if not given, to `new`, replacing an existing
+/// `new` only with `force`. Its reflog and config move along, and `HEAD` follows if it was
+/// on the branch.
+pub(crate) fn rename(
+    repo: &Repository,
+    old: Option<&str>,
+    new: &str,
+    force: bool,
+) -> anyhow::Result<()> {
+    let current = current(repo)?;
+    let Some(old) = old.or(current.as_deref()) else {
+        bail!("cannot rename the current branch while not on any.");
+    };
+    let old_reference = format!("refs/heads/{old}");
+    let new_reference = format!("refs/heads/{new}");
+    let commit = repo.resolve_ref(&old_reference)?;
+    if commit.is_none() && current.as_deref() != Some(old) {
+        bail!("No branch named '{old}'.");
+    }
+    check_name(new)?;
+    if old != new && repo.resolve_ref(&new_reference)?.is_some() && !force {
+        bail!("a branch named '{new}' already exists");
+    }
+    check_conflicts(repo, &new_reference, Some(&old_reference))?;
+
+    let message = format!("Branch: renamed {old_reference} to {new_reference}");
+    if let Some(commit) = &commit {
+        if old != new {
+            repo.delete_ref(&old_reference)?;
+            reflog::rename(repo, &old_reference, &new_reference)?;
+        }
+        repo.update_ref(&new_reference, commit)?;
+        reflog::append(repo, &new_reference, Some(commit), Some(commit), &message)?;
+    }
+    if current.as_deref() == Some(old) {
+        repo.files
+            .write("HEAD", format!("ref: {new_reference}\n").as_bytes())?;
+        // like git, as HEAD's branch going away and coming back
+        if let Some(commit) = &commit {
+            reflog::append(repo, "HEAD", Some(commit), None, &message)?;
+            reflog::append(repo, "HEAD", None, Some(commit), &message)?;
+        }
+    }
+    if old != new {
+        let mut config = repo.config()?;
+        config.remove_section(&format!("branch.{new}"));
+        config.rename_section(&format!("branch.{old}"), &format!("branch.{new}"));
+        repo.write_config(&config)?;
+    }
+    Ok(())
+}
+
+/// Lists the branches, the current one marked with `*`. A detached `HEAD` is listed first.
+/// With `verbose` each comes with its commit, how it compares with its upstream and the
+/// commit's subject, and with a `verbose` of 2 or more the upstream's name too.
+pub(crate) fn write_branches(
+    out: &mut impl Write,
+    repo: &Repository,
+    verbose: u8,
+) -> anyhow::Result<()> {
+    let mut branches = Vec::new();
+    let current = match repo.head()? {
+        Head::Branch(name) => name.strip_prefix("refs/heads/").map(str::to_string),
+        Head::Detached(hash) => {
+            let hash = hash.parse()?;
+            let detached = reflog::describe_detached(repo, &hash)?;
+            branches.push((format!("(HEAD detached {detached})"), hash));
+            None
+        }
+    };
+    let detached = branches.len();
+    for (reference, hash) in repo.refs()? {
+        if let Some(name) = reference.strip_prefix("refs/heads/") {
+            branches.push((name.to_string(), hash.parse()?));
+        }
+    }
+
+    let config = repo.config()?;
+    let width = branches
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (i, (name, commit)) in branches.iter().enumerate() {
+        let marker = if i < detached || current.as_deref() == Some(name) {
+            '*'
+        } else {
+            ' '
+        };
+        if verbose == 0 {
+            writeln!(out, "{marker} {name}")?;
+            continue;
+        }
+        let mut tracking = Vec::new();
+        let upstream = if i < detached {
+            None
+        } else {
+            upstream(repo, &config, name, commit)?
+        };
+        if let Some(upstream) = &upstream {
+            match upstream.ahead_behind {
+                Some((ahead, behind)) => {
+                    if ahead > 0 {
+                        tracking.push(format!("ahead {ahead}"));
+                    }
+                    if behind > 0 {
+                        tracking.push(format!("behind {behind}"));
+                    }
+                }
+                None => tracking.push("gone".to_string()),
+            }
+        }
+        let tracking = match (&upstream, verbose) {
+            (None, _) => String::new(),
+            (Some(_), 1) if tracking.is_empty() => String::new(),
+            (Some(_), 1) => format!("[{}] ", tracking.join(", ")),
+            (Some(upstream), _) if tracking.is_empty() => format!("[{}] ", upstream.name),
+            (Some(upstream), _) => format!("[{}: {}] ", upstream.name, tracking.join(", ")),
+        };
+        let message = Commit::read(repo.objects.as_ref(), &commit.to_string())?.message;
+        let (subject, _) = split_message(&message);
+        writeln!(
+            out,
+            "{marker} {name}{} {} {tracking}{subject}",
+            " ".repeat(width - name.chars().count()),
+            &commit.to_string()[..7]
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{commit::commit, hash::HashAlgorithm, pathspec::Pathspec, staging::add};
+
+    #[test]
+    fn test_branches() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let repo = Repository::on_disk(&worktree.join(".git"), HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::write(worktree.join("README"), "hello\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        let first = commit(&repo, "first")?.expect("a commit").to_string();
+
+        assert!(create(&repo, worktree, "bad..name", None, false).is_err());
+        assert_eq!(create(&repo, worktree, "topic/a", None, false)?, None);
+        assert!(create(&repo, worktree, "topic/a", None, false).is_err());
+        assert!(create(&repo, worktree, "topic", None, false).is_err());
+        let log = repo
+            .files
+            .read("logs/refs/heads/topic/a")?
+            .expect("a reflog");
+        assert!(String::from_utf8(log)?.ends_with("\tbranch: Created from master\n"));
+
+        rename(&repo, Some("topic/a"), "topic", false)?;
+        assert_eq!(repo.resolve_ref("refs/heads/topic")?, Some(first.clone()));
+        assert!(repo.files.read("logs/refs/heads/topic")?.is_some());
+        rename(&repo, None, "main", false)?;
+        assert_eq!(repo.head()?, Head::Branch("refs/heads/main".to_string()));
+
+        fs::write(worktree.join("README"), "changed\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        commit(&repo, "second")?;
+        create(&repo, worktree, "old", Some("main~1"), false)?;
+        repo.files.write("HEAD", b"ref: refs/heads/old\n")?;
+        assert!(delete(&repo, worktree, "main", false).is_err());
+        assert!(delete(&repo, worktree, "old", false).is_err());
+        assert!(delete(&repo, worktree, "main", true).is_ok());
+        assert_eq!(repo.resolve_ref("refs/heads/main")?, None);
+        assert!(repo.files.read("logs/refs/heads/main")?.is_none());
+
+        let mut out = Vec::new();
+        write_branches(&mut out, &repo, 1)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            format!(
+                "* old   {} first\n  topic {} first\n",
+                &first[..7],
+                &first[..7]
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! `checkout -- <paths>` and `restore`: putting the files of a tree or the index back into
+//! the worktree and the index.
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::bail;
+
+use crate::{
+    diff_tree::Version,
+    index::{IndexEntry, StatData},
+    pathspec::Pathspec,
+    repository::Repository,
+    tree::tree_files,
+    worktree::{remove_file, verify_path, write_file, ModeConfig},
+};
+
+/// Where `restore` takes files from and what it puts them into.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RestoreOptions<'a> {
+    /// The tree to take files from, the index when `None`.
+    pub(crate) source: Option<&'a str>,
+    pub(crate) staged: bool,
+    pub(crate) worktree: bool,
+    /// Leave the paths the source doesn't have alone rather than removing them.
+    pub(crate) overlay: bool,
+}
+
+/// Restores the paths matching `pathspec` from the source `options` picks into the index
+/// and the worktree. Executable bits and symlinks come along, index entries that end up
+/// matching the written files take their stat data, and without `overlay` tracked paths
+/// the source doesn't have are removed. Every pathspec item has to match something in the
+/// source or the index, and nothing is touched if any of the source's paths is invalid.
+pub(crate) fn restore(
+    repo: &Repository,
+    worktree: &Path,
+    pathspec: &Pathspec,
+    options: &RestoreOptions,
+) -> anyhow::Result<()> {
+    let mut index = repo.index()?;
+    let source: BTreeMap<Vec<u8>, Version> = match options.source {
+        Some(tree) => tree_files(repo.objects.as_ref(), tree)?
+            .into_iter()
+            .filter(|(path, _)| pathspec.matches(path))
+            .collect(),
+        None => {
+            let mut files = BTreeMap::new();
+            for entry in index.entries.iter().filter(|e| pathspec.matches(&e.path)) {
+                if entry.stage != 0 {
+                    bail!(
+                        "path '{}' is unmerged",
+                        String::from_utf8_lossy(&entry.path)
+                    );
+                }
+                if !entry.intent_to_add {
+                    files.insert(entry.path.clone(), (entry.mode, entry.hash));
+                }
+            }
+            files
+        }
+    };
+    for i in 0..pathspec.len() {
+        let known = |path: &Vec<u8>| pathspec.match_item(i, path).is_some();
+        if !source.keys().any(known) && !index.entries.iter().any(|entry| known(&entry.path)) {
+            bail!(
+                "pathspec '{}' did not match any file(s) known to git",
+                pathspec.original(i)
+            );
+        }
+    }
+    for path in source.keys() {
+        verify_path(path)?;
+    }
+    let mut gone: Vec<Vec<u8>> = Vec::new();
+    if !options.overlay {
+        for entry in &index.entries {
+            if pathspec.matches(&entry.path)
+                && !source.contains_key(&entry.path)
+                && gone.last() != Some(&entry.path)
+            {
+                gone.push(entry.path.clone());
+            }
+        }
+    }
+
+    let symlinks = ModeConfig::load(repo)?.symlinks;
+    for (path, &(mode, id)) in &source {
+        if options.staged {
+            index.add(IndexEntry {
+                stat: StatData::default(),
+                mode,
+                hash: id,
+                stage: 0,
+                assume_valid: false,
+                skip_worktree: false,
+                intent_to_add: false,
+                path: path.clone(),
+            });
+        }
+        if options.worktree {
+            let metadata = write_file(repo, worktree, path, mode, &id, symlinks)?;
+            if let Some(entry) = index.get_mut(path) {
+                if (entry.mode, entry.hash) == (mode, id) {
+                    entry.stat = StatData::from_metadata(&metadata);
+                }
+            }
+        }
+    }
+    for path in gone {
+        if options.staged {
+            index.remove(&path);
+        }
+        if options.worktree {
+            remove_file(worktree, &path)?;
+        }
+    }
+    repo.write_index(&index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::{
+        commit::commit,
+        hash::{HashAlgorithm, ObjectId},
+        object::{Object, ObjectType},
+        staging::add,
+    };
+
+    #[test]
+    fn test_restore() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let repo = Repository::on_disk(&worktree.join(".git"), HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::create_dir_all(worktree.join("bin"))?;
+        fs::write(worktree.join("README"), "hello\n")?;
+        fs::write(worktree.join("bin/run"), "#!/bin/sh\n")?;
+        fs::set_permissions(worktree.join("bin/run"), fs::Permissions::from_mode(0o755))?;
+        std::os::unix::fs::symlink("README", worktree.join("link"))?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        commit(&repo, "initial")?;
+        let head = repo.resolve_name("HEAD")?.expect("a commit");
+        let tree = crate::commit::Commit::read(repo.objects.as_ref(), &head.to_string())?.tree;
+
+        fs::write(worktree.join("README"), "changed\n")?;
+        fs::remove_dir_all(worktree.join("bin"))?;
+        fs::remove_file(worktree.join("link"))?;
+        fs::write(worktree.join("new"), "new\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        let everything = RestoreOptions {
+            source: Some(&tree),
+            staged: true,
+            worktree: true,
+            overlay: false,
+        };
+        restore(&repo, worktree, &Pathspec::default(), &everything)?;
+        assert_eq!(fs::read_to_string(worktree.join("README"))?, "hello\n");
+        let run = fs::metadata(worktree.join("bin/run"))?;
+        assert_eq!(run.permissions().mode() & 0o100, 0o100);
+        assert_eq!(fs::read_link(worktree.join("link"))?, Path::new("README"));
+        // tracked files the source doesn't have go
+        assert!(!worktree.join("new").exists());
+        let index = repo.index()?;
+        let paths: Vec<&[u8]> = index.entries.iter().map(|e| &e.path[..]).collect();
+        assert_eq!(paths, [&b"README"[..], b"bin/run", b"link"]);
+        assert_ne!(
+            index.get(b"README").map(|e| e.stat),
+            Some(StatData::default())
+        );
+
+        let missing = Pathspec::new(worktree, &["missing".into()])?;
+        assert!(restore(&repo, worktree, &missing, &everything).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_rejects_escaping_paths() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path().join("worktree");
+        fs::create_dir(&worktree)?;
+        let repo = Repository::on_disk(&worktree.join(".git"), HashAlgorithm::Sha1);
+        repo.init()?;
+        let write = |object_type, data: &[u8]| -> anyhow::Result<ObjectId> {
+            Object {
+                object_type,
+                expected_size: data.len() as u64,
+                reader: data,
+            }
+            .write_to_objects(repo.objects.as_ref())
+        };
+        let blob = write(ObjectType::Blob, b"gotcha\n")?;
+        let mut inner = b"100644 escaped\0".to_vec();
+        inner.extend(blob.as_bytes());
+        let inner = write(ObjectType::Tree, &inner)?;
+        // `-ok` sorts first, so nothing may be written before the bad path is seen
+        let mut tree = b"100644 -ok\0".to_vec();
+        tree.extend(blob.as_bytes());
+        tree.extend(b"40000 ..\0");
+        tree.extend(inner.as_bytes());
+        let tree = write(ObjectType::Tree, &tree)?.to_string();
+
+        let everything = RestoreOptions {
+            source: Some(&tree),
+            staged: true,
+            worktree: true,
+            overlay: false,
+        };
+        let err = restore(&repo, &worktree, &Pathspec::default(), &everything).unwrap_err();
+        assert_eq!(err.to_string(), "invalid path '../escaped'");
+        assert!(!temp_dir.path().join("escaped").exists());
+        assert!(!worktree.join("-ok").exists());
+        assert!(repo.index()?.entries.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! `clean`: deleting untracked files from the worktree.
+
+use std::{collections::BTreeSet, fs, path::Path};
+
+use anyhow::Context;
+
+use crate::{
+    pathspec::Pathspec,
+    repository::Repository,
+    status::{has_tracked_files, untracked_files, UntrackedFiles},
+    worktree::disk_path,
+};
+
+/// Which ignored files `clean` deletes.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum CleanIgnored {
+    /// Leave them alone.
+    #[default]
+    Keep,
+    /// Delete them along with the other untracked files (`-x`).
+    Also,
+    /// Delete nothing else (`-X`).
+    Only,
+}
+
+/// Deletes the untracked files matching `pathspec`, and returns them. Directories without any
+/// tracked files are only deleted if `directories` is set, and then as a whole unless they
+/// hold files that are to be kept. With `dry_run` nothing is deleted.
+pub(crate) fn remove_untracked(
+    repo: &Repository,
+    worktree: &Path,
+    pathspec: &Pathspec,
+    directories: bool,
+    ignored: CleanIgnored,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let index = repo.index()?;
+    let (untracked, ignored_files) =
+        untracked_files(repo, worktree, &index, UntrackedFiles::All, true)?;
+    let (selected, kept) = match ignored {
+        CleanIgnored::Keep => (untracked, ignored_files),
+        CleanIgnored::Also => ([untracked, ignored_files].concat(), Vec::new()),
+        CleanIgnored::Only => (ignored_files, untracked),
+    };
+    // directories holding files to keep can't go as a whole
+    let mut kept_dirs = BTreeSet::new();
+    for path in &kept {
+        for (i, _) in path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+            kept_dirs.insert(&path[..=i]);
+        }
+    }
+
+    let mut paths = BTreeSet::new();
+    for path in selected.iter().filter(|path| pathspec.matches(path)) {
+        let untracked_dir = path
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b'/')
+            .map(|(i, _)| &path[..=i])
+            .find(|dir| !has_tracked_files(&index, dir));
+        let Some(dir) = untracked_dir else {
+            paths.insert(path.clone());
+            continue;
+        };
+        // -X goes into untracked directories for the ignored files, the others stop there
+        if !directories && ignored != CleanIgnored::Only {
+            continue;
+        }
+        let whole = path
+            .iter()
+            .enumerate()
+            .filter(|(i, &b)| b == b'/' && *i >= dir.len() - 1)
+            .map(|(i, _)| &path[..=i])
+            .find(|dir| !kept_dirs.contains(dir));
+        match whole {
+            Some(dir) if directories => {
+                paths.insert(dir.to_vec());
+            }
+            Some(_) => {}
+            None => {
+                paths.insert(path.clone());
+            }
+        }
+    }
+
+    let mut removed: Vec<Vec<u8>> = Vec::new();
+    for path in paths {
+        // already gone with a directory it is in
+        if removed
+            .last()
+            .is_some_and(|dir| dir.ends_with(b"/") && path.starts_with(dir))
+        {
+            continue;
+        }
+        if !dry_run {
+            let is_dir = path.ends_with(b"/");
+            let file = disk_path(worktree, path.strip_suffix(b"/").unwrap_or(&path));
+            if is_dir {
+                fs::remove_dir_all(&file)
+            } else {
+                fs::remove_file(&file)
+            }
+            .with_context(|| format!("remove {}", file.display()))?;
+        }
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{hash::HashAlgorithm, staging::add};
+
+    use super::*;
+
+    #[test]
+    fn test_clean() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let repo = Repository::on_disk(&worktree.join(".git"), HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::create_dir_all(worktree.join("src"))?;
+        fs::create_dir_all(worktree.join("target/debug"))?;
+        fs::create_dir_all(worktree.join("scratch"))?;
+        fs::write(worktree.join(".gitignore"), "target/\n*.log\n")?;
+        fs::write(worktree.join("src/lib.rs"), "lib\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        fs::write(worktree.join("src/notes.txt"), "notes\n")?;
+        fs::write(worktree.join("src/debug.log"), "log\n")?;
+        fs::write(worktree.join("scratch/a.txt"), "a\n")?;
+        fs::write(worktree.join("target/debug/app"), "app\n")?;
+
+        let everything = Pathspec::default();
+        let removed = remove_untracked(
+            &repo,
+            worktree,
+            &everything,
+            false,
+            CleanIgnored::Keep,
+            true,
+        )?;
+        assert_eq!(removed, [b"src/notes.txt".to_vec()]);
+        assert!(worktree.join("src/notes.txt").exists());
+
+        let removed = remove_untracked(
+            &repo,
+            worktree,
+            &everything,
+            true,
+            CleanIgnored::Only,
+            false,
+        )?;
+        assert_eq!(removed, [b"src/debug.log".to_vec(), b"target/".to_vec()]);
+        assert!(!worktree.join("target").exists());
+
+        let removed = remove_untracked(
+            &repo,
+            worktree,
+            &everything,
+            true,
+            CleanIgnored::Also,
+            false,
+        )?;
+        assert_eq!(removed, [b"scratch/".to_vec(), b"src/notes.txt".to_vec()]);
+        assert!(worktree.join("src/lib.rs").exists());
+        assert!(!worktree.join("scratch").exists());
+        Ok(())
+    }
+}
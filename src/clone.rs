@@ -0,0 +1,143 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::{
+    checkout::{restore, RestoreOptions},
+    commit::Commit,
+    pack::write_pack,
+    pathspec::Pathspec,
+    repository::{Head, Repository},
+    walk::{reachable_objects, ref_roots},
+};
+
+/// The `.git` directory of the repository at `path`, which may be a worktree or a bare repository.
+/// A `.git` file, as submodules have, points to the directory elsewhere.
+pub(crate) fn git_dir(path: &Path) -> anyhow::Result<PathBuf> {
+    let dot_git = path.join(".git");
+    if dot_git.is_dir() {
+        Ok(dot_git)
+    } else if dot_git.is_file() {
+        let contents =
+            fs::read_to_string(&dot_git).with_context(|| format!("read {}", dot_git.display()))?;
+        let Some(dir) = contents.strip_prefix("gitdir:") else {
+            bail!("invalid gitfile format: {}", dot_git.display());
+        };
+        Ok(path.join(dir.trim()))
+    } else if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+        Ok(path.to_path_buf())
+    } else {
+        bail!(
+            "'{}' does not appear to be a git repository",
+            path.display()
+        )
+    }
+}
+
+/// Clones the local repository at `source` into the worktree `destination`. Objects available
+/// from `reference` are borrowed through `objects/info/alternates` instead of being copied.
+pub(crate) fn clone(
+    source: &Path,
+    destination: &Path,
+    reference: Option<&Path>,
+) -> anyhow::Result<()> {
+    let source_git = git_dir(source)?
+        .canonicalize()
+        .context("resolve source repository")?;
+    let source_repo = Repository::open(&source_git).context("open source repository")?;
+    ensure!(
+        !destination.exists() || fs::read_dir(destination)?.next().is_none(),
+        "destination path '{}' already exists and is not an empty directory",
+        destination.display()
+    );
+
+    fs::create_dir_all(destination).with_context(|| format!("create {}", destination.display()))?;
+    let dot_git_path = destination.join(".git");
+    let repo = Repository::on_disk(&dot_git_path, source_repo.hash_algorithm());
+    repo.init().context("initialize destination repository")?;
+    let repo = match reference {
+        Some(reference) => {
+            let objects_dir = git_dir(reference)?
+                .join("objects")
+                .canonicalize()
+                .context("resolve reference repository")?;
+            repo.files.write(
+                "objects/info/alternates",
+                format!("{}\n", objects_dir.display()).as_bytes(),
+            )?;
+            Repository::open(&dot_git_path)?
+        }
+        None => repo,
+    };
+
+    let roots = ref_roots(&source_repo)?;
+    let mut missing = Vec::new();
+    for (_, hash) in reachable_objects(&source_repo, &roots)? {
+        if !repo.objects.contains(&hash.to_string())? {
+            missing.push(hash);
+        }
+    }
+    if !missing.is_empty() {
+        write_pack(
+            source_repo.objects.as_ref(),
+            &missing,
+            &dot_git_path.join("objects/pack"),
+            repo.config()?.fsync()?.packs,
+        )
+        .context("copy objects")?;
+    }
+
+    let mut config = repo.config()?;
+    config.set("remote.origin.url", &source_git.display().to_string());
+    config.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*");
+    for (name, hash) in source_repo.refs()? {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            repo.update_ref(&format!("refs/remotes/origin/{branch}"), &hash)?;
+        } else if name.starts_with("refs/tags/") {
+            repo.update_ref(&name, &hash)?;
+        }
+    }
+
+    let head = match source_repo.head()? {
+        Head::Branch(name) => {
+            let branch = name
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&name)
+                .to_string();
+            repo.files
+                .write("HEAD", format!("ref: {name}\n").as_bytes())?;
+            config.set(&format!("branch.{branch}.remote"), "origin");
+            config.set(&format!("branch.{branch}.merge"), &name);
+            let hash = source_repo.resolve_ref(&name)?;
+            if let Some(hash) = &hash {
+                repo.update_ref(&name, hash)?;
+                repo.files.write(
+                    "refs/remotes/origin/HEAD",
+                    format!("ref: refs/remotes/origin/{branch}\n").as_bytes(),
+                )?;
+            }
+            hash
+        }
+        Head::Detached(hash) => {
+            repo.files.write("HEAD", format!("{hash}\n").as_bytes())?;
+            Some(hash)
+        }
+    };
+    repo.write_config(&config)?;
+
+    if let Some(hash) = head {
+        let commit = Commit::read(repo.objects.as_ref(), &hash)?;
+        let options = RestoreOptions {
+            source: Some(&commit.tree),
+            staged: true,
+            worktree: true,
+            overlay: true,
+        };
+        restore(&repo, destination, &Pathspec::default(), &options)
+            .context("check out working tree")?;
+    }
+    Ok(())
+}
@@ -1,43 +1,175 @@
-use std::path::Path;
+use anyhow::{bail, ensure, Context};
 
-use anyhow::Context;
+use crate::{
+    hash::ObjectId,
+    object::{Object, ObjectType},
+    reflog,
+    repository::{Head, Repository},
+    store::ObjectStore,
+    tree::{commit_tree, write_tree_from_index},
+};
 
-use crate::tree::{commit_tree, write_tree_for};
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub message: String,
+}
+
+/// An author, committer or tagger line: `Name <email> 1700000000 +0000`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub time: i64,
+    pub offset: String,
+}
+
+impl Signature {
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let (name, rest) = line
+            .split_once(" <")
+            .with_context(|| format!("missing email in '{line}'"))?;
+        let (email, rest) = rest
+            .split_once("> ")
+            .with_context(|| format!("bad email in '{line}'"))?;
+        let (time, offset) = rest
+            .split_once(' ')
+            .with_context(|| format!("missing timezone in '{line}'"))?;
+        let time = time
+            .parse()
+            .with_context(|| format!("bad timestamp in '{line}'"))?;
+        ensure!(
+            offset.len() == 5
+                && offset.starts_with(['+', '-'])
+                && offset[1..].bytes().all(|b| b.is_ascii_digit()),
+            "bad timezone in '{line}'"
+        );
+        Ok(Self {
+            name: name.to_string(),
+            email: email.to_string(),
+            time,
+            offset: offset.to_string(),
+        })
+    }
+
+    /// Who commits and moves refs, as of now.
+    pub fn now() -> anyhow::Result<Self> {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .context("current system time is before UNIX epoch")?;
+        Ok(Self {
+            name: "Perry Hertler".to_string(),
+            email: "perry@hertler.org".to_string(),
+            time: time.as_secs() as i64,
+            offset: "+0000".to_string(),
+        })
+    }
+}
 
-pub(crate) fn commit(
-    dot_git_path: &Path,
-    path: &Path,
-    message: &str,
-) -> anyhow::Result<Option<[u8; 20]>> {
-    let head_ref =
-        std::fs::read_to_string(dot_git_path.join("HEAD")).with_context(|| format!("read HEAD"))?;
-    let Some(head_ref) = head_ref.strip_prefix("ref: ") else {
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name, self.email, self.time, self.offset
+        )
+    }
+}
+
+/// Splits an object's `key value` header lines from its message, dropping continuation lines.
+pub(crate) fn parse_headers(data: &str) -> (Vec<(&str, &str)>, &str) {
+    let (headers, message) = data.split_once("\n\n").unwrap_or((data, ""));
+    let headers = headers
+        .lines()
+        .filter(|line| !line.starts_with(' '))
+        .map(|line| line.split_once(' ').unwrap_or((line, "")))
+        .collect();
+    (headers, message)
+}
+
+impl Commit {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let data = String::from_utf8_lossy(data);
+        let (headers, message) = parse_headers(&data);
+        let mut headers = headers.into_iter().peekable();
+
+        let Some(("tree", tree)) = headers.next() else {
+            bail!("commit does not start with a tree header");
+        };
+        ObjectId::from_hex(tree).context("bad tree id in commit")?;
+        let mut parents = Vec::new();
+        while let Some((_, parent)) = headers.next_if(|(key, _)| *key == "parent") {
+            ObjectId::from_hex(parent).context("bad parent id in commit")?;
+            parents.push(parent.to_string());
+        }
+        let Some(("author", author)) = headers.next() else {
+            bail!("commit is missing an author header");
+        };
+        let Some(("committer", committer)) = headers.next() else {
+            bail!("commit is missing a committer header");
+        };
+
+        Ok(Self {
+            tree: tree.to_string(),
+            parents,
+            author: Signature::parse(author).context("bad author in commit")?,
+            committer: Signature::parse(committer).context("bad committer in commit")?,
+            message: message.to_string(),
+        })
+    }
+
+    pub fn read(objects: &dyn ObjectStore, hash: &str) -> anyhow::Result<Self> {
+        let object = Object::read(objects, hash)?;
+        ensure!(
+            object.object_type == ObjectType::Commit,
+            "object {hash} is a {}, not a commit",
+            object.object_type
+        );
+        Self::parse(&object.into_bytes()?).with_context(|| format!("parse commit {hash}"))
+    }
+}
+
+/// Commits the tree staged in the index onto the branch HEAD points to.
+pub(crate) fn commit(repo: &Repository, message: &str) -> anyhow::Result<Option<ObjectId>> {
+    let Head::Branch(head_ref) = repo.head().context("read HEAD")? else {
         anyhow::bail!("refusing to commit onto detached HEAD");
     };
-    let head_ref = head_ref.trim();
-    let parent_hash = std::fs::read_to_string(format!(".git/{head_ref}"))
+    let parent_hash = repo
+        .resolve_ref(&head_ref)
         .with_context(|| format!("read HEAD reference target '{head_ref}'"))?;
-    let parent_hash = parent_hash.trim();
 
-    let Some(tree_hash) = write_tree_for(dot_git_path, path).context("write tree")? else {
+    let mut index = repo.index()?;
+    if index.entries.is_empty() {
         eprintln!("not committing empty tree");
         return Ok(None);
-    };
+    }
+    let tree_hash =
+        write_tree_from_index(repo.objects.as_ref(), &mut index).context("write tree")?;
+    repo.write_index(&index)?;
     let commit_hash = commit_tree(
-        dot_git_path,
-        &message,
-        &hex::encode(tree_hash),
-        Some(parent_hash),
+        repo.objects.as_ref(),
+        message,
+        &tree_hash.to_string(),
+        parent_hash.as_deref(),
     )
     .context("create commit")?;
 
     match commit_hash {
         Some(commit_hash) => {
-            std::fs::write(
-                dot_git_path.join(format!("{head_ref}")),
-                &hex::encode(commit_hash),
-            )
-            .with_context(|| format!("update HEAD reference target '{head_ref}'"))?;
+            let new = commit_hash.to_string();
+            repo.update_ref(&head_ref, &new)
+                .with_context(|| format!("update HEAD reference target '{head_ref}'"))?;
+            let subject = message.lines().next().unwrap_or_default();
+            let message = match parent_hash {
+                Some(_) => format!("commit: {subject}"),
+                None => format!("commit (initial): {subject}"),
+            };
+            for name in [head_ref.as_str(), "HEAD"] {
+                reflog::append(repo, name, parent_hash.as_deref(), Some(&new), &message)?;
+            }
             Ok(Some(commit_hash))
         }
         None => {
@@ -49,35 +181,40 @@ pub(crate) fn commit(
 
 #[cfg(test)]
 mod tests {
-    use std::{fs, path::PathBuf};
-    use tempfile::tempdir;
+    use std::path::Path;
 
-    use crate::{config::Config, git::Git};
+    use crate::{hash::HashAlgorithm, pathspec::Pathspec, staging::add, test::copy_dir};
 
     use super::*;
 
     #[test]
     fn test_commit_tree_complex() -> anyhow::Result<()> {
-        let tmp_dir = tempdir()?;
-        let dot_git = tmp_dir.path().join("dot-git");
-        let config = Config {
-            writer: Vec::new(),
-            error_writer: Vec::new(),
-            dot_git_path: dot_git.clone(),
-        };
-        Git { config }.init()?;
-        fs::create_dir_all(dot_git.join("refs/heads"))?;
-        let staging_git_dir = PathBuf::from(format!("tests/fixtures/complex-app"));
-        let result = write_tree_for(&dot_git, staging_git_dir.as_path());
-        assert!(&result.is_ok());
-        let tree_sha = hex::encode(result.unwrap().unwrap());
-        assert_eq!(tree_sha, "f33421767929a06951899aa91cc699df29c3893b");
-        let result = commit(&dot_git, &staging_git_dir, "initial commit")?;
-        let commit_sha = hex::encode(result.unwrap());
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        copy_dir(Path::new("tests/fixtures/complex-app"), worktree)?;
+        let repo = Repository::on_disk(&worktree.join(".git"), HashAlgorithm::Sha1);
+        repo.init()?;
+        assert_eq!(commit(&repo, "nothing staged")?, None);
+
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        let result = commit(&repo, "initial commit")?;
+        let commit_sha = result.unwrap().to_string();
         assert_eq!(commit_sha.len(), 40);
+        assert_eq!(repo.resolve_ref("HEAD")?, Some(commit_sha.clone()));
+        let first = Commit::read(repo.objects.as_ref(), &commit_sha)?;
+        assert_eq!(first.tree, "f33421767929a06951899aa91cc699df29c3893b");
+
+        // unstaged files stay out of the commit
+        std::fs::write(worktree.join("untracked.txt"), "not staged\n")?;
+        let result = commit(&repo, "second commit")?;
+        let second_sha = result.unwrap().to_string();
+        let second = Commit::read(repo.objects.as_ref(), &second_sha)?;
+        assert_eq!(second.tree, first.tree);
+        assert_eq!(second.parents, vec![commit_sha]);
+        assert_eq!(second.message, "second commit\n");
+        assert_eq!(second.author.email, "perry@hertler.org");
 
-        dbg!(&dot_git);
-        let head = fs::read_to_string(dot_git.join("HEAD"))?;
+        let head = String::from_utf8(repo.files.read("HEAD")?.unwrap())?;
         assert_eq!(head, "ref: refs/heads/master\n");
 
         Ok(())
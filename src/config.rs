@@ -14,3 +14,367 @@ impl Default for Config<std::io::Stdout, std::io::Stderr> {
         }
     }
 }
+
+/// Which kinds of files are fsynced before being moved into place, from `core.fsync`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fsync {
+    pub loose_objects: bool,
+    pub packs: bool,
+    pub refs: bool,
+    pub index: bool,
+}
+
+impl Default for Fsync {
+    /// Like git, packs are synced by default but loose objects, refs and the index aren't.
+    fn default() -> Self {
+        Self {
+            loose_objects: false,
+            packs: true,
+            refs: false,
+            index: false,
+        }
+    }
+}
+
+impl Fsync {
+    fn set(&mut self, component: &str, value: bool) -> anyhow::Result<()> {
+        match component {
+            "loose-object" => self.loose_objects = value,
+            "pack" | "pack-metadata" => self.packs = value,
+            "reference" => self.refs = value,
+            "index" => self.index = value,
+            "objects" => {
+                self.set("loose-object", value)?;
+                self.set("pack", value)?;
+            }
+            "committed" => {
+                self.set("objects", value)?;
+                self.set("reference", value)?;
+            }
+            "added" | "all" => {
+                self.set("committed", value)?;
+                self.set("index", value)?;
+            }
+            "derived-metadata" | "commit-graph" => {}
+            _ => anyhow::bail!("unknown core.fsync component '{component}'"),
+        }
+        Ok(())
+    }
+}
+
+/// The contents of a git config file, e.g. `.git/config`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GitConfig {
+    sections: Vec<ConfigSection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ConfigSection {
+    name: String,
+    subsection: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+impl ConfigSection {
+    fn matches(&self, name: &str, subsection: Option<&str>) -> bool {
+        self.name.eq_ignore_ascii_case(name) && self.subsection.as_deref() == subsection
+    }
+}
+
+/// Splits `remote.origin.url` into `("remote", Some("origin"), "url")`.
+fn split_key(key: &str) -> anyhow::Result<(&str, Option<&str>, &str)> {
+    let Some((section, name)) = key.split_once('.') else {
+        anyhow::bail!("config key '{key}' does not contain a section");
+    };
+    let Some((subsection, name)) = name.rsplit_once('.') else {
+        return Ok((section, None, name));
+    };
+    Ok((section, Some(subsection), name))
+}
+
+/// Splits `branch.topic` into `("branch", Some("topic"))`.
+fn split_section(name: &str) -> (&str, Option<&str>) {
+    match name.split_once('.') {
+        Some((section, subsection)) => (section, Some(subsection)),
+        None => (name, None),
+    }
+}
+
+fn parse_value(value: &str) -> String {
+    let mut parsed = String::new();
+    let mut in_quotes = false;
+    let mut chars = value.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' => match chars.next() {
+                Some('n') => parsed.push('\n'),
+                Some('t') => parsed.push('\t'),
+                Some(c) => parsed.push(c),
+                None => {}
+            },
+            '#' | ';' if !in_quotes => break,
+            c => parsed.push(c),
+        }
+    }
+    parsed.trim_end().to_string()
+}
+
+impl GitConfig {
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut config = GitConfig::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let Some((header, _)) = header.split_once(']') else {
+                    anyhow::bail!("invalid config section header '{line}'");
+                };
+                let (name, subsection) = match header.split_once(' ') {
+                    Some((name, subsection)) => {
+                        (name, Some(subsection.trim().trim_matches('"').to_string()))
+                    }
+                    None => match header.split_once('.') {
+                        Some((name, subsection)) => (name, Some(subsection.to_string())),
+                        None => (header, None),
+                    },
+                };
+                config.sections.push(ConfigSection {
+                    name: name.to_ascii_lowercase(),
+                    subsection,
+                    entries: Vec::new(),
+                });
+                continue;
+            }
+            let Some(section) = config.sections.last_mut() else {
+                anyhow::bail!("config entry '{line}' is outside of a section");
+            };
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_value(value)),
+                // a bare key is a boolean set to true
+                None => (line, String::from("true")),
+            };
+            section.entries.push((key.to_ascii_lowercase(), value));
+        }
+        Ok(config)
+    }
+
+    /// The last value set for `key`, like `git config --get`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).pop()
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let Ok((section, subsection, name)) = split_key(key) else {
+            return Vec::new();
+        };
+        self.sections
+            .iter()
+            .filter(|s| s.matches(section, subsection))
+            .flat_map(|s| s.entries.iter())
+            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// The names of the subsections of `section` in the order they first appear, like the
+    /// remotes of `[remote "origin"]`.
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for s in &self.sections {
+            if let Some(name) = s.subsection.as_deref() {
+                if s.name.eq_ignore_ascii_case(section) && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    pub fn get_bool(&self, key: &str) -> anyhow::Result<Option<bool>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        match value.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(Some(true)),
+            "false" | "no" | "off" | "0" | "" => Ok(Some(false)),
+            _ => anyhow::bail!("bad boolean config value '{value}' for '{key}'"),
+        }
+    }
+
+    /// The files to fsync according to `core.fsync` (a comma separated list of components,
+    /// each optionally prefixed with `-` to remove it) and the older `core.fsyncObjectFiles`.
+    pub fn fsync(&self) -> anyhow::Result<Fsync> {
+        let mut fsync = Fsync::default();
+        if self.get_bool("core.fsyncobjectfiles")? == Some(true) {
+            fsync.loose_objects = true;
+        }
+        for value in self.get_all("core.fsync") {
+            for component in value.split(',').map(str::trim) {
+                match component.strip_prefix('-') {
+                    _ if component == "none" => {
+                        fsync = Fsync {
+                            loose_objects: false,
+                            packs: false,
+                            refs: false,
+                            index: false,
+                        }
+                    }
+                    Some(component) => fsync.set(component, false)?,
+                    None if component.is_empty() => {}
+                    None => fsync.set(component, true)?,
+                }
+            }
+        }
+        Ok(fsync)
+    }
+
+    /// Replaces the value of `key`, adding the entry (and its section) if needed.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let (section, subsection, name) = split_key(key).expect("config key has a section");
+        let name = name.to_ascii_lowercase();
+        for s in self.sections.iter_mut().rev() {
+            if !s.matches(section, subsection) {
+                continue;
+            }
+            if let Some(entry) = s.entries.iter_mut().rev().find(|(k, _)| *k == name) {
+                entry.1 = value.to_string();
+                return;
+            }
+        }
+        self.add(key, value);
+    }
+
+    /// Adds another value for `key`, keeping any existing ones.
+    pub fn add(&mut self, key: &str, value: &str) {
+        let (section, subsection, name) = split_key(key).expect("config key has a section");
+        let entry = (name.to_ascii_lowercase(), value.to_string());
+        match self
+            .sections
+            .iter_mut()
+            .rev()
+            .find(|s| s.matches(section, subsection))
+        {
+            Some(s) => s.entries.push(entry),
+            None => self.sections.push(ConfigSection {
+                name: section.to_ascii_lowercase(),
+                subsection: subsection.map(str::to_string),
+                entries: vec![entry],
+            }),
+        }
+    }
+
+    /// Removes every value of `key`.
+    pub fn unset(&mut self, key: &str) {
+        let Ok((section, subsection, name)) = split_key(key) else {
+            return;
+        };
+        for s in self.sections.iter_mut() {
+            if s.matches(section, subsection) {
+                s.entries.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+            }
+        }
+        self.sections.retain(|s| !s.entries.is_empty());
+    }
+
+    /// Removes every `[branch "topic"]` section for `branch.topic`.
+    pub fn remove_section(&mut self, name: &str) {
+        let (section, subsection) = split_section(name);
+        self.sections.retain(|s| !s.matches(section, subsection));
+    }
+
+    /// Renames the sections for `from`, like `branch.topic`, to `to`, keeping their entries.
+    pub fn rename_section(&mut self, from: &str, to: &str) {
+        let (section, subsection) = split_section(from);
+        let (new_section, new_subsection) = split_section(to);
+        for s in self.sections.iter_mut() {
+            if s.matches(section, subsection) {
+                s.name = new_section.to_ascii_lowercase();
+                s.subsection = new_subsection.map(str::to_string);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for GitConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for section in &self.sections {
+            match &section.subsection {
+                Some(subsection) => writeln!(f, "[{} \"{}\"]", section.name, subsection)?,
+                None => writeln!(f, "[{}]", section.name)?,
+            }
+            for (key, value) in &section.entries {
+                let escaped = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+                    .replace('\t', "\\t");
+                if value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']) {
+                    writeln!(f, "\t{key} = \"{escaped}\"")?;
+                } else {
+                    writeln!(f, "\t{key} = {escaped}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fsync() -> anyhow::Result<()> {
+        let config = GitConfig::parse("[core]\n\tfsync = committed,-pack\n")?;
+        assert_eq!(
+            config.fsync()?,
+            Fsync {
+                loose_objects: true,
+                packs: false,
+                refs: true,
+                index: false,
+            }
+        );
+        let config = GitConfig::parse("[core]\n\tfsync = none,index\n")?;
+        assert_eq!(
+            config.fsync()?,
+            Fsync {
+                loose_objects: false,
+                packs: false,
+                refs: false,
+                index: true,
+            }
+        );
+        assert_eq!(GitConfig::default().fsync()?, Fsync::default());
+        assert!(GitConfig::parse("[core]\n\tfsync = bogus\n")?
+            .fsync()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_config_round_trip() -> anyhow::Result<()> {
+        let mut config = GitConfig::parse(
+            "[core]\n\tbare = false\n\tFileMode = true ; comment\n[remote \"origin\"]\n\turl = \"/tmp/a b\"\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n",
+        )?;
+        assert_eq!(config.get("core.bare"), Some("false"));
+        assert_eq!(config.get_bool("core.filemode")?, Some(true));
+        assert_eq!(config.get("remote.origin.url"), Some("/tmp/a b"));
+        assert_eq!(config.get("remote.upstream.url"), None);
+        assert_eq!(config.subsections("remote"), ["origin"]);
+
+        config.set("core.bare", "true");
+        config.set("branch.master.remote", "origin");
+        let config = GitConfig::parse(&config.to_string())?;
+        assert_eq!(config.get_bool("core.bare")?, Some(true));
+        assert_eq!(config.get("branch.master.remote"), Some("origin"));
+        assert_eq!(
+            config.get("remote.origin.fetch"),
+            Some("+refs/heads/*:refs/remotes/origin/*")
+        );
+        Ok(())
+    }
+}
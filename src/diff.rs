@@ -0,0 +1,2049 @@
+//! Line diffs: which lines of two files differ, and the unified patches and diffstats that
+//! show it, for changes between trees, the index and the worktree.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    ops::Range,
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::{
+    diff_tree::{ChangeKind, TreeChange, TreeDiff, Version},
+    hash::ObjectId,
+    index::{index_time, StatData},
+    object::Object,
+    pathspec::Pathspec,
+    rename::{RenameOptions, MAX_SCORE},
+    repository::Repository,
+    status::quote_path,
+    store::ObjectStore,
+    tree::tree_files,
+    worktree::{disk_path, hash_file, is_nested_repo, nested_repo_head, read_file, ModeConfig},
+};
+
+/// How the lines two files have in common are picked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DiffAlgorithm {
+    /// The shortest edit script, as Myers described it.
+    #[default]
+    Myers,
+    /// Anchors on lines that occur once on each side, which keeps moved blocks readable.
+    Patience,
+    /// Anchors on the lines that occur least often, a faster take on patience.
+    Histogram,
+    /// Myers' algorithm without the shortcuts it takes on large files.
+    Minimal,
+}
+
+impl FromStr for DiffAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "myers" | "default" => Ok(DiffAlgorithm::Myers),
+            "minimal" => Ok(DiffAlgorithm::Minimal),
+            "patience" => Ok(DiffAlgorithm::Patience),
+            "histogram" => Ok(DiffAlgorithm::Histogram),
+            _ => bail!("option diff-algorithm accepts \"myers\", \"minimal\", \"patience\" and \"histogram\""),
+        }
+    }
+}
+
+/// What `diff` shows of each changed file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    #[default]
+    Patch,
+    /// A diffstat with a line per file.
+    Stat,
+    /// Lines added and deleted, tab separated, for scripts.
+    Numstat,
+    NameOnly,
+    /// The path with the letter for the kind of change.
+    NameStatus,
+    /// Modes and ids before and after with the letter and path, like `diff-tree` shows.
+    Raw,
+}
+
+/// What the command line asks of patches, before the configuration fills in the rest.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiffFlags {
+    pub context: Option<usize>,
+    pub algorithm: Option<DiffAlgorithm>,
+    /// `-M` or `-C`.
+    pub renames: Option<RenameOptions>,
+    /// `--no-renames`.
+    pub no_renames: bool,
+}
+
+/// How patches are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffOptions {
+    pub algorithm: DiffAlgorithm,
+    /// Unchanged lines shown around each change.
+    pub context: usize,
+    /// Write binary changes as `GIT binary patch` data that can be applied, rather than
+    /// only saying that they differ.
+    pub binary: bool,
+    /// How to look for renames, if at all.
+    pub renames: Option<RenameOptions>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: DiffAlgorithm::default(),
+            context: 3,
+            binary: false,
+            renames: None,
+        }
+    }
+}
+
+/// Git treats contents with a NUL byte near the start as binary.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+/// The lines of `data`, each with its newline if it has one.
+pub(crate) fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Which lines of `old` and of `new` aren't part of what they have in common.
+pub fn changed_lines<'a>(
+    old: &[&'a [u8]],
+    new: &[&'a [u8]],
+    algorithm: DiffAlgorithm,
+) -> (Vec<bool>, Vec<bool>) {
+    // comparing numbers is cheaper than comparing lines
+    let mut ids: HashMap<&[u8], usize> = HashMap::new();
+    let mut intern = |lines: &[&'a [u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|&line| {
+                let next = ids.len();
+                *ids.entry(line).or_insert(next)
+            })
+            .collect()
+    };
+    let a = intern(old);
+    let b = intern(new);
+    let mut diff = LineDiff {
+        a: &a,
+        b: &b,
+        old_changed: vec![false; a.len()],
+        new_changed: vec![false; b.len()],
+    };
+    match algorithm {
+        DiffAlgorithm::Myers => diff.classic(0..a.len(), 0..b.len(), false),
+        DiffAlgorithm::Minimal => diff.classic(0..a.len(), 0..b.len(), true),
+        DiffAlgorithm::Patience => diff.patience(0..a.len(), 0..b.len()),
+        DiffAlgorithm::Histogram => diff.histogram(0..a.len(), 0..b.len()),
+    }
+    let LineDiff {
+        mut old_changed,
+        mut new_changed,
+        ..
+    } = diff;
+    compact(old, &a, &mut old_changed, &mut new_changed);
+    compact(new, &b, &mut new_changed, &mut old_changed);
+    (old_changed, new_changed)
+}
+
+/// Lines that show up this often in the other file are only kept for the comparison when
+/// they sit among lines that need to be.
+const MAX_EQUAL_LIMIT: usize = 1024;
+/// How far around a frequent line to look for lines with no match at all.
+const SIMILAR_SCAN_WINDOW: usize = 100;
+/// Once past this many edits, a search that found a long enough run in common may split
+/// there rather than keep looking for the shortest script.
+const HEURISTIC_MIN_COST: usize = 256;
+/// The fewest edits to try before settling for the furthest-reaching path.
+const MAX_COST_MIN: usize = 256;
+/// How long a run in common the heuristic needs.
+const SNAKE_COUNT: usize = 20;
+/// How far beyond the cost a path has to have come for the heuristic to split on it.
+const HEURISTIC_FACTOR: isize = 4;
+/// Lines appearing more often than this are no anchors for the histogram algorithm.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// An integer near the square root of `n`, for scaling limits to file sizes.
+fn rough_sqrt(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// The lines of both files as numbers, equal lines getting equal numbers, and what has been
+/// found to differ so far. The algorithms follow git's xdiff closely, since diffs are only
+/// useful when they match what others see.
+struct LineDiff<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    old_changed: Vec<bool>,
+    new_changed: Vec<bool>,
+}
+
+/// Where a Myers search splits a comparison in two, and whether each half has to be
+/// compared exactly.
+struct Split {
+    a: usize,
+    b: usize,
+    minimal_low: bool,
+    minimal_high: bool,
+}
+
+/// The furthest point reached along each diagonal, which can be negative.
+struct Diagonals {
+    values: Vec<isize>,
+    offset: isize,
+}
+
+impl std::ops::Index<isize> for Diagonals {
+    type Output = isize;
+
+    fn index(&self, d: isize) -> &isize {
+        &self.values[(d + self.offset) as usize]
+    }
+}
+
+impl std::ops::IndexMut<isize> for Diagonals {
+    fn index_mut(&mut self, d: isize) -> &mut isize {
+        &mut self.values[(d + self.offset) as usize]
+    }
+}
+
+impl LineDiff<'_> {
+    /// Narrows both ranges to the lines between their common start and end.
+    fn trim(&self, mut a: Range<usize>, mut b: Range<usize>) -> (Range<usize>, Range<usize>) {
+        while a.start < a.end && b.start < b.end && self.a[a.start] == self.b[b.start] {
+            a.start += 1;
+            b.start += 1;
+        }
+        while a.start < a.end && b.start < b.end && self.a[a.end - 1] == self.b[b.end - 1] {
+            a.end -= 1;
+            b.end -= 1;
+        }
+        (a, b)
+    }
+
+    fn mark(&mut self, a: Range<usize>, b: Range<usize>) {
+        self.old_changed[a].fill(true);
+        self.new_changed[b].fill(true);
+    }
+
+    /// Myers' algorithm on the lines in `a` and `b`. Lines with no match on the other side,
+    /// and frequent lines among those, are marked changed up front to keep the search small.
+    fn classic(&mut self, a: Range<usize>, b: Range<usize>, minimal: bool) {
+        let (a_range, b_range) = (a.clone(), b.clone());
+        let (a, b) = self.trim(a, b);
+        let mut counts: HashMap<usize, (usize, usize)> = HashMap::new();
+        for &line in &self.a[a_range.clone()] {
+            counts.entry(line).or_default().0 += 1;
+        }
+        for &line in &self.b[b_range.clone()] {
+            counts.entry(line).or_default().1 += 1;
+        }
+        // 0 for no match, 1 for some, 2 for many
+        let matches = |lines: &[usize], range: &Range<usize>, len: usize, theirs: bool| {
+            let limit = rough_sqrt(len).min(MAX_EQUAL_LIMIT);
+            lines[range.clone()]
+                .iter()
+                .map(|line| {
+                    let (ours, other) = counts[line];
+                    let count = if theirs { other } else { ours };
+                    match count {
+                        0 => 0u8,
+                        count if count >= limit && !minimal => 2,
+                        _ => 1,
+                    }
+                })
+                .collect::<Vec<u8>>()
+        };
+        let a_matches = matches(self.a, &a, a_range.len(), true);
+        let b_matches = matches(self.b, &b, b_range.len(), false);
+        let keep = |matches: &[u8], i: usize| {
+            matches[i] == 1 || (matches[i] == 2 && !surrounded_by_unmatched(matches, i))
+        };
+        let mut a_kept = Vec::new();
+        for i in 0..a.len() {
+            if keep(&a_matches, i) {
+                a_kept.push(a.start + i);
+            } else {
+                self.old_changed[a.start + i] = true;
+            }
+        }
+        let mut b_kept = Vec::new();
+        for i in 0..b.len() {
+            if keep(&b_matches, i) {
+                b_kept.push(b.start + i);
+            } else {
+                self.new_changed[b.start + i] = true;
+            }
+        }
+
+        let a_lines: Vec<usize> = a_kept.iter().map(|&i| self.a[i]).collect();
+        let b_lines: Vec<usize> = b_kept.iter().map(|&j| self.b[j]).collect();
+        let diagonals = a_kept.len() + b_kept.len() + 3;
+        let max_cost = rough_sqrt(diagonals).max(MAX_COST_MIN);
+        let mut forward = Diagonals {
+            values: vec![0; diagonals],
+            offset: b_kept.len() as isize + 1,
+        };
+        let mut backward = Diagonals {
+            values: vec![0; diagonals],
+            offset: b_kept.len() as isize + 1,
+        };
+        let mut search = MyersSearch {
+            a: &a_lines,
+            b: &b_lines,
+            forward: &mut forward,
+            backward: &mut backward,
+            max_cost,
+            old_changed: vec![false; a_lines.len()],
+            new_changed: vec![false; b_lines.len()],
+        };
+        search.compare(0..a_lines.len(), 0..b_lines.len(), minimal);
+        for (k, &i) in a_kept.iter().enumerate() {
+            self.old_changed[i] |= search.old_changed[k];
+        }
+        for (k, &j) in b_kept.iter().enumerate() {
+            self.new_changed[j] |= search.new_changed[k];
+        }
+    }
+
+    /// Anchors on the lines that occur exactly once on each side, in the longest order both
+    /// sides agree on, and compares what lies between them the same way.
+    fn patience(&mut self, a: Range<usize>, b: Range<usize>) {
+        if a.start == a.end || b.start == b.end {
+            return self.mark(a, b);
+        }
+        // each line in order of first occurrence in `a`, where it is and where in `b` it
+        // is, with `None` when it is in `b` more than once or in `a` more than once
+        let mut order: Vec<usize> = Vec::new();
+        let mut lines: HashMap<usize, (usize, Option<Option<usize>>)> = HashMap::new();
+        for i in a.clone() {
+            match lines.get_mut(&self.a[i]) {
+                Some(entry) => entry.1 = Some(None),
+                None => {
+                    order.push(self.a[i]);
+                    lines.insert(self.a[i], (i, None));
+                }
+            }
+        }
+        let mut has_matches = false;
+        for j in b.clone() {
+            if let Some(entry) = lines.get_mut(&self.b[j]) {
+                has_matches = true;
+                entry.1 = match entry.1 {
+                    None => Some(Some(j)),
+                    Some(_) => Some(None),
+                };
+            }
+        }
+        if !has_matches {
+            return self.mark(a, b);
+        }
+        let unique: Vec<(usize, usize)> = order
+            .iter()
+            .filter_map(|line| match lines[line] {
+                (i, Some(Some(j))) => Some((i, j)),
+                _ => None,
+            })
+            .collect();
+        if unique.is_empty() {
+            return self.classic(a, b, false);
+        }
+        let (mut i, mut j) = (a.start, b.start);
+        let anchors = longest_increasing(&unique);
+        let mut k = 0;
+        loop {
+            let (mut next_i, mut next_j) = match anchors.get(k) {
+                Some(&(anchor_i, anchor_j)) => (anchor_i, anchor_j),
+                None => (a.end, b.end),
+            };
+            if k < anchors.len() {
+                while next_i > i && next_j > j && self.a[next_i - 1] == self.b[next_j - 1] {
+                    next_i -= 1;
+                    next_j -= 1;
+                }
+            }
+            while i < next_i && j < next_j && self.a[i] == self.b[j] {
+                i += 1;
+                j += 1;
+            }
+            if next_i > i || next_j > j {
+                self.patience(i..next_i, j..next_j);
+            }
+            if k == anchors.len() {
+                return;
+            }
+            while k + 1 < anchors.len() && anchors[k + 1] == (anchors[k].0 + 1, anchors[k].1 + 1) {
+                k += 1;
+            }
+            (i, j) = (anchors[k].0 + 1, anchors[k].1 + 1);
+            k += 1;
+        }
+    }
+
+    /// Anchors on the longest region in common whose rarest line is as rare as can be, and
+    /// compares what comes before and after it the same way.
+    fn histogram(&mut self, mut a: Range<usize>, mut b: Range<usize>) {
+        loop {
+            if a.start == a.end || b.start == b.end {
+                return self.mark(a, b);
+            }
+            let mut positions: HashMap<usize, Vec<usize>> = HashMap::new();
+            for i in a.clone() {
+                positions.entry(self.a[i]).or_default().push(i);
+            }
+            let count = |i: usize| positions[&self.a[i]].len();
+            let mut best: Option<(Range<usize>, usize)> = None;
+            let mut lowest = MAX_CHAIN_LENGTH + 1;
+            let mut has_common = false;
+            let mut j = b.start;
+            while j < b.end {
+                let mut next_j = j + 1;
+                let starts = match positions.get(&self.b[j]) {
+                    Some(starts) => starts,
+                    None => {
+                        j = next_j;
+                        continue;
+                    }
+                };
+                has_common = true;
+                if starts.len() > lowest {
+                    j = next_j;
+                    continue;
+                }
+                let mut next = 0;
+                while let Some(&start) = starts.get(next) {
+                    let (mut a_start, mut b_start, mut a_end, mut b_end) = (start, j, start, j);
+                    let mut rarest = starts.len();
+                    while a.start < a_start
+                        && b.start < b_start
+                        && self.a[a_start - 1] == self.b[b_start - 1]
+                    {
+                        a_start -= 1;
+                        b_start -= 1;
+                        if rarest > 1 {
+                            rarest = rarest.min(count(a_start));
+                        }
+                    }
+                    while a_end + 1 < a.end
+                        && b_end + 1 < b.end
+                        && self.a[a_end + 1] == self.b[b_end + 1]
+                    {
+                        a_end += 1;
+                        b_end += 1;
+                        if rarest > 1 {
+                            rarest = rarest.min(count(a_end));
+                        }
+                    }
+                    next_j = next_j.max(b_end + 1);
+                    let longer = best
+                        .as_ref()
+                        .map_or(true, |(region, _)| region.len() - 1 < a_end - a_start);
+                    if longer || rarest < lowest {
+                        best = Some((a_start..a_end + 1, b_start));
+                        lowest = rarest;
+                    }
+                    // the next occurrence past the region just found
+                    while starts.get(next).is_some_and(|&start| start <= a_end) {
+                        next += 1;
+                    }
+                }
+                j = next_j;
+            }
+            match best {
+                Some((region, b_start)) => {
+                    let b_end = b_start + region.len();
+                    self.histogram(a.start..region.start, b.start..b_start);
+                    (a, b) = (region.end..a.end, b_end..b.end);
+                }
+                None if has_common => return self.classic(a, b, false),
+                None => return self.mark(a, b),
+            }
+        }
+    }
+}
+
+/// Whether the frequent line at `i` sits in a run of lines that are mostly unmatched, where
+/// it is likely a coincidence rather than part of what the files share.
+fn surrounded_by_unmatched(matches: &[u8], i: usize) -> bool {
+    let start = i.saturating_sub(SIMILAR_SCAN_WINDOW);
+    let end = (i + SIMILAR_SCAN_WINDOW).min(matches.len() - 1);
+    let (mut unmatched_before, mut frequent_before) = (0, 1);
+    for &line in matches[start..i].iter().rev() {
+        match line {
+            0 => unmatched_before += 1,
+            2 => frequent_before += 1,
+            _ => break,
+        }
+    }
+    if unmatched_before == 0 {
+        return false;
+    }
+    let (mut unmatched, mut frequent) = (0, 1);
+    for &line in &matches[i + 1..=end] {
+        match line {
+            0 => unmatched += 1,
+            2 => frequent += 1,
+            _ => break,
+        }
+    }
+    if unmatched == 0 {
+        return false;
+    }
+    unmatched += unmatched_before;
+    frequent += frequent_before;
+    frequent * 4 < frequent + unmatched
+}
+
+/// Myers' divide and conquer search, with the heuristics that keep it fast on large files.
+struct MyersSearch<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    forward: &'a mut Diagonals,
+    backward: &'a mut Diagonals,
+    max_cost: usize,
+    old_changed: Vec<bool>,
+    new_changed: Vec<bool>,
+}
+
+impl MyersSearch<'_> {
+    fn compare(&mut self, mut a: Range<usize>, mut b: Range<usize>, minimal: bool) {
+        while a.start < a.end && b.start < b.end && self.a[a.start] == self.b[b.start] {
+            a.start += 1;
+            b.start += 1;
+        }
+        while a.start < a.end && b.start < b.end && self.a[a.end - 1] == self.b[b.end - 1] {
+            a.end -= 1;
+            b.end -= 1;
+        }
+        if a.start == a.end || b.start == b.end {
+            self.old_changed[a].fill(true);
+            self.new_changed[b].fill(true);
+            return;
+        }
+        let split = self.split(&a, &b, minimal);
+        self.compare(a.start..split.a, b.start..split.b, split.minimal_low);
+        self.compare(split.a..a.end, split.b..b.end, split.minimal_high);
+    }
+
+    /// Where a shortest edit script from `a` to `b` passes through the middle, searching
+    /// from both ends at once. Unless `minimal`, long searches settle for a good split.
+    fn split(&mut self, a: &Range<usize>, b: &Range<usize>, minimal: bool) -> Split {
+        let (off1, lim1) = (a.start as isize, a.end as isize);
+        let (off2, lim2) = (b.start as isize, b.end as isize);
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax, mut bmin, mut bmax) = (fmid, fmid, bmid, bmid);
+        let snake = SNAKE_COUNT as isize;
+        let (ha1, ha2) = (self.a, self.b);
+        let same = |i1: isize, i2: isize| ha1[i1 as usize] == ha2[i2 as usize];
+        let found = |i1: isize, i2: isize| Split {
+            a: i1 as usize,
+            b: i2 as usize,
+            minimal_low: true,
+            minimal_high: true,
+        };
+        self.forward[fmid] = off1;
+        self.backward[bmid] = lim1;
+
+        let mut cost = 1;
+        loop {
+            let mut got_snake = false;
+
+            if fmin > dmin {
+                fmin -= 1;
+                self.forward[fmin - 1] = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.forward[fmax + 1] = -1;
+            } else {
+                fmax -= 1;
+            }
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if self.forward[d - 1] >= self.forward[d + 1] {
+                    self.forward[d - 1] + 1
+                } else {
+                    self.forward[d + 1]
+                };
+                let start = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && same(i1, i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - start > snake {
+                    got_snake = true;
+                }
+                self.forward[d] = i1;
+                if odd && bmin <= d && d <= bmax && self.backward[d] <= i1 {
+                    return found(i1, i2);
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.backward[bmin - 1] = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.backward[bmax + 1] = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if self.backward[d - 1] < self.backward[d + 1] {
+                    self.backward[d - 1]
+                } else {
+                    self.backward[d + 1] - 1
+                };
+                let start = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && same(i1 - 1, i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if start - i1 > snake {
+                    got_snake = true;
+                }
+                self.backward[d] = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= self.forward[d] {
+                    return found(i1, i2);
+                }
+                d -= 2;
+            }
+
+            if minimal {
+                cost += 1;
+                continue;
+            }
+
+            // a path that got far for its cost and ends in a long enough run in common is
+            // as good a place to split as any
+            if got_snake && cost > HEURISTIC_MIN_COST {
+                let mut best = 0;
+                let mut split = None;
+                let mut d = fmax;
+                while d >= fmin {
+                    let distance = (d - fmid).abs();
+                    let i1 = self.forward[d];
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - distance;
+                    if v > HEURISTIC_FACTOR * cost as isize
+                        && v > best
+                        && off1 + snake <= i1
+                        && i1 < lim1
+                        && off2 + snake <= i2
+                        && i2 < lim2
+                        && (1..=snake).all(|k| same(i1 - k, i2 - k))
+                    {
+                        best = v;
+                        split = Some((i1, i2));
+                    }
+                    d -= 2;
+                }
+                if let Some((i1, i2)) = split {
+                    return Split {
+                        minimal_high: false,
+                        ..found(i1, i2)
+                    };
+                }
+
+                let mut d = bmax;
+                while d >= bmin {
+                    let distance = (d - bmid).abs();
+                    let i1 = self.backward[d];
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - distance;
+                    if v > HEURISTIC_FACTOR * cost as isize
+                        && v > best
+                        && off1 < i1
+                        && i1 <= lim1 - snake
+                        && off2 < i2
+                        && i2 <= lim2 - snake
+                        && (0..snake).all(|k| same(i1 + k, i2 + k))
+                    {
+                        best = v;
+                        split = Some((i1, i2));
+                    }
+                    d -= 2;
+                }
+                if let Some((i1, i2)) = split {
+                    return Split {
+                        minimal_low: false,
+                        ..found(i1, i2)
+                    };
+                }
+            }
+
+            // enough searching: split at whichever end got furthest
+            if cost >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = self.forward[d].min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                    d -= 2;
+                }
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = self.backward[d].max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                    d -= 2;
+                }
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Split {
+                        minimal_high: false,
+                        ..found(fbest1, fbest - fbest1)
+                    }
+                } else {
+                    Split {
+                        minimal_low: false,
+                        ..found(bbest1, bbest - bbest1)
+                    }
+                };
+            }
+            cost += 1;
+        }
+    }
+}
+
+/// The longest run of `pairs`, in order of their first halves, whose second halves increase
+/// too, found by patience sorting.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // the index of the pair ending the best run of each length, and each pair's predecessor
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (index, &(_, j)) in pairs.iter().enumerate() {
+        let length = tails.partition_point(|&tail| pairs[tail].1 < j);
+        previous[index] = length.checked_sub(1).map(|before| tails[before]);
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+    let mut run = Vec::new();
+    let mut at = tails.last().copied();
+    while let Some(index) = at {
+        run.push(pairs[index]);
+        at = previous[index];
+    }
+    run.reverse();
+    run
+}
+
+/// A run of changed lines, with the lines just outside it unchanged.
+#[derive(Debug, Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+/// Flags with an unchanged line before the first and after the last, so groups can be
+/// walked without bounds checks, the way xdiff does it.
+struct Flags<'a> {
+    changed: &'a mut [bool],
+}
+
+impl Flags<'_> {
+    fn get(&self, i: isize) -> bool {
+        i >= 0 && (i as usize) < self.changed.len() && self.changed[i as usize]
+    }
+
+    fn len(&self) -> usize {
+        self.changed.len()
+    }
+
+    fn first_group(&self) -> Group {
+        let mut end = 0;
+        while self.get(end as isize) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    fn next_group(&self, group: &mut Group) -> bool {
+        if group.end == self.len() {
+            return false;
+        }
+        group.start = group.end + 1;
+        group.end = group.start;
+        while self.get(group.end as isize) {
+            group.end += 1;
+        }
+        true
+    }
+
+    fn previous_group(&self, group: &mut Group) -> bool {
+        if group.start == 0 {
+            return false;
+        }
+        group.end = group.start - 1;
+        group.start = group.end;
+        while self.get(group.start as isize - 1) {
+            group.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, lines: &[usize], group: &mut Group) -> bool {
+        if group.end < self.len() && lines[group.start] == lines[group.end] {
+            self.changed[group.start] = false;
+            self.changed[group.end] = true;
+            group.start += 1;
+            group.end += 1;
+            while self.get(group.end as isize) {
+                group.end += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    fn slide_up(&mut self, lines: &[usize], group: &mut Group) -> bool {
+        if group.start > 0 && lines[group.start - 1] == lines[group.end - 1] {
+            group.start -= 1;
+            group.end -= 1;
+            self.changed[group.start] = true;
+            self.changed[group.end] = false;
+            while self.get(group.start as isize - 1) {
+                group.start -= 1;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+/// Slides each run of changed lines in one file to where it reads best, as git does: next
+/// to a change in the other file if it can be, otherwise where the indentation suggests a
+/// block starts and ends.
+fn compact(text: &[&[u8]], lines: &[usize], changed: &mut [bool], other_changed: &mut [bool]) {
+    const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+    let mut flags = Flags { changed };
+    let other = Flags {
+        changed: other_changed,
+    };
+    let mut group = flags.first_group();
+    let mut other_group = other.first_group();
+    loop {
+        if group.end != group.start {
+            let mut size;
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                size = group.end - group.start;
+                end_matching_other = None;
+                while flags.slide_up(lines, &mut group) {
+                    other.previous_group(&mut other_group);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+                while flags.slide_down(lines, &mut group) {
+                    other.next_group(&mut other_group);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+                // sliding can merge groups, and then the bigger one needs another look
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+            if group.end == earliest_end {
+                // it can't move
+            } else if end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    flags.slide_up(lines, &mut group);
+                    other.previous_group(&mut other_group);
+                }
+            } else {
+                let mut shift = earliest_end.max(group.end.saturating_sub(size + 1));
+                shift = shift.max(group.end.saturating_sub(INDENT_HEURISTIC_MAX_SLIDING));
+                let mut best: Option<(usize, SplitScore)> = None;
+                while shift <= group.end {
+                    let mut score = SplitScore::default();
+                    score.add(&SplitMeasurement::new(text, shift));
+                    score.add(&SplitMeasurement::new(text, shift - size));
+                    if best
+                        .as_ref()
+                        .map_or(true, |(_, best_score)| score.cmp(best_score) <= 0)
+                    {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+                if let Some((best_shift, _)) = best {
+                    while group.end > best_shift {
+                        flags.slide_up(lines, &mut group);
+                        other.previous_group(&mut other_group);
+                    }
+                }
+            }
+        }
+        if !flags.next_group(&mut group) {
+            break;
+        }
+        other.next_group(&mut other_group);
+    }
+}
+
+/// The indentation of `line` with tabs to multiples of eight, `None` if it is blank.
+fn indent(line: &[u8]) -> Option<usize> {
+    const MAX_INDENT: usize = 200;
+    let mut indent = 0;
+    for &b in line {
+        match b {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            b'\n' | b'\r' | b'\x0b' | b'\x0c' => {}
+            _ => return Some(indent),
+        }
+        if indent >= MAX_INDENT {
+            return Some(MAX_INDENT);
+        }
+    }
+    None
+}
+
+/// The surroundings of a place a run of changed lines could start or end, before `split`.
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: Option<usize>,
+    pre_blank: usize,
+    pre_indent: Option<usize>,
+    post_blank: usize,
+    post_indent: Option<usize>,
+}
+
+impl SplitMeasurement {
+    fn new(text: &[&[u8]], split: usize) -> Self {
+        const MAX_BLANKS: usize = 20;
+        let end_of_file = split >= text.len();
+        let mut measurement = Self {
+            end_of_file,
+            indent: if end_of_file {
+                None
+            } else {
+                indent(text[split])
+            },
+            pre_blank: 0,
+            pre_indent: None,
+            post_blank: 0,
+            post_indent: None,
+        };
+        for line in text[..split.min(text.len())].iter().rev() {
+            measurement.pre_indent = indent(line);
+            if measurement.pre_indent.is_some() {
+                break;
+            }
+            measurement.pre_blank += 1;
+            if measurement.pre_blank == MAX_BLANKS {
+                measurement.pre_indent = Some(0);
+                break;
+            }
+        }
+        for line in text.iter().skip(split + 1) {
+            measurement.post_indent = indent(line);
+            if measurement.post_indent.is_some() {
+                break;
+            }
+            measurement.post_blank += 1;
+            if measurement.post_blank == MAX_BLANKS {
+                measurement.post_indent = Some(0);
+                break;
+            }
+        }
+        measurement
+    }
+}
+
+/// How bad a pair of splits looks; lower is better. The weights are git's.
+#[derive(Debug, Default)]
+struct SplitScore {
+    effective_indent: isize,
+    penalty: isize,
+}
+
+impl SplitScore {
+    fn add(&mut self, m: &SplitMeasurement) {
+        let post_blank = if m.indent.is_none() {
+            1 + m.post_blank
+        } else {
+            0
+        };
+        let total_blank = (m.pre_blank + post_blank) as isize;
+        if m.pre_indent.is_none() && m.pre_blank == 0 {
+            self.penalty += 1;
+        }
+        if m.end_of_file {
+            self.penalty += 21;
+        }
+        self.penalty += -30 * total_blank + 6 * post_blank as isize;
+        let indent = m.indent.or(m.post_indent);
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent.map_or(-1, |indent| indent as isize);
+        let (Some(indent), Some(pre_indent)) = (indent, m.pre_indent) else {
+            return;
+        };
+        if indent > pre_indent {
+            self.penalty += if any_blanks { 10 } else { -4 };
+        } else if indent < pre_indent {
+            self.penalty += match m.post_indent {
+                Some(post_indent) if post_indent > indent => {
+                    if any_blanks {
+                        17
+                    } else {
+                        24
+                    }
+                }
+                _ => {
+                    if any_blanks {
+                        17
+                    } else {
+                        23
+                    }
+                }
+            };
+        }
+    }
+
+    fn cmp(&self, other: &Self) -> isize {
+        let indents = (self.effective_indent > other.effective_indent) as isize
+            - (self.effective_indent < other.effective_indent) as isize;
+        60 * indents + (self.penalty - other.penalty)
+    }
+}
+
+/// A line of a hunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HunkLine<'a> {
+    Context(&'a [u8]),
+    Deleted(&'a [u8]),
+    Added(&'a [u8]),
+}
+
+/// A run of changes with the unchanged lines around them, as a unified diff shows it. Starts
+/// count lines from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk<'a> {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    /// The nearest line before the hunk that looks like the start of a function.
+    pub function: Option<&'a [u8]>,
+    pub lines: Vec<HunkLine<'a>>,
+}
+
+/// The hunks turning `old` into `new`.
+pub fn hunks<'a>(old: &'a [u8], new: &'a [u8], options: &DiffOptions) -> Vec<Hunk<'a>> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let (old_changed, new_changed) = changed_lines(&old_lines, &new_lines, options.algorithm);
+
+    // each change as the lines it replaces and the lines replacing them
+    let mut changes: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old_lines.len() || j < new_lines.len() {
+        let (old_start, new_start) = (i, j);
+        while old_changed.get(i) == Some(&true) {
+            i += 1;
+        }
+        while new_changed.get(j) == Some(&true) {
+            j += 1;
+        }
+        if (i, j) == (old_start, new_start) {
+            i += 1;
+            j += 1;
+        } else {
+            changes.push((old_start..i, new_start..j));
+        }
+    }
+
+    let context = options.context;
+    let mut hunks = Vec::new();
+    let mut at = 0;
+    while at < changes.len() {
+        let mut last = at;
+        while last + 1 < changes.len()
+            && changes[last + 1].0.start - changes[last].0.end <= 2 * context
+        {
+            last += 1;
+        }
+        let (first, end) = (&changes[at], &changes[last]);
+        let old_start = first.0.start.saturating_sub(context);
+        let new_start = first.1.start - (first.0.start - old_start);
+        let old_end = (end.0.end + context).min(old_lines.len());
+        let new_end = end.1.end + (old_end - end.0.end);
+        let mut lines = Vec::new();
+        let mut old_at = old_start;
+        for (deleted, added) in &changes[at..=last] {
+            lines.extend(
+                old_lines[old_at..deleted.start]
+                    .iter()
+                    .map(|line| HunkLine::Context(line)),
+            );
+            lines.extend(
+                old_lines[deleted.clone()]
+                    .iter()
+                    .map(|line| HunkLine::Deleted(line)),
+            );
+            lines.extend(
+                new_lines[added.clone()]
+                    .iter()
+                    .map(|line| HunkLine::Added(line)),
+            );
+            old_at = deleted.end;
+        }
+        lines.extend(
+            old_lines[old_at..old_end]
+                .iter()
+                .map(|line| HunkLine::Context(line)),
+        );
+        let function = old_lines[..old_start]
+            .iter()
+            .rev()
+            .find(|line| {
+                line.first()
+                    .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$')
+            })
+            .map(|line| {
+                let line = &line[..line.len().min(80)];
+                let end = line
+                    .iter()
+                    .rposition(|b| !b.is_ascii_whitespace())
+                    .map_or(0, |i| i + 1);
+                &line[..end]
+            });
+        hunks.push(Hunk {
+            old_start: old_start + 1,
+            old_len: old_end - old_start,
+            new_start: new_start + 1,
+            new_len: new_end - new_start,
+            function,
+            lines,
+        });
+        at = last + 1;
+    }
+    hunks
+}
+
+/// `start,len` the way hunk headers write it: the length left out when it's one, and the
+/// line before the hunk as the start when it's empty.
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start - 1),
+        1 => format!("{start}"),
+        _ => format!("{start},{len}"),
+    }
+}
+
+/// Writes the hunks turning `old` into `new` in the unified format.
+pub fn write_hunks(
+    out: &mut dyn Write,
+    old: &[u8],
+    new: &[u8],
+    options: &DiffOptions,
+) -> anyhow::Result<()> {
+    for hunk in hunks(old, new, options) {
+        write!(
+            out,
+            "@@ -{} +{} @@",
+            hunk_range(hunk.old_start, hunk.old_len),
+            hunk_range(hunk.new_start, hunk.new_len)
+        )?;
+        if let Some(function) = hunk.function {
+            out.write_all(b" ")?;
+            out.write_all(function)?;
+        }
+        out.write_all(b"\n")?;
+        for line in hunk.lines {
+            let (marker, text) = match line {
+                HunkLine::Context(text) => (b' ', text),
+                HunkLine::Deleted(text) => (b'-', text),
+                HunkLine::Added(text) => (b'+', text),
+            };
+            out.write_all(&[marker])?;
+            out.write_all(text)?;
+            if !text.ends_with(b"\n") {
+                out.write_all(b"\n\\ No newline at end of file\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One side of a file being compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffFile {
+    pub path: Vec<u8>,
+    pub mode: u32,
+    pub id: ObjectId,
+    pub data: Vec<u8>,
+}
+
+impl DiffFile {
+    /// The file at `path` as `version` has it, read from `objects`. Submodules show as the
+    /// commit they are at.
+    pub fn read(objects: &dyn ObjectStore, path: &[u8], version: Version) -> anyhow::Result<Self> {
+        let (mode, id) = version;
+        let data = if mode == 0o160000 {
+            format!("Subproject commit {id}\n").into_bytes()
+        } else {
+            Object::read(objects, &id.to_string())?.into_bytes()?
+        };
+        Ok(Self {
+            path: path.to_vec(),
+            mode,
+            id,
+            data,
+        })
+    }
+}
+
+/// A path found to hold what another path held, with how much of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rename {
+    /// How alike the two files are, out of [`MAX_SCORE`].
+    pub score: u32,
+    /// The old path stayed, so the new one is a copy of it.
+    pub copy: bool,
+}
+
+/// What a changed path was and became, `None` on the side where it is absent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePair {
+    pub old: Option<DiffFile>,
+    pub new: Option<DiffFile>,
+    /// Set when `old` is at another path that `new` was renamed or copied from.
+    pub rename: Option<Rename>,
+}
+
+impl FilePair {
+    pub fn new(old: Option<DiffFile>, new: Option<DiffFile>) -> Self {
+        Self {
+            old,
+            new,
+            rename: None,
+        }
+    }
+
+    /// How the two sides differ.
+    pub fn kind(&self) -> ChangeKind {
+        match (&self.old, &self.new, self.rename) {
+            (_, _, Some(Rename { copy: true, .. })) => ChangeKind::Copied,
+            (_, _, Some(_)) => ChangeKind::Renamed,
+            (None, _, _) => ChangeKind::Added,
+            (_, None, _) => ChangeKind::Deleted,
+            (Some(old), Some(new), _) if old.mode & 0o170000 != new.mode & 0o170000 => {
+                ChangeKind::TypeChanged
+            }
+            _ => ChangeKind::Modified,
+        }
+    }
+
+    /// The path the pair is about, the new one for renames and copies.
+    pub fn path(&self) -> &[u8] {
+        match (&self.old, &self.new) {
+            (_, Some(file)) | (Some(file), None) => &file.path,
+            (None, None) => &[],
+        }
+    }
+
+    /// The letter `--name-status` shows, followed by the similarity in percent for renames
+    /// and copies, like `R087`.
+    pub fn status(&self) -> String {
+        let letter = self.kind().letter();
+        match self.rename {
+            Some(rename) => format!("{letter}{:03}", similarity(rename.score)),
+            None => letter.to_string(),
+        }
+    }
+
+    /// The paths `--name-status` shows, tab separated.
+    pub fn paths(&self) -> String {
+        match (&self.old, &self.new, self.rename) {
+            (Some(old), Some(new), Some(_)) => {
+                format!("{}\t{}", quote_path(&old.path), quote_path(&new.path))
+            }
+            _ => quote_path(self.path()),
+        }
+    }
+
+    /// The name diffstats show: the path, or for renames and copies both paths with what they
+    /// have in common written once, like `src/{old => new}/main.rs`.
+    pub fn display_name(&self) -> String {
+        let (Some(old), Some(new), Some(_)) = (&self.old, &self.new, self.rename) else {
+            return quote_path(self.path());
+        };
+        let (a, b) = (&old.path[..], &new.path[..]);
+        let (quoted_a, quoted_b) = (quote_path(a), quote_path(b));
+        if quoted_a.starts_with('"') || quoted_b.starts_with('"') {
+            return format!("{quoted_a} => {quoted_b}");
+        }
+        // the common prefix ends after a slash and the common suffix starts at one
+        let mut prefix = 0;
+        for (i, (x, y)) in a.iter().zip(b).enumerate() {
+            if x != y {
+                break;
+            }
+            if *x == b'/' {
+                prefix = i + 1;
+            }
+        }
+        let at = |path: &[u8], i: usize| path.get(i).copied().unwrap_or(0);
+        let stop = prefix.saturating_sub(1);
+        let mut suffix = 0;
+        let (mut i, mut j) = (a.len(), b.len());
+        while i >= stop && j >= stop && at(a, i) == at(b, j) {
+            if at(a, i) == b'/' {
+                suffix = a.len() - i;
+            }
+            if i == 0 || j == 0 {
+                break;
+            }
+            i -= 1;
+            j -= 1;
+        }
+        let a_middle = &a[prefix..a.len().saturating_sub(suffix).max(prefix)];
+        let b_middle = &b[prefix..b.len().saturating_sub(suffix).max(prefix)];
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        if prefix + suffix == 0 {
+            return format!("{} => {}", text(a_middle), text(b_middle));
+        }
+        format!(
+            "{}{{{} => {}}}{}",
+            text(&a[..prefix]),
+            text(a_middle),
+            text(b_middle),
+            text(&a[a.len() - suffix..])
+        )
+    }
+}
+
+/// A rename score in whole percent, rounded down.
+fn similarity(score: u32) -> u32 {
+    score * 100 / MAX_SCORE
+}
+
+/// Both sides of a change between trees, read from `objects`.
+pub(crate) fn file_pair(
+    objects: &dyn ObjectStore,
+    change: &TreeChange,
+) -> anyhow::Result<FilePair> {
+    let read = |version: Option<Version>| {
+        version
+            .map(|version| DiffFile::read(objects, &change.path, version))
+            .transpose()
+    };
+    Ok(FilePair::new(read(change.old)?, read(change.new)?))
+}
+
+/// The changes from the tree `old` to the tree `new` in the paths `pathspec` picks; a missing
+/// side counts as an empty tree.
+pub(crate) fn tree_changes(
+    objects: &dyn ObjectStore,
+    old: Option<&str>,
+    new: Option<&str>,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<FilePair>> {
+    let mut pairs = Vec::new();
+    for change in TreeDiff::new(objects, old, new, true)? {
+        let change = change?;
+        if pathspec.matches(&change.path) {
+            pairs.push(file_pair(objects, &change)?);
+        }
+    }
+    Ok(pairs)
+}
+
+/// The changes from the tree `tree`, or from nothing, to what the index stages. Unmerged paths
+/// are left out, and so are paths only added with intent to add.
+pub(crate) fn index_changes(
+    repo: &Repository,
+    tree: Option<&str>,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<FilePair>> {
+    let objects = repo.objects.as_ref();
+    let mut base = match tree {
+        Some(tree) => tree_files(objects, tree)?,
+        None => BTreeMap::new(),
+    };
+    let mut pairs = Vec::new();
+    for entry in &repo.index()?.entries {
+        let old = base.remove(&entry.path);
+        if entry.stage != 0 || !pathspec.matches(&entry.path) {
+            continue;
+        }
+        let new = (!entry.intent_to_add).then_some((entry.mode, entry.hash));
+        if old != new {
+            pairs.push(read_pair(objects, &entry.path, old, new)?);
+        }
+    }
+    for (path, version) in base {
+        if pathspec.matches(&path) {
+            pairs.push(read_pair(objects, &path, Some(version), None)?);
+        }
+    }
+    pairs.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(pairs)
+}
+
+/// The changes from the tracked files to the worktree, where they are tracked by the index or,
+/// given `tree`, by that tree. Untracked files are left out, and so are unmerged paths.
+pub(crate) fn worktree_changes(
+    repo: &Repository,
+    dot_git_path: &Path,
+    tree: Option<&str>,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<FilePair>> {
+    let worktree = dot_git_path
+        .parent()
+        .context("repository has no working tree")?;
+    let objects = repo.objects.as_ref();
+    let index = repo.index()?;
+    let index_time = index_time(dot_git_path);
+    let modes = ModeConfig::load(repo)?;
+    let mut base = tree.map(|tree| tree_files(objects, tree)).transpose()?;
+    let mut pairs = Vec::new();
+    for entry in &index.entries {
+        let tracked = base.as_mut().map(|files| files.remove(&entry.path));
+        if entry.stage != 0 || !pathspec.matches(&entry.path) {
+            continue;
+        }
+        let old = match tracked {
+            Some(version) => version,
+            None if entry.intent_to_add => None,
+            None => Some((entry.mode, entry.hash)),
+        };
+        let Some(metadata) = fs::symlink_metadata(disk_path(worktree, &entry.path))
+            .ok()
+            .filter(|metadata| !metadata.is_dir() || entry.mode == 0o160000)
+        else {
+            if old.is_some() {
+                pairs.push(read_pair(objects, &entry.path, old, None)?);
+            }
+            continue;
+        };
+        let mode = modes.mode_of(&metadata, Some(entry.mode));
+        let id = if mode == 0o160000 {
+            // a submodule that isn't checked out counts as unchanged
+            let path = disk_path(worktree, &entry.path);
+            let head = if is_nested_repo(&path) {
+                nested_repo_head(&path)?
+            } else {
+                None
+            };
+            head.unwrap_or(entry.hash)
+        } else if mode == entry.mode
+            && entry.is_stat_clean(&StatData::from_metadata(&metadata), index_time)
+        {
+            entry.hash
+        } else {
+            hash_file(repo, worktree, &entry.path, &metadata, mode, false)?
+        };
+        if old == Some((mode, id)) {
+            continue;
+        }
+        let data = if mode == 0o160000 {
+            format!("Subproject commit {id}\n").into_bytes()
+        } else {
+            read_file(repo, worktree, &entry.path, &metadata, mode)?
+        };
+        let new = DiffFile {
+            path: entry.path.clone(),
+            mode,
+            id,
+            data,
+        };
+        let mut pair = read_pair(objects, &entry.path, old, None)?;
+        pair.new = Some(new);
+        pairs.push(pair);
+    }
+    for (path, version) in base.into_iter().flatten() {
+        if pathspec.matches(&path) {
+            pairs.push(read_pair(objects, &path, Some(version), None)?);
+        }
+    }
+    pairs.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(pairs)
+}
+
+/// The pair of `old` and `new` at `path`, read from `objects`.
+fn read_pair(
+    objects: &dyn ObjectStore,
+    path: &[u8],
+    old: Option<Version>,
+    new: Option<Version>,
+) -> anyhow::Result<FilePair> {
+    let read = |version: Option<Version>| {
+        version
+            .map(|version| DiffFile::read(objects, path, version))
+            .transpose()
+    };
+    Ok(FilePair::new(read(old)?, read(new)?))
+}
+
+/// Writes the patch for one file, from the `diff --git` line on.
+pub fn write_patch(
+    out: &mut dyn Write,
+    pair: &FilePair,
+    options: &DiffOptions,
+) -> anyhow::Result<()> {
+    let (old, new) = (pair.old.as_ref(), pair.new.as_ref());
+    // git shows a file turning into another kind of file as a deletion and an addition
+    if pair.kind() == ChangeKind::TypeChanged {
+        write_file_patch(out, old, None, None, options)?;
+        return write_file_patch(out, None, new, None, options);
+    }
+    write_file_patch(out, old, new, pair.rename, options)
+}
+
+fn write_file_patch(
+    out: &mut dyn Write,
+    old: Option<&DiffFile>,
+    new: Option<&DiffFile>,
+    rename: Option<Rename>,
+    options: &DiffOptions,
+) -> anyhow::Result<()> {
+    let (Some(either), Some(other)) = (old.or(new), new.or(old)) else {
+        return Ok(());
+    };
+    let a = [b"a/", &either.path[..]].concat();
+    let b = [b"b/", &other.path[..]].concat();
+    writeln!(out, "diff --git {} {}", quote_path(&a), quote_path(&b))?;
+    match (old, new) {
+        (None, Some(new)) => writeln!(out, "new file mode {:06o}", new.mode)?,
+        (Some(old), None) => writeln!(out, "deleted file mode {:06o}", old.mode)?,
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            writeln!(out, "old mode {:06o}\nnew mode {:06o}", old.mode, new.mode)?
+        }
+        _ => {}
+    }
+    if let Some(rename) = rename {
+        let verb = if rename.copy { "copy" } else { "rename" };
+        writeln!(out, "similarity index {}%", similarity(rename.score))?;
+        writeln!(out, "{verb} from {}", quote_path(&either.path))?;
+        writeln!(out, "{verb} to {}", quote_path(&other.path))?;
+    }
+    if old.map(|file| file.id) == new.map(|file| file.id) {
+        return Ok(());
+    }
+    let empty = Vec::new();
+    let old_data = old.map_or(&empty, |file| &file.data);
+    let new_data = new.map_or(&empty, |file| &file.data);
+    let binary = is_binary(old_data) || is_binary(new_data);
+    let null = either.id.algorithm().null_id();
+    let abbreviate = |id: ObjectId| {
+        let hex = id.to_string();
+        if binary && options.binary {
+            hex
+        } else {
+            hex[..7].to_string()
+        }
+    };
+    write!(
+        out,
+        "index {}..{}",
+        abbreviate(old.map_or(null, |file| file.id)),
+        abbreviate(new.map_or(null, |file| file.id))
+    )?;
+    match (old, new) {
+        (Some(old), Some(new)) if old.mode == new.mode => writeln!(out, " {:06o}", old.mode)?,
+        _ => writeln!(out)?,
+    }
+    let old_name = match old {
+        Some(_) => quote_path(&a),
+        None => "/dev/null".to_string(),
+    };
+    let new_name = match new {
+        Some(_) => quote_path(&b),
+        None => "/dev/null".to_string(),
+    };
+    if binary {
+        if options.binary {
+            writeln!(out, "GIT binary patch")?;
+            write_binary_literal(out, new_data)?;
+            write_binary_literal(out, old_data)?;
+        } else {
+            writeln!(out, "Binary files {old_name} and {new_name} differ")?;
+        }
+        return Ok(());
+    }
+    // creating or deleting an empty file changes no lines
+    if old_data.is_empty() && new_data.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "--- {old_name}\n+++ {new_name}")?;
+    write_hunks(out, old_data, new_data, options)
+}
+
+/// Writes `data` deflated and base85 encoded, the way `GIT binary patch` carries it.
+fn write_binary_literal(out: &mut dyn Write, data: &[u8]) -> anyhow::Result<()> {
+    const ALPHABET: &[u8; 85] =
+        b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    let deflated = encoder.finish()?;
+    writeln!(out, "literal {}", data.len())?;
+    for line in deflated.chunks(52) {
+        let length = match line.len() {
+            n @ 1..=26 => b'A' + n as u8 - 1,
+            n => b'a' + n as u8 - 27,
+        };
+        let mut encoded = vec![length];
+        for group in line.chunks(4) {
+            let mut bytes = [0; 4];
+            bytes[..group.len()].copy_from_slice(group);
+            let mut value = u32::from_be_bytes(bytes);
+            let mut digits = [0; 5];
+            for digit in digits.iter_mut().rev() {
+                *digit = ALPHABET[(value % 85) as usize];
+                value /= 85;
+            }
+            encoded.extend(digits);
+        }
+        encoded.push(b'\n');
+        out.write_all(&encoded)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// How much a file changed, for diffstats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    /// The name to show, already quoted.
+    pub path: String,
+    pub added: usize,
+    pub deleted: usize,
+    /// The sizes before and after, for binary files, which have no lines to count.
+    pub binary: Option<(usize, usize)>,
+}
+
+impl FileStat {
+    pub fn new(pair: &FilePair, options: &DiffOptions) -> Self {
+        let (old, new) = (pair.old.as_ref(), pair.new.as_ref());
+        let empty = Vec::new();
+        let old_data = old.map_or(&empty, |file| &file.data);
+        let new_data = new.map_or(&empty, |file| &file.data);
+        let path = pair.display_name();
+        if is_binary(old_data) || is_binary(new_data) {
+            return Self {
+                path,
+                added: 0,
+                deleted: 0,
+                // git doesn't give sizes when the contents are the same
+                binary: match old.map(|file| file.id) == new.map(|file| file.id) {
+                    true => Some((0, 0)),
+                    false => Some((old_data.len(), new_data.len())),
+                },
+            };
+        }
+        let (old_changed, new_changed) = changed_lines(
+            &split_lines(old_data),
+            &split_lines(new_data),
+            options.algorithm,
+        );
+        Self {
+            path,
+            added: new_changed.iter().filter(|&&changed| changed).count(),
+            deleted: old_changed.iter().filter(|&&changed| changed).count(),
+            binary: None,
+        }
+    }
+}
+
+/// Writes `pairs` the way `format` has them shown.
+pub fn write_changes(
+    out: &mut dyn Write,
+    pairs: &[FilePair],
+    format: DiffFormat,
+    options: &DiffOptions,
+) -> anyhow::Result<()> {
+    let stats = || pairs.iter().map(|pair| FileStat::new(pair, options));
+    match format {
+        DiffFormat::Patch => {
+            for pair in pairs {
+                write_patch(out, pair, options)?;
+            }
+        }
+        DiffFormat::Stat => write_stat(out, &stats().collect::<Vec<_>>(), 80)?,
+        DiffFormat::Numstat => {
+            for stat in stats() {
+                let path = &stat.path;
+                match stat.binary {
+                    Some(_) => writeln!(out, "-\t-\t{path}")?,
+                    None => writeln!(out, "{}\t{}\t{path}", stat.added, stat.deleted)?,
+                }
+            }
+        }
+        DiffFormat::NameOnly => {
+            for pair in pairs {
+                writeln!(out, "{}", quote_path(pair.path()))?;
+            }
+        }
+        DiffFormat::NameStatus => {
+            for pair in pairs {
+                writeln!(out, "{}\t{}", pair.status(), pair.paths())?;
+            }
+        }
+        DiffFormat::Raw => {
+            for pair in pairs {
+                let Some(either) = pair.old.as_ref().or(pair.new.as_ref()) else {
+                    continue;
+                };
+                let null = either.id.algorithm().null_id();
+                let side = |file: &Option<DiffFile>| {
+                    file.as_ref().map_or((0, null), |file| (file.mode, file.id))
+                };
+                let ((old_mode, old_id), (new_mode, new_id)) = (side(&pair.old), side(&pair.new));
+                writeln!(
+                    out,
+                    ":{old_mode:06o} {new_mode:06o} {old_id} {new_id} {}\t{}",
+                    pair.status(),
+                    pair.paths()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a diffstat of `stats` at most `width` columns wide, with the totals line.
+pub fn write_stat(out: &mut dyn Write, stats: &[FileStat], width: usize) -> anyhow::Result<()> {
+    let names: Vec<&String> = stats.iter().map(|stat| &stat.path).collect();
+    let max_len = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max_change = stats
+        .iter()
+        .filter(|stat| stat.binary.is_none())
+        .map(|stat| stat.added + stat.deleted)
+        .max()
+        .unwrap_or(0);
+    let mut number_width = 0;
+    let mut bin_width = 0;
+    for stat in stats {
+        if let Some((old, new)) = stat.binary {
+            bin_width = bin_width.max(14 + decimal_width(old) + decimal_width(new));
+            number_width = 3;
+        }
+    }
+    number_width = number_width.max(decimal_width(max_change));
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        let limit = (width * 3 / 8).saturating_sub(number_width + 6);
+        if graph_width > limit {
+            graph_width = limit.max(6);
+        }
+        let rest = width.saturating_sub(number_width + 6 + graph_width);
+        if name_width > rest {
+            name_width = rest;
+        } else {
+            graph_width = width.saturating_sub(number_width + 6 + name_width);
+        }
+    }
+
+    let (mut insertions, mut deletions) = (0, 0);
+    for (stat, name) in stats.iter().zip(&names) {
+        let length = name.chars().count();
+        let shown = if length > name_width {
+            // keep the end of the name, from a directory boundary if there is one
+            let skip = length - name_width.saturating_sub(3);
+            let tail: String = name.chars().skip(skip).collect();
+            let tail = match tail.find('/') {
+                Some(slash) => tail[slash..].to_string(),
+                None => tail,
+            };
+            format!("...{tail:<width$}", width = name_width.saturating_sub(3))
+        } else {
+            format!("{name:<name_width$}")
+        };
+        write!(out, " {shown} |")?;
+        match stat.binary {
+            Some((0, 0)) => {
+                writeln!(out, " Bin")?;
+                continue;
+            }
+            Some((old, new)) => {
+                writeln!(out, " Bin {old} -> {new} bytes")?;
+                continue;
+            }
+            None => {}
+        }
+        let total = stat.added + stat.deleted;
+        insertions += stat.added;
+        deletions += stat.deleted;
+        let (mut added, mut deleted) = (stat.added, stat.deleted);
+        if graph_width <= max_change {
+            let scale = |count: usize| {
+                if count == 0 {
+                    0
+                } else {
+                    1 + count * (graph_width - 1) / max_change
+                }
+            };
+            let mut scaled = scale(total);
+            if scaled < 2 && added > 0 && deleted > 0 {
+                scaled = 2;
+            }
+            if added < deleted {
+                added = scale(added);
+                deleted = scaled - added;
+            } else {
+                deleted = scale(deleted);
+                added = scaled - deleted;
+            }
+        }
+        writeln!(
+            out,
+            " {total:>number_width$}{}{}{}",
+            if total > 0 { " " } else { "" },
+            "+".repeat(added),
+            "-".repeat(deleted)
+        )?;
+    }
+    write_stat_totals(out, stats.len(), insertions, deletions)
+}
+
+fn decimal_width(n: usize) -> usize {
+    n.to_string().len()
+}
+
+/// The `N files changed, N insertions(+), N deletions(-)` line.
+pub fn write_stat_totals(
+    out: &mut dyn Write,
+    files: usize,
+    insertions: usize,
+    deletions: usize,
+) -> anyhow::Result<()> {
+    if files == 0 {
+        writeln!(out, " 0 files changed")?;
+        return Ok(());
+    }
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    write!(out, " {files} file{} changed", plural(files))?;
+    if insertions > 0 || deletions == 0 {
+        write!(out, ", {insertions} insertion{}(+)", plural(insertions))?;
+    }
+    if deletions > 0 || insertions == 0 {
+        write!(out, ", {deletions} deletion{}(-)", plural(deletions))?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// The `create mode`, `delete mode`, `rename` and `mode change` lines of a summary.
+pub fn write_summary(out: &mut dyn Write, pair: &FilePair) -> anyhow::Result<()> {
+    match (&pair.old, &pair.new) {
+        (None, Some(new)) => writeln!(
+            out,
+            " create mode {:06o} {}",
+            new.mode,
+            quote_path(&new.path)
+        )?,
+        (Some(old), None) => writeln!(
+            out,
+            " delete mode {:06o} {}",
+            old.mode,
+            quote_path(&old.path)
+        )?,
+        (Some(old), Some(new)) => {
+            if let Some(rename) = pair.rename {
+                let verb = if rename.copy { "copy" } else { "rename" };
+                writeln!(
+                    out,
+                    " {verb} {} ({}%)",
+                    pair.display_name(),
+                    similarity(rename.score)
+                )?;
+            }
+            if old.mode != new.mode {
+                write!(out, " mode change {:06o} => {:06o}", old.mode, new.mode)?;
+                match pair.rename {
+                    Some(_) => writeln!(out)?,
+                    None => writeln!(out, " {}", quote_path(&new.path))?,
+                }
+            }
+        }
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(old: &str, new: &str, algorithm: DiffAlgorithm) -> anyhow::Result<String> {
+        let mut out = Vec::new();
+        let options = DiffOptions {
+            algorithm,
+            ..DiffOptions::default()
+        };
+        write_hunks(&mut out, old.as_bytes(), new.as_bytes(), &options)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn test_hunks() -> anyhow::Result<()> {
+        let old = "fn main() {\n    one();\n    two();\n}\n";
+        let new = "fn main() {\n    one();\n    three();\n}\n";
+        let expected =
+            "@@ -1,4 +1,4 @@\n fn main() {\n     one();\n-    two();\n+    three();\n }\n";
+        for algorithm in [
+            DiffAlgorithm::Myers,
+            DiffAlgorithm::Patience,
+            DiffAlgorithm::Histogram,
+        ] {
+            assert_eq!(patch(old, new, algorithm)?, expected);
+        }
+
+        // far apart changes get hunks of their own, named after the function they are in
+        let old: String = (1..=20).map(|i| format!("line {i}\n")).collect();
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 19\n", "");
+        assert_eq!(
+            patch(&old, &new, DiffAlgorithm::Myers)?,
+            "@@ -1,5 +1,5 @@\n line 1\n-line 2\n+line two\n line 3\n line 4\n line 5\n\
+             @@ -16,5 +16,4 @@ line 15\n line 16\n line 17\n line 18\n-line 19\n line 20\n"
+        );
+
+        // an added block slides to where the indentation says it belongs
+        let old = "a {\n  x\n}\n\nb {\n  y\n}\n";
+        let new = "a {\n  x\n}\n\nc {\n  z\n}\n\nb {\n  y\n}\n";
+        assert_eq!(
+            patch(old, new, DiffAlgorithm::Myers)?,
+            "@@ -2,6 +2,10 @@ a {\n   x\n }\n \n+c {\n+  z\n+}\n+\n b {\n   y\n }\n"
+        );
+        assert_eq!(
+            patch("same", "changed", DiffAlgorithm::Myers)?,
+            "@@ -1 +1 @@\n-same\n\\ No newline at end of file\n+changed\n\\ No newline at end of file\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stat() -> anyhow::Result<()> {
+        let mut out = Vec::new();
+        let stats = [
+            FileStat {
+                path: "bin.dat".to_string(),
+                added: 0,
+                deleted: 0,
+                binary: Some((0, 3)),
+            },
+            FileStat {
+                path: "main.rs".to_string(),
+                added: 2,
+                deleted: 1,
+                binary: None,
+            },
+        ];
+        write_stat(&mut out, &stats, 80)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            " bin.dat | Bin 0 -> 3 bytes\n main.rs |   3 ++-\n 2 files changed, 2 insertions(+), 1 deletion(-)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_changes() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let dot_git_path = worktree.join(".git");
+        let repo = Repository::on_disk(&dot_git_path, crate::hash::HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::create_dir_all(worktree.join("src"))?;
+        fs::write(worktree.join("README"), "hello\n")?;
+        fs::write(worktree.join("src/lib.rs"), "lib\n")?;
+        fs::write(worktree.join("src/main.rs"), "main\n")?;
+        crate::staging::add(&repo, worktree, &Pathspec::default(), false, false)?;
+        let head = crate::commit::commit(&repo, "initial")?.unwrap();
+        let tree = crate::commit::Commit::read(repo.objects.as_ref(), &head.to_string())?.tree;
+
+        fs::write(worktree.join("src/lib.rs"), "staged\n")?;
+        crate::staging::add(&repo, worktree, &Pathspec::default(), false, false)?;
+        fs::write(worktree.join("README"), "changed\n")?;
+        fs::remove_file(worktree.join("src/main.rs"))?;
+        let show = |pairs: Vec<FilePair>| -> anyhow::Result<String> {
+            let mut out = Vec::new();
+            write_changes(
+                &mut out,
+                &pairs,
+                DiffFormat::NameStatus,
+                &DiffOptions::default(),
+            )?;
+            Ok(String::from_utf8(out)?)
+        };
+        let everything = Pathspec::default();
+        assert_eq!(
+            show(worktree_changes(&repo, &dot_git_path, None, &everything)?)?,
+            "M\tREADME\nD\tsrc/main.rs\n"
+        );
+        assert_eq!(
+            show(index_changes(&repo, Some(&tree), &everything)?)?,
+            "M\tsrc/lib.rs\n"
+        );
+        let src = Pathspec::new(worktree, &["src".into()])?;
+        assert_eq!(
+            show(worktree_changes(&repo, &dot_git_path, Some(&tree), &src)?)?,
+            "M\tsrc/lib.rs\nD\tsrc/main.rs\n"
+        );
+        Ok(())
+    }
+}
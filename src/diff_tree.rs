@@ -0,0 +1,297 @@
+//! Comparing two trees entry by entry, the way `diff-tree` does.
+
+use std::{cmp::Ordering, collections::VecDeque, fmt};
+
+use anyhow::bail;
+
+use crate::{
+    commit::Commit,
+    hash::ObjectId,
+    object::{Object, ObjectType},
+    status::quote_path,
+    store::ObjectStore,
+    tag::Tag,
+    tree::{build_tree, compare_tree_entry_names, TreeEntry, TreeEntryMode},
+};
+
+/// A mode and the object it applies to, one side of a change.
+pub type Version = (u32, ObjectId);
+
+/// How a path differs between two trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    Modified,
+    /// Turned from one kind of non-tree entry into another, like a file into a symlink.
+    TypeChanged,
+    /// Moved from another path, which is gone, found by rename detection.
+    Renamed,
+    /// Copied from another path, which stayed.
+    Copied,
+}
+
+impl ChangeKind {
+    /// The letter `--name-status` and the raw format show.
+    pub fn letter(self) -> char {
+        match self {
+            ChangeKind::Added => 'A',
+            ChangeKind::Deleted => 'D',
+            ChangeKind::Modified => 'M',
+            ChangeKind::TypeChanged => 'T',
+            ChangeKind::Renamed => 'R',
+            ChangeKind::Copied => 'C',
+        }
+    }
+}
+
+/// A path that differs between two trees, with what it was and what it became.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    pub path: Vec<u8>,
+    pub old: Option<Version>,
+    pub new: Option<Version>,
+}
+
+impl TreeChange {
+    pub fn kind(&self) -> ChangeKind {
+        match (&self.old, &self.new) {
+            (None, _) => ChangeKind::Added,
+            (_, None) => ChangeKind::Deleted,
+            (Some((old, _)), Some((new, _))) if old & 0o170000 != new & 0o170000 => {
+                ChangeKind::TypeChanged
+            }
+            _ => ChangeKind::Modified,
+        }
+    }
+}
+
+impl fmt::Display for TreeChange {
+    /// The raw format: `:<old mode> <new mode> <old id> <new id> <letter>\t<path>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (_, id) = self.old.or(self.new).ok_or(fmt::Error)?;
+        let absent = (0, id.algorithm().null_id());
+        let (old_mode, old_id) = self.old.unwrap_or(absent);
+        let (new_mode, new_id) = self.new.unwrap_or(absent);
+        write!(
+            f,
+            ":{old_mode:06o} {new_mode:06o} {old_id} {new_id} {}\t{}",
+            self.kind().letter(),
+            quote_path(&self.path)
+        )
+    }
+}
+
+/// The entries of one directory on both sides still to be compared.
+struct Level {
+    /// Where the directory is, ending in `/` unless it is the top.
+    prefix: Vec<u8>,
+    old: VecDeque<TreeEntry>,
+    new: VecDeque<TreeEntry>,
+}
+
+/// The changes between two trees, in tree order. Subtrees with the same id on both sides are
+/// skipped without being read, and unless `recursive` is set changed subtrees are reported as
+/// such rather than descended into.
+pub struct TreeDiff<'a> {
+    objects: &'a dyn ObjectStore,
+    recursive: bool,
+    /// The directories being compared, innermost last.
+    stack: Vec<Level>,
+}
+
+impl<'a> TreeDiff<'a> {
+    /// Compares the trees `old` and `new`; a missing side counts as an empty tree.
+    pub fn new(
+        objects: &'a dyn ObjectStore,
+        old: Option<&str>,
+        new: Option<&str>,
+        recursive: bool,
+    ) -> anyhow::Result<Self> {
+        let mut diff = Self {
+            objects,
+            recursive,
+            stack: Vec::new(),
+        };
+        diff.push(Vec::new(), old, new)?;
+        Ok(diff)
+    }
+
+    fn push(
+        &mut self,
+        prefix: Vec<u8>,
+        old: Option<&str>,
+        new: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let entries = |tree: Option<&str>| -> anyhow::Result<VecDeque<TreeEntry>> {
+            match tree {
+                Some(tree) => Ok(build_tree(self.objects, tree)?.entries.into()),
+                None => Ok(VecDeque::new()),
+            }
+        };
+        let level = Level {
+            old: entries(old)?,
+            new: entries(new)?,
+            prefix,
+        };
+        self.stack.push(level);
+        Ok(())
+    }
+
+    /// The change between two entries with the same name, `None` when there is none to
+    /// report there.
+    fn compare(
+        &mut self,
+        prefix: Vec<u8>,
+        old: Option<TreeEntry>,
+        new: Option<TreeEntry>,
+    ) -> anyhow::Result<Option<TreeChange>> {
+        let name = match (&old, &new) {
+            (Some(entry), _) | (None, Some(entry)) => &entry.name,
+            (None, None) => return Ok(None),
+        };
+        let mut path = prefix;
+        path.extend(name);
+        let is_tree = |entry: &Option<TreeEntry>| {
+            entry
+                .as_ref()
+                .is_some_and(|entry| entry.mode == TreeEntryMode::Directory)
+        };
+        if old.as_ref().map(|entry| (entry.mode, &entry.sha))
+            == new.as_ref().map(|entry| (entry.mode, &entry.sha))
+        {
+            return Ok(None);
+        }
+        if self.recursive && (is_tree(&old) || is_tree(&new)) {
+            path.push(b'/');
+            let old = old.as_ref().map(|entry| entry.sha.as_str());
+            let new = new.as_ref().map(|entry| entry.sha.as_str());
+            self.push(path, old, new)?;
+            return Ok(None);
+        }
+        let version = |entry: Option<TreeEntry>| -> anyhow::Result<Option<Version>> {
+            entry
+                .map(|entry| Ok((entry.mode.bits(), ObjectId::from_hex(&entry.sha)?)))
+                .transpose()
+        };
+        Ok(Some(TreeChange {
+            path,
+            old: version(old)?,
+            new: version(new)?,
+        }))
+    }
+}
+
+impl Iterator for TreeDiff<'_> {
+    type Item = anyhow::Result<TreeChange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let level = self.stack.last_mut()?;
+            let is_dir = |entry: &TreeEntry| entry.mode == TreeEntryMode::Directory;
+            // a file sorts before a directory of the same name, so they count as two paths
+            let order = match (level.old.front(), level.new.front()) {
+                (None, None) => {
+                    self.stack.pop();
+                    continue;
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(old), Some(new)) => {
+                    compare_tree_entry_names(&old.name, is_dir(old), &new.name, is_dir(new))
+                }
+            };
+            let (old, new) = match order {
+                Ordering::Less => (level.old.pop_front(), None),
+                Ordering::Greater => (None, level.new.pop_front()),
+                Ordering::Equal => (level.old.pop_front(), level.new.pop_front()),
+            };
+            let prefix = level.prefix.clone();
+            match self.compare(prefix, old, new) {
+                Ok(None) => continue,
+                Ok(Some(change)) => return Some(Ok(change)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// The tree `hash` names, peeling tags and commits.
+pub(crate) fn peel_to_tree(objects: &dyn ObjectStore, hash: &str) -> anyhow::Result<String> {
+    let mut hash = hash.to_string();
+    loop {
+        hash = match Object::read(objects, &hash)?.object_type {
+            ObjectType::Tree => return Ok(hash),
+            ObjectType::Commit => Commit::read(objects, &hash)?.tree,
+            ObjectType::Tag => Tag::read(objects, &hash)?.object,
+            ObjectType::Blob => bail!("object {hash} is a blob, not a tree"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{hash::HashAlgorithm, store::MemoryObjectStore, tree::write_tree_for};
+
+    use super::*;
+
+    #[test]
+    fn test_tree_diff() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let objects = MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let write = |files: &[(&str, &str)]| -> anyhow::Result<String> {
+            let dir = tempfile::tempdir_in(temp_dir.path())?;
+            for (path, contents) in files {
+                let path = dir.path().join(path);
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(path, contents)?;
+            }
+            let tree = write_tree_for(&objects, &dir.path().join(".git"), dir.path())?;
+            Ok(tree.unwrap().to_string())
+        };
+        let old = write(&[
+            ("README", "hello\n"),
+            ("docs/guide.md", "guide\n"),
+            ("src/lib.rs", "lib\n"),
+            ("src/main.rs", "main\n"),
+            ("tool", "script\n"),
+        ])?;
+        let new = write(&[
+            ("README", "hello\n"),
+            ("docs/guide.md", "guide\n"),
+            ("src/lib.rs", "changed\n"),
+            ("src/util.rs", "util\n"),
+            ("tool/run", "script\n"),
+        ])?;
+        let changes = |recursive| -> anyhow::Result<Vec<(char, String)>> {
+            TreeDiff::new(&objects, Some(&old), Some(&new), recursive)?
+                .map(|change| {
+                    let change = change?;
+                    let path = String::from_utf8(change.path.clone())?;
+                    Ok((change.kind().letter(), path))
+                })
+                .collect()
+        };
+        assert_eq!(
+            changes(true)?,
+            [
+                ('M', "src/lib.rs".to_string()),
+                ('D', "src/main.rs".to_string()),
+                ('A', "src/util.rs".to_string()),
+                ('D', "tool".to_string()),
+                ('A', "tool/run".to_string()),
+            ]
+        );
+        assert_eq!(
+            changes(false)?,
+            [
+                ('M', "src".to_string()),
+                ('D', "tool".to_string()),
+                ('A', "tool".to_string()),
+            ]
+        );
+        Ok(())
+    }
+}
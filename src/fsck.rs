@@ -0,0 +1,396 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::{
+    commit::Commit,
+    hash::{HashAlgorithm, ObjectId},
+    object::{Object, ObjectType},
+    repository::Repository,
+    tag::Tag,
+    tree::{compare_tree_entry_names, TreeEntryMode},
+    walk::roots,
+};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum FsckIssue {
+    Corrupt {
+        hash: ObjectId,
+        reason: String,
+    },
+    /// Something git tolerates but wouldn't write itself, such as a legacy file mode.
+    Warning {
+        hash: ObjectId,
+        reason: String,
+    },
+    /// Referenced from `referrer` (an object id, a ref name, a reflog or the index) but not in the
+    /// object store.
+    Missing {
+        object_type: ObjectType,
+        hash: ObjectId,
+        referrer: String,
+    },
+    Unreachable {
+        object_type: ObjectType,
+        hash: ObjectId,
+    },
+    /// Unreachable and not referenced by any other unreachable object.
+    Dangling {
+        object_type: ObjectType,
+        hash: ObjectId,
+    },
+}
+
+impl FsckIssue {
+    pub(crate) fn is_error(&self) -> bool {
+        matches!(self, FsckIssue::Corrupt { .. } | FsckIssue::Missing { .. })
+    }
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckIssue::Corrupt { hash, reason } => write!(f, "error in object {hash}: {reason}"),
+            FsckIssue::Warning { hash, reason } => write!(f, "warning in object {hash}: {reason}"),
+            FsckIssue::Missing {
+                object_type,
+                hash,
+                referrer,
+            } => write!(f, "missing {object_type} {hash} (referenced by {referrer})"),
+            FsckIssue::Unreachable { object_type, hash } => {
+                write!(f, "unreachable {object_type} {hash}")
+            }
+            FsckIssue::Dangling { object_type, hash } => {
+                write!(f, "dangling {object_type} {hash}")
+            }
+        }
+    }
+}
+
+/// Checks every object in the repository and the connectivity of everything reachable from refs,
+/// reflogs and the index.
+pub(crate) fn fsck(repo: &Repository, show_unreachable: bool) -> anyhow::Result<Vec<FsckIssue>> {
+    let mut issues = Vec::new();
+    let mut objects = BTreeMap::new();
+    for hash in repo.objects.iter()? {
+        match check_object(repo, &hash) {
+            Ok((object_type, references, problems)) => {
+                for reason in problems.errors {
+                    issues.push(FsckIssue::Corrupt { hash, reason });
+                }
+                for reason in problems.warnings {
+                    issues.push(FsckIssue::Warning { hash, reason });
+                }
+                objects.insert(hash, (object_type, references));
+            }
+            Err(e) => issues.push(FsckIssue::Corrupt {
+                hash,
+                reason: format!("{e:#}"),
+            }),
+        }
+    }
+
+    let mut queue: VecDeque<_> = roots(repo)?.into();
+    let mut reachable = BTreeSet::new();
+    while let Some((expected_type, hash, referrer)) = queue.pop_front() {
+        if !reachable.insert(hash) {
+            continue;
+        }
+        let Some((object_type, references)) = objects.get(&hash) else {
+            if !repo.objects.contains(&hash.to_string())? {
+                issues.push(FsckIssue::Missing {
+                    object_type: expected_type,
+                    hash,
+                    referrer,
+                });
+            }
+            continue;
+        };
+        // refs, and so their reflogs, may point at tags as well as commits
+        if *object_type != expected_type
+            && !referrer.starts_with("refs/")
+            && !referrer.starts_with("logs/")
+        {
+            issues.push(FsckIssue::Corrupt {
+                hash,
+                reason: format!("expected a {expected_type} but found a {object_type}"),
+            });
+        }
+        for (reference_type, reference) in references {
+            queue.push_back((*reference_type, *reference, hash.to_string()));
+        }
+    }
+
+    let unreachable: BTreeMap<_, _> = objects
+        .iter()
+        .filter(|(hash, _)| !reachable.contains(*hash))
+        .collect();
+    let referenced: BTreeSet<_> = unreachable
+        .values()
+        .flat_map(|(_, references)| references.iter().map(|(_, hash)| *hash))
+        .collect();
+    for (hash, (object_type, _)) in unreachable {
+        if show_unreachable {
+            issues.push(FsckIssue::Unreachable {
+                object_type: *object_type,
+                hash: *hash,
+            });
+        } else if !referenced.contains(hash) {
+            issues.push(FsckIssue::Dangling {
+                object_type: *object_type,
+                hash: *hash,
+            });
+        }
+    }
+
+    issues.sort();
+    Ok(issues)
+}
+
+type References = Vec<(ObjectType, ObjectId)>;
+
+/// What is wrong with an object that could still be parsed.
+#[derive(Debug, Default)]
+struct Problems {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Re-hashes an object, validates its contents and returns the objects it points to along
+/// with any problems that didn't stop it from being parsed.
+fn check_object(
+    repo: &Repository,
+    hash: &ObjectId,
+) -> anyhow::Result<(ObjectType, References, Problems)> {
+    let object = Object::read(repo.objects.as_ref(), &hash.to_string())?;
+    let object_type = object.object_type;
+    let data = object.into_bytes()?;
+
+    let actual = Object::hash_of(object_type, &data, repo.hash_algorithm());
+    ensure!(actual == *hash, "hash mismatch, contents hash to {actual}");
+
+    let mut problems = Problems::default();
+    let references = parse_references(
+        object_type,
+        &data,
+        repo.hash_algorithm().raw_len(),
+        &mut problems,
+    )?;
+    Ok((object_type, references, problems))
+}
+
+/// Errors unless `data` is well-formed contents for an object of `object_type`.
+pub(crate) fn validate_object(
+    object_type: ObjectType,
+    data: &[u8],
+    hash: HashAlgorithm,
+) -> anyhow::Result<()> {
+    let mut problems = Problems::default();
+    parse_references(object_type, data, hash.raw_len(), &mut problems)
+        .with_context(|| format!("invalid {object_type}"))?;
+    if let Some(problem) = problems.errors.first() {
+        bail!("invalid {object_type}: {problem}");
+    }
+    Ok(())
+}
+
+/// The objects an object's contents point to. Problems that don't stop the contents from
+/// being parsed are added to `problems`.
+fn parse_references(
+    object_type: ObjectType,
+    data: &[u8],
+    hash_len: usize,
+    problems: &mut Problems,
+) -> anyhow::Result<References> {
+    Ok(match object_type {
+        ObjectType::Blob => Vec::new(),
+        ObjectType::Tree => check_tree(data, hash_len, problems)?,
+        ObjectType::Commit => {
+            let commit = Commit::parse(data)?;
+            let mut references = vec![(ObjectType::Tree, commit.tree.parse()?)];
+            for parent in commit.parents {
+                references.push((ObjectType::Commit, parent.parse()?));
+            }
+            references
+        }
+        ObjectType::Tag => {
+            let tag = Tag::parse(data)?;
+            vec![(tag.object_type, tag.object.parse()?)]
+        }
+    })
+}
+
+fn check_tree(data: &[u8], hash_len: usize, problems: &mut Problems) -> anyhow::Result<References> {
+    let mut references = Vec::new();
+    let mut previous: Option<(&[u8], bool)> = None;
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|&b| b == b' ')
+            .context("tree entry is missing a mode")?;
+        let mode = std::str::from_utf8(&rest[..space]).context("tree entry mode isn't ASCII")?;
+        rest = &rest[space + 1..];
+        let nul = rest
+            .iter()
+            .position(|&b| b == 0)
+            .context("tree entry is missing a name")?;
+        let name = &rest[..nul];
+        rest = &rest[nul + 1..];
+        ensure!(rest.len() >= hash_len, "tree entry id is truncated");
+        let hash = ObjectId::from_bytes(&rest[..hash_len])?;
+        rest = &rest[hash_len..];
+
+        let display_name = String::from_utf8_lossy(name);
+        let parsed = TreeEntryMode::try_from(mode);
+        let is_dir = matches!(parsed, Ok(TreeEntryMode::Directory));
+        let bad_mode = format!("tree entry '{display_name}' has bad mode {mode}");
+        match parsed {
+            Err(_) => problems.errors.push(bad_mode),
+            // legacy modes like 100664 still say what the entry is
+            Ok(_) if !matches!(mode, "40000" | "100644" | "100755" | "120000" | "160000") => {
+                problems.warnings.push(bad_mode)
+            }
+            Ok(_) => {}
+        }
+        if matches!(name, b"" | b"." | b".." | b".git") || name.contains(&b'/') {
+            problems
+                .errors
+                .push(format!("tree entry has bad name '{display_name}'"));
+        }
+        if let Some((previous_name, previous_is_dir)) = previous {
+            match compare_tree_entry_names(previous_name, previous_is_dir, name, is_dir) {
+                Ordering::Less => {}
+                Ordering::Equal => problems
+                    .errors
+                    .push(format!("tree has duplicate entry '{display_name}'")),
+                Ordering::Greater => problems
+                    .errors
+                    .push(format!("tree entry '{display_name}' is not sorted")),
+            }
+        }
+        previous = Some((name, is_dir));
+
+        match parsed {
+            Ok(TreeEntryMode::Directory) => references.push((ObjectType::Tree, hash)),
+            // gitlinks point at commits in another repository
+            Ok(TreeEntryMode::Gitlink) => {}
+            _ => references.push((ObjectType::Blob, hash)),
+        }
+    }
+    Ok(references)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn write(repo: &Repository, object_type: ObjectType, data: &[u8]) -> anyhow::Result<ObjectId> {
+        Object {
+            object_type,
+            expected_size: data.len() as u64,
+            reader: data,
+        }
+        .write_to_objects(repo.objects.as_ref())
+    }
+
+    #[test]
+    fn test_fsck_clean_repository() -> anyhow::Result<()> {
+        let repo = Repository::open(Path::new("tests/fixtures/packed-app/dot-git"))?;
+        assert_eq!(fsck(&repo, false)?, vec![]);
+        let repo = Repository::open(Path::new("tests/fixtures/sha256-app/dot-git"))?;
+        assert_eq!(fsck(&repo, false)?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fsck_reports_problems() -> anyhow::Result<()> {
+        let repo = Repository::in_memory();
+        repo.init()?;
+        let blob = write(&repo, ObjectType::Blob, b"hello\n")?;
+        let missing = HashAlgorithm::Sha1.hash(b"not stored");
+        let mut tree = Vec::new();
+        for (mode, name, hash) in [("100644", "b", blob), ("100644", "a", missing)] {
+            tree.extend(format!("{mode} {name}\0").as_bytes());
+            tree.extend(hash.as_bytes());
+        }
+        let unsorted = write(&repo, ObjectType::Tree, &tree)?;
+        let commit = write(
+            &repo,
+            ObjectType::Commit,
+            format!(
+                "tree {unsorted}\nauthor A <a@b.c> 1 +0000\ncommitter A <a@b.c> 1 +0000\n\nm\n"
+            )
+            .as_bytes(),
+        )?;
+        repo.update_ref("refs/heads/master", &commit.to_string())?;
+        let dangling = write(&repo, ObjectType::Blob, b"nobody points here\n")?;
+
+        let issues = fsck(&repo, false)?;
+        let expected = vec![
+            FsckIssue::Corrupt {
+                hash: unsorted,
+                reason: "tree entry 'a' is not sorted".to_string(),
+            },
+            FsckIssue::Missing {
+                object_type: ObjectType::Blob,
+                hash: missing,
+                referrer: unsorted.to_string(),
+            },
+            FsckIssue::Dangling {
+                object_type: ObjectType::Blob,
+                hash: dangling,
+            },
+        ];
+        assert_eq!(issues, expected);
+        assert!(issues.iter().any(FsckIssue::is_error));
+
+        let issues = fsck(&repo, true)?;
+        assert!(issues.contains(&FsckIssue::Unreachable {
+            object_type: ObjectType::Blob,
+            hash: dangling,
+        }));
+
+        // a commit only the reflog remembers, as after a reset, is still reachable
+        let orphan = write(
+            &repo,
+            ObjectType::Commit,
+            format!(
+                "tree {unsorted}\nparent {commit}\nauthor A <a@b.c> 2 +0000\n\
+                 committer A <a@b.c> 2 +0000\n\nm\n"
+            )
+            .as_bytes(),
+        )?;
+        let log = format!(
+            "{commit} {orphan} A <a@b.c> 2 +0000\tcommit: m\n\
+             {orphan} {commit} A <a@b.c> 3 +0000\treset: moving to HEAD~1\n"
+        );
+        repo.files.write("logs/HEAD", log.as_bytes())?;
+        // neither dangling nor unreachable, and nothing else changes
+        assert_eq!(fsck(&repo, false)?, expected);
+        assert!(!fsck(&repo, true)?.contains(&FsckIssue::Unreachable {
+            object_type: ObjectType::Commit,
+            hash: orphan,
+        }));
+
+        // a legacy mode is only worth a warning
+        let mut tree = b"100664 a\0".to_vec();
+        tree.extend(blob.as_bytes());
+        let legacy = write(&repo, ObjectType::Tree, &tree)?;
+        repo.update_ref("refs/tags/legacy", &legacy.to_string())?;
+        let issues = fsck(&repo, false)?;
+        assert!(issues.contains(&FsckIssue::Warning {
+            hash: legacy,
+            reason: "tree entry 'a' has bad mode 100664".to_string(),
+        }));
+        let errors: Vec<&FsckIssue> = issues.iter().filter(|issue| issue.is_error()).collect();
+        assert_eq!(errors, [&expected[0], &expected[1]]);
+        Ok(())
+    }
+}
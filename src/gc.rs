@@ -0,0 +1,343 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    hash::ObjectId,
+    object::Object,
+    pack::{write_pack, WrittenPack},
+    repository::Repository,
+    store::{LooseObjectStore, ObjectStore, PackedObjectStore},
+    walk::{reachable_objects, roots},
+};
+
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// Packs objects into a single new pack. With `all` that's every object reachable from a
+/// ref, a reflog or the index, otherwise just the loose objects; either way only objects
+/// this repository holds itself, not ones borrowed from its alternates. With `delete` the
+/// packs and loose objects that are now redundant are removed.
+pub(crate) fn repack(
+    repo: &Repository,
+    dot_git_path: &Path,
+    all: bool,
+    delete: bool,
+) -> anyhow::Result<Option<WrittenPack>> {
+    let objects_dir = dot_git_path.join("objects");
+    let pack_dir = objects_dir.join("pack");
+    let loose = LooseObjectStore::new(&objects_dir, repo.hash_algorithm());
+    let hashes: Vec<ObjectId> = if all {
+        let packed = PackedObjectStore::new(&pack_dir, repo.hash_algorithm());
+        let mut local = Vec::new();
+        for (_, hash) in reachable_objects(repo, &root_ids(repo)?)? {
+            let hex = hash.to_string();
+            if loose.contains(&hex)? || packed.contains(&hex)? {
+                local.push(hash);
+            }
+        }
+        local
+    } else {
+        loose.iter()?.collect()
+    };
+    let old_packs = pack_files(&pack_dir)?;
+    if hashes.is_empty() {
+        return Ok(None);
+    }
+    let fsync = repo.config()?.fsync()?.packs;
+    let written =
+        write_pack(repo.objects.as_ref(), &hashes, &pack_dir, fsync).context("write pack")?;
+
+    if delete {
+        if all {
+            for pack in old_packs.iter().filter(|pack| **pack != written.pack_path) {
+                fs::remove_file(pack.with_extension("idx"))
+                    .with_context(|| format!("remove {}", pack.display()))?;
+                fs::remove_file(pack).with_context(|| format!("remove {}", pack.display()))?;
+            }
+        }
+        prune_packed(&objects_dir, repo)?;
+    }
+    Ok(Some(written))
+}
+
+/// Packs refs and reachable objects, then removes unreachable loose objects older than
+/// `prune` (or `gc.pruneExpire`, two weeks by default).
+pub(crate) fn gc(
+    repo: &Repository,
+    dot_git_path: &Path,
+    prune: Option<&str>,
+) -> anyhow::Result<Option<WrittenPack>> {
+    let config = repo.config()?;
+    let prune = prune
+        .or(config.get("gc.pruneexpire"))
+        .unwrap_or(DEFAULT_PRUNE_EXPIRE);
+    let expire = parse_expiry(prune, SystemTime::now())?;
+
+    repo.pack_refs().context("pack refs")?;
+
+    let objects_dir = dot_git_path.join("objects");
+    let reachable: BTreeSet<ObjectId> = reachable_objects(repo, &root_ids(repo)?)?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+
+    // unreachable objects only living in packs that are about to be deleted get a grace
+    // period as loose objects, unless they'd be pruned straight away
+    if prune != "now" {
+        let loose = LooseObjectStore::new(&objects_dir, repo.hash_algorithm());
+        let packed = PackedObjectStore::new(objects_dir.join("pack"), repo.hash_algorithm());
+        for hash in packed.iter()? {
+            let hex = hash.to_string();
+            if reachable.contains(&hash) || loose.contains(&hex)? {
+                continue;
+            }
+            let object = Object::read(&packed, &hex)?;
+            let mut reader = object.reader;
+            loose.write(Object {
+                object_type: object.object_type,
+                expected_size: object.expected_size,
+                reader: &mut reader,
+            })?;
+        }
+    }
+
+    let written = repack(repo, dot_git_path, true, true)?;
+
+    if let Some(expire) = expire {
+        let loose = LooseObjectStore::new(&objects_dir, repo.hash_algorithm());
+        for hash in loose.iter()? {
+            if reachable.contains(&hash) {
+                continue;
+            }
+            let path = loose_path(&objects_dir, &hash);
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .with_context(|| format!("stat {}", path.display()))?;
+            if modified <= expire {
+                fs::remove_file(&path).with_context(|| format!("remove {}", path.display()))?;
+            }
+        }
+        remove_empty_fanout_dirs(&objects_dir)?;
+    }
+    Ok(written)
+}
+
+/// Parses an expiry like `now`, `never` or `2.weeks.ago` into the cutoff time,
+/// `None` meaning nothing ever expires.
+pub(crate) fn parse_expiry(value: &str, now: SystemTime) -> anyhow::Result<Option<SystemTime>> {
+    match value {
+        "now" => return Ok(Some(now)),
+        "never" | "false" => return Ok(None),
+        _ => {}
+    }
+    let parts: Vec<&str> = value.split(['.', ' ']).collect();
+    let [count, unit, "ago"] = parts[..] else {
+        bail!("invalid expiry '{value}'");
+    };
+    let count: u64 = count
+        .parse()
+        .with_context(|| format!("invalid expiry '{value}'"))?;
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => bail!("invalid expiry '{value}'"),
+    };
+    Ok(Some(
+        now.checked_sub(Duration::from_secs(count * seconds))
+            .unwrap_or(SystemTime::UNIX_EPOCH),
+    ))
+}
+
+/// The objects that keep everything reachable from them out of reach of pruning.
+fn root_ids(repo: &Repository) -> anyhow::Result<Vec<ObjectId>> {
+    Ok(roots(repo)?.into_iter().map(|(_, hash, _)| hash).collect())
+}
+
+fn pack_files(pack_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(pack_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("list .git/objects/pack"),
+    };
+    let mut packs = Vec::new();
+    for entry in entries {
+        let path = entry.context("bad entry in .git/objects/pack")?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "pack")
+        {
+            packs.push(path);
+        }
+    }
+    Ok(packs)
+}
+
+fn loose_path(objects_dir: &Path, hash: &ObjectId) -> PathBuf {
+    let hex = hash.to_string();
+    objects_dir.join(&hex[..2]).join(&hex[2..])
+}
+
+/// Removes loose objects that are also stored in a pack.
+fn prune_packed(objects_dir: &Path, repo: &Repository) -> anyhow::Result<()> {
+    let loose = LooseObjectStore::new(objects_dir, repo.hash_algorithm());
+    let packed = PackedObjectStore::new(objects_dir.join("pack"), repo.hash_algorithm());
+    for hash in loose.iter()? {
+        if packed.contains(&hash.to_string())? {
+            let path = loose_path(objects_dir, &hash);
+            fs::remove_file(&path).with_context(|| format!("remove {}", path.display()))?;
+        }
+    }
+    remove_empty_fanout_dirs(objects_dir)
+}
+
+fn remove_empty_fanout_dirs(objects_dir: &Path) -> anyhow::Result<()> {
+    for entry in fs::read_dir(objects_dir).context("list .git/objects")? {
+        let entry = entry.context("bad entry in .git/objects")?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.len() != 2 || !name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        if fs::read_dir(entry.path())?.next().is_none() {
+            fs::remove_dir(entry.path()).context("remove empty .git/objects subdir")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commit::{commit, Commit},
+        hash::HashAlgorithm,
+        object::ObjectType,
+        pathspec::Pathspec,
+        staging::add,
+        test::copy_dir,
+        tree::build_tree,
+    };
+
+    #[test]
+    fn test_parse_expiry() -> anyhow::Result<()> {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(2_000_000);
+        assert_eq!(parse_expiry("now", now)?, Some(now));
+        assert_eq!(parse_expiry("never", now)?, None);
+        assert_eq!(
+            parse_expiry("2.weeks.ago", now)?,
+            Some(now - Duration::from_secs(14 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_expiry("1.hour.ago", now)?,
+            Some(now - Duration::from_secs(3600))
+        );
+        assert!(parse_expiry("yesterday", now).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dot_git_path = temp_dir.path().join(".git");
+        copy_dir(
+            Path::new("tests/fixtures/packed-app/dot-git"),
+            &dot_git_path,
+        )?;
+        let repo = Repository::open(&dot_git_path)?;
+        let before: Vec<ObjectId> = repo.objects.iter()?.collect();
+        let unreachable = Object {
+            object_type: ObjectType::Blob,
+            expected_size: 4,
+            reader: &b"junk"[..],
+        }
+        .write_to_objects(repo.objects.as_ref())?;
+        let reachable = Object {
+            object_type: ObjectType::Blob,
+            expected_size: 6,
+            reader: &b"loose\n"[..],
+        }
+        .write_to_objects(repo.objects.as_ref())?;
+        repo.update_ref("refs/tags/loose", &reachable.to_string())?;
+
+        let written = gc(&repo, &dot_git_path, Some("now"))?.expect("a pack was written");
+        assert_eq!(written.objects, before.len() + 1);
+        assert!(written.deltas > 0);
+
+        let repo = Repository::open(&dot_git_path)?;
+        assert_eq!(pack_files(&dot_git_path.join("objects/pack"))?.len(), 1);
+        assert!(!repo.objects.contains(&unreachable.to_string())?);
+        for hash in before.iter().chain([&reachable]) {
+            Object::read(repo.objects.as_ref(), &hash.to_string())?.into_bytes()?;
+        }
+        assert_eq!(repo.files.read("refs/tags/loose")?, None);
+        assert_eq!(
+            repo.resolve_ref("refs/tags/loose")?,
+            Some(reachable.to_string())
+        );
+        let loose = LooseObjectStore::new(dot_git_path.join("objects"), repo.hash_algorithm());
+        assert_eq!(loose.iter()?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_keeps_staged_objects() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let dot_git_path = worktree.join(".git");
+        let repo = Repository::on_disk(&dot_git_path, HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::write(worktree.join("staged.txt"), "x\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+
+        gc(&repo, &dot_git_path, Some("now"))?;
+        let head = commit(&repo, "staged")?.expect("a commit");
+        let tree = Commit::read(repo.objects.as_ref(), &head.to_string())?.tree;
+        for entry in build_tree(repo.objects.as_ref(), &tree)?.entries {
+            assert!(repo.objects.contains(&entry.sha)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_leaves_alternates_alone() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let shared = Path::new("tests/fixtures/packed-app/dot-git");
+        let dot_git_path = temp_dir.path().join(".git");
+        Repository::on_disk(&dot_git_path, HashAlgorithm::Sha1).init()?;
+        fs::create_dir_all(dot_git_path.join("objects/info"))?;
+        fs::write(
+            dot_git_path.join("objects/info/alternates"),
+            format!("{}\n", fs::canonicalize(shared.join("objects"))?.display()),
+        )?;
+        let repo = Repository::open(&dot_git_path)?;
+        let borrowed = Repository::open(shared)?
+            .resolve_name("HEAD")?
+            .expect("a commit");
+        repo.update_ref("refs/heads/master", &borrowed.to_string())?;
+        let own = Object {
+            object_type: ObjectType::Blob,
+            expected_size: 4,
+            reader: &b"own\n"[..],
+        }
+        .write_to_objects(repo.objects.as_ref())?;
+        repo.update_ref("refs/tags/own", &own.to_string())?;
+
+        let written = gc(&repo, &dot_git_path, Some("now"))?.expect("a pack was written");
+        assert_eq!(written.objects, 1);
+        let repo = Repository::open(&dot_git_path)?;
+        let packed = PackedObjectStore::new(dot_git_path.join("objects/pack"), HashAlgorithm::Sha1);
+        assert!(packed.contains(&own.to_string())?);
+        assert!(!packed.contains(&borrowed.to_string())?);
+        assert!(repo.objects.contains(&borrowed.to_string())?);
+        Ok(())
+    }
+}
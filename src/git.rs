@@ -1,77 +1,329 @@
 use std::{
+    collections::BTreeMap,
     fs,
+    io::{BufRead, Read},
     path::{Path, PathBuf},
 };
 
 use anyhow::{bail, ensure, Context};
 
 use crate::{
-    commit::commit,
+    attributes::clean,
+    branch,
+    checkout::{restore, RestoreOptions},
+    clean::{remove_untracked, CleanIgnored},
+    clone::clone,
+    commit::{commit, Commit},
     config::Config,
+    diff::{
+        file_pair, index_changes, tree_changes, worktree_changes, write_changes, DiffAlgorithm,
+        DiffFlags, DiffFormat, DiffOptions,
+    },
+    diff_tree::{peel_to_tree, TreeDiff},
+    fsck::{fsck, validate_object},
+    gc::{gc, repack},
+    hash::ObjectId,
+    log::{
+        patch_file_name, split_message, walk_commits, write_commit_header, write_commit_patch,
+        write_mail,
+    },
     object::{Object, ObjectType},
-    tree::{build_tree, commit_tree, write_tree_for},
+    pathspec::Pathspec,
+    rename::{add_unmodified, detect_renames, RenameOptions},
+    repository::{AmbiguousName, Repository},
+    staging::{add, rm},
+    status::{quote_path, status, write_status, write_tracking, StatusOptions},
+    submodule,
+    switch::{
+        destination, local_changes, move_head, switch_trees, unmerged_paths, Destination, Origin,
+    },
+    tag::Tag,
+    tree::{build_tree, commit_tree, tree_files, write_tree_from_index, TreeEntryMode},
 };
+/// How `hash-object` turns its input into an object.
+#[derive(Debug, Default)]
+pub struct HashObjectOptions {
+    /// `blob` when not given.
+    pub object_type: Option<String>,
+    pub write: bool,
+    /// Skip checking that the data is a well-formed object of its type.
+    pub literally: bool,
+    /// Apply the filters for this path instead of those of the file being hashed.
+    pub path: Option<PathBuf>,
+}
+
 #[derive(Debug)]
 pub struct Git<W: std::io::Write, X: std::io::Write> {
     pub config: Config<W, X>,
+    pub repo: Repository,
 }
 
 impl<W: std::io::Write, X: std::io::Write> Git<W, X> {
+    /// Uses the repository on disk at `config.dot_git_path`.
+    pub fn new(config: Config<W, X>) -> anyhow::Result<Self> {
+        let repo = Repository::open(&config.dot_git_path).context("open repository")?;
+        Ok(Self::with_repository(config, repo))
+    }
+
+    pub fn with_repository(config: Config<W, X>, repo: Repository) -> Self {
+        Self { config, repo }
+    }
+
     pub fn init(&mut self) -> anyhow::Result<()> {
-        fs::create_dir(&self.config.dot_git_path)?;
-        fs::create_dir(self.config.dot_git_path.join("objects"))?;
-        fs::create_dir(self.config.dot_git_path.join("refs"))?;
-        fs::write(
-            self.config.dot_git_path.join("HEAD"),
-            "ref: refs/heads/master\n",
-        )?;
+        self.repo.init()?;
 
         writeln!(self.config.writer, "Initialized git directory")?;
         Ok(())
     }
 
-    pub fn hash_object(&mut self, write: &bool, file: &PathBuf) -> anyhow::Result<()> {
-        let object = Object::blob_from_file(file).context("open blob input file")?;
-        let hash = if *write {
-            object
-                .write_to_objects(&self.config.dot_git_path)
-                .context("stream file into blob object file")?
-        } else {
-            object
-                .write(std::io::sink())
-                .context("stream file into blob object")?
+    pub fn hash_object(&mut self, write: &bool, file: &Path) -> anyhow::Result<()> {
+        let options = HashObjectOptions {
+            write: *write,
+            ..Default::default()
         };
+        let hash = self.hash_file(file, &options)?;
 
-        write!(self.config.writer, "{}", hex::encode(hash))?;
+        write!(self.config.writer, "{hash}")?;
 
         Ok(())
     }
 
-    pub fn cat_file(&mut self, _pretty_print: &bool, object_hash: &str) -> anyhow::Result<()> {
-        let mut object = Object::read(&self.config.dot_git_path, object_hash)
-            .context("parse out blob object file")?;
+    /// Hashes each of `files`, printing one id per line.
+    pub fn hash_object_files(
+        &mut self,
+        files: &[PathBuf],
+        options: &HashObjectOptions,
+    ) -> anyhow::Result<()> {
+        for file in files {
+            let hash = self.hash_file(file, options)?;
+            writeln!(self.config.writer, "{hash}")?;
+        }
+        Ok(())
+    }
+
+    /// Hashes everything read from `input`, which is only filtered when `options.path` is given.
+    pub fn hash_object_stdin(
+        &mut self,
+        mut input: impl Read,
+        options: &HashObjectOptions,
+    ) -> anyhow::Result<()> {
+        let mut data = Vec::new();
+        input
+            .read_to_end(&mut data)
+            .context("read object from stdin")?;
+        let hash = self.hash_data(data, options.path.as_deref(), options)?;
+        writeln!(self.config.writer, "{hash}")?;
+        Ok(())
+    }
+
+    fn hash_file(&self, file: &Path, options: &HashObjectOptions) -> anyhow::Result<ObjectId> {
+        let data = fs::read(file).with_context(|| format!("read {}", file.display()))?;
+        let path = options.path.as_deref().unwrap_or(file);
+        self.hash_data(data, Some(path), options)
+    }
+
+    fn hash_data(
+        &self,
+        data: Vec<u8>,
+        path: Option<&Path>,
+        options: &HashObjectOptions,
+    ) -> anyhow::Result<ObjectId> {
+        let object_type: ObjectType = options.object_type.as_deref().unwrap_or("blob").parse()?;
+        let worktree_path = path.and_then(|path| self.worktree_path(path));
+        let data = match (
+            object_type,
+            worktree_path,
+            self.config.dot_git_path.parent(),
+        ) {
+            (ObjectType::Blob, Some(path), Some(worktree)) => {
+                clean(&self.repo, worktree, &path, data).context("apply filters")?
+            }
+            _ => data,
+        };
+        if !options.literally {
+            validate_object(object_type, &data, self.repo.hash_algorithm())?;
+        }
+
+        if !options.write {
+            return Ok(Object::hash_of(
+                object_type,
+                &data,
+                self.repo.hash_algorithm(),
+            ));
+        }
+        Object {
+            object_type,
+            expected_size: data.len() as u64,
+            reader: data.as_slice(),
+        }
+        .write_to_objects(self.repo.objects.as_ref())
+        .context("stream data into object file")
+    }
+
+    /// `path` relative to the working tree with `/` separators, or `None` if it's outside it.
+    fn worktree_path(&self, path: &Path) -> Option<String> {
+        let worktree = self.config.dot_git_path.parent()?.canonicalize().ok()?;
+        let path = worktree.join(path);
+        let parent = path.parent()?.canonicalize().ok()?;
+        let path = parent.join(path.file_name()?);
+        Some(path.strip_prefix(&worktree).ok()?.to_str()?.to_string())
+    }
+
+    /// Prints an object's contents. With `pretty_print` trees are listed the way `ls-tree`
+    /// lists them instead of as raw entries.
+    pub fn cat_file(&mut self, pretty_print: &bool, object_hash: &str) -> anyhow::Result<()> {
+        let hash = self.resolve_object(object_hash)?;
+        let mut object = Object::read(self.repo.objects.as_ref(), &hash.to_string())
+            .context("parse out object file")?;
 
-        match object.object_type {
-            ObjectType::Blob => {
-                let n = std::io::copy(&mut object.reader, &mut self.config.writer)
-                    .context("Failed to write to stdout")?;
-                ensure!(
-                    n == object.expected_size,
-                    ".git/object file was not the expected size (expected: {}, actual: {})",
-                    object.expected_size,
-                    n
-                );
+        if *pretty_print && object.object_type == ObjectType::Tree {
+            drop(object);
+            return self.ls_tree(&false, &hash.to_string());
+        }
+        let n = std::io::copy(&mut object.reader, &mut self.config.writer)
+            .context("Failed to write to stdout")?;
+        ensure!(
+            n == object.expected_size,
+            ".git/object file was not the expected size (expected: {}, actual: {})",
+            object.expected_size,
+            n
+        );
+        Ok(())
+    }
+
+    /// Prints the raw contents of `object_hash` as `object_type`, dereferencing tags and
+    /// commits (for trees) to reach an object of that type.
+    pub fn cat_file_as(&mut self, object_type: &str, object_hash: &str) -> anyhow::Result<()> {
+        let wanted: ObjectType = object_type.parse()?;
+        let objects = self.repo.objects.as_ref();
+        let mut hash = self.resolve_object(object_hash)?.to_string();
+        loop {
+            let object = Object::read(objects, &hash)?;
+            if object.object_type == wanted {
+                let data = object.into_bytes()?;
+                self.config.writer.write_all(&data)?;
+                return Ok(());
             }
-            _ => bail!("object type not supported"),
+            hash = match object.object_type {
+                ObjectType::Tag => Tag::read(objects, &hash)?.object,
+                ObjectType::Commit if wanted == ObjectType::Tree => {
+                    Commit::read(objects, &hash)?.tree
+                }
+                _ => bail!("git cat-file {object_hash}: bad file"),
+            };
         }
+    }
+
+    pub fn cat_file_type(&mut self, object_hash: &str) -> anyhow::Result<()> {
+        let hash = self.resolve_object(object_hash)?;
+        let object = Object::read(self.repo.objects.as_ref(), &hash.to_string())?;
+        writeln!(self.config.writer, "{}", object.object_type)?;
+        Ok(())
+    }
+
+    pub fn cat_file_size(&mut self, object_hash: &str) -> anyhow::Result<()> {
+        let hash = self.resolve_object(object_hash)?;
+        let object = Object::read(self.repo.objects.as_ref(), &hash.to_string())?;
+        writeln!(self.config.writer, "{}", object.expected_size)?;
+        Ok(())
+    }
+
+    /// Whether `object_hash` names an object in the repository, for `cat-file -e`.
+    pub fn cat_file_exists(&self, object_hash: &str) -> anyhow::Result<bool> {
+        Ok(self.repo.resolve_name(object_hash)?.is_some())
+    }
+
+    fn resolve_object(&self, name: &str) -> anyhow::Result<ObjectId> {
+        self.repo
+            .resolve_revision(name)?
+            .with_context(|| format!("Not a valid object name {name}"))
+    }
+
+    /// The two ends of a range `a..b`, either of which defaults to `HEAD`, or `None` when
+    /// `revision` isn't a range.
+    fn resolve_range(&self, revision: &str) -> anyhow::Result<Option<(ObjectId, ObjectId)>> {
+        let Some((from, to)) = revision.split_once("..") else {
+            return Ok(None);
+        };
+        let end = |name: &str| self.resolve_object(if name.is_empty() { "HEAD" } else { name });
+        Ok(Some((end(from)?, end(to)?)))
+    }
+
+    /// Reads object names from `input`, one per line, and writes a line in `format` for each.
+    /// With `contents` the object itself follows its line. With `all_objects` every object in
+    /// the repository is listed instead and `input` is ignored.
+    pub fn cat_file_batch(
+        &mut self,
+        input: impl BufRead,
+        format: &str,
+        contents: &bool,
+        all_objects: &bool,
+    ) -> anyhow::Result<()> {
+        if *all_objects {
+            let mut hashes: Vec<_> = self.repo.objects.iter()?.collect();
+            hashes.sort();
+            hashes.dedup();
+            for hash in hashes {
+                self.cat_file_batch_answer(&hash.to_string(), "", format, contents)?;
+            }
+            return Ok(());
+        }
+        // answered as they come, as callers often wait on each answer before asking for the
+        // next object
+        for line in input.lines() {
+            let line = line.context("read object names")?;
+            // without %(rest) the whole line names the object
+            let (name, rest) = match line.split_once(char::is_whitespace) {
+                Some((name, rest)) if format.contains("%(rest)") => (name, rest.trim_start()),
+                _ => (line.as_str(), ""),
+            };
+            self.cat_file_batch_answer(name, rest, format, contents)?;
+        }
+        Ok(())
+    }
+
+    fn cat_file_batch_answer(
+        &mut self,
+        name: &str,
+        rest: &str,
+        format: &str,
+        contents: &bool,
+    ) -> anyhow::Result<()> {
+        let hash = match self.repo.resolve_revision(name) {
+            Ok(Some(hash)) => hash,
+            Ok(None) => {
+                writeln!(self.config.writer, "{name} missing")?;
+                return Ok(self.config.writer.flush()?);
+            }
+            Err(e) if e.downcast_ref::<AmbiguousName>().is_some() => {
+                writeln!(self.config.writer, "{name} ambiguous")?;
+                return Ok(self.config.writer.flush()?);
+            }
+            Err(e) => return Err(e),
+        };
+        let object = Object::read(self.repo.objects.as_ref(), &hash.to_string())?;
+        let line = expand_batch_format(
+            format,
+            &hash.to_string(),
+            object.object_type,
+            object.expected_size,
+            rest,
+        )?;
+        writeln!(self.config.writer, "{line}")?;
+        if *contents {
+            let data = object.into_bytes()?;
+            self.config.writer.write_all(&data)?;
+            writeln!(self.config.writer)?;
+        }
+        self.config.writer.flush()?;
         Ok(())
     }
 
     pub fn ls_tree(&mut self, name_only: &bool, tree_sha: &str) -> anyhow::Result<()> {
-        let tree = build_tree(&self.config.dot_git_path, tree_sha)?;
+        let tree = build_tree(self.repo.objects.as_ref(), tree_sha)?;
         for entry in tree.entries {
             if *name_only {
-                writeln!(self.config.writer, "{}", &entry.name)?;
+                writeln!(self.config.writer, "{}", quote_path(&entry.name))?;
             } else {
                 writeln!(
                     self.config.writer,
@@ -79,21 +331,808 @@ impl<W: std::io::Write, X: std::io::Write> Git<W, X> {
                     entry.mode,
                     entry.tree_entry_type(),
                     entry.sha,
-                    entry.name
+                    quote_path(&entry.name)
                 )?;
             }
         }
         Ok(())
     }
 
-    pub fn write_tree(&mut self) -> anyhow::Result<()> {
-        let Some(hash) = write_tree_for(&self.config.dot_git_path, Path::new("."))
-            .context("construct root tree object")?
-        else {
-            anyhow::bail!("failed to write tree");
+    /// Lists the paths that differ between the trees `old` and `new` name, which may also be
+    /// commits or tags. Without `recursive` changed directories are listed instead of what
+    /// changed inside them.
+    pub fn diff_tree(
+        &mut self,
+        recursive: &bool,
+        name_status: &bool,
+        renames: Option<RenameOptions>,
+        old: &str,
+        new: &str,
+    ) -> anyhow::Result<()> {
+        let objects = self.repo.objects.as_ref();
+        let old = peel_to_tree(objects, &self.resolve_object(old)?.to_string())?;
+        let new = peel_to_tree(objects, &self.resolve_object(new)?.to_string())?;
+        let changes = TreeDiff::new(objects, Some(&old), Some(&new), *recursive)?;
+        if let Some(renames) = renames {
+            // renames need the contents of both sides
+            let mut pairs = Vec::new();
+            for change in changes {
+                pairs.push(file_pair(objects, &change?)?);
+            }
+            let (pairs, _) = detect_renames(pairs, &renames);
+            let format = match name_status {
+                true => DiffFormat::NameStatus,
+                false => DiffFormat::Raw,
+            };
+            return write_changes(
+                &mut self.config.writer,
+                &pairs,
+                format,
+                &DiffOptions::default(),
+            );
+        }
+        for change in changes {
+            let change = change?;
+            if *name_status {
+                writeln!(
+                    self.config.writer,
+                    "{}\t{}",
+                    change.kind().letter(),
+                    quote_path(&change.path)
+                )?;
+            } else {
+                writeln!(self.config.writer, "{change}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// How patches are written: as `flags` asks, falling back to `diff.algorithm`, and to
+    /// `diff.renames` and `diff.renameLimit` for rename detection, which is on by default.
+    fn diff_options(&self, flags: &DiffFlags) -> anyhow::Result<DiffOptions> {
+        let config = self.repo.config()?;
+        let algorithm = match flags.algorithm {
+            Some(algorithm) => algorithm,
+            None => match config.get("diff.algorithm") {
+                Some(algorithm) => algorithm.parse()?,
+                None => DiffAlgorithm::default(),
+            },
+        };
+        let mut renames = match (flags.renames, config.get("diff.renames")) {
+            _ if flags.no_renames => None,
+            (Some(renames), _) => Some(renames),
+            (None, Some(value))
+                if value.eq_ignore_ascii_case("copies") || value.eq_ignore_ascii_case("copy") =>
+            {
+                Some(RenameOptions {
+                    copies: true,
+                    ..RenameOptions::default()
+                })
+            }
+            (None, _) => config
+                .get_bool("diff.renames")?
+                .unwrap_or(true)
+                .then(RenameOptions::default),
+        };
+        if let (Some(renames), Some(limit)) = (&mut renames, config.get("diff.renameLimit")) {
+            renames.limit = limit
+                .parse()
+                .with_context(|| format!("bad diff.renameLimit '{limit}'"))?;
+        }
+        Ok(DiffOptions {
+            algorithm,
+            context: flags.context.unwrap_or(3),
+            renames,
+            ..DiffOptions::default()
+        })
+    }
+
+    /// Shows `revision`, `HEAD` when not given: commits with their patch, tags with what they
+    /// point to and blobs as they are.
+    pub fn show(&mut self, revision: Option<&str>, flags: &DiffFlags) -> anyhow::Result<()> {
+        let options = self.diff_options(flags)?;
+        let objects = self.repo.objects.as_ref();
+        let mut id = self.resolve_object(revision.unwrap_or("HEAD"))?;
+        loop {
+            match Object::read(objects, &id.to_string())?.object_type {
+                ObjectType::Commit => {
+                    let commit = Commit::read(objects, &id.to_string())?;
+                    let out = &mut self.config.writer;
+                    write_commit_header(out, &id, &commit)?;
+                    if commit.parents.len() < 2 {
+                        writeln!(out)?;
+                    }
+                    return write_commit_patch(out, &self.repo, &commit, &options);
+                }
+                ObjectType::Tag => {
+                    let tag = Tag::read(objects, &id.to_string())?;
+                    writeln!(self.config.writer, "tag {}", tag.name)?;
+                    if let Some(tagger) = &tag.tagger {
+                        writeln!(
+                            self.config.writer,
+                            "Tagger: {} <{}>",
+                            tagger.name, tagger.email
+                        )?;
+                        writeln!(
+                            self.config.writer,
+                            "Date:   {}",
+                            crate::log::format_date(tagger)
+                        )?;
+                    }
+                    writeln!(self.config.writer, "\n{}\n", tag.message.trim_end())?;
+                    id = tag.object.parse()?;
+                }
+                ObjectType::Tree => {
+                    writeln!(self.config.writer, "tree {}\n", revision.unwrap_or("HEAD"))?;
+                    for entry in build_tree(objects, &id.to_string())?.entries {
+                        let slash = if entry.mode == TreeEntryMode::Directory {
+                            "/"
+                        } else {
+                            ""
+                        };
+                        writeln!(self.config.writer, "{}{slash}", quote_path(&entry.name))?;
+                    }
+                    return Ok(());
+                }
+                ObjectType::Blob => {
+                    let data = Object::read(objects, &id.to_string())?.into_bytes()?;
+                    self.config.writer.write_all(&data)?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Lists the commits reachable from `revision`, `HEAD` when not given, newest first.
+    /// `a..b` lists those reachable from `b` but not `a`. With `patch` each commit's changes
+    /// follow it.
+    pub fn log(
+        &mut self,
+        revision: Option<&str>,
+        patch: &bool,
+        flags: &DiffFlags,
+    ) -> anyhow::Result<()> {
+        let options = self.diff_options(flags)?;
+        let revision = revision.unwrap_or("HEAD");
+        let (excluded, included) = match self.resolve_range(revision)? {
+            Some((from, to)) => (vec![from], to),
+            None => (Vec::new(), self.resolve_object(revision)?),
+        };
+        let objects = self.repo.objects.as_ref();
+        for (i, id) in walk_commits(&self.repo, &[included], &excluded)?
+            .iter()
+            .enumerate()
+        {
+            let commit = Commit::read(objects, &id.to_string())?;
+            let out = &mut self.config.writer;
+            if i > 0 {
+                writeln!(out)?;
+            }
+            write_commit_header(out, id, &commit)?;
+            if *patch && commit.parents.len() < 2 {
+                writeln!(out)?;
+                write_commit_patch(out, &self.repo, &commit, &options)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes commits as mails carrying their patches, one file each in `output_directory`
+    /// or all to the output with `stdout`. `revisions` picks the commits: `-<n>` takes the last
+    /// n up to a revision, `a..b` those in the range, and a lone `a` those since `a`.
+    pub fn format_patch(
+        &mut self,
+        revisions: &[String],
+        output_directory: Option<&Path>,
+        stdout: &bool,
+        flags: &DiffFlags,
+    ) -> anyhow::Result<()> {
+        let options = DiffOptions {
+            binary: true,
+            ..self.diff_options(flags)?
+        };
+        let mut count = None;
+        let mut names = Vec::new();
+        for revision in revisions {
+            match revision.strip_prefix('-') {
+                Some(n) => {
+                    count = Some(
+                        n.parse::<usize>()
+                            .with_context(|| format!("unknown option '{revision}'"))?,
+                    )
+                }
+                None => names.push(revision.as_str()),
+            }
+        }
+        ensure!(
+            names.len() <= 1,
+            "format-patch takes a single revision range"
+        );
+        let range = match names.first() {
+            Some(name) => self.resolve_range(name)?,
+            None => None,
+        };
+        let (excluded, included) = match (range, names.first()) {
+            (Some((from, to)), _) => (vec![from], to),
+            (None, Some(name)) if count.is_some() => (Vec::new(), self.resolve_object(name)?),
+            (None, Some(name)) => (
+                vec![self.resolve_object(name)?],
+                self.resolve_object("HEAD")?,
+            ),
+            (None, None) => {
+                ensure!(count.is_some(), "format-patch needs a revision range");
+                (Vec::new(), self.resolve_object("HEAD")?)
+            }
+        };
+        let mut commits = walk_commits(&self.repo, &[included], &excluded)?;
+        if let Some(count) = count {
+            commits.truncate(count);
+        }
+        commits.reverse();
+
+        let cwd = self
+            .config
+            .dot_git_path
+            .parent()
+            .context("find working directory")?
+            .to_path_buf();
+        if let Some(directory) = output_directory.filter(|_| !*stdout) {
+            fs::create_dir_all(cwd.join(directory))
+                .with_context(|| format!("create {}", directory.display()))?;
+        }
+        let total = commits.len();
+        for (i, id) in commits.iter().enumerate() {
+            let commit = Commit::read(self.repo.objects.as_ref(), &id.to_string())?;
+            let number = (total > 1).then_some((i + 1, total));
+            if *stdout {
+                if i > 0 {
+                    writeln!(self.config.writer)?;
+                }
+                write_mail(
+                    &mut self.config.writer,
+                    &self.repo,
+                    id,
+                    &commit,
+                    number,
+                    &options,
+                )?;
+                continue;
+            }
+            let name = patch_file_name(i + 1, &commit.message);
+            let path = match output_directory {
+                Some(directory) => directory.join(name),
+                None => PathBuf::from(name),
+            };
+            let mut mail = Vec::new();
+            write_mail(&mut mail, &self.repo, id, &commit, number, &options)?;
+            fs::write(cwd.join(&path), mail)
+                .with_context(|| format!("write {}", path.display()))?;
+            writeln!(self.config.writer, "{}", path.display())?;
+        }
+        Ok(())
+    }
+
+    /// Shows changes: from the index to the worktree, from HEAD or a commit to the index with
+    /// `cached`, from a commit to the worktree, or between two commits given as `a..b` or
+    /// `a b`. The leading `args` that name revisions are taken as such and the rest, with
+    /// `paths`, limit the paths compared. Returns whether anything differed.
+    pub fn diff(
+        &mut self,
+        cached: &bool,
+        args: &[String],
+        paths: &[String],
+        format: DiffFormat,
+        flags: &DiffFlags,
+    ) -> anyhow::Result<bool> {
+        let options = self.diff_options(flags)?;
+        let objects = self.repo.objects.as_ref();
+        let tree = |id: ObjectId| peel_to_tree(objects, &id.to_string());
+        let mut revisions = Vec::new();
+        let mut pathspecs = Vec::new();
+        for arg in args {
+            if pathspecs.is_empty() && revisions.len() < 2 {
+                if let Some((from, to)) = self.resolve_range(arg)? {
+                    revisions.extend([from, to]);
+                    continue;
+                }
+                if let Some(id) = self.repo.resolve_revision(arg)? {
+                    revisions.push(id);
+                    continue;
+                }
+            }
+            pathspecs.push(arg.clone());
+        }
+        pathspecs.extend(paths.iter().cloned());
+        let dot_git_path = self.config.dot_git_path.clone();
+        let worktree = dot_git_path.parent().context("find working directory")?;
+        let pathspec = Pathspec::new(worktree, &pathspecs)?;
+
+        let mut pairs = match (&revisions[..], *cached) {
+            ([old, new], _) => {
+                tree_changes(objects, Some(&tree(*old)?), Some(&tree(*new)?), &pathspec)?
+            }
+            ([base], true) => index_changes(&self.repo, Some(&tree(*base)?), &pathspec)?,
+            ([], true) => {
+                let head = match self.repo.resolve_name("HEAD")? {
+                    Some(head) => Some(tree(head)?),
+                    None => None,
+                };
+                index_changes(&self.repo, head.as_deref(), &pathspec)?
+            }
+            ([base], false) => {
+                worktree_changes(&self.repo, &dot_git_path, Some(&tree(*base)?), &pathspec)?
+            }
+            ([], false) => worktree_changes(&self.repo, &dot_git_path, None, &pathspec)?,
+            _ => bail!("too many revisions"),
+        };
+        if options.renames.is_some_and(|renames| renames.harder) {
+            // what didn't change on the old side can be copied from too
+            let old_files = match (&revisions[..], *cached) {
+                ([old, ..], _) => tree_files(objects, &tree(*old)?)?,
+                ([], true) => match self.repo.resolve_name("HEAD")? {
+                    Some(head) => tree_files(objects, &tree(head)?)?,
+                    None => BTreeMap::new(),
+                },
+                ([], false) => self
+                    .repo
+                    .index()?
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.stage == 0 && !entry.intent_to_add)
+                    .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+                    .collect(),
+            };
+            let old_files = old_files
+                .into_iter()
+                .filter(|(path, _)| pathspec.matches(path));
+            add_unmodified(objects, &mut pairs, old_files)?;
+        }
+        let (pairs, needed) = match &options.renames {
+            Some(renames) => detect_renames(pairs, renames),
+            None => (pairs, None),
+        };
+        write_changes(&mut self.config.writer, &pairs, format, &options)?;
+        if let Some(needed) = needed {
+            self.config.writer.flush()?;
+            writeln!(
+                self.config.error_writer,
+                "warning: exhaustive rename detection was skipped due to too many files.\n\
+                 warning: you may want to set your diff.renameLimit variable to at least \
+                 {needed} and retry the command."
+            )?;
+        }
+        Ok(!pairs.is_empty())
+    }
+
+    pub fn ls_files(&mut self, stage: &bool) -> anyhow::Result<()> {
+        let index = self.repo.index()?;
+        for entry in &index.entries {
+            if *stage {
+                writeln!(self.config.writer, "{entry}")?;
+            } else {
+                self.config.writer.write_all(&entry.path)?;
+                writeln!(self.config.writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The top of the working tree.
+    fn worktree(&self) -> anyhow::Result<&Path> {
+        self.config
+            .dot_git_path
+            .parent()
+            .context("repository has no working tree")
+    }
+
+    /// Stages the files matching `pathspecs`. With `update` only tracked files are staged, and
+    /// `update` or `all` without pathspecs stage the whole tree. Ignored files are only staged
+    /// with `force`.
+    pub fn add(
+        &mut self,
+        pathspecs: &[String],
+        update: &bool,
+        all: &bool,
+        force: &bool,
+    ) -> anyhow::Result<()> {
+        if pathspecs.is_empty() && !*update && !*all {
+            bail!("Nothing specified, nothing added.");
+        }
+        let worktree = self.worktree()?;
+        let pathspec = Pathspec::new(worktree, pathspecs)?;
+        add(&self.repo, worktree, &pathspec, *update, *force)
+    }
+
+    /// Deletes the untracked files matching `pathspecs`, or with `dry_run` lists what would go.
+    /// Unless `clean.requireForce` is turned off one of `dry_run` and `force` is required.
+    pub fn clean(
+        &mut self,
+        pathspecs: &[String],
+        dry_run: &bool,
+        force: &bool,
+        directories: &bool,
+        ignored: CleanIgnored,
+    ) -> anyhow::Result<()> {
+        if !*dry_run && !*force {
+            match self.repo.config()?.get_bool("clean.requireForce")? {
+                Some(false) => {}
+                Some(true) => bail!(
+                    "clean.requireForce set to true and neither -i, -n, nor -f given; \
+                     refusing to clean"
+                ),
+                None => bail!(
+                    "clean.requireForce defaults to true and neither -i, -n, nor -f given; \
+                     refusing to clean"
+                ),
+            }
+        }
+        let worktree = self.worktree()?;
+        let pathspec = Pathspec::new(worktree, pathspecs)?;
+        let removed = remove_untracked(
+            &self.repo,
+            worktree,
+            &pathspec,
+            *directories,
+            ignored,
+            *dry_run,
+        )?;
+        let action = if *dry_run { "Would remove" } else { "Removing" };
+        for path in removed {
+            writeln!(self.config.writer, "{action} {}", quote_path(&path))?;
+        }
+        Ok(())
+    }
+
+    pub fn rm(
+        &mut self,
+        pathspecs: &[String],
+        cached: &bool,
+        recursive: &bool,
+        force: &bool,
+    ) -> anyhow::Result<()> {
+        let worktree = self.worktree()?;
+        let pathspec = Pathspec::new(worktree, pathspecs)?;
+        let removed = rm(&self.repo, worktree, &pathspec, *cached, *recursive, *force)?;
+        for path in removed {
+            writeln!(
+                self.config.writer,
+                "rm '{}'",
+                String::from_utf8_lossy(&path)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// `checkout [<tree-ish>] [--] <paths>`: restores the paths from the tree-ish into the
+    /// index and the worktree, or from the index into the worktree. Without `--` the first
+    /// argument is taken as a tree-ish if it names one. With `no_overlay` tracked paths the
+    /// tree-ish doesn't have are removed.
+    pub fn checkout(
+        &mut self,
+        args: &[String],
+        paths: &[String],
+        no_overlay: &bool,
+    ) -> anyhow::Result<()> {
+        let (revision, mut pathspecs) = match args {
+            [revision, rest @ ..]
+                if !paths.is_empty() || self.repo.resolve_revision(revision)?.is_some() =>
+            {
+                (Some(revision), rest.to_vec())
+            }
+            _ => (None, args.to_vec()),
+        };
+        if !paths.is_empty() && !pathspecs.is_empty() {
+            bail!("only one tree-ish can be given before '--'");
+        }
+        pathspecs.extend(paths.iter().cloned());
+        if pathspecs.is_empty() {
+            bail!("checkout only restores paths; use switch to change branches");
+        }
+        let tree = match revision {
+            Some(revision) => Some(peel_to_tree(
+                self.repo.objects.as_ref(),
+                &self.resolve_object(revision)?.to_string(),
+            )?),
+            None => None,
+        };
+        let worktree = self.worktree()?;
+        let pathspec = Pathspec::new(worktree, &pathspecs)?;
+        let options = RestoreOptions {
+            source: tree.as_deref(),
+            staged: tree.is_some(),
+            worktree: true,
+            overlay: !*no_overlay,
+        };
+        restore(&self.repo, worktree, &pathspec, &options)
+    }
+
+    /// Restores the paths matching `pathspecs` in the worktree, or with `staged` in the index,
+    /// or both when both are asked for. Files come from `source`, or else `HEAD` for the
+    /// index and the index for the worktree. Unless `overlay` is set tracked paths the
+    /// source doesn't have are removed.
+    pub fn restore(
+        &mut self,
+        pathspecs: &[String],
+        source: Option<&str>,
+        staged: &bool,
+        worktree: &bool,
+        overlay: &bool,
+    ) -> anyhow::Result<()> {
+        if pathspecs.is_empty() {
+            bail!("you must specify path(s) to restore");
+        }
+        let source = match source.or(staged.then_some("HEAD")) {
+            Some(revision) => Some(peel_to_tree(
+                self.repo.objects.as_ref(),
+                &self.resolve_object(revision)?.to_string(),
+            )?),
+            None => None,
+        };
+        let dir = self.worktree()?;
+        let pathspec = Pathspec::new(dir, pathspecs)?;
+        let options = RestoreOptions {
+            source: source.as_deref(),
+            staged: *staged,
+            worktree: *worktree || !*staged,
+            overlay: *overlay,
         };
+        restore(&self.repo, dir, &pathspec, &options)
+    }
 
-        writeln!(self.config.writer, "{}", hex::encode(hash))?;
+    /// Lists the branches, with `verbose` along with their commits and upstreams.
+    pub fn branch_list(&mut self, verbose: u8) -> anyhow::Result<()> {
+        branch::write_branches(&mut self.config.writer, &self.repo, verbose)
+    }
+
+    /// Creates the branch `name` at `start`, replacing an existing one with `force`.
+    pub fn branch_create(
+        &mut self,
+        name: &str,
+        start: Option<&str>,
+        force: &bool,
+    ) -> anyhow::Result<()> {
+        let worktree = self.worktree()?;
+        if let Some(upstream) = branch::create(&self.repo, worktree, name, start, *force)? {
+            writeln!(
+                self.config.writer,
+                "branch '{name}' set up to track '{upstream}'."
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the branches `names`, unmerged ones only with `force`. Every branch is tried
+    /// even after one fails, and whether they all went is returned.
+    pub fn branch_delete(&mut self, names: &[String], force: &bool) -> anyhow::Result<bool> {
+        if names.is_empty() {
+            bail!("branch name required");
+        }
+        let worktree = self.worktree()?.to_path_buf();
+        let mut deleted_all = true;
+        for name in names {
+            match branch::delete(&self.repo, &worktree, name, *force) {
+                Ok(deleted) => {
+                    if let Some(upstream) = deleted.only_merged_to {
+                        writeln!(
+                            self.config.error_writer,
+                            "warning: deleting branch '{name}' that has been merged to\n         \
+                             '{upstream}', but not yet merged to HEAD."
+                        )?;
+                    }
+                    writeln!(
+                        self.config.writer,
+                        "Deleted branch {name} (was {}).",
+                        &deleted.commit.to_string()[..7]
+                    )?;
+                }
+                Err(e) => {
+                    writeln!(self.config.error_writer, "error: {e}")?;
+                    deleted_all = false;
+                }
+            }
+        }
+        Ok(deleted_all)
+    }
+
+    /// Renames the branch given first, or the current one when only the new name is given.
+    pub fn branch_rename(&mut self, names: &[String], force: &bool) -> anyhow::Result<()> {
+        match names {
+            [new] => branch::rename(&self.repo, None, new, *force),
+            [old, new] => branch::rename(&self.repo, Some(old), new, *force),
+            [] => bail!("branch name required"),
+            _ => bail!("too many arguments for a rename operation"),
+        }
+    }
+
+    /// Switches to the branch `target`, with `create` to a new branch of that name starting at
+    /// `target` or `HEAD`, or with `detach` to the commit `target` names. A branch that only a
+    /// remote has is created to track it, and `-` is what was checked out before. The index
+    /// and the worktree follow, keeping local changes unless they would be overwritten, which
+    /// stops the switch unless `merge` is set to merge them into the new files. `quiet` leaves
+    /// out all but the errors. Returns whether the switch went ahead.
+    pub fn switch(
+        &mut self,
+        target: Option<&str>,
+        create: Option<&str>,
+        detach: &bool,
+        merge: &bool,
+        quiet: &bool,
+    ) -> anyhow::Result<bool> {
+        let origin = Origin::read(&self.repo)?;
+        let destination = destination(&self.repo, &origin, target, create, *detach)?;
+
+        let index = self.repo.index()?;
+        if index.has_conflicts() {
+            for path in unmerged_paths(&index) {
+                writeln!(self.config.writer, "{}: needs merge", quote_path(path))?;
+            }
+            writeln!(
+                self.config.error_writer,
+                "error: you need to resolve your current index first"
+            )?;
+            return Ok(false);
+        }
+        if let Some(commit) = destination.commit() {
+            let objects = self.repo.objects.as_ref();
+            let tree_of = |id: &ObjectId| -> anyhow::Result<String> {
+                Ok(Commit::read(objects, &id.to_string())?.tree)
+            };
+            let old_tree = origin.commit.as_ref().map(tree_of).transpose()?;
+            let refused = switch_trees(
+                &mut self.config.error_writer,
+                &self.repo,
+                &self.config.dot_git_path,
+                old_tree.as_deref(),
+                &tree_of(commit)?,
+                merge.then_some(destination.name()),
+            )?;
+            if let Some(refusal) = refused {
+                write!(self.config.error_writer, "{refusal}")?;
+                return Ok(false);
+            }
+            if !quiet {
+                for (path, letter) in local_changes(&self.repo, &self.config.dot_git_path, commit)?
+                {
+                    writeln!(self.config.writer, "{letter}\t{}", quote_path(&path))?;
+                }
+            }
+        }
+
+        if let (None, Some(old_commit), false) = (&origin.branch, &origin.commit, quiet) {
+            if destination.commit() != Some(old_commit) {
+                let previous = self.describe_commit(old_commit)?;
+                writeln!(
+                    self.config.error_writer,
+                    "Previous HEAD position was {previous}"
+                )?;
+            }
+        }
+        let worktree = self.worktree()?.to_path_buf();
+        let upstream = move_head(&self.repo, &worktree, &origin, &destination)?;
+        if *quiet {
+            return Ok(true);
+        }
+        if let (Destination::Branch { name, .. }, Some(upstream)) = (&destination, upstream) {
+            writeln!(
+                self.config.writer,
+                "branch '{name}' set up to track '{upstream}'."
+            )?;
+        }
+
+        match &destination {
+            Destination::Detached { commit, .. } => {
+                let head = self.describe_commit(commit)?;
+                writeln!(self.config.error_writer, "HEAD is now at {head}")?;
+            }
+            Destination::Branch { name, .. } if origin.branch.as_ref() == Some(name) => {
+                writeln!(self.config.error_writer, "Already on '{name}'")?;
+            }
+            Destination::Branch {
+                name,
+                start: Some(_),
+                ..
+            } => writeln!(
+                self.config.error_writer,
+                "Switched to a new branch '{name}'"
+            )?,
+            Destination::Branch { name, .. } => {
+                writeln!(self.config.error_writer, "Switched to branch '{name}'")?
+            }
+        }
+        if let Destination::Branch {
+            name,
+            start: None,
+            commit: Some(commit),
+        } = &destination
+        {
+            let config = self.repo.config()?;
+            if let Some(upstream) = branch::upstream(&self.repo, &config, name, commit)? {
+                write_tracking(&mut self.config.writer, &upstream)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// A commit as `switch` shows it: the abbreviated id and the subject.
+    fn describe_commit(&self, id: &ObjectId) -> anyhow::Result<String> {
+        let commit = Commit::read(self.repo.objects.as_ref(), &id.to_string())?;
+        let (subject, _) = split_message(&commit.message);
+        Ok(format!("{} {subject}", &id.to_string()[..7]))
+    }
+
+    /// Registers the URLs of the submodules matching `pathspecs` in the config.
+    pub fn submodule_init(&mut self, pathspecs: &[String]) -> anyhow::Result<()> {
+        let worktree = self.worktree()?;
+        let pathspec = Pathspec::new(worktree, pathspecs)?;
+        for submodule in submodule::init(&self.repo, worktree, &pathspec)? {
+            writeln!(
+                self.config.error_writer,
+                "Submodule '{}' ({}) registered for path '{}'",
+                submodule.name,
+                submodule.url.unwrap_or_default(),
+                submodule.path
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Checks out the registered submodules matching `pathspecs` at the commits the index
+    /// records for them, cloning them first if needed. With `init` they are registered first.
+    pub fn submodule_update(&mut self, pathspecs: &[String], init: &bool) -> anyhow::Result<()> {
+        if *init {
+            self.submodule_init(pathspecs)?;
+        }
+        let worktree = self.worktree()?.to_path_buf();
+        let pathspec = Pathspec::new(&worktree, pathspecs)?;
+        for updated in submodule::update(&self.repo, &worktree, &pathspec)? {
+            if updated.cloned {
+                writeln!(
+                    self.config.error_writer,
+                    "Cloning into '{}'...",
+                    worktree.join(&updated.path).display()
+                )?;
+            }
+            writeln!(
+                self.config.writer,
+                "Submodule path '{}': checked out '{}'",
+                updated.path, updated.commit
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn submodule_status(&mut self, pathspecs: &[String]) -> anyhow::Result<()> {
+        let worktree = self.worktree()?;
+        let pathspec = Pathspec::new(worktree, pathspecs)?;
+        for status in submodule::status(&self.repo, worktree, &pathspec)? {
+            write!(
+                self.config.writer,
+                "{}{} {}",
+                status.state, status.commit, status.path
+            )?;
+            match status.describe {
+                Some(describe) => writeln!(self.config.writer, " ({describe})")?,
+                None => writeln!(self.config.writer)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn status(&mut self, options: &StatusOptions) -> anyhow::Result<()> {
+        let status = status(&self.repo, &self.config.dot_git_path, options)?;
+        write_status(&mut self.config.writer, &status, options)
+    }
+
+    pub fn write_tree(&mut self) -> anyhow::Result<()> {
+        let mut index = self.repo.index()?;
+        let cache_tree = index.cache_tree.clone();
+        let hash = write_tree_from_index(self.repo.objects.as_ref(), &mut index)
+            .context("construct root tree object")?;
+        if index.cache_tree != cache_tree {
+            self.repo.write_index(&index)?;
+        }
+
+        writeln!(self.config.writer, "{hash}")?;
         Ok(())
     }
 
@@ -104,41 +1143,152 @@ impl<W: std::io::Write, X: std::io::Write> Git<W, X> {
         parent_hash: Option<String>,
     ) -> anyhow::Result<()> {
         let hash = commit_tree(
-            &self.config.dot_git_path,
+            self.repo.objects.as_ref(),
             message,
             tree_hash,
             parent_hash.as_deref(),
         )
         .context("commit tree")?;
         match hash {
-            Some(hash) => writeln!(self.config.writer, "{}", hex::encode(hash))?,
+            Some(hash) => writeln!(self.config.writer, "{hash}")?,
             None => bail!("failed to commit tree"),
         }
         Ok(())
     }
 
     pub fn commit(&mut self, message: &str) -> anyhow::Result<()> {
-        let hash = commit(&self.config.dot_git_path, Path::new("."), message).context("commit")?;
+        let hash = commit(&self.repo, message).context("commit")?;
         match hash {
-            Some(hash) => writeln!(self.config.writer, "{}", hex::encode(hash))?,
+            Some(hash) => writeln!(self.config.writer, "{hash}")?,
             None => bail!("failed to commit"),
         }
         Ok(())
     }
 
-    // http://ftp.newartisans.com/pub/git.from.bottom.up.pdf
-    pub fn clone(&mut self, repo_url: &str) -> anyhow::Result<()> {
-        todo!("clone"); // https://stefan.saasen.me/articles/git-clone-in-haskell-from-the-bottom-up/#format_of_the_delta_representation
+    pub fn fsck(&mut self, unreachable: &bool) -> anyhow::Result<()> {
+        let issues = fsck(&self.repo, *unreachable).context("check repository")?;
+        for issue in &issues {
+            writeln!(self.config.writer, "{issue}")?;
+        }
+        let errors = issues.iter().filter(|issue| issue.is_error()).count();
+        if errors > 0 {
+            bail!("fsck found {errors} problem(s)");
+        }
+        Ok(())
+    }
+
+    pub fn repack(&mut self, all: &bool, delete: &bool) -> anyhow::Result<()> {
+        let written = repack(&self.repo, &self.config.dot_git_path, *all, *delete)
+            .context("repack objects")?;
+        self.report_pack(written)
+    }
+
+    pub fn gc(&mut self, prune: Option<&str>) -> anyhow::Result<()> {
+        let written =
+            gc(&self.repo, &self.config.dot_git_path, prune).context("collect garbage")?;
+        self.report_pack(written)
+    }
+
+    fn report_pack(&mut self, written: Option<crate::pack::WrittenPack>) -> anyhow::Result<()> {
+        match written {
+            Some(written) => writeln!(
+                self.config.error_writer,
+                "Total {} (delta {})",
+                written.objects, written.deltas
+            )?,
+            None => writeln!(self.config.error_writer, "Nothing new to pack.")?,
+        }
+        Ok(())
+    }
+
+    /// Clones a repository on the local filesystem, into `directory` or a directory named
+    /// after the repository next to the current one.
+    pub fn clone(
+        &mut self,
+        repo_url: &str,
+        directory: Option<&Path>,
+        reference: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        let Some(source) = local_path(repo_url) else {
+            bail!("only repositories on the local filesystem can be cloned, not '{repo_url}'");
+        };
+        let cwd = self
+            .config
+            .dot_git_path
+            .parent()
+            .context("find working directory")?;
+        let destination = match directory {
+            Some(directory) => cwd.join(directory),
+            None => {
+                let name = source
+                    .canonicalize()
+                    .unwrap_or_else(|_| source.clone())
+                    .file_name()
+                    .context("repository path has no name")?
+                    .to_string_lossy()
+                    .to_string();
+                let name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+                cwd.join(name)
+            }
+        };
+        writeln!(
+            self.config.error_writer,
+            "Cloning into '{}'...",
+            destination.display()
+        )?;
+        let reference = reference.map(|reference| cwd.join(reference));
+        clone(&cwd.join(source), &destination, reference.as_deref()).context("clone repository")
     }
 }
 
+/// The `%(objectname) %(objecttype) %(objectsize)` style lines of `cat-file --batch`.
+fn expand_batch_format(
+    format: &str,
+    hash: &str,
+    object_type: ObjectType,
+    size: u64,
+    rest: &str,
+) -> anyhow::Result<String> {
+    let mut line = String::new();
+    let mut remaining = format;
+    while let Some(start) = remaining.find("%(") {
+        line.push_str(&remaining[..start]);
+        let Some(end) = remaining[start..].find(')') else {
+            bail!("unterminated format atom in '{format}'");
+        };
+        match &remaining[start + 2..start + end] {
+            "objectname" => line.push_str(hash),
+            "objecttype" => line.push_str(&object_type.to_string()),
+            "objectsize" => line.push_str(&size.to_string()),
+            "rest" => line.push_str(rest),
+            atom => bail!("unknown format element: %({atom})"),
+        }
+        remaining = &remaining[start + end + 1..];
+    }
+    line.push_str(remaining);
+    Ok(line)
+}
+
+fn local_path(repo_url: &str) -> Option<PathBuf> {
+    if let Some(path) = repo_url.strip_prefix("file://") {
+        return Some(PathBuf::from(path));
+    }
+    if repo_url.contains("://") {
+        return None;
+    }
+    Some(PathBuf::from(repo_url))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hash::HashAlgorithm;
     use crate::test::{
-        build_git_from_fixture, build_simple_app_git, build_test_git, write_to_git_objects,
+        build_git_from_fixture, build_memory_git, build_simple_app_git, build_test_git,
+        write_to_git_objects,
     };
     use flate2::read::ZlibDecoder;
+    use std::fs;
     use std::io::{BufRead, Read, Write};
     use tempfile::tempdir;
 
@@ -152,7 +1302,7 @@ mod tests {
             error_writer,
             dot_git_path: temp_dir.path().to_path_buf().join(".git"),
         };
-        let mut git = Git { config };
+        let mut git = Git::new(config)?;
         git.init()?;
         let git_dir = temp_dir.path().join(".git");
         assert!(git_dir.exists());
@@ -170,6 +1320,69 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_in_memory_git() -> anyhow::Result<()> {
+        let mut tmp_file = tempfile::NamedTempFile::new()?;
+        tmp_file.write_all(b"hello world")?;
+
+        let mut git = build_memory_git()?;
+        git.init()?;
+        git.hash_object(&true, tmp_file.path())?;
+        let output = String::from_utf8(std::mem::take(&mut git.config.writer))?;
+        assert_eq!(
+            output,
+            "Initialized git directory\n95d09f2b10159347eece71399a7e2e907ea3df4f"
+        );
+
+        git.cat_file(&true, "95d09f2b10159347eece71399a7e2e907ea3df4f")?;
+        assert_eq!(String::from_utf8(git.config.writer)?, "hello world");
+        assert!(!git.config.dot_git_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_sha256() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let config = Config {
+            writer: Vec::new(),
+            error_writer: Vec::new(),
+            dot_git_path: temp_dir.path().join(".git"),
+        };
+        let repo = Repository::on_disk(&config.dot_git_path, HashAlgorithm::Sha256);
+        Git::with_repository(config, repo).init()?;
+
+        let git = Git::new(Config {
+            writer: Vec::new(),
+            error_writer: Vec::new(),
+            dot_git_path: temp_dir.path().join(".git"),
+        })?;
+        assert_eq!(git.repo.hash_algorithm(), HashAlgorithm::Sha256);
+        let config = git.repo.config()?;
+        assert_eq!(config.get("core.repositoryformatversion"), Some("1"));
+        assert_eq!(config.get("extensions.objectformat"), Some("sha256"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ls_tree_sha256() -> anyhow::Result<()> {
+        let mut git = build_git_from_fixture("sha256-app")?;
+        let commit = git.repo.resolve_ref("HEAD")?.unwrap();
+        assert_eq!(
+            commit,
+            "8b470da31ea53239c6d4f6880478a1b5ba00637df93b351f7ab1321724c99fe9"
+        );
+        git.ls_tree(
+            &false,
+            "02414a7ae2b224310056a15f1ba3f603dbfcafb9505504f847686c6bfe2224d6",
+        )?;
+        let actual = String::from_utf8(git.config.writer).expect("Found invalid UTF-8");
+        assert_eq!(
+            actual,
+            "100644 blob 713b95b9d7deeae70bc21397e13f19c3af77e719bf11b028585cf08eda79ca29\tfoo.rs\n"
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_cat_file() -> anyhow::Result<()> {
         let file_contents = b"blob 11\0hello world";
@@ -191,7 +1404,7 @@ mod tests {
             error_writer,
             dot_git_path: git.config.dot_git_path.as_path().to_path_buf(),
         };
-        let mut git = Git { config };
+        let mut git = Git::new(config)?;
         git.cat_file(&true, &hash)
             .context("unable to cat the file")?;
         let result_string = String::from_utf8(git.config.writer).expect("Found invalid UTF-8");
@@ -215,7 +1428,7 @@ mod tests {
             dot_git_path: temp_dir.path().to_path_buf().join(".git"),
         };
         fs::create_dir(&config.dot_git_path)?;
-        let mut git = Git { config };
+        let mut git = Git::new(config)?;
         git.hash_object(&true, &tmp_file_path)?;
         let hash = String::from_utf8(git.config.writer).expect("Found invalid UTF-8");
         let object_path = temp_dir
@@ -252,6 +1465,223 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_clone_with_reference() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let mut git = Git::new(Config {
+            writer: Vec::new(),
+            error_writer: Vec::new(),
+            dot_git_path: temp_dir.path().join(".git"),
+        })?;
+        let fixture = Path::new("tests/fixtures/packed-app");
+        let source = fixture.join("dot-git").canonicalize()?;
+        let source = source.to_str().unwrap();
+
+        git.clone(source, Some(Path::new("plain")), None)?;
+        let plain = temp_dir.path().join("plain");
+        assert_eq!(
+            fs::read(plain.join("hello.txt"))?,
+            fs::read(fixture.join("hello.txt"))?
+        );
+        assert_eq!(
+            fs::read(plain.join("src/main.rs"))?,
+            fs::read(fixture.join("src/main.rs"))?
+        );
+        assert_eq!(fs::read_dir(plain.join(".git/objects/pack"))?.count(), 2);
+
+        git.clone(source, Some(Path::new("shared")), Some(&plain))?;
+        let shared = temp_dir.path().join("shared");
+        assert_eq!(
+            fs::read_to_string(shared.join(".git/objects/info/alternates"))?,
+            format!("{}\n", plain.join(".git/objects").canonicalize()?.display())
+        );
+        assert!(!shared.join(".git/objects/pack").exists());
+        let repo = Repository::open(&shared.join(".git"))?;
+        assert_eq!(
+            repo.resolve_ref("refs/remotes/origin/HEAD")?.as_deref(),
+            Some("388f38c3f0448af4063b9b9b727ae38ef73a23e2")
+        );
+        assert_eq!(fsck(&repo, false)?, vec![]);
+        assert_eq!(
+            fs::read(shared.join("numbers.txt"))?,
+            fs::read(fixture.join("numbers.txt"))?
+        );
+
+        assert!(git.clone(source, Some(Path::new("shared")), None).is_err());
+        assert!(git
+            .clone("https://example.com/repo.git", None, None)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cat_file_pretty_print() -> anyhow::Result<()> {
+        let mut git = build_git_from_fixture("packed-app")?;
+        git.cat_file(&true, "master")?;
+        let commit = String::from_utf8(std::mem::take(&mut git.config.writer))?;
+        assert!(commit.starts_with("tree fd2cb86555bda1ffdee0be070d2693982b39c12c\n"));
+        assert!(commit.ends_with("\n\nmore numbers\n"));
+
+        git.cat_file(&true, "fd2cb86555bda1ffdee0be070d2693982b39c12c")?;
+        let tree = String::from_utf8(std::mem::take(&mut git.config.writer))?;
+        assert!(tree.contains("100644 blob ce013625030ba8dba906f756967f9e9ca394464a\thello.txt\n"));
+        assert!(tree.contains("040000 tree "));
+
+        git.cat_file(&true, "v1")?;
+        let tag = String::from_utf8(std::mem::take(&mut git.config.writer))?;
+        assert!(tag.starts_with("object 388f38c3f0448af4063b9b9b727ae38ef73a23e2\ntype commit\n"));
+
+        git.cat_file_type("v1")?;
+        git.cat_file_size("master")?;
+        assert_eq!(
+            String::from_utf8(std::mem::take(&mut git.config.writer))?,
+            "tag\n179\n"
+        );
+
+        git.cat_file_as("commit", "v1")?;
+        assert_eq!(
+            String::from_utf8(std::mem::take(&mut git.config.writer))?,
+            commit
+        );
+        git.cat_file_as("tree", "v1")?;
+        let raw_tree = std::mem::take(&mut git.config.writer);
+        git.cat_file(&false, "fd2cb86555bda1ffdee0be070d2693982b39c12c")?;
+        assert_eq!(git.config.writer, raw_tree);
+        assert!(git.cat_file_as("blob", "master").is_err());
+
+        assert!(git.cat_file_exists("master")?);
+        assert!(!git.cat_file_exists("ea8c4bf7f35f6f77f75d92ad8ce8349f6e81ddba")?);
+        assert!(git.cat_file(&true, "nope").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cat_file_batch() -> anyhow::Result<()> {
+        let mut git = build_git_from_fixture("packed-app")?;
+        let input = "388f38c3f0448af4063b9b9b727ae38ef73a23e2\nv1\nnot-there\nv1~0\nHEAD~50\n";
+        git.cat_file_batch(
+            input.as_bytes(),
+            "%(objectname) %(objecttype) %(objectsize)",
+            &false,
+            &false,
+        )?;
+        assert_eq!(
+            String::from_utf8(git.config.writer.clone())?,
+            "388f38c3f0448af4063b9b9b727ae38ef73a23e2 commit 179\n\
+             3844e8f7fa93d6aac63576776d275dead317af0a tag 114\n\
+             not-there missing\n\
+             388f38c3f0448af4063b9b9b727ae38ef73a23e2 commit 179\n\
+             HEAD~50 missing\n"
+        );
+
+        git.config.writer.clear();
+        let hello = "ce013625030ba8dba906f756967f9e9ca394464a";
+        git.cat_file_batch(
+            format!("{hello} greeting\n").as_bytes(),
+            "%(objecttype) %(rest)",
+            &true,
+            &false,
+        )?;
+        assert_eq!(
+            String::from_utf8(git.config.writer.clone())?,
+            "blob greeting\nhello\n\n"
+        );
+
+        git.config.writer.clear();
+        git.cat_file_batch("".as_bytes(), "%(objectname)", &false, &true)?;
+        assert_eq!(
+            String::from_utf8(git.config.writer.clone())?
+                .lines()
+                .count(),
+            10
+        );
+        assert!(git
+            .cat_file_batch("v1\n".as_bytes(), "%(bogus)", &false, &false)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_object_options() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let mut git = Git::new(Config {
+            writer: Vec::new(),
+            error_writer: Vec::new(),
+            dot_git_path: temp_dir.path().join(".git"),
+        })?;
+        git.init()?;
+        git.config.writer.clear();
+
+        git.hash_object_stdin("hello world\n".as_bytes(), &HashObjectOptions::default())?;
+        assert_eq!(
+            String::from_utf8(std::mem::take(&mut git.config.writer))?,
+            "3b18e512dba79e4c8300dd08aeb37f8e728b8dad\n"
+        );
+
+        let tree = HashObjectOptions {
+            object_type: Some("tree".into()),
+            write: true,
+            ..Default::default()
+        };
+        assert!(git
+            .hash_object_stdin("not a tree".as_bytes(), &tree)
+            .is_err());
+        let literally = HashObjectOptions {
+            literally: true,
+            ..tree
+        };
+        git.hash_object_stdin("not a tree".as_bytes(), &literally)?;
+        let hash = String::from_utf8(std::mem::take(&mut git.config.writer))?;
+        assert!(git.repo.objects.contains(hash.trim())?);
+        let commit = HashObjectOptions {
+            object_type: Some("commit".into()),
+            ..Default::default()
+        };
+        git.hash_object_stdin(
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+             author A <a@b.c> 1 +0000\ncommitter A <a@b.c> 1 +0000\n\nm\n"
+                .as_bytes(),
+            &commit,
+        )?;
+        assert!(git
+            .hash_object_stdin("tree nope\n".as_bytes(), &commit)
+            .is_err());
+        assert!(git
+            .hash_object_stdin(
+                "".as_bytes(),
+                &HashObjectOptions {
+                    object_type: Some("bogus".into()),
+                    ..Default::default()
+                }
+            )
+            .is_err());
+
+        fs::write(temp_dir.path().join(".gitattributes"), "*.txt text\n")?;
+        fs::write(temp_dir.path().join("crlf.txt"), "hello world\r\n")?;
+        fs::write(temp_dir.path().join("crlf.bin"), "hello world\r\n")?;
+        git.config.writer.clear();
+        git.hash_object_files(
+            &[
+                temp_dir.path().join("crlf.txt"),
+                temp_dir.path().join("crlf.bin"),
+            ],
+            &HashObjectOptions::default(),
+        )?;
+        git.hash_object_stdin(
+            "hello world\r\n".as_bytes(),
+            &HashObjectOptions {
+                path: Some("notes.txt".into()),
+                ..Default::default()
+            },
+        )?;
+        let hashes = String::from_utf8(std::mem::take(&mut git.config.writer))?;
+        let hashes: Vec<&str> = hashes.lines().collect();
+        assert_eq!(hashes[0], "3b18e512dba79e4c8300dd08aeb37f8e728b8dad");
+        assert_ne!(hashes[1], hashes[0]);
+        assert_eq!(hashes[2], hashes[0]);
+        Ok(())
+    }
+
     #[test]
     fn test_ls_tree_name_only() -> anyhow::Result<()> {
         let mut git = build_simple_app_git()?;
@@ -276,4 +1706,22 @@ mod tests {
         assert_eq!(actual, expected);
         Ok(())
     }
+
+    #[test]
+    fn test_ls_files() -> anyhow::Result<()> {
+        let mut git = build_simple_app_git()?;
+        git.ls_files(&true)?;
+        let actual = String::from_utf8(std::mem::take(&mut git.config.writer))?;
+        assert_eq!(
+            actual,
+            "100644 ea8c4bf7f35f6f77f75d92ad8ce8349f6e81ddba 0\t.gitignore
+100644 f195397afef8ad7a138507d1cf1c118d6e0d6dfc 0\tCargo.toml
+100644 e7a11a969c037e00a796aafeff6258501ec15e9a 0\tsrc/main.rs
+"
+        );
+        git.ls_files(&false)?;
+        let actual = String::from_utf8(git.config.writer)?;
+        assert_eq!(actual, ".gitignore\nCargo.toml\nsrc/main.rs\n");
+        Ok(())
+    }
 }
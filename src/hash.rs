@@ -0,0 +1,193 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use anyhow::{bail, Context};
+use sha1::{Digest, Sha1};
+
+use crate::sha256::Sha256;
+
+/// The hash function a repository names its objects with (`extensions.objectFormat`).
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Length of an object id in bytes.
+    pub fn raw_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+        }
+    }
+
+    pub fn hex_len(self) -> usize {
+        self.raw_len() * 2
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn hash(self, data: &[u8]) -> ObjectId {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// The all-zero id git uses for "no object".
+    pub fn null_id(self) -> ObjectId {
+        ObjectId {
+            bytes: [0; 32],
+            len: self.raw_len() as u8,
+        }
+    }
+
+    fn for_len(len: usize) -> anyhow::Result<Self> {
+        match len {
+            20 => Ok(HashAlgorithm::Sha1),
+            32 => Ok(HashAlgorithm::Sha256),
+            _ => bail!("object ids are 20 or 32 bytes, not {len}"),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashAlgorithm::Sha1 => write!(f, "sha1"),
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => bail!("unknown object format '{s}'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> ObjectId {
+        match self {
+            Hasher::Sha1(hasher) => {
+                ObjectId::from_bytes(&hasher.finalize()).expect("sha1 digests are 20 bytes")
+            }
+            Hasher::Sha256(hasher) => {
+                ObjectId::from_bytes(&hasher.finalize()).expect("sha256 digests are 32 bytes")
+            }
+        }
+    }
+}
+
+/// The name of an object: a SHA-1 or SHA-256 digest.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectId {
+    bytes: [u8; 32],
+    len: u8,
+}
+
+impl ObjectId {
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        HashAlgorithm::for_len(bytes.len())?;
+        let mut id = [0; 32];
+        id[..bytes.len()].copy_from_slice(bytes);
+        Ok(Self {
+            bytes: id,
+            len: bytes.len() as u8,
+        })
+    }
+
+    pub fn from_hex(hex: &str) -> anyhow::Result<Self> {
+        let bytes = hex::decode(hex).with_context(|| format!("invalid object hash '{hex}'"))?;
+        Self::from_bytes(&bytes).with_context(|| format!("invalid object hash '{hex}'"))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::for_len(self.len as usize).expect("object ids have a valid length")
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.as_bytes().iter().all(|&b| b == 0)
+    }
+}
+
+impl PartialOrd for ObjectId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ObjectId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.as_bytes()))
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({self})")
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_algorithms() -> anyhow::Result<()> {
+        let sha1 = HashAlgorithm::Sha1.hash(b"blob 0\0");
+        assert_eq!(sha1.to_string(), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        let sha256 = HashAlgorithm::Sha256.hash(b"blob 0\0");
+        assert_eq!(
+            sha256.to_string(),
+            "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813"
+        );
+        assert_eq!(sha256.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(ObjectId::from_hex(&sha256.to_string())?, sha256);
+        assert!(ObjectId::from_hex("abc").is_err());
+        assert!(HashAlgorithm::Sha256.null_id().is_null());
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+//! Which untracked files git should leave alone: the `.gitignore` files of the worktree,
+//! `info/exclude` and `core.excludesFile`.
+
+use std::{
+    env, fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{config::GitConfig, repository::Repository, wildmatch::wildmatch, worktree::disk_path};
+
+/// One line of an ignore file.
+#[derive(Debug)]
+struct IgnorePattern {
+    pattern: Vec<u8>,
+    /// Written with a leading `!`, so a match re-includes the path.
+    negated: bool,
+    /// Written with a trailing `/`, so it only matches directories.
+    dir_only: bool,
+    /// Contains a `/` other than a trailing one, so it matches the path relative to the
+    /// ignore file rather than any file name.
+    anchored: bool,
+}
+
+/// The patterns of one ignore file.
+#[derive(Debug, Default)]
+struct PatternList {
+    /// The directory the patterns are relative to, ending in `/`; empty for the top of the
+    /// worktree.
+    base: Vec<u8>,
+    patterns: Vec<IgnorePattern>,
+}
+
+impl PatternList {
+    fn parse(base: Vec<u8>, contents: &[u8]) -> Self {
+        let mut patterns = Vec::new();
+        for line in contents.split(|&b| b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line = trim_trailing_spaces(line);
+            if line.is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+            let (line, negated) = match line.strip_prefix(b"!") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            let (line, dir_only) = match line.strip_suffix(b"/") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.is_empty() {
+                continue;
+            }
+            patterns.push(IgnorePattern {
+                anchored: line.contains(&b'/'),
+                pattern: line.strip_prefix(b"/").unwrap_or(line).to_vec(),
+                negated,
+                dir_only,
+            });
+        }
+        Self { base, patterns }
+    }
+
+    /// Whether the last pattern matching `path` ignores it, `None` when none matches.
+    fn matches(&self, path: &[u8], is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base[..])?;
+        let name = relative.rsplit(|&b| b == b'/').next().unwrap_or(relative);
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| {
+                if pattern.dir_only && !is_dir {
+                    return false;
+                }
+                if pattern.anchored {
+                    wildmatch(&pattern.pattern, relative, true)
+                } else {
+                    wildmatch(&pattern.pattern, name, true)
+                }
+            })
+            .map(|pattern| !pattern.negated)
+    }
+}
+
+/// The ignore rules of a worktree.
+#[derive(Debug, Default)]
+pub struct IgnoreRules {
+    /// The `.gitignore` files, parents before the directories inside them.
+    per_directory: Vec<PatternList>,
+    /// `info/exclude` and then `core.excludesFile`, which only count when no `.gitignore`
+    /// has a say.
+    global: Vec<PatternList>,
+}
+
+impl IgnoreRules {
+    /// The rules of the `.gitignore` files in `worktree`, and of `info/exclude` and
+    /// `core.excludesFile` in `repo`.
+    pub fn load(repo: &Repository, worktree: &Path) -> anyhow::Result<Self> {
+        let exclude = repo.files.read("info/exclude")?;
+        Self::with_excludes(worktree, exclude, &repo.config()?)
+    }
+
+    /// Like [`IgnoreRules::load`], for the repository at `dot_git_path` on disk.
+    pub fn open(dot_git_path: &Path, worktree: &Path) -> anyhow::Result<Self> {
+        let exclude = fs::read(dot_git_path.join("info/exclude")).ok();
+        let config = match fs::read_to_string(dot_git_path.join("config")) {
+            Ok(contents) => GitConfig::parse(&contents).context("parse config")?,
+            Err(_) => GitConfig::default(),
+        };
+        Self::with_excludes(worktree, exclude, &config)
+    }
+
+    fn with_excludes(
+        worktree: &Path,
+        exclude: Option<Vec<u8>>,
+        config: &GitConfig,
+    ) -> anyhow::Result<Self> {
+        let mut rules = Self::default();
+        if let Some(contents) = exclude {
+            rules.global.push(PatternList::parse(Vec::new(), &contents));
+        }
+        if let Some(contents) = excludes_file(config).and_then(|file| fs::read(file).ok()) {
+            rules.global.push(PatternList::parse(Vec::new(), &contents));
+        }
+        rules.read_gitignores(worktree, &[])?;
+        Ok(rules)
+    }
+
+    /// Reads the `.gitignore` of the directory `dir` and of the directories below it that
+    /// aren't ignored, since git never looks inside those.
+    fn read_gitignores(&mut self, worktree: &Path, dir: &[u8]) -> anyhow::Result<()> {
+        let path = disk_path(worktree, dir);
+        if let Ok(contents) = fs::read(path.join(".gitignore")) {
+            self.per_directory
+                .push(PatternList::parse(dir.to_vec(), &contents));
+        }
+        let entries =
+            fs::read_dir(&path).with_context(|| format!("open directory {}", path.display()))?;
+        for entry in entries {
+            let entry =
+                entry.with_context(|| format!("bad directory entry in {}", path.display()))?;
+            let name = entry.file_name();
+            if name == ".git" || !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let mut subdir = dir.to_vec();
+            subdir.extend(name.as_bytes());
+            if self.check(&subdir, true) == Some(true) {
+                continue;
+            }
+            subdir.push(b'/');
+            self.read_gitignores(worktree, &subdir)?;
+        }
+        Ok(())
+    }
+
+    /// Whether `path`, a directory if `is_dir`, is ignored, by itself or through one of the
+    /// directories it is in. A `!` pattern can't bring back a path inside an ignored
+    /// directory.
+    pub fn is_ignored(&self, path: &[u8], is_dir: bool) -> bool {
+        for (i, _) in path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+            if self.check(&path[..i], true) == Some(true) {
+                return true;
+            }
+        }
+        self.check(path, is_dir) == Some(true)
+    }
+
+    /// What the ignore file with the highest priority that has a pattern for `path` says: the
+    /// deepest `.gitignore`, then `info/exclude`, then `core.excludesFile`.
+    fn check(&self, path: &[u8], is_dir: bool) -> Option<bool> {
+        self.per_directory
+            .iter()
+            .rev()
+            .chain(&self.global)
+            .find_map(|list| list.matches(path, is_dir))
+    }
+}
+
+/// `core.excludesFile`, by default `git/ignore` in the XDG config directory.
+fn excludes_file(config: &GitConfig) -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    match config.get("core.excludesFile") {
+        Some(file) => match (file.strip_prefix("~/"), home) {
+            (Some(file), Some(home)) => Some(home.join(file)),
+            _ => Some(PathBuf::from(file)),
+        },
+        None => match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => Some(PathBuf::from(dir).join("git/ignore")),
+            None => Some(home?.join(".config/git/ignore")),
+        },
+    }
+}
+
+/// Trailing spaces are dropped unless escaped with a backslash.
+fn trim_trailing_spaces(line: &[u8]) -> &[u8] {
+    let mut end = line.len();
+    while end > 0 && line[end - 1] == b' ' {
+        if end > 1 && line[end - 2] == b'\\' {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let dot_git_path = worktree.join(".git");
+        fs::create_dir_all(dot_git_path.join("info"))?;
+        fs::create_dir_all(worktree.join("src/generated"))?;
+        fs::create_dir_all(worktree.join("target/debug"))?;
+        fs::write(dot_git_path.join("info/exclude"), "*.swp\n")?;
+        fs::write(
+            dot_git_path.join("config"),
+            format!(
+                "[core]\n\texcludesFile = {}\n",
+                worktree.join("global-ignore").display()
+            ),
+        )?;
+        fs::write(worktree.join("global-ignore"), ".DS_Store\n*.tmp\n")?;
+        fs::write(
+            worktree.join(".gitignore"),
+            "# build output\n/target/\n*.log\n!keep.log\ndocs/**/*.html\n",
+        )?;
+        fs::write(worktree.join("src/.gitignore"), "generated/\n!*.tmp\n")?;
+        // never read, being inside an ignored directory
+        fs::write(worktree.join("target/.gitignore"), "!*\n")?;
+
+        let rules = IgnoreRules::open(&dot_git_path, worktree)?;
+        assert!(rules.is_ignored(b"target", true));
+        assert!(rules.is_ignored(b"target/debug/app", false));
+        assert!(!rules.is_ignored(b"src/target", true));
+        assert!(rules.is_ignored(b"src/debug.log", false));
+        assert!(!rules.is_ignored(b"src/keep.log", false));
+        assert!(rules.is_ignored(b"docs/html/a/b.html", false));
+        assert!(rules.is_ignored(b"docs/b.html", false));
+        assert!(!rules.is_ignored(b"other/docs/b.html", false));
+        assert!(rules.is_ignored(b"src/generated/mod.rs", false));
+        assert!(!rules.is_ignored(b"generated", true));
+        assert!(rules.is_ignored(b"a/b/file.swp", false));
+        assert!(rules.is_ignored(b".DS_Store", false));
+        assert!(rules.is_ignored(b"scratch.tmp", false));
+        // a .gitignore outranks the excludes files
+        assert!(!rules.is_ignored(b"src/scratch.tmp", false));
+        Ok(())
+    }
+}
@@ -0,0 +1,1046 @@
+use std::{
+    fmt,
+    fs::{self, Metadata},
+    os::unix::fs::MetadataExt,
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::hash::{HashAlgorithm, ObjectId};
+
+const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const NAME_MASK: u16 = 0x0fff;
+const EXTENDED_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_INTENT_TO_ADD: u16 = 0x2000;
+
+/// Optional extensions that only cache information about the index itself or the worktree,
+/// which would go stale as soon as the entries change.
+const DROPPED_EXTENSIONS: [&[u8; 4]; 4] = [b"EOIE", b"IEOT", b"UNTR", b"FSMN"];
+
+/// The `stat(2)` fields git records to tell whether a file changed without rehashing it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatData {
+    pub ctime: u32,
+    pub ctime_nsec: u32,
+    pub mtime: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// Truncated to 32 bits.
+    pub size: u32,
+}
+
+/// One path in the index, at one merge stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub stat: StatData,
+    pub mode: u32,
+    pub hash: ObjectId,
+    /// 0 for a normal entry, 1 to 3 for the base, ours and theirs sides of a conflict.
+    pub stage: u8,
+    pub assume_valid: bool,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    /// Relative to the top of the working tree, separated by `/`.
+    pub path: Vec<u8>,
+}
+
+impl StatData {
+    /// What git records for a file with `metadata`, each field truncated to 32 bits.
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        Self {
+            ctime: metadata.ctime() as u32,
+            ctime_nsec: metadata.ctime_nsec() as u32,
+            mtime: metadata.mtime() as u32,
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+}
+
+/// When the index file of the repository at `dot_git_path` was last written, `None` when
+/// there is none.
+pub fn index_time(dot_git_path: &Path) -> Option<(u32, u32)> {
+    fs::metadata(dot_git_path.join("index"))
+        .ok()
+        .map(|metadata| (metadata.mtime() as u32, metadata.mtime_nsec() as u32))
+}
+
+impl IndexEntry {
+    /// Whether a file with `stat` can be taken to still hold what the entry has without
+    /// hashing it. Besides matching, the stat data has to be older than the index written at
+    /// `index_time`: a file changed in the same instant could look the same and be racily
+    /// clean.
+    pub fn is_stat_clean(&self, stat: &StatData, index_time: Option<(u32, u32)>) -> bool {
+        *stat == self.stat
+            && index_time.is_some_and(|time| (self.stat.mtime, self.stat.mtime_nsec) < time)
+    }
+
+    /// A stage 0 entry for `path`, which was hashed as `hash` when it had `metadata`.
+    pub fn new(path: Vec<u8>, mode: u32, hash: ObjectId, metadata: &Metadata) -> Self {
+        Self {
+            stat: StatData::from_metadata(metadata),
+            mode,
+            hash,
+            stage: 0,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path,
+        }
+    }
+
+    /// Whether the entry has flags that only version 3 and later can record.
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+impl fmt::Display for IndexEntry {
+    /// The entry as `ls-files --stage` shows it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:06o} {} {}\t{}",
+            self.mode,
+            self.hash,
+            self.stage,
+            String::from_utf8_lossy(&self.path)
+        )
+    }
+}
+
+/// The `TREE` extension: the tree ids of directories whose entries haven't changed since
+/// they were last written out, so they don't have to be hashed again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheTree {
+    /// The directory's name within its parent, empty for the root.
+    pub name: Vec<u8>,
+    /// How many index entries the tree covers, or -1 once it has been invalidated.
+    pub entry_count: i32,
+    /// `None` when invalidated.
+    pub hash: Option<ObjectId>,
+    pub subtrees: Vec<CacheTree>,
+}
+
+/// An entry of the `REUC` extension: the conflicting stages a path had before the conflict
+/// was resolved, so that it can be recreated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveUndo {
+    pub path: Vec<u8>,
+    /// The modes of stages 1 to 3, 0 for a stage that didn't exist.
+    pub modes: [u32; 3],
+    pub hashes: [Option<ObjectId>; 3],
+}
+
+/// The `link` extension of a split index, whose entries are changes to a shared index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitIndexLink {
+    /// Names the shared index, `.git/sharedindex.<id>`.
+    pub shared_index: ObjectId,
+    /// Positions in the shared index of entries that have been removed.
+    pub delete: Vec<usize>,
+    /// Positions in the shared index of entries replaced by the first entries of this index,
+    /// in order.
+    pub replace: Vec<usize>,
+}
+
+/// `.git/index`, also known as the dircache or the staging area.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    /// 2, 3 or 4. Version 2 is upgraded to 3 when written if any entry has extended flags.
+    pub version: u32,
+    /// Sorted by path, then stage.
+    pub entries: Vec<IndexEntry>,
+    pub cache_tree: Option<CacheTree>,
+    pub resolve_undo: Vec<ResolveUndo>,
+    pub link: Option<SplitIndexLink>,
+    /// Optional extensions that aren't interpreted here, written back as they were read.
+    pub other_extensions: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+            resolve_undo: Vec::new(),
+            link: None,
+            other_extensions: Vec::new(),
+        }
+    }
+}
+
+impl Index {
+    /// Where the entry for `path` at `stage` is, or where it would be inserted.
+    fn position(&self, path: &[u8], stage: u8) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|entry| (&entry.path[..], entry.stage).cmp(&(path, stage)))
+    }
+
+    /// The stage 0 entry for `path`.
+    pub fn get(&self, path: &[u8]) -> Option<&IndexEntry> {
+        self.position(path, 0).ok().map(|i| &self.entries[i])
+    }
+
+    /// The stage 0 entry for `path`, to update in place.
+    pub fn get_mut(&mut self, path: &[u8]) -> Option<&mut IndexEntry> {
+        self.position(path, 0).ok().map(|i| &mut self.entries[i])
+    }
+
+    /// The entries of `path` at every stage.
+    pub fn stages(&self, path: &[u8]) -> &[IndexEntry] {
+        let start = self.position(path, 0).unwrap_or_else(|i| i);
+        let len = self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.path == path)
+            .count();
+        &self.entries[start..start + len]
+    }
+
+    /// Whether any path still has conflicting stages.
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.stage != 0)
+    }
+
+    /// Stages `entry`. A stage 0 entry resolves any conflict on its path, remembering the
+    /// conflicting stages in the resolve-undo list. Entries that a file can't coexist with,
+    /// such as a file where the entry needs a directory, are removed.
+    pub fn add(&mut self, entry: IndexEntry) {
+        self.invalidate(&entry.path);
+        if entry.stage == 0 {
+            let conflicts = self.stages(&entry.path);
+            if conflicts.iter().any(|conflict| conflict.stage != 0) {
+                let mut undo = ResolveUndo {
+                    path: entry.path.clone(),
+                    modes: [0; 3],
+                    hashes: [None; 3],
+                };
+                for conflict in conflicts.iter().filter(|conflict| conflict.stage != 0) {
+                    let stage = conflict.stage as usize - 1;
+                    undo.modes[stage] = conflict.mode;
+                    undo.hashes[stage] = Some(conflict.hash);
+                }
+                self.resolve_undo.retain(|other| other.path != entry.path);
+                self.resolve_undo.push(undo);
+                self.resolve_undo.sort_by(|a, b| a.path.cmp(&b.path));
+            }
+            self.remove(&entry.path);
+        }
+
+        let mut dir = entry.path.clone();
+        dir.push(b'/');
+        let is_parent = |other: &IndexEntry| {
+            entry.path.starts_with(&other.path) && entry.path.get(other.path.len()) == Some(&b'/')
+        };
+        let replaced: Vec<Vec<u8>> = self
+            .entries
+            .iter()
+            .filter(|other| other.path.starts_with(&dir) || is_parent(other))
+            .map(|other| other.path.clone())
+            .collect();
+        for path in replaced {
+            self.remove(&path);
+        }
+
+        match self.position(&entry.path, entry.stage) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Unstages `path` at every stage, returning whether it was in the index.
+    pub fn remove(&mut self, path: &[u8]) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        if self.entries.len() == len {
+            return false;
+        }
+        self.invalidate(path);
+        true
+    }
+
+    /// Marks the cached trees of the directories leading to `path` as out of date.
+    fn invalidate(&mut self, path: &[u8]) {
+        let Some(mut tree) = self.cache_tree.as_mut() else {
+            return;
+        };
+        let mut components = path.split(|&b| b == b'/').peekable();
+        loop {
+            tree.entry_count = -1;
+            tree.hash = None;
+            let Some(component) = components.next() else {
+                break;
+            };
+            if components.peek().is_none() {
+                break;
+            }
+            match tree.subtrees.iter_mut().find(|sub| sub.name == component) {
+                Some(subtree) => tree = subtree,
+                None => break,
+            }
+        }
+    }
+
+    /// Parses the contents of an index file, checking its trailing checksum.
+    pub fn parse(data: &[u8], hash: HashAlgorithm) -> anyhow::Result<Self> {
+        let hash_len = hash.raw_len();
+        ensure!(data.len() >= 12 + hash_len, "index is truncated");
+        let (content, checksum) = data.split_at(data.len() - hash_len);
+        // index.skipHash leaves the checksum zeroed
+        if checksum.iter().any(|&b| b != 0) {
+            ensure!(
+                hash.hash(content).as_bytes() == checksum,
+                "index checksum mismatch"
+            );
+        }
+
+        let mut reader = Reader::new(content);
+        ensure!(
+            reader.bytes(4)? == INDEX_SIGNATURE,
+            "index has a bad signature"
+        );
+        let version = reader.u32()?;
+        ensure!(
+            (2..=4).contains(&version),
+            "unsupported index version {version}"
+        );
+        let count = reader.u32()? as usize;
+        let mut index = Index {
+            version,
+            ..Default::default()
+        };
+
+        let mut previous_path: Vec<u8> = Vec::new();
+        for _ in 0..count {
+            let entry = parse_entry(&mut reader, version, hash, &previous_path)
+                .with_context(|| format!("parse index entry {}", index.entries.len()))?;
+            previous_path.clone_from(&entry.path);
+            index.entries.push(entry);
+        }
+
+        while !reader.is_empty() {
+            let signature: [u8; 4] = reader.bytes(4)?.try_into()?;
+            let len = reader.u32()? as usize;
+            let mut data = Reader::new(reader.bytes(len)?);
+            let name = String::from_utf8_lossy(&signature).to_string();
+            match &signature {
+                b"TREE" => {
+                    if !data.is_empty() {
+                        index.cache_tree = Some(
+                            parse_cache_tree(&mut data, hash).context("parse TREE extension")?,
+                        );
+                    }
+                }
+                b"REUC" => {
+                    while !data.is_empty() {
+                        index.resolve_undo.push(
+                            parse_resolve_undo(&mut data, hash).context("parse REUC extension")?,
+                        );
+                    }
+                }
+                b"link" => {
+                    index.link = Some(parse_link(&mut data, hash).context("parse link extension")?);
+                }
+                _ if DROPPED_EXTENSIONS.contains(&&signature) => {}
+                // extensions starting with an uppercase letter are optional
+                _ if signature[0].is_ascii_uppercase() => {
+                    index.other_extensions.push((signature, data.data.to_vec()));
+                }
+                _ => bail!("unsupported index extension '{name}'"),
+            }
+        }
+        Ok(index)
+    }
+
+    /// Serializes the index, including the trailing checksum.
+    pub fn to_bytes(&self, hash: HashAlgorithm) -> anyhow::Result<Vec<u8>> {
+        let version = if self.version == 2 && self.entries.iter().any(IndexEntry::is_extended) {
+            3
+        } else {
+            self.version
+        };
+        ensure!(
+            (2..=4).contains(&version),
+            "unsupported index version {version}"
+        );
+
+        let mut out = Vec::new();
+        out.extend(INDEX_SIGNATURE);
+        out.extend(version.to_be_bytes());
+        out.extend((self.entries.len() as u32).to_be_bytes());
+        let mut previous_path: &[u8] = &[];
+        for entry in &self.entries {
+            ensure!(
+                entry.hash.algorithm() == hash,
+                "index entry {} isn't a {hash} id",
+                String::from_utf8_lossy(&entry.path)
+            );
+            write_entry(&mut out, entry, version, previous_path);
+            previous_path = &entry.path;
+        }
+
+        if let Some(link) = &self.link {
+            let mut data = link.shared_index.as_bytes().to_vec();
+            write_ewah(&mut data, &link.delete);
+            write_ewah(&mut data, &link.replace);
+            write_extension(&mut out, b"link", &data);
+        }
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut data = Vec::new();
+            write_cache_tree(&mut data, cache_tree);
+            write_extension(&mut out, b"TREE", &data);
+        }
+        if !self.resolve_undo.is_empty() {
+            let mut data = Vec::new();
+            for undo in &self.resolve_undo {
+                data.extend(&undo.path);
+                data.push(0);
+                for mode in undo.modes {
+                    data.extend(format!("{mode:o}").as_bytes());
+                    data.push(0);
+                }
+                for (mode, hash) in undo.modes.iter().zip(&undo.hashes) {
+                    if *mode != 0 {
+                        let hash = hash.context("resolve-undo stage is missing its object")?;
+                        data.extend(hash.as_bytes());
+                    }
+                }
+            }
+            write_extension(&mut out, b"REUC", &data);
+        }
+        for (signature, data) in &self.other_extensions {
+            write_extension(&mut out, signature, data);
+        }
+
+        let checksum = hash.hash(&out);
+        out.extend(checksum.as_bytes());
+        Ok(out)
+    }
+
+    /// Turns a split index into a complete one by applying its entries to `shared`, the
+    /// index its `link` extension names.
+    pub fn merge_shared(&mut self, shared: Index) -> anyhow::Result<()> {
+        let Some(link) = self.link.take() else {
+            return Ok(());
+        };
+        let mut entries: Vec<Option<IndexEntry>> = shared.entries.into_iter().map(Some).collect();
+        let mut changes = std::mem::take(&mut self.entries).into_iter();
+        for &position in &link.replace {
+            let mut replacement = changes
+                .next()
+                .context("split index has fewer entries than replacements")?;
+            let original = entries
+                .get_mut(position)
+                .and_then(Option::take)
+                .context("split index replaces an entry the shared index doesn't have")?;
+            // replacements only store the path when it changed
+            if replacement.path.is_empty() {
+                replacement.path = original.path;
+            }
+            entries[position] = Some(replacement);
+        }
+        for &position in &link.delete {
+            ensure!(
+                position < entries.len(),
+                "split index deletes an entry the shared index doesn't have"
+            );
+            entries[position] = None;
+        }
+        self.entries = entries.into_iter().flatten().chain(changes).collect();
+        self.entries
+            .sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+        Ok(())
+    }
+}
+
+/// Reads big-endian integers and terminated strings from an index.
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, at: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        ensure!(self.at + n <= self.data.len(), "index is truncated");
+        let bytes = &self.data[self.at..self.at + n];
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn hash(&mut self, hash: HashAlgorithm) -> anyhow::Result<ObjectId> {
+        ObjectId::from_bytes(self.bytes(hash.raw_len())?)
+    }
+
+    /// The bytes up to `terminator`, consuming the terminator too.
+    fn until(&mut self, terminator: u8) -> anyhow::Result<&'a [u8]> {
+        let len = self.data[self.at..]
+            .iter()
+            .position(|&b| b == terminator)
+            .context("index is truncated")?;
+        let bytes = &self.data[self.at..self.at + len];
+        self.at += len + 1;
+        Ok(bytes)
+    }
+
+    /// A number written out in ASCII with the given radix, up to `terminator`.
+    fn number(&mut self, terminator: u8, radix: u32) -> anyhow::Result<i64> {
+        let text = String::from_utf8_lossy(self.until(terminator)?).to_string();
+        i64::from_str_radix(&text, radix).with_context(|| format!("invalid number '{text}'"))
+    }
+
+    /// The variable length integers of version 4 path compression, the same encoding packs
+    /// use for delta offsets.
+    fn varint(&mut self) -> anyhow::Result<usize> {
+        let mut byte = self.bytes(1)?[0];
+        let mut value = (byte & 0x7f) as usize;
+        while byte & 0x80 != 0 {
+            byte = self.bytes(1)?[0];
+            value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+        }
+        Ok(value)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.at == self.data.len()
+    }
+}
+
+fn parse_entry(
+    reader: &mut Reader,
+    version: u32,
+    hash: HashAlgorithm,
+    previous_path: &[u8],
+) -> anyhow::Result<IndexEntry> {
+    let start = reader.at;
+    let ctime = reader.u32()?;
+    let ctime_nsec = reader.u32()?;
+    let mtime = reader.u32()?;
+    let mtime_nsec = reader.u32()?;
+    let dev = reader.u32()?;
+    let ino = reader.u32()?;
+    let mode = reader.u32()?;
+    let stat = StatData {
+        ctime,
+        ctime_nsec,
+        mtime,
+        mtime_nsec,
+        dev,
+        ino,
+        uid: reader.u32()?,
+        gid: reader.u32()?,
+        size: reader.u32()?,
+    };
+    let id = reader.hash(hash)?;
+    let flags = reader.u16()?;
+    let extended_flags = if flags & FLAG_EXTENDED != 0 {
+        ensure!(version >= 3, "version {version} entries can't be extended");
+        reader.u16()?
+    } else {
+        0
+    };
+
+    let path = if version == 4 {
+        let strip = reader.varint()?;
+        ensure!(
+            strip <= previous_path.len(),
+            "path compression strips more than the previous path"
+        );
+        let mut path = previous_path[..previous_path.len() - strip].to_vec();
+        path.extend(reader.until(0)?);
+        path
+    } else {
+        let path_start = reader.at;
+        let path = reader.until(0)?.to_vec();
+        // entries are NUL padded to a multiple of eight bytes
+        let len = (path_start - start + path.len() + 8) & !7;
+        reader.at = start + len;
+        ensure!(reader.at <= reader.data.len(), "index is truncated");
+        path
+    };
+    let name_len = (flags & NAME_MASK) as usize;
+    ensure!(
+        name_len == path.len().min(NAME_MASK as usize),
+        "path length doesn't match the entry's flags"
+    );
+
+    Ok(IndexEntry {
+        stat,
+        mode,
+        hash: id,
+        stage: ((flags >> FLAG_STAGE_SHIFT) & 0x3) as u8,
+        assume_valid: flags & FLAG_ASSUME_VALID != 0,
+        skip_worktree: extended_flags & EXTENDED_SKIP_WORKTREE != 0,
+        intent_to_add: extended_flags & EXTENDED_INTENT_TO_ADD != 0,
+        path,
+    })
+}
+
+fn write_entry(out: &mut Vec<u8>, entry: &IndexEntry, version: u32, previous_path: &[u8]) {
+    let start = out.len();
+    let stat = &entry.stat;
+    for field in [
+        stat.ctime,
+        stat.ctime_nsec,
+        stat.mtime,
+        stat.mtime_nsec,
+        stat.dev,
+        stat.ino,
+        entry.mode,
+        stat.uid,
+        stat.gid,
+        stat.size,
+    ] {
+        out.extend(field.to_be_bytes());
+    }
+    out.extend(entry.hash.as_bytes());
+    let extended = version >= 3 && entry.is_extended();
+    let mut flags = entry.path.len().min(NAME_MASK as usize) as u16
+        | (entry.stage as u16 & 0x3) << FLAG_STAGE_SHIFT;
+    if entry.assume_valid {
+        flags |= FLAG_ASSUME_VALID;
+    }
+    if extended {
+        flags |= FLAG_EXTENDED;
+    }
+    out.extend(flags.to_be_bytes());
+    if extended {
+        let mut extended_flags = 0;
+        if entry.skip_worktree {
+            extended_flags |= EXTENDED_SKIP_WORKTREE;
+        }
+        if entry.intent_to_add {
+            extended_flags |= EXTENDED_INTENT_TO_ADD;
+        }
+        out.extend(extended_flags.to_be_bytes());
+    }
+
+    if version == 4 {
+        let common = previous_path
+            .iter()
+            .zip(&entry.path)
+            .take_while(|(a, b)| a == b)
+            .count();
+        write_varint(out, previous_path.len() - common);
+        out.extend(&entry.path[common..]);
+        out.push(0);
+    } else {
+        out.extend(&entry.path);
+        let len = (out.len() - start + 8) & !7;
+        out.resize(start + len, 0);
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes.reverse();
+    out.extend(bytes);
+}
+
+fn write_extension(out: &mut Vec<u8>, signature: &[u8; 4], data: &[u8]) {
+    out.extend(signature);
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend(data);
+}
+
+/// Cache tree entries are stored depth first, each followed by its subtrees.
+fn parse_cache_tree(reader: &mut Reader, hash: HashAlgorithm) -> anyhow::Result<CacheTree> {
+    let name = reader.until(0)?.to_vec();
+    let entry_count = i32::try_from(reader.number(b' ', 10)?)?;
+    let subtree_count = usize::try_from(reader.number(b'\n', 10)?)?;
+    let id = if entry_count >= 0 {
+        Some(reader.hash(hash)?)
+    } else {
+        None
+    };
+    let subtrees = (0..subtree_count)
+        .map(|_| parse_cache_tree(reader, hash))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(CacheTree {
+        name,
+        entry_count,
+        hash: id,
+        subtrees,
+    })
+}
+
+fn write_cache_tree(out: &mut Vec<u8>, tree: &CacheTree) {
+    out.extend(&tree.name);
+    out.push(0);
+    out.extend(format!("{} {}\n", tree.entry_count, tree.subtrees.len()).as_bytes());
+    if let (true, Some(hash)) = (tree.entry_count >= 0, &tree.hash) {
+        out.extend(hash.as_bytes());
+    }
+    for subtree in &tree.subtrees {
+        write_cache_tree(out, subtree);
+    }
+}
+
+fn parse_resolve_undo(reader: &mut Reader, hash: HashAlgorithm) -> anyhow::Result<ResolveUndo> {
+    let path = reader.until(0)?.to_vec();
+    let mut modes = [0; 3];
+    for mode in &mut modes {
+        *mode = u32::try_from(reader.number(0, 8)?)?;
+    }
+    let mut hashes = [None; 3];
+    for (mode, id) in modes.iter().zip(&mut hashes) {
+        if *mode != 0 {
+            *id = Some(reader.hash(hash)?);
+        }
+    }
+    Ok(ResolveUndo {
+        path,
+        modes,
+        hashes,
+    })
+}
+
+fn parse_link(reader: &mut Reader, hash: HashAlgorithm) -> anyhow::Result<SplitIndexLink> {
+    let shared_index = reader.hash(hash)?;
+    let (delete, replace) = if reader.is_empty() {
+        (Vec::new(), Vec::new())
+    } else {
+        (read_ewah(reader)?, read_ewah(reader)?)
+    };
+    Ok(SplitIndexLink {
+        shared_index,
+        delete,
+        replace,
+    })
+}
+
+/// Decodes an EWAH compressed bitmap into the positions of its set bits. The bitmap is a
+/// sequence of marker words, each giving a run of identical words followed by a number of
+/// literal words.
+fn read_ewah(reader: &mut Reader) -> anyhow::Result<Vec<usize>> {
+    let _bit_len = reader.u32()?;
+    let word_count = reader.u32()? as usize;
+    let mut words = Vec::with_capacity(word_count);
+    for _ in 0..word_count {
+        words.push(reader.u64()?);
+    }
+    let _last_marker = reader.u32()?;
+
+    let mut positions = Vec::new();
+    let mut bit = 0;
+    let mut words = words.into_iter();
+    while let Some(marker) = words.next() {
+        let run_bit = marker & 1 != 0;
+        let run_len = ((marker >> 1) & 0xffff_ffff) as usize;
+        let literal_count = (marker >> 33) as usize;
+        if run_bit {
+            positions.extend(bit..bit + run_len * 64);
+        }
+        bit += run_len * 64;
+        for _ in 0..literal_count {
+            let word = words.next().context("EWAH bitmap is truncated")?;
+            positions.extend((0..64).filter(|i| word & (1 << i) != 0).map(|i| bit + i));
+            bit += 64;
+        }
+    }
+    Ok(positions)
+}
+
+/// Encodes sorted positions as an EWAH bitmap made only of literal words.
+fn write_ewah(out: &mut Vec<u8>, positions: &[usize]) {
+    let bit_len = positions.last().map_or(0, |last| last + 1);
+    let mut literals = vec![0u64; positions.last().map_or(0, |last| last / 64 + 1)];
+    for &position in positions {
+        literals[position / 64] |= 1 << (position % 64);
+    }
+    let mut words = Vec::new();
+    let mut last_marker = 0;
+    // a marker's literal count has 31 bits
+    for chunk in literals.chunks(0x7fff_ffff) {
+        last_marker = words.len();
+        words.push((chunk.len() as u64) << 33);
+        words.extend(chunk);
+    }
+    out.extend((bit_len as u32).to_be_bytes());
+    out.extend((words.len() as u32).to_be_bytes());
+    for word in words {
+        out.extend(word.to_be_bytes());
+    }
+    out.extend((last_marker as u32).to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, stage: u8, hash: &str) -> anyhow::Result<IndexEntry> {
+        Ok(IndexEntry {
+            stat: StatData {
+                mtime: 1_700_000_000,
+                size: 12,
+                ..Default::default()
+            },
+            mode: 0o100644,
+            hash: ObjectId::from_hex(hash)?,
+            stage,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path: path.as_bytes().to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_parse_fixture() -> anyhow::Result<()> {
+        let data = std::fs::read("tests/fixtures/simple-app/dot-git/index")?;
+        let index = Index::parse(&data, HashAlgorithm::Sha1)?;
+        assert_eq!(index.version, 2);
+        let paths: Vec<&[u8]> = index.entries.iter().map(|e| &e.path[..]).collect();
+        assert_eq!(paths, [&b".gitignore"[..], b"Cargo.toml", b"src/main.rs"]);
+        let cargo_toml = &index.entries[1];
+        assert_eq!(cargo_toml.mode, 0o100644);
+        assert_eq!(cargo_toml.stat.size, 179);
+        assert_eq!(cargo_toml.stat.uid, 501);
+        assert_eq!(
+            cargo_toml.hash.to_string(),
+            "f195397afef8ad7a138507d1cf1c118d6e0d6dfc"
+        );
+
+        let cache_tree = index
+            .cache_tree
+            .as_ref()
+            .expect("fixture has a TREE extension");
+        assert_eq!(cache_tree.entry_count, 3);
+        assert_eq!(
+            cache_tree.hash.map(|hash| hash.to_string()).as_deref(),
+            Some("825ad6339808aa69dd0b2d487586a32fe4b6be17")
+        );
+        assert_eq!(cache_tree.subtrees.len(), 1);
+        assert_eq!(cache_tree.subtrees[0].name, b"src");
+        assert_eq!(cache_tree.subtrees[0].entry_count, 1);
+
+        assert_eq!(index.to_bytes(HashAlgorithm::Sha1)?, data);
+
+        let mut corrupt = data.clone();
+        corrupt[20] ^= 1;
+        assert!(Index::parse(&corrupt, HashAlgorithm::Sha1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip_versions() -> anyhow::Result<()> {
+        let mut index = Index {
+            entries: vec![
+                entry("conflicted", 1, "df967b96a579e45a18b8251732d16804b2e56a55")?,
+                entry("conflicted", 2, "ba2906d0666cf726c7eaadd2cd3db615dedfdf3a")?,
+                entry("conflicted", 3, "2299c37978265a95cbe835a4b0f0bbf15aad5549")?,
+                entry("src/lib.rs", 0, "e7a11a969c037e00a796aafeff6258501ec15e9a")?,
+                entry("src/main.rs", 0, "e7a11a969c037e00a796aafeff6258501ec15e9a")?,
+            ],
+            ..Default::default()
+        };
+        index.entries[3].skip_worktree = true;
+        index.entries[4].intent_to_add = true;
+        index.entries[4].assume_valid = true;
+        index.cache_tree = Some(CacheTree {
+            name: Vec::new(),
+            entry_count: -1,
+            hash: None,
+            subtrees: vec![CacheTree {
+                name: b"src".to_vec(),
+                entry_count: 2,
+                hash: Some(ObjectId::from_hex(
+                    "305157a396c6858705a9cb625bab219053264ee4",
+                )?),
+                subtrees: Vec::new(),
+            }],
+        });
+        index.resolve_undo = vec![ResolveUndo {
+            path: b"resolved".to_vec(),
+            modes: [0o100644, 0, 0o100755],
+            hashes: [
+                Some(ObjectId::from_hex(
+                    "df967b96a579e45a18b8251732d16804b2e56a55",
+                )?),
+                None,
+                Some(ObjectId::from_hex(
+                    "2299c37978265a95cbe835a4b0f0bbf15aad5549",
+                )?),
+            ],
+        }];
+        index
+            .other_extensions
+            .push((*b"ZZZZ", b"kept as is".to_vec()));
+
+        // extended flags need at least version 3
+        let bytes = index.to_bytes(HashAlgorithm::Sha1)?;
+        let parsed = Index::parse(&bytes, HashAlgorithm::Sha1)?;
+        assert_eq!(parsed.version, 3);
+        assert_eq!(
+            Index {
+                version: 2,
+                ..parsed
+            },
+            index
+        );
+
+        index.version = 4;
+        let compressed = index.to_bytes(HashAlgorithm::Sha1)?;
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(Index::parse(&compressed, HashAlgorithm::Sha1)?, index);
+
+        let mut unknown = index.clone();
+        unknown.other_extensions = vec![(*b"zzzz", Vec::new())];
+        let bytes = unknown.to_bytes(HashAlgorithm::Sha1)?;
+        assert!(Index::parse(&bytes, HashAlgorithm::Sha1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_index() -> anyhow::Result<()> {
+        let blob = "e7a11a969c037e00a796aafeff6258501ec15e9a";
+        let changed = "f195397afef8ad7a138507d1cf1c118d6e0d6dfc";
+        let shared = Index {
+            entries: vec![
+                entry("a", 0, blob)?,
+                entry("b", 0, blob)?,
+                entry("c", 0, blob)?,
+            ],
+            ..Default::default()
+        };
+        let shared_bytes = shared.to_bytes(HashAlgorithm::Sha1)?;
+        let mut replacement = entry("", 0, changed)?;
+        replacement.stat.size = 40;
+        let split = Index {
+            entries: vec![replacement, entry("d", 0, blob)?],
+            link: Some(SplitIndexLink {
+                shared_index: HashAlgorithm::Sha1.hash(&shared_bytes[..shared_bytes.len() - 20]),
+                delete: vec![0],
+                replace: vec![2],
+            }),
+            ..Default::default()
+        };
+        let bytes = split.to_bytes(HashAlgorithm::Sha1)?;
+        let mut merged = Index::parse(&bytes, HashAlgorithm::Sha1)?;
+        assert_eq!(merged, split);
+
+        merged.merge_shared(shared)?;
+        assert_eq!(merged.link, None);
+        let summary: Vec<(String, String)> = merged
+            .entries
+            .iter()
+            .map(|e| {
+                (
+                    String::from_utf8_lossy(&e.path).to_string(),
+                    e.hash.to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("b".to_string(), blob.to_string()),
+                ("c".to_string(), changed.to_string()),
+                ("d".to_string(), blob.to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ewah() -> anyhow::Result<()> {
+        for positions in [vec![], vec![0], vec![3, 64, 65, 200]] {
+            let mut out = Vec::new();
+            write_ewah(&mut out, &positions);
+            assert_eq!(read_ewah(&mut Reader::new(&out))?, positions);
+        }
+        // a run of 64 set bits followed by a literal word with bit 1 set
+        let mut out = Vec::new();
+        out.extend(130u32.to_be_bytes());
+        out.extend(2u32.to_be_bytes());
+        out.extend(((1u64 << 33) | (1 << 1) | 1).to_be_bytes());
+        out.extend(2u64.to_be_bytes());
+        out.extend(0u32.to_be_bytes());
+        let expected: Vec<usize> = (0..64).chain([65]).collect();
+        assert_eq!(read_ewah(&mut Reader::new(&out))?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_and_remove() -> anyhow::Result<()> {
+        let blob = "e7a11a969c037e00a796aafeff6258501ec15e9a";
+        let resolved = "f195397afef8ad7a138507d1cf1c118d6e0d6dfc";
+        let mut index = Index {
+            entries: vec![
+                entry("a", 0, blob)?,
+                entry("dir/file", 0, blob)?,
+                entry("f", 1, blob)?,
+                entry("f", 2, blob)?,
+            ],
+            cache_tree: Some(CacheTree {
+                name: Vec::new(),
+                entry_count: 4,
+                hash: Some(ObjectId::from_hex(blob)?),
+                subtrees: vec![CacheTree {
+                    name: b"dir".to_vec(),
+                    entry_count: 1,
+                    hash: Some(ObjectId::from_hex(blob)?),
+                    subtrees: Vec::new(),
+                }],
+            }),
+            ..Default::default()
+        };
+
+        index.add(entry("f", 0, resolved)?);
+        assert_eq!(index.stages(b"f").len(), 1);
+        assert!(!index.has_conflicts());
+        assert_eq!(index.resolve_undo.len(), 1);
+        assert_eq!(index.resolve_undo[0].modes, [0o100644, 0o100644, 0]);
+        let cache_tree = index.cache_tree.as_ref().unwrap();
+        assert_eq!(cache_tree.entry_count, -1);
+        assert_eq!(cache_tree.subtrees[0].entry_count, 1);
+
+        // a file replaces the directory of the same name, and the other way around
+        index.add(entry("dir", 0, blob)?);
+        index.add(entry("a/nested", 0, blob)?);
+        let paths: Vec<&[u8]> = index.entries.iter().map(|e| &e.path[..]).collect();
+        assert_eq!(paths, [&b"a/nested"[..], b"dir", b"f"]);
+        assert_eq!(
+            index.get(b"f").map(|e| e.hash.to_string()).as_deref(),
+            Some(resolved)
+        );
+
+        assert!(index.remove(b"dir"));
+        assert!(!index.remove(b"dir"));
+        assert_eq!(index.get(b"dir"), None);
+        Ok(())
+    }
+}
@@ -1,6 +1,36 @@
+pub mod attributes;
+pub mod branch;
+pub mod checkout;
+pub mod clean;
+pub mod clone;
 pub mod commit;
 pub mod config;
+pub mod diff;
+pub mod diff_tree;
+pub mod fsck;
+pub mod gc;
 pub mod git;
+pub mod hash;
+pub mod ignore;
+pub mod index;
+pub mod log;
+pub mod merge;
 pub mod object;
+pub mod pack;
+pub mod pathspec;
+pub mod reflog;
+pub mod rename;
+pub mod repository;
+pub mod sha256;
+pub mod staging;
+pub mod status;
+pub mod store;
+pub mod submodule;
+pub mod switch;
+pub mod tag;
+#[cfg(test)]
 pub mod test;
 pub mod tree;
+pub mod walk;
+pub mod wildmatch;
+pub mod worktree;
@@ -0,0 +1,343 @@
+//! Showing commits: `log`, `show` and the mails `format-patch` writes.
+
+use std::{
+    collections::{BTreeSet, BinaryHeap},
+    io::Write,
+};
+
+use anyhow::Context;
+
+use crate::{
+    commit::{Commit, Signature},
+    diff::{tree_changes, write_patch, write_stat, write_summary, DiffOptions, FilePair, FileStat},
+    hash::ObjectId,
+    pathspec::Pathspec,
+    rename::{add_unmodified, detect_renames},
+    repository::Repository,
+    tree::tree_files,
+    walk::ancestors,
+};
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The date and time of `signature` in its own timezone, as year, month, day, weekday,
+/// hours, minutes and seconds.
+fn local_time(signature: &Signature) -> (i64, usize, i64, usize, i64, i64, i64) {
+    let sign = if signature.offset.starts_with('-') {
+        -1
+    } else {
+        1
+    };
+    let hours: i64 = signature.offset[1..3].parse().unwrap_or(0);
+    let minutes: i64 = signature.offset[3..5].parse().unwrap_or(0);
+    let time = signature.time + sign * (hours * 3600 + minutes * 60);
+    let (days, seconds) = (time.div_euclid(86400), time.rem_euclid(86400));
+    // from Howard Hinnant's days_from_civil algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    // the epoch was a Thursday
+    let weekday = (days + 4).rem_euclid(7) as usize;
+    (
+        year,
+        month as usize - 1,
+        day,
+        weekday,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+/// The date of `signature` the way `log` shows it: `Thu Oct 5 12:00:00 2023 +0200`.
+pub fn format_date(signature: &Signature) -> String {
+    let (year, month, day, weekday, hours, minutes, seconds) = local_time(signature);
+    format!(
+        "{} {} {day} {hours:02}:{minutes:02}:{seconds:02} {year} {}",
+        DAYS[weekday], MONTHS[month], signature.offset
+    )
+}
+
+/// The date of `signature` for a mail header: `Thu, 5 Oct 2023 12:00:00 +0200`.
+pub fn format_rfc2822_date(signature: &Signature) -> String {
+    let (year, month, day, weekday, hours, minutes, seconds) = local_time(signature);
+    format!(
+        "{}, {day} {} {year} {hours:02}:{minutes:02}:{seconds:02} {}",
+        DAYS[weekday], MONTHS[month], signature.offset
+    )
+}
+
+/// The commits reachable from `starts` but not from `excluded`, newest first by commit date.
+pub(crate) fn walk_commits(
+    repo: &Repository,
+    starts: &[ObjectId],
+    excluded: &[ObjectId],
+) -> anyhow::Result<Vec<ObjectId>> {
+    let mut hidden = BTreeSet::new();
+    for id in excluded {
+        hidden.extend(ancestors(repo, id)?);
+    }
+    let objects = repo.objects.as_ref();
+    let mut queue = BinaryHeap::new();
+    let mut seen = BTreeSet::new();
+    for id in starts {
+        if seen.insert(*id) {
+            let commit = Commit::read(objects, &id.to_string())?;
+            queue.push((commit.committer.time, *id, commit.parents));
+        }
+    }
+    let mut commits = Vec::new();
+    while let Some((_, id, parents)) = queue.pop() {
+        if !hidden.contains(&id) {
+            commits.push(id);
+        }
+        for parent in parents {
+            let parent: ObjectId = parent.parse()?;
+            if seen.insert(parent) {
+                let commit = Commit::read(objects, &parent.to_string())?;
+                queue.push((commit.committer.time, parent, commit.parents));
+            }
+        }
+    }
+    Ok(commits)
+}
+
+/// The `commit` line, author, date and indented message, the way `log` shows a commit.
+pub(crate) fn write_commit_header(
+    out: &mut dyn Write,
+    id: &ObjectId,
+    commit: &Commit,
+) -> anyhow::Result<()> {
+    writeln!(out, "commit {id}")?;
+    if commit.parents.len() > 1 {
+        let parents: Vec<&str> = commit.parents.iter().map(|parent| &parent[..7]).collect();
+        writeln!(out, "Merge: {}", parents.join(" "))?;
+    }
+    writeln!(
+        out,
+        "Author: {} <{}>",
+        commit.author.name, commit.author.email
+    )?;
+    writeln!(out, "Date:   {}", format_date(&commit.author))?;
+    writeln!(out)?;
+    for line in commit.message.trim_end().lines() {
+        writeln!(out, "    {line}")?;
+    }
+    Ok(())
+}
+
+/// The changes `commit` made to its first parent's tree, or to nothing for a root commit,
+/// each as the old and new side of a file, with renames found as `options` asks.
+pub(crate) fn commit_changes(
+    repo: &Repository,
+    commit: &Commit,
+    options: &DiffOptions,
+) -> anyhow::Result<Vec<FilePair>> {
+    let objects = repo.objects.as_ref();
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(Commit::read(objects, parent)?.tree),
+        None => None,
+    };
+    let mut pairs = tree_changes(
+        objects,
+        parent_tree.as_deref(),
+        Some(&commit.tree),
+        &Pathspec::default(),
+    )?;
+    if let (Some(renames), Some(parent_tree)) = (&options.renames, &parent_tree) {
+        if renames.harder {
+            add_unmodified(objects, &mut pairs, tree_files(objects, parent_tree)?)?;
+        }
+    }
+    Ok(match &options.renames {
+        Some(renames) => detect_renames(pairs, renames).0,
+        None => pairs,
+    })
+}
+
+/// Writes the patch of every file `commit` changed. Merges show nothing, the way git shows
+/// merges that needed no manual resolution.
+pub(crate) fn write_commit_patch(
+    out: &mut dyn Write,
+    repo: &Repository,
+    commit: &Commit,
+    options: &DiffOptions,
+) -> anyhow::Result<()> {
+    if commit.parents.len() > 1 {
+        return Ok(());
+    }
+    for pair in commit_changes(repo, commit, options)? {
+        write_patch(out, &pair, options)?;
+    }
+    Ok(())
+}
+
+/// The subject of a commit message, its first paragraph on one line, and the rest.
+pub(crate) fn split_message(message: &str) -> (String, &str) {
+    let message = message.trim_start_matches('\n');
+    let (subject, body) = message.split_once("\n\n").unwrap_or((message, ""));
+    let subject = subject.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+    (subject, body.trim_start_matches('\n').trim_end())
+}
+
+/// A header value with non-ASCII text as an RFC 2047 encoded word.
+fn encode_header(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+    let mut encoded = String::from("=?UTF-8?q?");
+    for b in text.bytes() {
+        match b {
+            b' ' => encoded.push('_'),
+            b if b.is_ascii_alphanumeric() || b"!*+-/".contains(&b) => encoded.push(b as char),
+            b => encoded.push_str(&format!("={b:02X}")),
+        }
+    }
+    encoded.push_str("?=");
+    encoded
+}
+
+/// `line` wrapped at spaces to lines of at most 78 columns, later ones indented by a space
+/// as mail headers continue.
+fn fold_header(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for word in line.split(' ') {
+        if width > 0 && width + 1 + word.len() > 78 {
+            folded.push_str("\n ");
+            width = 1;
+        } else if width > 0 {
+            folded.push(' ');
+            width += 1;
+        }
+        folded.push_str(word);
+        width += word.len();
+    }
+    folded
+}
+
+/// The file name `format-patch` gives the `number`th patch: the subject with runs of other
+/// characters than letters, digits, `.` and `_` turned into dashes.
+pub(crate) fn patch_file_name(number: usize, message: &str) -> String {
+    let (subject, _) = split_message(message);
+    let mut name = String::new();
+    let mut space = false;
+    let mut chars = subject.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if space && !name.is_empty() {
+                name.push('-');
+            }
+            space = false;
+            name.push(c);
+            if c == '.' {
+                while chars.next_if_eq(&'.').is_some() {}
+            }
+        } else {
+            space = true;
+        }
+    }
+    let name = name.trim_end_matches(['.', '-']);
+    let mut name = format!("{number:04}-{name}");
+    name.truncate(64 - ".patch".len() - 1);
+    name + ".patch"
+}
+
+/// Writes `commit` as a mail carrying its patch, the way `format-patch` does. `number` is the
+/// patch's place in the series and the series length when there is more than one.
+pub(crate) fn write_mail(
+    out: &mut dyn Write,
+    repo: &Repository,
+    id: &ObjectId,
+    commit: &Commit,
+    number: Option<(usize, usize)>,
+    options: &DiffOptions,
+) -> anyhow::Result<()> {
+    let (subject, body) = split_message(&commit.message);
+    let prefix = match number {
+        Some((number, total)) => format!("[PATCH {number}/{total}]"),
+        None => "[PATCH]".to_string(),
+    };
+    writeln!(out, "From {id} Mon Sep 17 00:00:00 2001")?;
+    writeln!(
+        out,
+        "From: {} <{}>",
+        encode_header(&commit.author.name),
+        commit.author.email
+    )?;
+    writeln!(out, "Date: {}", format_rfc2822_date(&commit.author))?;
+    writeln!(
+        out,
+        "{}",
+        fold_header(&format!("Subject: {prefix} {}", encode_header(&subject)))
+    )?;
+    writeln!(out)?;
+    if !body.is_empty() {
+        writeln!(out, "{body}")?;
+    }
+    writeln!(out, "---")?;
+
+    let pairs = commit_changes(repo, commit, options).context("diff commit")?;
+    let stats: Vec<FileStat> = pairs
+        .iter()
+        .map(|pair| FileStat::new(pair, options))
+        .collect();
+    write_stat(out, &stats, 72)?;
+    for pair in &pairs {
+        write_summary(out, pair)?;
+    }
+    writeln!(out)?;
+    for pair in &pairs {
+        write_patch(out, pair, options)?;
+    }
+    writeln!(out, "-- \n{}\n", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatting() -> anyhow::Result<()> {
+        let signature = Signature::parse("A U Thor <author@example.com> 1696600000 -0500")?;
+        assert_eq!(format_date(&signature), "Fri Oct 6 08:46:40 2023 -0500");
+        assert_eq!(
+            format_rfc2822_date(&signature),
+            "Fri, 6 Oct 2023 08:46:40 -0500"
+        );
+        let leap_day = Signature::parse("A U Thor <author@example.com> 951782400 +0000")?;
+        assert_eq!(format_date(&leap_day), "Tue Feb 29 00:00:00 2000 +0000");
+
+        assert_eq!(
+            patch_file_name(2, "Say hello, world!\n\nMore.\n"),
+            "0002-Say-hello-world.patch"
+        );
+        assert_eq!(
+            patch_file_name(
+                1,
+                "This is a rather long subject line that should wrap somewhere around\n"
+            ),
+            "0001-This-is-a-rather-long-subject-line-that-should-wrap-.patch"
+        );
+        assert_eq!(
+            fold_header("Subject: [PATCH] This is a rather long subject line that should wrap somewhere around it"),
+            "Subject: [PATCH] This is a rather long subject line that should wrap somewhere\n around it"
+        );
+        Ok(())
+    }
+}
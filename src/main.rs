@@ -1,9 +1,18 @@
-use std::path::PathBuf;
+use std::{ffi::OsString, io::BufRead, path::PathBuf};
 
-use clap::command;
 use clap::Parser;
 use clap::Subcommand;
-use git_starter_rust::git::Git;
+use git_starter_rust::{
+    clean::CleanIgnored,
+    config::Config,
+    diff::{DiffAlgorithm, DiffFlags, DiffFormat},
+    git::{Git, HashObjectOptions},
+    rename::{parse_score, RenameOptions, DEFAULT_SCORE},
+    repository::Repository,
+    status::{StatusFormat, StatusOptions, UntrackedFiles},
+};
+
+const BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -14,18 +23,66 @@ struct Args {
 #[derive(Subcommand, Debug)]
 #[command(about, long_about = None)]
 enum Command {
-    Init,
+    Init {
+        #[clap(long, default_value = "sha1")]
+        object_format: String,
+    },
     CatFile {
         #[clap(short = 'p', long)]
         pretty_print: bool,
 
-        #[clap(name = "object-hash")]
-        object_hash: String,
+        /// Print the object named on each line of stdin, optionally in a custom format
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = BATCH_FORMAT)]
+        batch: Option<String>,
+
+        /// Like --batch but without the object contents
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = BATCH_FORMAT)]
+        batch_check: Option<String>,
+
+        /// With --batch or --batch-check, list every object instead of reading stdin
+        #[clap(long)]
+        batch_all_objects: bool,
+
+        /// Print the object's type
+        #[clap(short = 't', conflicts_with_all = ["pretty_print", "size", "exists"])]
+        show_type: bool,
+
+        /// Print the object's size
+        #[clap(short = 's', conflicts_with_all = ["pretty_print", "exists"])]
+        size: bool,
+
+        /// Exit with a non-zero status if the object doesn't exist
+        #[clap(short = 'e', conflicts_with = "pretty_print")]
+        exists: bool,
+
+        /// `<type> <object>` to print the object as that type, or just `<object>`
+        #[clap(
+            name = "object-hash",
+            num_args = 1..=2,
+            required_unless_present_any = ["batch", "batch_check"]
+        )]
+        object_hash: Vec<String>,
     },
     HashObject {
         #[clap(short = 'w')]
         write: bool,
-        file: PathBuf,
+        /// Kind of object to create
+        #[clap(short = 't', default_value = "blob")]
+        object_type: String,
+        /// Read the object from stdin
+        #[clap(long)]
+        stdin: bool,
+        /// Read file paths from stdin, one per line
+        #[clap(long, conflicts_with_all = ["stdin", "files"])]
+        stdin_paths: bool,
+        /// Don't check the contents are a valid object of its type
+        #[clap(long)]
+        literally: bool,
+        /// Hash as if the contents were at this path, applying its filters
+        #[clap(long)]
+        path: Option<PathBuf>,
+        #[clap(required_unless_present_any = ["stdin", "stdin_paths"])]
+        files: Vec<PathBuf>,
     },
     LsTree {
         #[clap(short = 'n', long)]
@@ -33,6 +90,209 @@ enum Command {
         #[clap(name = "tree-hash")]
         tree_hash: String,
     },
+    DiffTree {
+        /// Compare the trees inside changed directories too.
+        #[clap(short = 'r')]
+        recursive: bool,
+        /// Only show the kind of change and the path.
+        #[clap(long)]
+        name_status: bool,
+        /// Detect renames, of files at least this similar.
+        #[clap(
+            short = 'M',
+            long = "find-renames",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ""
+        )]
+        find_renames: Option<String>,
+        old: String,
+        new: String,
+    },
+    Diff {
+        /// Compare with the index instead of the worktree.
+        #[clap(long, visible_alias = "staged")]
+        cached: bool,
+        #[clap(long)]
+        stat: bool,
+        #[clap(long)]
+        numstat: bool,
+        #[clap(long)]
+        name_only: bool,
+        #[clap(long)]
+        name_status: bool,
+        /// Exit with 1 when there are differences.
+        #[clap(long)]
+        exit_code: bool,
+        #[command(flatten)]
+        diff: DiffArgs,
+        /// Up to two revisions, then paths to limit the comparison to.
+        args: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+    Show {
+        revision: Option<String>,
+        #[command(flatten)]
+        diff: DiffArgs,
+    },
+    Log {
+        /// Show each commit's patch.
+        #[clap(short = 'p', long)]
+        patch: bool,
+        revision: Option<String>,
+        #[command(flatten)]
+        diff: DiffArgs,
+    },
+    FormatPatch {
+        /// Where to write the patch files.
+        #[clap(short = 'o', long)]
+        output_directory: Option<PathBuf>,
+        /// Write the mails to the output instead of to files.
+        #[clap(long)]
+        stdout: bool,
+        #[command(flatten)]
+        diff: DiffArgs,
+        /// `-<n>`, a revision range, or the commit to start after.
+        #[clap(allow_negative_numbers = true)]
+        revisions: Vec<String>,
+    },
+    LsFiles {
+        /// Show each entry's mode, object and stage as well as its path.
+        #[clap(short = 's', long)]
+        stage: bool,
+    },
+    Add {
+        /// Stage modifications and removals of tracked files only.
+        #[clap(short = 'u', long)]
+        update: bool,
+        /// Stage every change, including new files.
+        #[clap(short = 'A', long)]
+        all: bool,
+        /// Stage ignored files too.
+        #[clap(short = 'f', long)]
+        force: bool,
+        pathspecs: Vec<String>,
+    },
+    Rm {
+        /// Only remove the paths from the index, keeping the files.
+        #[clap(long)]
+        cached: bool,
+        #[clap(short = 'r')]
+        recursive: bool,
+        /// Remove paths even if their changes would be lost.
+        #[clap(short = 'f', long)]
+        force: bool,
+        #[clap(required = true)]
+        pathspecs: Vec<String>,
+    },
+    Checkout {
+        /// Remove the tracked paths the tree-ish doesn't have.
+        #[clap(long)]
+        no_overlay: bool,
+        /// The tree-ish to restore from, the index when not given, then paths.
+        args: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+    Restore {
+        /// The tree-ish to restore from: `HEAD` with `--staged`, the index otherwise.
+        #[clap(short = 's', long)]
+        source: Option<String>,
+        /// Restore the index.
+        #[clap(short = 'S', long)]
+        staged: bool,
+        /// Restore the worktree, the default unless `--staged` is given.
+        #[clap(short = 'W', long)]
+        worktree: bool,
+        /// Keep the tracked paths the source doesn't have.
+        #[clap(long)]
+        overlay: bool,
+        pathspecs: Vec<String>,
+    },
+    Branch {
+        /// Delete the branches, which have to be merged.
+        #[clap(short = 'd', long)]
+        delete: bool,
+        /// Delete the branches whether or not they are merged.
+        #[clap(short = 'D')]
+        force_delete: bool,
+        /// Rename a branch, the current one if only the new name is given.
+        #[clap(short = 'm', long = "move")]
+        rename: bool,
+        /// Rename a branch even if the new name is taken.
+        #[clap(short = 'M')]
+        force_rename: bool,
+        /// Replace an existing branch, or delete an unmerged one.
+        #[clap(short = 'f', long)]
+        force: bool,
+        /// Show the commit and upstream of each branch, twice for the upstream's name too.
+        #[clap(short = 'v', long, action = clap::ArgAction::Count)]
+        verbose: u8,
+        /// The new branch and where it starts, or the branches to delete or rename.
+        args: Vec<String>,
+    },
+    Switch {
+        /// Create a branch of this name, starting at the target or `HEAD`, and switch to it.
+        #[clap(short = 'c', long)]
+        create: Option<String>,
+        /// Detach `HEAD` at the commit the target names.
+        #[clap(long)]
+        detach: bool,
+        /// Merge local changes into the files switched to instead of refusing to overwrite them.
+        #[clap(short = 'm', long)]
+        merge: bool,
+        /// Only report errors.
+        #[clap(short = 'q', long)]
+        quiet: bool,
+        /// The branch to switch to, `-` for the one before, or where the new branch starts.
+        target: Option<String>,
+    },
+    Clean {
+        /// Only show what would be deleted.
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+        #[clap(short = 'f', long)]
+        force: bool,
+        /// Delete untracked directories too.
+        #[clap(short = 'd')]
+        directories: bool,
+        /// Delete ignored files too.
+        #[clap(short = 'x', conflicts_with = "only_ignored")]
+        ignored: bool,
+        /// Delete only ignored files.
+        #[clap(short = 'X')]
+        only_ignored: bool,
+        pathspecs: Vec<String>,
+    },
+    Status {
+        #[clap(short = 's', long)]
+        short: bool,
+        /// Show the branch and its upstream in the short and porcelain formats.
+        #[clap(short = 'b', long)]
+        branch: bool,
+        /// Stable output for scripts, `v1` or `v2`.
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "v1")]
+        porcelain: Option<String>,
+        /// Terminate entries with NUL instead of newline, implying `--porcelain`.
+        #[clap(short = 'z')]
+        nul: bool,
+        /// Which untracked files to show: `no`, `normal` or `all`.
+        #[clap(
+            short = 'u',
+            long = "untracked-files",
+            num_args = 0..=1,
+            default_value = "normal",
+            default_missing_value = "all"
+        )]
+        untracked_files: UntrackedFiles,
+        #[clap(long)]
+        ignored: bool,
+    },
+    Submodule {
+        #[command(subcommand)]
+        command: Option<SubmoduleCommand>,
+    },
     WriteTree,
     CommitTree {
         #[clap(short = 'm')]
@@ -48,25 +308,412 @@ enum Command {
     Clone {
         #[clap(name = "repo-url")]
         repo_url: String,
+        directory: Option<PathBuf>,
+        #[clap(long)]
+        reference: Option<PathBuf>,
+    },
+    Fsck {
+        #[clap(long)]
+        unreachable: bool,
+    },
+    Repack {
+        #[clap(short = 'a')]
+        all: bool,
+        #[clap(short = 'd')]
+        delete: bool,
+    },
+    Gc {
+        #[clap(long)]
+        prune: Option<String>,
+    },
+}
+
+/// How the commands that show patches write them.
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Lines of context around each change.
+    #[clap(short = 'U', long = "unified")]
+    context: Option<usize>,
+    #[clap(long)]
+    diff_algorithm: Option<DiffAlgorithm>,
+    #[clap(long)]
+    patience: bool,
+    #[clap(long)]
+    histogram: bool,
+    /// Detect renames, of files at least this similar.
+    #[clap(
+        short = 'M',
+        long = "find-renames",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    find_renames: Option<String>,
+    /// Detect copies as well as renames, given twice from unchanged files too.
+    #[clap(
+        short = 'C',
+        long = "find-copies",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        action = clap::ArgAction::Append
+    )]
+    find_copies: Vec<String>,
+    #[clap(long)]
+    find_copies_harder: bool,
+    #[clap(long)]
+    no_renames: bool,
+}
+
+impl DiffArgs {
+    fn flags(&self) -> anyhow::Result<DiffFlags> {
+        let algorithm = if self.patience {
+            Some(DiffAlgorithm::Patience)
+        } else if self.histogram {
+            Some(DiffAlgorithm::Histogram)
+        } else {
+            self.diff_algorithm
+        };
+        let harder = self.find_copies_harder || self.find_copies.len() > 1;
+        let copies = match self.find_copies.last() {
+            Some(score) => Some(score.as_str()),
+            None => harder.then_some(""),
+        };
+        let renames = match (copies, &self.find_renames) {
+            (Some(copies), _) => Some(RenameOptions {
+                copies: true,
+                harder,
+                min_score: rename_score(copies)?,
+                ..RenameOptions::default()
+            }),
+            (None, Some(renames)) => Some(RenameOptions {
+                min_score: rename_score(renames)?,
+                ..RenameOptions::default()
+            }),
+            (None, None) => None,
+        };
+        Ok(DiffFlags {
+            context: self.context,
+            algorithm,
+            renames,
+            no_renames: self.no_renames,
+        })
+    }
+}
+
+/// The score of `-M<score>`, 50% when only `-M` is given.
+fn rename_score(score: &str) -> anyhow::Result<u32> {
+    match score {
+        "" => Ok(DEFAULT_SCORE),
+        score => parse_score(score),
+    }
+}
+
+/// `-M` and `-C` take their score attached, as in `-M90%`, which clap can't tell apart from
+/// the flag followed by a revision, so those are spelled as long options before parsing.
+/// Anything else, including arguments that aren't UTF-8, is passed on untouched.
+fn expand_scores(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut expanded = Vec::new();
+    let mut options = true;
+    for arg in args {
+        if arg == "--" {
+            options = false;
+        }
+        let score = [("-M", "--find-renames="), ("-C", "--find-copies=")]
+            .into_iter()
+            .filter(|_| options)
+            .find_map(|(short, long)| {
+                arg.to_str()?
+                    .strip_prefix(short)
+                    .filter(|score| score.starts_with(|c: char| c.is_ascii_digit() || c == '.'))
+                    .map(|score| format!("{long}{score}").into())
+            });
+        expanded.push(score.unwrap_or(arg));
+    }
+    expanded
+}
+
+#[derive(Subcommand, Debug)]
+enum SubmoduleCommand {
+    Init {
+        paths: Vec<String>,
+    },
+    Update {
+        /// Initialize the submodules first.
+        #[clap(long)]
+        init: bool,
+        paths: Vec<String>,
+    },
+    Status {
+        paths: Vec<String>,
     },
 }
 
 fn main() -> anyhow::Result<()> {
-    let mut git = Git {
-        config: Default::default(),
+    match run() {
+        // whoever was reading stopped, as in `git log | head`, which git dies of quietly
+        Err(e) if is_broken_pipe(&e) => std::process::exit(141),
+        result => result,
+    }
+}
+
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+    })
+}
+
+fn run() -> anyhow::Result<()> {
+    let args = Args::parse_from(expand_scores(std::env::args_os()));
+    let config = Config::default();
+    let mut git = match &args.command {
+        Command::Init { object_format } => {
+            let repo = Repository::on_disk(&config.dot_git_path, object_format.parse()?);
+            Git::with_repository(config, repo)
+        }
+        _ => Git::new(config)?,
     };
-    let args = Args::parse();
     match args.command {
-        Command::Init => git.init(),
+        Command::Init { .. } => git.init(),
+        Command::CatFile {
+            batch: Some(format),
+            batch_all_objects,
+            ..
+        } => git.cat_file_batch(std::io::stdin().lock(), &format, &true, &batch_all_objects),
+        Command::CatFile {
+            batch_check: Some(format),
+            batch_all_objects,
+            ..
+        } => git.cat_file_batch(std::io::stdin().lock(), &format, &false, &batch_all_objects),
         Command::CatFile {
             pretty_print,
+            show_type,
+            size,
+            exists,
             object_hash,
-        } => git.cat_file(&pretty_print, &object_hash),
-        Command::HashObject { write, file } => git.hash_object(&write, &file),
+            ..
+        } => match object_hash.as_slice() {
+            [object_type, object_hash] => git.cat_file_as(object_type, object_hash),
+            [object_hash] if show_type => git.cat_file_type(object_hash),
+            [object_hash] if size => git.cat_file_size(object_hash),
+            [object_hash] if exists => {
+                if !git.cat_file_exists(object_hash)? {
+                    std::process::exit(1);
+                }
+                Ok(())
+            }
+            [object_hash] => git.cat_file(&pretty_print, object_hash),
+            _ => unreachable!("clap takes one or two object arguments"),
+        },
+        Command::HashObject {
+            write,
+            object_type,
+            stdin,
+            stdin_paths,
+            literally,
+            path,
+            mut files,
+        } => {
+            let options = HashObjectOptions {
+                object_type: Some(object_type),
+                write,
+                literally,
+                path,
+            };
+            if stdin {
+                git.hash_object_stdin(std::io::stdin().lock(), &options)?;
+            }
+            if stdin_paths {
+                for line in std::io::stdin().lock().lines() {
+                    files.push(PathBuf::from(line?));
+                }
+            }
+            git.hash_object_files(&files, &options)
+        }
         Command::LsTree {
             name_only,
             tree_hash,
         } => git.ls_tree(&name_only, &tree_hash),
+        Command::DiffTree {
+            recursive,
+            name_status,
+            find_renames,
+            old,
+            new,
+        } => {
+            let renames = find_renames
+                .map(|score| -> anyhow::Result<_> {
+                    Ok(RenameOptions {
+                        min_score: rename_score(&score)?,
+                        ..RenameOptions::default()
+                    })
+                })
+                .transpose()?;
+            git.diff_tree(&recursive, &name_status, renames, &old, &new)
+        }
+        Command::Diff {
+            cached,
+            stat,
+            numstat,
+            name_only,
+            name_status,
+            exit_code,
+            diff,
+            args,
+            paths,
+        } => {
+            let format = if stat {
+                DiffFormat::Stat
+            } else if numstat {
+                DiffFormat::Numstat
+            } else if name_only {
+                DiffFormat::NameOnly
+            } else if name_status {
+                DiffFormat::NameStatus
+            } else {
+                DiffFormat::Patch
+            };
+            let differs = git.diff(&cached, &args, &paths, format, &diff.flags()?)?;
+            if exit_code && differs {
+                std::io::Write::flush(&mut std::io::stdout())?;
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Show { revision, diff } => git.show(revision.as_deref(), &diff.flags()?),
+        Command::Log {
+            patch,
+            revision,
+            diff,
+        } => git.log(revision.as_deref(), &patch, &diff.flags()?),
+        Command::FormatPatch {
+            output_directory,
+            stdout,
+            diff,
+            revisions,
+        } => git.format_patch(
+            &revisions,
+            output_directory.as_deref(),
+            &stdout,
+            &diff.flags()?,
+        ),
+        Command::LsFiles { stage } => git.ls_files(&stage),
+        Command::Add {
+            update,
+            all,
+            force,
+            pathspecs,
+        } => git.add(&pathspecs, &update, &all, &force),
+        Command::Rm {
+            cached,
+            recursive,
+            force,
+            pathspecs,
+        } => git.rm(&pathspecs, &cached, &recursive, &force),
+        Command::Checkout {
+            no_overlay,
+            args,
+            paths,
+        } => git.checkout(&args, &paths, &no_overlay),
+        Command::Restore {
+            source,
+            staged,
+            worktree,
+            overlay,
+            pathspecs,
+        } => git.restore(&pathspecs, source.as_deref(), &staged, &worktree, &overlay),
+        Command::Branch {
+            delete,
+            force_delete,
+            rename,
+            force_rename,
+            force,
+            verbose,
+            args,
+        } => {
+            if delete || force_delete {
+                if !git.branch_delete(&args, &(force || force_delete))? {
+                    std::io::Write::flush(&mut std::io::stdout())?;
+                    std::process::exit(1);
+                }
+                Ok(())
+            } else if rename || force_rename {
+                git.branch_rename(&args, &(force || force_rename))
+            } else {
+                match &args[..] {
+                    [] => git.branch_list(verbose),
+                    [name] => git.branch_create(name, None, &force),
+                    [name, start] => git.branch_create(name, Some(start), &force),
+                    _ => anyhow::bail!("too many arguments for a create operation"),
+                }
+            }
+        }
+        Command::Switch {
+            create,
+            detach,
+            merge,
+            quiet,
+            target,
+        } => {
+            if !git.switch(
+                target.as_deref(),
+                create.as_deref(),
+                &detach,
+                &merge,
+                &quiet,
+            )? {
+                std::io::Write::flush(&mut std::io::stdout())?;
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Clean {
+            dry_run,
+            force,
+            directories,
+            ignored,
+            only_ignored,
+            pathspecs,
+        } => {
+            let ignored = match (ignored, only_ignored) {
+                (_, true) => CleanIgnored::Only,
+                (true, _) => CleanIgnored::Also,
+                _ => CleanIgnored::Keep,
+            };
+            git.clean(&pathspecs, &dry_run, &force, &directories, ignored)
+        }
+        Command::Status {
+            short,
+            branch,
+            porcelain,
+            nul,
+            untracked_files,
+            ignored,
+        } => {
+            let format = match porcelain.as_deref() {
+                Some("v1" | "1") => StatusFormat::PorcelainV1,
+                Some("v2" | "2") => StatusFormat::PorcelainV2,
+                Some(version) => anyhow::bail!("unsupported porcelain version '{version}'"),
+                None if short => StatusFormat::Short,
+                None if nul => StatusFormat::PorcelainV1,
+                None => StatusFormat::Long,
+            };
+            git.status(&StatusOptions {
+                format,
+                branch,
+                nul,
+                untracked: untracked_files,
+                ignored,
+            })
+        }
+        Command::Submodule { command } => match command {
+            Some(SubmoduleCommand::Init { paths }) => git.submodule_init(&paths),
+            Some(SubmoduleCommand::Update { init, paths }) => git.submodule_update(&paths, &init),
+            Some(SubmoduleCommand::Status { paths }) => git.submodule_status(&paths),
+            None => git.submodule_status(&[]),
+        },
         Command::WriteTree => git.write_tree(),
         Command::CommitTree {
             message,
@@ -74,6 +721,13 @@ fn main() -> anyhow::Result<()> {
             parent_hash,
         } => git.commit_tree(&message, &tree_hash, parent_hash),
         Command::Commit { message } => git.commit(&message),
-        Command::Clone { repo_url } => git.clone(&repo_url),
+        Command::Clone {
+            repo_url,
+            directory,
+            reference,
+        } => git.clone(&repo_url, directory.as_deref(), reference.as_deref()),
+        Command::Fsck { unreachable } => git.fsck(&unreachable),
+        Command::Repack { all, delete } => git.repack(&all, &delete),
+        Command::Gc { prune } => git.gc(prune.as_deref()),
     }
 }
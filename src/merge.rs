@@ -0,0 +1,306 @@
+//! Three-way merges of file contents, line by line, the way git's default merge driver does
+//! them.
+
+use std::ops::Range;
+
+use crate::diff::{changed_lines, split_lines, DiffAlgorithm};
+
+/// A run of lines one side changed: where it is in the base and what replaced it.
+#[derive(Debug, Clone)]
+struct Edit {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+/// The edits that turn `base` into `side`, in order.
+fn edits(base: &[&[u8]], side: &[&[u8]]) -> Vec<Edit> {
+    let (base_changed, side_changed) = changed_lines(base, side, DiffAlgorithm::Myers);
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < base.len() || j < side.len() {
+        if base_changed.get(i) == Some(&true) || side_changed.get(j) == Some(&true) {
+            let (start_i, start_j) = (i, j);
+            while base_changed.get(i) == Some(&true) {
+                i += 1;
+            }
+            while side_changed.get(j) == Some(&true) {
+                j += 1;
+            }
+            edits.push(Edit {
+                base: start_i..i,
+                side: start_j..j,
+            });
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+    edits
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Take {
+    Conflict,
+    Ours,
+    Theirs,
+    /// Both sides made the same change.
+    Either,
+}
+
+/// A stretch of the result, in the lines of ours and of theirs.
+#[derive(Debug, Clone)]
+struct Chunk {
+    take: Take,
+    ours: Range<usize>,
+    theirs: Range<usize>,
+}
+
+/// Adds `chunk`, folding it into the previous one when they touch on either side, which
+/// makes a conflict of them unless both take the same side.
+fn append(chunks: &mut Vec<Chunk>, chunk: Chunk) {
+    if let Some(last) = chunks.last_mut() {
+        if chunk.ours.start <= last.ours.end || chunk.theirs.start <= last.theirs.end {
+            if chunk.take != last.take {
+                last.take = Take::Conflict;
+            }
+            last.ours.end = chunk.ours.end;
+            last.theirs.end = chunk.theirs.end;
+            return;
+        }
+    }
+    chunks.push(chunk);
+}
+
+/// The changes of both sides lined up: one-sided edits, and conflicts where the edits of the
+/// two sides overlap or touch in the base without being the same.
+fn chunks(
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+    ours_edits: &[Edit],
+    theirs_edits: &[Edit],
+) -> Vec<Chunk> {
+    // where a base line is on a side, going by the offset at that side's next edit; an edit
+    // reaching back before that lands in the chunk before it, so zero will do
+    let shift = |base: &Range<usize>, edit: Option<&Edit>, end_delta: isize| -> Range<usize> {
+        let delta = edit.map_or(end_delta, |edit| {
+            edit.side.start as isize - edit.base.start as isize
+        });
+        let at = |line: usize| (line as isize + delta).max(0) as usize;
+        at(base.start)..at(base.end)
+    };
+    let base_len_delta = |edits: &[Edit]| -> isize {
+        edits
+            .iter()
+            .map(|e| e.side.len() as isize - e.base.len() as isize)
+            .sum()
+    };
+    let ours_delta = base_len_delta(ours_edits);
+    let theirs_delta = base_len_delta(theirs_edits);
+
+    let mut chunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < ours_edits.len() || j < theirs_edits.len() {
+        let (o, t) = (ours_edits.get(i), theirs_edits.get(j));
+        match (o, t) {
+            (Some(o), t) if t.map_or(true, |t| o.base.end < t.base.start) => {
+                append(
+                    &mut chunks,
+                    Chunk {
+                        take: Take::Ours,
+                        ours: o.side.clone(),
+                        theirs: shift(&o.base, t, theirs_delta),
+                    },
+                );
+                i += 1;
+            }
+            (o, Some(t)) if o.map_or(true, |o| t.base.end < o.base.start) => {
+                append(
+                    &mut chunks,
+                    Chunk {
+                        take: Take::Theirs,
+                        ours: shift(&t.base, o, ours_delta),
+                        theirs: t.side.clone(),
+                    },
+                );
+                j += 1;
+            }
+            (Some(o), Some(t)) => {
+                let same = o.base == t.base && ours[o.side.clone()] == theirs[t.side.clone()];
+                if !same {
+                    // widen each side's edit to the base lines the other one covers; where that
+                    // reaches back past an edit already taken it joins that chunk anyway
+                    let start = o.base.start.min(t.base.start);
+                    let end = o.base.end.max(t.base.end);
+                    let ours_range = o.side.start.saturating_sub(o.base.start - start)
+                        ..o.side.end + (end - o.base.end);
+                    let theirs_range = t.side.start.saturating_sub(t.base.start - start)
+                        ..t.side.end + (end - t.base.end);
+                    append(
+                        &mut chunks,
+                        Chunk {
+                            take: Take::Conflict,
+                            ours: ours_range,
+                            theirs: theirs_range,
+                        },
+                    );
+                }
+                if o.base.end >= t.base.end {
+                    j += 1;
+                }
+                if t.base.end >= o.base.end {
+                    i += 1;
+                }
+            }
+            // one side having run out of edits is handled above
+            _ => unreachable!(),
+        }
+    }
+    chunks
+}
+
+/// Splits each conflict where the two sides have lines in common, then joins conflicts back
+/// together when only a few lines separate them.
+fn refine(ours: &[&[u8]], theirs: &[&[u8]], chunks: Vec<Chunk>) -> Vec<Chunk> {
+    let mut refined = Vec::new();
+    for chunk in chunks {
+        if chunk.take != Take::Conflict || chunk.ours.is_empty() || chunk.theirs.is_empty() {
+            refined.push(chunk);
+            continue;
+        }
+        let differences = edits(&ours[chunk.ours.clone()], &theirs[chunk.theirs.clone()]);
+        if differences.is_empty() {
+            refined.push(Chunk {
+                take: Take::Either,
+                ..chunk
+            });
+            continue;
+        }
+        for difference in differences {
+            refined.push(Chunk {
+                take: Take::Conflict,
+                ours: chunk.ours.start + difference.base.start
+                    ..chunk.ours.start + difference.base.end,
+                theirs: chunk.theirs.start + difference.side.start
+                    ..chunk.theirs.start + difference.side.end,
+            });
+        }
+    }
+
+    let mut simplified: Vec<Chunk> = Vec::new();
+    for chunk in refined {
+        match simplified.last_mut() {
+            Some(last)
+                if last.take == Take::Conflict
+                    && chunk.take == Take::Conflict
+                    && chunk.ours.start - last.ours.end <= 3 =>
+            {
+                last.ours.end = chunk.ours.end;
+                last.theirs.end = chunk.theirs.end;
+            }
+            _ => simplified.push(chunk),
+        }
+    }
+    simplified
+}
+
+/// Adds `lines` to `out`, ending the last one with a newline if `terminate` is set.
+fn copy_lines(out: &mut Vec<u8>, lines: &[&[u8]], terminate: bool) {
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+    if terminate && lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+        out.push(b'\n');
+    }
+}
+
+/// Merges the changes `ours` and `theirs` each made to `base`. Where they disagree both
+/// versions are kept between conflict markers naming them `ours_label` and `theirs_label`,
+/// and whether that happened is returned with the result.
+pub(crate) fn merge_lines(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    ours_label: &str,
+    theirs_label: &str,
+) -> (Vec<u8>, bool) {
+    let base = split_lines(base);
+    let ours = split_lines(ours);
+    let theirs = split_lines(theirs);
+    let ours_edits = edits(&base, &ours);
+    let theirs_edits = edits(&base, &theirs);
+    if ours_edits.is_empty() {
+        return (theirs.concat(), false);
+    }
+    if theirs_edits.is_empty() {
+        return (ours.concat(), false);
+    }
+    let chunks = refine(
+        &ours,
+        &theirs,
+        chunks(&ours, &theirs, &ours_edits, &theirs_edits),
+    );
+
+    let mut out = Vec::new();
+    let mut conflicted = false;
+    let mut copied = 0;
+    for chunk in chunks {
+        if chunk.take == Take::Either {
+            continue;
+        }
+        copy_lines(&mut out, &ours[copied..chunk.ours.start], false);
+        match chunk.take {
+            Take::Ours | Take::Either => copy_lines(&mut out, &ours[chunk.ours.clone()], false),
+            Take::Theirs => copy_lines(&mut out, &theirs[chunk.theirs.clone()], false),
+            Take::Conflict => {
+                conflicted = true;
+                out.extend(format!("<<<<<<< {ours_label}\n").as_bytes());
+                copy_lines(&mut out, &ours[chunk.ours.clone()], true);
+                out.extend(b"=======\n");
+                copy_lines(&mut out, &theirs[chunk.theirs.clone()], true);
+                out.extend(format!(">>>>>>> {theirs_label}\n").as_bytes());
+            }
+        }
+        copied = chunk.ours.end;
+    }
+    copy_lines(&mut out, &ours[copied..], false);
+    (out, conflicted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_lines() {
+        let base = b"1\n2\n3\n4\n5\n";
+        let merged = merge_lines(
+            base,
+            b"1\nX\n3\n4\n5\n",
+            b"1\n2\n3\n4\nY\n",
+            "ours",
+            "theirs",
+        );
+        assert_eq!(merged, (b"1\nX\n3\n4\nY\n".to_vec(), false));
+        let same = merge_lines(
+            base,
+            b"1\nX\n3\n4\n5\n",
+            b"1\nX\n3\n4\n5\n",
+            "ours",
+            "theirs",
+        );
+        assert_eq!(same, (b"1\nX\n3\n4\n5\n".to_vec(), false));
+
+        let (merged, conflicted) = merge_lines(
+            base,
+            b"1\nX\n3\n4\n5\n",
+            b"1\nZ\n3\n4\n5\n",
+            "other",
+            "local",
+        );
+        assert!(conflicted);
+        assert_eq!(
+            String::from_utf8_lossy(&merged),
+            "1\n<<<<<<< other\nX\n=======\nZ\n>>>>>>> local\n3\n4\n5\n"
+        );
+    }
+}
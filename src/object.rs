@@ -1,35 +1,31 @@
 use anyhow::Context;
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use sha1::Digest;
-use sha1::Sha1;
 use std::ffi::CStr;
 use std::fmt;
-use std::fs;
 use std::io::prelude::*;
-use std::io::BufReader;
 use std::path::Path;
 
+use crate::hash::{HashAlgorithm, Hasher, ObjectId};
+use crate::store::ObjectStore;
+
 #[derive(Debug)]
-pub(crate) struct Object<R> {
-    pub(crate) object_type: ObjectType,
-    pub(crate) expected_size: u64,
-    pub(crate) reader: R,
+pub struct Object<R> {
+    pub object_type: ObjectType,
+    pub expected_size: u64,
+    pub reader: R,
 }
 
-#[derive(Debug)]
-pub(crate) enum ObjectType {
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum ObjectType {
     Blob,
     Tree,
     Commit,
-    // Tag,
+    Tag,
 }
 
 #[derive(Debug)]
 struct HashWriter<W: std::io::Write> {
     writer: W,
-    hasher: Sha1,
+    hasher: Hasher,
 }
 
 impl<W> std::io::Write for HashWriter<W>
@@ -53,11 +49,34 @@ impl fmt::Display for ObjectType {
             ObjectType::Blob => write!(f, "blob"),
             ObjectType::Tree => write!(f, "tree"),
             ObjectType::Commit => write!(f, "commit"),
+            ObjectType::Tag => write!(f, "tag"),
+        }
+    }
+}
+
+impl std::str::FromStr for ObjectType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(ObjectType::Blob),
+            "tree" => Ok(ObjectType::Tree),
+            "commit" => Ok(ObjectType::Commit),
+            "tag" => Ok(ObjectType::Tag),
+            _ => anyhow::bail!("unknown object_type '{s}'"),
         }
     }
 }
 
 impl Object<()> {
+    /// The id an object with this type and contents has, without compressing or storing it.
+    pub(crate) fn hash_of(object_type: ObjectType, data: &[u8], hash: HashAlgorithm) -> ObjectId {
+        let mut hasher = hash.hasher();
+        hasher.update(format!("{object_type} {}\0", data.len()).as_bytes());
+        hasher.update(data);
+        hasher.finalize()
+    }
+
     pub(crate) fn blob_from_file(file: impl AsRef<Path>) -> anyhow::Result<Object<impl Read>> {
         let file = file.as_ref();
         let stat = std::fs::metadata(file).with_context(|| format!("stat {}", file.display()))?;
@@ -70,42 +89,31 @@ impl Object<()> {
         })
     }
 
-    pub(crate) fn read(dot_git_path: &Path, hash: &str) -> anyhow::Result<Object<impl BufRead>> {
-        let f = std::fs::File::open(dot_git_path.join(format!(
-            "objects/{}/{}",
-            &hash[..2],
-            &hash[2..]
-        )))
-        .context("open in .git/objects")?;
-        let z = ZlibDecoder::new(f);
-        let mut z = BufReader::new(z);
+    pub(crate) fn read_header(reader: &mut impl BufRead) -> anyhow::Result<(ObjectType, u64)> {
         let mut buf = Vec::new();
-        z.read_until(0, &mut buf)
-            .context("read header from .git/objects")?;
-        let header = CStr::from_bytes_with_nul(&buf)
-            .expect("know there is exactly one nul, and it's at the end");
-        let header = header
-            .to_str()
-            .context(".git/objects file header isn't valid UTF-8")?;
+        reader
+            .read_until(0, &mut buf)
+            .context("read object header")?;
+        let header =
+            CStr::from_bytes_with_nul(&buf).context("object header is not nul terminated")?;
+        let header = header.to_str().context("object header isn't valid UTF-8")?;
         let Some((object_type, size)) = header.split_once(' ') else {
-            anyhow::bail!(".git/objects file header did not start with a known type: '{header}'");
-        };
-        let object_type: ObjectType = match object_type {
-            "blob" => ObjectType::Blob,
-            "tree" => ObjectType::Tree,
-            "commit" => ObjectType::Commit,
-            _ => anyhow::bail!("unknown object_type '{object_type}'"),
+            anyhow::bail!("object header did not start with a known type: '{header}'");
         };
+        let object_type: ObjectType = object_type.parse()?;
         let size = size
             .parse::<u64>()
-            .context(".git/objects file header has invalid size: {size}")?;
+            .with_context(|| format!("object header has invalid size: {size}"))?;
+        Ok((object_type, size))
+    }
 
-        let z = z.take(size);
-        Ok(Object {
-            object_type,
-            expected_size: size,
-            reader: z,
-        })
+    pub fn read<'a>(
+        objects: &'a dyn ObjectStore,
+        hash: &str,
+    ) -> anyhow::Result<Object<Box<dyn BufRead + 'a>>> {
+        objects
+            .read(hash)?
+            .with_context(|| format!("object {hash} not found"))
     }
 }
 
@@ -113,32 +121,47 @@ impl<R> Object<R>
 where
     R: Read,
 {
-    pub(crate) fn write(mut self, writer: impl Write) -> anyhow::Result<[u8; 20]> {
-        let writer = ZlibEncoder::new(writer, Compression::default());
+    /// Copies the object, header included, into `writer` as it is and returns its hash.
+    pub(crate) fn write_uncompressed(
+        mut self,
+        writer: impl Write,
+        hash: HashAlgorithm,
+    ) -> anyhow::Result<ObjectId> {
         let mut writer = HashWriter {
             writer,
-            hasher: Sha1::new(),
+            hasher: hash.hasher(),
         };
         write!(writer, "{} {}\0", self.object_type, self.expected_size)?;
-        std::io::copy(&mut self.reader, &mut writer).context("stream file into blob")?;
-        let _ = writer.writer.finish()?;
-        let hash = writer.hasher.finalize();
-        Ok(hash.into())
+        let copied =
+            std::io::copy(&mut self.reader, &mut writer).context("stream file into blob")?;
+        anyhow::ensure!(
+            copied == self.expected_size,
+            "object was not the expected size (expected: {}, actual: {copied})",
+            self.expected_size,
+        );
+        Ok(writer.hasher.finalize())
+    }
+
+    pub fn write_to_objects(mut self, objects: &dyn ObjectStore) -> anyhow::Result<ObjectId> {
+        objects.write(Object {
+            object_type: self.object_type,
+            expected_size: self.expected_size,
+            reader: &mut self.reader,
+        })
     }
 
-    pub(crate) fn write_to_objects(self, dot_git_path: &Path) -> anyhow::Result<[u8; 20]> {
-        let tempfile = tempfile::NamedTempFile::new().context("create temporary file")?;
-        let hash = self
-            .write(&tempfile)
-            .context("stream tree object into tree object file")?;
-        let hash_hex = hex::encode(hash);
-        fs::create_dir_all(dot_git_path.join(format!("objects/{}/", &hash_hex[..2])))
-            .context("create subdir of .git/objects")?;
-        fs::rename(
-            tempfile.path(),
-            dot_git_path.join(format!("objects/{}/{}", &hash_hex[..2], &hash_hex[2..])),
-        )
-        .context("move tree file into .git/objects")?;
-        Ok(hash)
+    /// Reads the rest of the object into memory, checking it against the header size.
+    pub fn into_bytes(mut self) -> anyhow::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.expected_size as usize);
+        self.reader
+            .read_to_end(&mut data)
+            .context("read object contents")?;
+        anyhow::ensure!(
+            data.len() as u64 == self.expected_size,
+            "object was not the expected size (expected: {}, actual: {})",
+            self.expected_size,
+            data.len()
+        );
+        Ok(data)
     }
 }
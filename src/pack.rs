@@ -0,0 +1,682 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+
+use crate::{
+    hash::{HashAlgorithm, ObjectId},
+    object::{Object, ObjectType},
+    store::ObjectStore,
+};
+
+const IDX_MAGIC: &[u8; 4] = b"\xfftOc";
+const PACK_MAGIC: &[u8; 4] = b"PACK";
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+// https://git-scm.com/docs/pack-format
+#[derive(Debug)]
+pub(crate) struct Pack {
+    pack_path: PathBuf,
+    hash: HashAlgorithm,
+    hashes: Vec<ObjectId>,
+    offsets: Vec<u64>,
+}
+
+impl Pack {
+    pub(crate) fn open(idx_path: &Path, hash: HashAlgorithm) -> anyhow::Result<Pack> {
+        let idx = fs::read(idx_path).with_context(|| format!("read {}", idx_path.display()))?;
+        ensure!(
+            idx.len() >= 8 + 256 * 4 && &idx[..4] == IDX_MAGIC,
+            "{} is not a version 2 pack index",
+            idx_path.display()
+        );
+        let version = be_u32(&idx[4..8]);
+        ensure!(version == 2, "unsupported pack index version {version}");
+
+        let fanout = &idx[8..8 + 256 * 4];
+        let count = be_u32(&fanout[255 * 4..]) as usize;
+        let hashes_start = 8 + 256 * 4;
+        let crc_start = hashes_start + count * hash.raw_len();
+        let offsets_start = crc_start + count * 4;
+        let large_offsets_start = offsets_start + count * 4;
+        ensure!(
+            idx.len() >= large_offsets_start + 2 * hash.raw_len(),
+            "{} is truncated",
+            idx_path.display()
+        );
+
+        let hashes = idx[hashes_start..crc_start]
+            .chunks_exact(hash.raw_len())
+            .map(ObjectId::from_bytes)
+            .collect::<anyhow::Result<_>>()?;
+        let mut offsets = Vec::with_capacity(count);
+        for chunk in idx[offsets_start..large_offsets_start].chunks_exact(4) {
+            let offset = be_u32(chunk);
+            if offset & 0x8000_0000 == 0 {
+                offsets.push(offset as u64);
+            } else {
+                let at = large_offsets_start + (offset & 0x7fff_ffff) as usize * 8;
+                let large = idx
+                    .get(at..at + 8)
+                    .context("pack index large offset out of range")?;
+                offsets.push(u64::from_be_bytes(large.try_into().expect("8 bytes")));
+            }
+        }
+
+        Ok(Pack {
+            pack_path: idx_path.with_extension("pack"),
+            hash,
+            hashes,
+            offsets,
+        })
+    }
+
+    pub(crate) fn hashes(&self) -> &[ObjectId] {
+        &self.hashes
+    }
+
+    pub(crate) fn find(&self, hash: &ObjectId) -> Option<u64> {
+        self.hashes
+            .binary_search(hash)
+            .ok()
+            .map(|position| self.offsets[position])
+    }
+
+    pub(crate) fn read(&self, hash: &ObjectId) -> anyhow::Result<Option<(ObjectType, Vec<u8>)>> {
+        let Some(offset) = self.find(hash) else {
+            return Ok(None);
+        };
+        let mut file = fs::File::open(&self.pack_path)
+            .with_context(|| format!("open {}", self.pack_path.display()))?;
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic).context("read pack header")?;
+        ensure!(
+            &magic == PACK_MAGIC,
+            "{} is not a pack file",
+            self.pack_path.display()
+        );
+        self.read_at(&mut file, offset).map(Some)
+    }
+
+    fn read_at(&self, file: &mut fs::File, offset: u64) -> anyhow::Result<(ObjectType, Vec<u8>)> {
+        file.seek(SeekFrom::Start(offset))
+            .context("seek to pack entry")?;
+        let mut reader = BufReader::new(&mut *file);
+        let (kind, size) = read_entry_header(&mut reader)?;
+        match kind {
+            OBJ_OFS_DELTA => {
+                let distance = read_offset_delta(&mut reader)?;
+                let delta = inflate(&mut reader, size)?;
+                let base_offset = offset
+                    .checked_sub(distance)
+                    .context("offset delta points before start of pack")?;
+                let (object_type, base) = self.read_at(file, base_offset)?;
+                Ok((object_type, apply_delta(&base, &delta)?))
+            }
+            OBJ_REF_DELTA => {
+                let mut base_hash = vec![0; self.hash.raw_len()];
+                reader
+                    .read_exact(&mut base_hash)
+                    .context("read ref delta base")?;
+                let base_hash = ObjectId::from_bytes(&base_hash)?;
+                let delta = inflate(&mut reader, size)?;
+                let base_offset = self
+                    .find(&base_hash)
+                    .with_context(|| format!("ref delta base {base_hash} is not in pack"))?;
+                let (object_type, base) = self.read_at(file, base_offset)?;
+                Ok((object_type, apply_delta(&base, &delta)?))
+            }
+            kind => Ok((object_type_for(kind)?, inflate(&mut reader, size)?)),
+        }
+    }
+}
+
+fn pack_type_for(object_type: ObjectType) -> u8 {
+    match object_type {
+        ObjectType::Commit => OBJ_COMMIT,
+        ObjectType::Tree => OBJ_TREE,
+        ObjectType::Blob => OBJ_BLOB,
+        ObjectType::Tag => OBJ_TAG,
+    }
+}
+
+fn object_type_for(kind: u8) -> anyhow::Result<ObjectType> {
+    Ok(match kind {
+        OBJ_COMMIT => ObjectType::Commit,
+        OBJ_TREE => ObjectType::Tree,
+        OBJ_BLOB => ObjectType::Blob,
+        OBJ_TAG => ObjectType::Tag,
+        _ => bail!("unknown pack object type {kind}"),
+    })
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().expect("4 bytes"))
+}
+
+fn read_byte(reader: &mut impl Read) -> anyhow::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte).context("read pack byte")?;
+    Ok(byte[0])
+}
+
+fn read_entry_header(reader: &mut impl Read) -> anyhow::Result<(u8, u64)> {
+    let mut byte = read_byte(reader)?;
+    let kind = (byte >> 4) & 0b111;
+    let mut size = (byte & 0b1111) as u64;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = read_byte(reader)?;
+        size |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+    }
+    Ok((kind, size))
+}
+
+fn read_offset_delta(reader: &mut impl Read) -> anyhow::Result<u64> {
+    let mut byte = read_byte(reader)?;
+    let mut distance = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = read_byte(reader)?;
+        distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+    }
+    Ok(distance)
+}
+
+fn inflate(reader: &mut impl Read, size: u64) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size as usize);
+    ZlibDecoder::new(reader)
+        .take(size)
+        .read_to_end(&mut data)
+        .context("inflate pack entry")?;
+    ensure!(
+        data.len() as u64 == size,
+        "pack entry was not the expected size (expected: {}, actual: {})",
+        size,
+        data.len()
+    );
+    Ok(data)
+}
+
+fn delta_size(delta: &[u8], at: &mut usize) -> anyhow::Result<u64> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*at).context("delta header is truncated")?;
+        *at += 1;
+        size |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+// https://git-scm.com/docs/pack-format#_deltified_representation
+pub(crate) fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut at = 0;
+    let base_size = delta_size(delta, &mut at)?;
+    ensure!(
+        base_size == base.len() as u64,
+        "delta base size mismatch (expected: {}, actual: {})",
+        base_size,
+        base.len()
+    );
+    let target_size = delta_size(delta, &mut at)?;
+    let mut target = Vec::with_capacity(target_size as usize);
+
+    while at < delta.len() {
+        let instruction = delta[at];
+        at += 1;
+        if instruction & 0x80 != 0 {
+            let mut offset = 0usize;
+            let mut size = 0usize;
+            for i in 0..4 {
+                if instruction & (1 << i) != 0 {
+                    offset |=
+                        (*delta.get(at).context("copy offset is truncated")? as usize) << (8 * i);
+                    at += 1;
+                }
+            }
+            for i in 0..3 {
+                if instruction & (1 << (4 + i)) != 0 {
+                    size |= (*delta.get(at).context("copy size is truncated")? as usize) << (8 * i);
+                    at += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let source = base
+                .get(offset..offset + size)
+                .context("delta copy is out of range of base")?;
+            target.extend_from_slice(source);
+        } else if instruction != 0 {
+            let size = instruction as usize;
+            let data = delta
+                .get(at..at + size)
+                .context("delta insert is truncated")?;
+            target.extend_from_slice(data);
+            at += size;
+        } else {
+            bail!("reserved delta instruction 0");
+        }
+    }
+
+    ensure!(
+        target.len() as u64 == target_size,
+        "delta target size mismatch (expected: {}, actual: {})",
+        target_size,
+        target.len()
+    );
+    Ok(target)
+}
+
+/// How many earlier objects of the same type are tried as delta bases.
+const DELTA_WINDOW: usize = 10;
+/// Longest chain of deltas an object may sit at the end of.
+const MAX_DELTA_DEPTH: usize = 50;
+const DELTA_BLOCK: usize = 16;
+
+#[derive(Debug)]
+pub(crate) struct WrittenPack {
+    pub(crate) pack_path: PathBuf,
+    pub(crate) objects: usize,
+    pub(crate) deltas: usize,
+}
+
+struct PackInput {
+    hash: ObjectId,
+    object_type: ObjectType,
+    data: Vec<u8>,
+}
+
+struct PackEntry {
+    input: usize,
+    /// Index into the write order of the base this entry is a delta against.
+    base: Option<(usize, Vec<u8>)>,
+}
+
+/// Writes `hashes` into a new `pack-<checksum>.pack` with its index in `pack_dir`,
+/// storing objects as deltas against similar objects of the same type where that's smaller.
+/// With `fsync` both files are flushed to disk before being moved into place.
+pub(crate) fn write_pack(
+    objects: &dyn ObjectStore,
+    hashes: &[ObjectId],
+    pack_dir: &Path,
+    fsync: bool,
+) -> anyhow::Result<WrittenPack> {
+    let hash_algorithm = objects.hash_algorithm();
+    let mut inputs = Vec::with_capacity(hashes.len());
+    for hash in hashes {
+        let object = Object::read(objects, &hash.to_string())?;
+        inputs.push(PackInput {
+            hash: *hash,
+            object_type: object.object_type,
+            data: object.into_bytes()?,
+        });
+    }
+
+    // similar objects end up next to each other, biggest first so deltas mostly delete
+    let mut order: Vec<usize> = (0..inputs.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&inputs[a], &inputs[b]);
+        a.object_type
+            .cmp(&b.object_type)
+            .then(b.data.len().cmp(&a.data.len()))
+            .then(a.hash.cmp(&b.hash))
+    });
+
+    let mut entries: Vec<PackEntry> = Vec::with_capacity(order.len());
+    let mut depths: Vec<usize> = Vec::with_capacity(order.len());
+    for (position, &input) in order.iter().enumerate() {
+        let target = &inputs[input];
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        let max_size = (target.data.len() / 2).saturating_sub(20);
+        for candidate in position.saturating_sub(DELTA_WINDOW)..position {
+            let base = &inputs[entries[candidate].input];
+            if base.object_type != target.object_type || depths[candidate] >= MAX_DELTA_DEPTH {
+                continue;
+            }
+            let delta = create_delta(&base.data, &target.data);
+            let best_size = best.as_ref().map_or(max_size, |(_, delta)| delta.len());
+            if delta.len() < best_size {
+                best = Some((candidate, delta));
+            }
+        }
+        depths.push(best.as_ref().map_or(0, |(base, _)| depths[*base] + 1));
+        entries.push(PackEntry { input, base: best });
+    }
+
+    fs::create_dir_all(pack_dir).context("create .git/objects/pack")?;
+    let mut pack = PackWriter {
+        writer: tempfile::NamedTempFile::new_in(pack_dir).context("create temporary pack")?,
+        hasher: hash_algorithm.hasher(),
+        crc: Crc::new(),
+        written: 0,
+    };
+    pack.write_all(PACK_MAGIC)?;
+    pack.write_all(&2u32.to_be_bytes())?;
+    pack.write_all(&(entries.len() as u32).to_be_bytes())?;
+
+    let mut offsets = Vec::with_capacity(entries.len());
+    let mut index = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let input = &inputs[entry.input];
+        let offset = pack.written;
+        offsets.push(offset);
+        pack.crc = Crc::new();
+        let data = match &entry.base {
+            Some((base, delta)) => {
+                write_entry_header(&mut pack, OBJ_OFS_DELTA, delta.len() as u64)?;
+                write_offset_delta(&mut pack, offset - offsets[*base])?;
+                delta
+            }
+            None => {
+                let kind = pack_type_for(input.object_type);
+                write_entry_header(&mut pack, kind, input.data.len() as u64)?;
+                &input.data
+            }
+        };
+        let mut z = ZlibEncoder::new(&mut pack, Compression::default());
+        z.write_all(data).context("compress pack entry")?;
+        z.finish().context("compress pack entry")?;
+        index.push((input.hash, pack.crc.sum(), offset));
+    }
+    let checksum = pack.hasher.clone().finalize();
+    pack.writer
+        .write_all(checksum.as_bytes())
+        .context("write pack checksum")?;
+
+    let pack_path = pack_dir.join(format!("pack-{checksum}.pack"));
+    finish_pack_file(pack.writer, &pack_path, fsync)?;
+    index.sort();
+    write_index(&index, &checksum, &pack_path.with_extension("idx"), fsync)?;
+
+    Ok(WrittenPack {
+        pack_path,
+        objects: entries.len(),
+        deltas: entries.iter().filter(|entry| entry.base.is_some()).count(),
+    })
+}
+
+/// Hashes everything written for the trailing checksum and checksums each entry for the index.
+struct PackWriter<W> {
+    writer: W,
+    hasher: crate::hash::Hasher,
+    crc: Crc,
+    written: u64,
+}
+
+impl<W: Write> Write for PackWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.crc.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn write_entry_header(writer: &mut impl Write, kind: u8, size: u64) -> anyhow::Result<()> {
+    let mut byte = (kind << 4) | (size & 0b1111) as u8;
+    let mut size = size >> 4;
+    let mut header = Vec::new();
+    while size != 0 {
+        header.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    header.push(byte);
+    writer.write_all(&header).context("write pack entry header")
+}
+
+fn write_offset_delta(writer: &mut impl Write, mut distance: u64) -> anyhow::Result<()> {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance != 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    writer.write_all(&bytes).context("write offset delta")
+}
+
+/// Makes a finished pack or index read-only and moves it into place.
+fn finish_pack_file(
+    tempfile: tempfile::NamedTempFile,
+    path: &Path,
+    fsync: bool,
+) -> anyhow::Result<()> {
+    if fsync {
+        tempfile.as_file().sync_all().context("fsync pack file")?;
+    }
+    fs::set_permissions(tempfile.path(), fs::Permissions::from_mode(0o444))
+        .context("make pack file read-only")?;
+    tempfile
+        .persist(path)
+        .context("move pack file into .git/objects/pack")?;
+    Ok(())
+}
+
+fn write_index(
+    index: &[(ObjectId, u32, u64)],
+    pack_checksum: &ObjectId,
+    idx_path: &Path,
+    fsync: bool,
+) -> anyhow::Result<()> {
+    let mut idx = Vec::new();
+    idx.extend(IDX_MAGIC);
+    idx.extend(2u32.to_be_bytes());
+    let mut fanout = [0u32; 256];
+    for (hash, _, _) in index {
+        fanout[hash.as_bytes()[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        idx.extend(total.to_be_bytes());
+    }
+    for (hash, _, _) in index {
+        idx.extend(hash.as_bytes());
+    }
+    for (_, crc, _) in index {
+        idx.extend(crc.to_be_bytes());
+    }
+    let mut large_offsets = Vec::new();
+    for (_, _, offset) in index {
+        if *offset < 0x8000_0000 {
+            idx.extend((*offset as u32).to_be_bytes());
+        } else {
+            idx.extend((0x8000_0000 | large_offsets.len() as u32).to_be_bytes());
+            large_offsets.push(*offset);
+        }
+    }
+    for offset in large_offsets {
+        idx.extend(offset.to_be_bytes());
+    }
+    idx.extend(pack_checksum.as_bytes());
+    let checksum = pack_checksum.algorithm().hash(&idx);
+    idx.extend(checksum.as_bytes());
+
+    let dir = idx_path.parent().context("pack index has a directory")?;
+    let mut tempfile = tempfile::NamedTempFile::new_in(dir).context("create temporary index")?;
+    tempfile.write_all(&idx).context("write pack index")?;
+    finish_pack_file(tempfile, idx_path, fsync)
+}
+
+fn write_delta_size(delta: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            delta.push(byte);
+            return;
+        }
+        delta.push(byte | 0x80);
+    }
+}
+
+fn flush_insert(delta: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    for chunk in pending.chunks(0x7f) {
+        delta.push(chunk.len() as u8);
+        delta.extend(chunk);
+    }
+    pending.clear();
+}
+
+fn write_copy(delta: &mut Vec<u8>, offset: usize, size: usize) {
+    let mut instruction = 0x80u8;
+    let mut args = Vec::new();
+    for i in 0..4 {
+        let byte = (offset >> (8 * i)) as u8;
+        if byte != 0 {
+            instruction |= 1 << i;
+            args.push(byte);
+        }
+    }
+    // a size of 0x10000 is encoded as no size bytes at all
+    if size != 0x10000 {
+        for i in 0..3 {
+            let byte = (size >> (8 * i)) as u8;
+            if byte != 0 {
+                instruction |= 1 << (4 + i);
+                args.push(byte);
+            }
+        }
+    }
+    delta.push(instruction);
+    delta.extend(args);
+}
+
+/// Encodes `target` as copies from `base` plus inserted bytes, the inverse of [`apply_delta`].
+pub(crate) fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_delta_size(&mut delta, base.len());
+    write_delta_size(&mut delta, target.len());
+
+    let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(DELTA_BLOCK - 1)).step_by(DELTA_BLOCK) {
+        blocks
+            .entry(&base[offset..offset + DELTA_BLOCK])
+            .or_default()
+            .push(offset);
+    }
+
+    let mut pending = Vec::new();
+    let mut at = 0;
+    while at < target.len() {
+        let mut best = (0, 0);
+        if at + DELTA_BLOCK <= target.len() {
+            if let Some(candidates) = blocks.get(&target[at..at + DELTA_BLOCK]) {
+                for &offset in candidates {
+                    let len = base[offset..]
+                        .iter()
+                        .zip(&target[at..])
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if len > best.1 {
+                        best = (offset, len);
+                    }
+                }
+            }
+        }
+        if best.1 < DELTA_BLOCK {
+            pending.push(target[at]);
+            at += 1;
+            continue;
+        }
+        flush_insert(&mut delta, &mut pending);
+        let (mut offset, mut len) = best;
+        at += len;
+        while len > 0 {
+            let chunk = len.min(0x10000);
+            write_copy(&mut delta, offset, chunk);
+            offset += chunk;
+            len -= chunk;
+        }
+    }
+    flush_insert(&mut delta, &mut pending);
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryObjectStore;
+
+    #[test]
+    fn test_apply_delta() -> anyhow::Result<()> {
+        let base = b"hello world";
+        // base 11, target 16, copy 6 bytes from offset 0, insert "there", copy "world"
+        let delta = [11, 16, 0x90, 6, 5, b't', b'h', b'e', b'r', b'e', 0x91, 6, 5];
+        let target = apply_delta(base, &delta)?;
+        assert_eq!(target, b"hello thereworld");
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_delta_round_trip() -> anyhow::Result<()> {
+        let base: Vec<u8> = (1..=2000)
+            .flat_map(|n| format!("{n}\n").into_bytes())
+            .collect();
+        let mut target = base.clone();
+        target.splice(100..120, b"something else entirely".iter().copied());
+        target.extend(b"2001\n");
+
+        let delta = create_delta(&base, &target);
+        assert!(delta.len() < 100, "delta is {} bytes", delta.len());
+        assert_eq!(apply_delta(&base, &delta)?, target);
+        assert_eq!(apply_delta(b"", &create_delta(b"", b"new"))?, b"new");
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_pack_round_trip() -> anyhow::Result<()> {
+        let store = MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let mut hashes = Vec::new();
+        for lines in [200, 201, 5] {
+            let data: String = (1..=lines).map(|n| format!("{n}\n")).collect();
+            hashes.push(
+                Object {
+                    object_type: ObjectType::Blob,
+                    expected_size: data.len() as u64,
+                    reader: data.as_bytes(),
+                }
+                .write_to_objects(&store)?,
+            );
+        }
+        let tmp_dir = tempfile::tempdir()?;
+        let written = write_pack(&store, &hashes, tmp_dir.path(), false)?;
+        assert_eq!(written.objects, 3);
+        assert_eq!(written.deltas, 1);
+
+        let pack = Pack::open(
+            &written.pack_path.with_extension("idx"),
+            HashAlgorithm::Sha1,
+        )?;
+        for hash in &hashes {
+            let (object_type, data) = pack.read(hash)?.expect("object is in the pack");
+            assert_eq!(object_type, ObjectType::Blob);
+            assert_eq!(data, Object::read(&store, &hash.to_string())?.into_bytes()?);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! Pathspecs: the patterns commands like `add` and `rm` take to pick the paths they work on.
+
+use std::{
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, ensure};
+
+use crate::wildmatch::wildmatch;
+
+/// How a path matched one item of a pathspec.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum PathspecMatch {
+    /// The item names the path itself.
+    Exact,
+    /// The item names a directory the path is in.
+    Directory,
+    /// The item is a glob pattern matching the path.
+    Glob,
+}
+
+#[derive(Debug, Clone)]
+struct PathspecItem {
+    /// As given on the command line, for messages.
+    original: String,
+    /// Relative to the top of the worktree; empty for the whole tree.
+    pattern: Vec<u8>,
+    /// Only matches what's inside a directory, when written with a trailing `/`.
+    dir_only: bool,
+    is_glob: bool,
+}
+
+/// The paths a command should touch, relative to the top of the worktree.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Pathspec {
+    items: Vec<PathspecItem>,
+}
+
+impl Pathspec {
+    /// Relative arguments are taken relative to the top of `worktree`, absolute ones have to
+    /// be inside it. `.` selects everything.
+    pub(crate) fn new(worktree: &Path, args: &[String]) -> anyhow::Result<Self> {
+        let worktree = worktree
+            .canonicalize()
+            .unwrap_or_else(|_| worktree.to_path_buf());
+        let mut items = Vec::new();
+        for arg in args {
+            let path = Path::new(arg);
+            let relative = if path.is_absolute() {
+                let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+                match path.strip_prefix(&worktree) {
+                    Ok(relative) => relative.to_path_buf(),
+                    Err(_) => bail!("'{arg}' is outside repository at '{}'", worktree.display()),
+                }
+            } else {
+                path.to_path_buf()
+            };
+            let mut normalized = PathBuf::new();
+            for component in relative.components() {
+                match component {
+                    Component::Normal(name) => normalized.push(name),
+                    Component::ParentDir => ensure!(
+                        normalized.pop(),
+                        "'{arg}' is outside repository at '{}'",
+                        worktree.display()
+                    ),
+                    _ => {}
+                }
+            }
+            let pattern = normalized.as_os_str().as_bytes().to_vec();
+            items.push(PathspecItem {
+                original: arg.clone(),
+                dir_only: arg.ends_with('/') && !pattern.is_empty(),
+                is_glob: pattern
+                    .iter()
+                    .any(|b| matches!(b, b'*' | b'?' | b'[' | b'\\')),
+                pattern,
+            });
+        }
+        Ok(Self { items })
+    }
+
+    /// Whether any patterns were given; a pathspec without any matches every path.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The item at `i` as it was written.
+    pub(crate) fn original(&self, i: usize) -> &str {
+        &self.items[i].original
+    }
+
+    pub(crate) fn matches(&self, path: &[u8]) -> bool {
+        self.is_empty() || (0..self.items.len()).any(|i| self.match_item(i, path).is_some())
+    }
+
+    /// How the item at `i` matches `path`, if it does.
+    pub(crate) fn match_item(&self, i: usize, path: &[u8]) -> Option<PathspecMatch> {
+        let item = &self.items[i];
+        let pattern = &item.pattern[..];
+        if pattern.is_empty()
+            || (path.starts_with(pattern) && path.get(pattern.len()) == Some(&b'/'))
+        {
+            return Some(PathspecMatch::Directory);
+        }
+        if path == pattern && !item.dir_only {
+            return Some(PathspecMatch::Exact);
+        }
+        if item.is_glob && wildmatch(pattern, path, false) {
+            return Some(PathspecMatch::Glob);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pathspec() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let absolute = worktree.join("docs").display().to_string();
+        let pathspec = Pathspec::new(
+            worktree,
+            &[
+                "src/./main.rs".into(),
+                "lib/".into(),
+                "*.md".into(),
+                absolute,
+            ],
+        )?;
+        assert_eq!(
+            pathspec.match_item(0, b"src/main.rs"),
+            Some(PathspecMatch::Exact)
+        );
+        assert_eq!(pathspec.match_item(1, b"lib"), None);
+        assert_eq!(
+            pathspec.match_item(1, b"lib/a.rs"),
+            Some(PathspecMatch::Directory)
+        );
+        assert_eq!(
+            pathspec.match_item(2, b"notes/todo.md"),
+            Some(PathspecMatch::Glob)
+        );
+        assert_eq!(
+            pathspec.match_item(3, b"docs/index.html"),
+            Some(PathspecMatch::Directory)
+        );
+        assert!(!pathspec.matches(b"src/lib.rs"));
+        assert!(!pathspec.matches(b"docsite"));
+
+        let everything = Pathspec::new(worktree, &[".".into()])?;
+        assert!(everything.matches(b"any/path"));
+        assert!(Pathspec::new(worktree, &["../elsewhere".into()]).is_err());
+        assert!(Pathspec::new(worktree, &["/elsewhere".into()]).is_err());
+        Ok(())
+    }
+}
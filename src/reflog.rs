@@ -0,0 +1,155 @@
+//! Reflogs: where `HEAD` and each branch pointed before, kept under `.git/logs`.
+
+use anyhow::Context;
+
+use crate::{
+    commit::Signature,
+    hash::ObjectId,
+    object::{Object, ObjectType},
+    repository::Repository,
+    tag::Tag,
+};
+
+/// Whether updates to `name` start a reflog when it doesn't have one yet. Like git that is
+/// `HEAD` and the branches unless `core.logAllRefUpdates` says otherwise, which bare
+/// repositories default to.
+fn logs_updates(repo: &Repository, name: &str) -> anyhow::Result<bool> {
+    let config = repo.config()?;
+    let bare = config.get_bool("core.bare")?.unwrap_or(false);
+    let enabled = match config.get("core.logallrefupdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => return Ok(true),
+        Some(_) => config.get_bool("core.logallrefupdates")?.unwrap_or(false),
+        None => !bare,
+    };
+    Ok(enabled
+        && (name == "HEAD"
+            || ["refs/heads/", "refs/remotes/"]
+                .iter()
+                .any(|p| name.starts_with(p))))
+}
+
+/// Records that the ref `name` moved from `old` to `new`, `None` standing for a ref that
+/// didn't or won't exist.
+pub(crate) fn append(
+    repo: &Repository,
+    name: &str,
+    old: Option<&str>,
+    new: Option<&str>,
+    message: &str,
+) -> anyhow::Result<()> {
+    let path = format!("logs/{name}");
+    let existing = repo.files.read(&path)?;
+    if existing.is_none() && !logs_updates(repo, name)? {
+        return Ok(());
+    }
+    let null = "0".repeat(repo.hash_algorithm().hex_len());
+    let mut log = existing.unwrap_or_default();
+    // a message only ever takes up one line
+    let message = message.lines().collect::<Vec<_>>().join(" ");
+    log.extend(
+        format!(
+            "{} {} {}\t{message}\n",
+            old.unwrap_or(&null),
+            new.unwrap_or(&null),
+            Signature::now()?
+        )
+        .as_bytes(),
+    );
+    repo.files
+        .write(&path, &log)
+        .with_context(|| format!("write reflog of '{name}'"))
+}
+
+/// Moves the reflog of the ref `old` over to `new`, which replaces whatever `new` had.
+pub(crate) fn rename(repo: &Repository, old: &str, new: &str) -> anyhow::Result<()> {
+    let old_path = format!("logs/{old}");
+    let new_path = format!("logs/{new}");
+    // gone first, as `topic/a` has to be before it can become `topic`
+    let log = repo.files.read(&old_path)?;
+    repo.files.remove(&old_path)?;
+    repo.files.remove(&new_path)?;
+    match log {
+        Some(log) => repo.files.write(&new_path, &log),
+        None => Ok(()),
+    }
+}
+
+/// Forgets the history of the ref `name`, as when the ref itself goes.
+pub(crate) fn remove(repo: &Repository, name: &str) -> anyhow::Result<()> {
+    repo.files.remove(&format!("logs/{name}"))
+}
+
+/// Every object id the reflogs recorded, old and new, with the reflog it's in. Ids of objects
+/// that have since been pruned are left out, as are the null ids of refs being created or
+/// deleted.
+pub(crate) fn logged_objects(repo: &Repository) -> anyhow::Result<Vec<(String, ObjectId)>> {
+    let mut objects = Vec::new();
+    for path in repo.files.list("logs")? {
+        let log = repo.files.read(&path)?.unwrap_or_default();
+        for line in String::from_utf8_lossy(&log).lines() {
+            for hex in line.split(' ').take(2) {
+                let Ok(id) = hex.parse::<ObjectId>() else {
+                    continue;
+                };
+                if hex.bytes().any(|b| b != b'0') && repo.objects.contains(hex)? {
+                    objects.push((path.clone(), id));
+                }
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// What was checked out before the last checkout recorded in the reflog of `HEAD`, as it was
+/// named then: what `switch -` goes back to.
+pub(crate) fn previous_checkout(repo: &Repository) -> anyhow::Result<Option<String>> {
+    let log = repo.files.read("logs/HEAD")?.unwrap_or_default();
+    let log = String::from_utf8_lossy(&log);
+    Ok(log.lines().rev().find_map(|line| {
+        let (_, message) = line.split_once('\t')?;
+        let (from, _) = message
+            .strip_prefix("checkout: moving from ")?
+            .split_once(" to ")?;
+        Some(from.to_string())
+    }))
+}
+
+/// How a detached `HEAD` at `head` is described, going by the last checkout recorded in its
+/// reflog: `at <what was checked out>`, or `from <it>` once `HEAD` has moved on. That is the
+/// tag, remote-tracking branch or full branch ref named, or the abbreviated commit.
+pub(crate) fn describe_detached(repo: &Repository, head: &ObjectId) -> anyhow::Result<String> {
+    let log = repo.files.read("logs/HEAD")?.unwrap_or_default();
+    let log = String::from_utf8_lossy(&log);
+    let checkout = log.lines().rev().find_map(|line| {
+        let (ids, message) = line.split_once('\t')?;
+        let (_, target) = message
+            .strip_prefix("checkout: moving from ")?
+            .rsplit_once(" to ")?;
+        let new = ids.split(' ').nth(1)?.parse::<ObjectId>().ok()?;
+        Some((new, target.to_string()))
+    });
+    let Some((checked_out, target)) = checkout else {
+        return Ok(format!("at {}", &head.to_string()[..7]));
+    };
+    let mut name = checked_out.to_string()[..7].to_string();
+    if let Some((reference, mut id)) = repo.expand_ref(&target)? {
+        // a tag names the commit it points to
+        while id != checked_out
+            && repo.objects.contains(&id.to_string())?
+            && Object::read(repo.objects.as_ref(), &id.to_string())?.object_type == ObjectType::Tag
+        {
+            id = Tag::read(repo.objects.as_ref(), &id.to_string())?
+                .object
+                .parse()?;
+        }
+        if id == checked_out {
+            name = ["refs/tags/", "refs/remotes/"]
+                .iter()
+                .find_map(|prefix| reference.strip_prefix(prefix))
+                .unwrap_or(&reference)
+                .to_string();
+        }
+    }
+    let at = if checked_out == *head { "at" } else { "from" };
+    Ok(format!("{at} {name}"))
+}
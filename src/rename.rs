@@ -0,0 +1,500 @@
+//! Rename and copy detection: pairing paths that went away, and for copies paths that
+//! changed, with new paths holding the same or similar contents, the way git's
+//! diffcore-rename does.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+};
+
+use anyhow::bail;
+
+use crate::{
+    diff::{is_binary, DiffFile, FilePair, Rename},
+    diff_tree::Version,
+    store::ObjectStore,
+};
+
+/// The score of files with the same contents. Scores are kept in these units rather than in
+/// percent so `-M` thresholds like `-M.875` keep their precision.
+pub const MAX_SCORE: u32 = 60000;
+/// Files must be at least this alike, 50%, to be called a rename unless told otherwise.
+pub const DEFAULT_SCORE: u32 = MAX_SCORE / 2;
+/// How many of the most similar sources are kept for each new path.
+const CANDIDATES: usize = 4;
+/// Chunk hashes are taken modulo this prime.
+const HASH_BASE: u32 = 107927;
+
+/// How renames are looked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenameOptions {
+    /// Look for copies of changed paths too.
+    pub copies: bool,
+    /// With `copies`, of paths that didn't change as well, as `-C -C` does. Those have to be
+    /// handed in with [`add_unmodified`].
+    pub harder: bool,
+    /// How alike, out of [`MAX_SCORE`], files must be to count.
+    pub min_score: u32,
+    /// Comparing every remaining source with every new path is skipped when there are more
+    /// pairs than this squared. 0 means no limit.
+    pub limit: usize,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            copies: false,
+            harder: false,
+            min_score: DEFAULT_SCORE,
+            limit: 1000,
+        }
+    }
+}
+
+/// Parses a similarity threshold the way `-M` and `-C` take it: digits are a fraction after
+/// an implied decimal point, so `5` and `.5` are 50%, unless followed by `%`.
+pub fn parse_score(arg: &str) -> anyhow::Result<u32> {
+    let (mut number, mut scale, mut dot) = (0u64, 1u64, false);
+    let mut chars = arg.chars();
+    for c in chars.by_ref() {
+        match c {
+            '.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            '%' => {
+                scale = if dot { scale * 100 } else { 100 };
+                break;
+            }
+            '0'..='9' => {
+                if scale < 100000 {
+                    scale *= 10;
+                    number = number * 10 + u64::from(c as u8 - b'0');
+                }
+            }
+            _ => bail!("invalid argument to -M/-C: {arg}"),
+        }
+    }
+    if chars.next().is_some() {
+        bail!("invalid argument to -M/-C: {arg}");
+    }
+    Ok(if number >= scale {
+        MAX_SCORE
+    } else {
+        (u64::from(MAX_SCORE) * number / scale) as u32
+    })
+}
+
+/// A source that may have become a destination, and how alike they are.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    score: u32,
+    /// Whether the two have the same file name.
+    same_name: bool,
+    destination: usize,
+    source: usize,
+}
+
+/// Orders candidates best first, empty slots last.
+fn compare(a: &Option<Candidate>, b: &Option<Candidate>) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => b.score.cmp(&a.score).then(b.same_name.cmp(&a.same_name)),
+    }
+}
+
+/// The part of `path` after its last slash.
+fn file_name(path: &[u8]) -> &[u8] {
+    path.rsplit(|&b| b == b'/').next().unwrap_or(path)
+}
+
+fn is_regular(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+/// How many bytes of `data` fall into each chunk hash, a chunk being a line or 64 bytes of
+/// one. What is left after the last full chunk doesn't count.
+fn chunk_counts(data: &[u8]) -> HashMap<u32, usize> {
+    let text = !is_binary(data);
+    let mut counts = HashMap::new();
+    let (mut accum1, mut accum2, mut length) = (0u32, 0u32, 0);
+    for (i, &c) in data.iter().enumerate() {
+        // line endings don't make text files differ
+        if text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old_accum1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old_accum1 >> 25);
+        accum1 = accum1.wrapping_add(u32::from(c));
+        length += 1;
+        if length < 64 && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE;
+        *counts.entry(hash).or_insert(0) += length;
+        (accum1, accum2, length) = (0, 0, 0);
+    }
+    counts
+}
+
+/// The files being compared, with their chunk counts worked out as they are needed.
+struct Similarity<'a> {
+    pairs: &'a [FilePair],
+    chunks: Vec<Option<HashMap<u32, usize>>>,
+    min_score: u32,
+}
+
+impl Similarity<'_> {
+    /// The side of pair `index` that takes part: the old one of sources, the new one of
+    /// destinations.
+    fn file(&self, index: usize) -> &DiffFile {
+        let pair = &self.pairs[index];
+        pair.new
+            .as_ref()
+            .filter(|_| pair.old.is_none())
+            .or(pair.old.as_ref())
+            .expect("pair has a side")
+    }
+
+    /// How much of the destination, out of [`MAX_SCORE`], is material from the source. Only
+    /// regular files are compared, and files whose sizes differ too much to reach the
+    /// minimum score score 0.
+    fn score(&mut self, source: usize, destination: usize) -> u32 {
+        let (old, new) = (self.file(source), self.file(destination));
+        if !is_regular(old.mode) || !is_regular(new.mode) {
+            return 0;
+        }
+        let (old_size, new_size) = (old.data.len() as u64, new.data.len() as u64);
+        let max_size = old_size.max(new_size);
+        let delta = max_size - old_size.min(new_size);
+        if max_size * u64::from(MAX_SCORE - self.min_score) < delta * u64::from(MAX_SCORE) {
+            return 0;
+        }
+        if new_size == 0 {
+            return 0;
+        }
+        for index in [source, destination] {
+            if self.chunks[index].is_none() {
+                self.chunks[index] = Some(chunk_counts(&self.file(index).data));
+            }
+        }
+        let (Some(old_chunks), Some(new_chunks)) =
+            (&self.chunks[source], &self.chunks[destination])
+        else {
+            return 0;
+        };
+        let copied: usize = old_chunks
+            .iter()
+            .map(|(hash, &count)| count.min(new_chunks.get(hash).copied().unwrap_or(0)))
+            .sum();
+        (copied as u64 * u64::from(MAX_SCORE) / max_size) as u32
+    }
+}
+
+/// Whether the two paths end in the same file name.
+fn same_file_name(a: &[u8], b: &[u8]) -> bool {
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        i -= 1;
+        j -= 1;
+        if a[i] != b[j] {
+            return false;
+        }
+        if a[i] == b'/' {
+            return true;
+        }
+    }
+    (i == 0 || a[i - 1] == b'/') && (j == 0 || b[j - 1] == b'/')
+}
+
+/// Adds the files of `old_files` that `pairs` has no change for, with the same file on both
+/// sides, as sources for [`RenameOptions::harder`]. [`detect_renames`] leaves them out again.
+pub fn add_unmodified(
+    objects: &dyn ObjectStore,
+    pairs: &mut Vec<FilePair>,
+    old_files: impl IntoIterator<Item = (Vec<u8>, Version)>,
+) -> anyhow::Result<()> {
+    let changed: BTreeSet<Vec<u8>> = pairs
+        .iter()
+        .filter_map(|pair| pair.old.as_ref())
+        .map(|old| old.path.clone())
+        .collect();
+    for (path, version) in old_files {
+        if !changed.contains(&path) {
+            let file = DiffFile::read(objects, &path, version)?;
+            pairs.push(FilePair::new(Some(file.clone()), Some(file)));
+        }
+    }
+    pairs.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(())
+}
+
+fn is_unmodified(pair: &FilePair) -> bool {
+    match (&pair.old, &pair.new) {
+        (Some(old), Some(new)) => (old.mode, old.id) == (new.mode, new.id),
+        _ => false,
+    }
+}
+
+/// Replaces the additions in `pairs` that came from a deleted path, or with copies from any
+/// changed one, by a pair from that path, and drops the deletions that were renamed. Exact
+/// matches come first, then files with the same name elsewhere, then the most similar
+/// files of all. When there are too many files for the last step it is skipped, and the
+/// limit that would have been needed is returned alongside.
+pub fn detect_renames(
+    pairs: Vec<FilePair>,
+    options: &RenameOptions,
+) -> (Vec<FilePair>, Option<usize>) {
+    let mut sources = Vec::new();
+    let mut destinations = Vec::new();
+    // how many times the old side of each pair has been used, counting a changed path that
+    // stays as one use
+    let mut used = vec![0; pairs.len()];
+    for (i, pair) in pairs.iter().enumerate() {
+        match (&pair.old, &pair.new) {
+            (None, Some(_)) => destinations.push(i),
+            (Some(_), None) => sources.push(i),
+            (Some(_), Some(_)) if options.copies => {
+                used[i] += 1;
+                sources.push(i);
+            }
+            _ => {}
+        }
+    }
+    if destinations.is_empty() || sources.is_empty() {
+        let pairs = pairs
+            .into_iter()
+            .filter(|pair| !is_unmodified(pair))
+            .collect();
+        return (pairs, None);
+    }
+
+    // the source and score each destination was matched with
+    let mut found: HashMap<usize, (usize, u32)> = HashMap::new();
+
+    // the same contents, preferring sources not used yet and with the same file name
+    for &destination in &destinations {
+        let new = pairs[destination].new.as_ref().expect("destination");
+        let mut best = None;
+        for &source in &sources {
+            let old = pairs[source].old.as_ref().expect("source");
+            if old.id != new.id {
+                continue;
+            }
+            if (!is_regular(old.mode) || !is_regular(new.mode)) && old.mode != new.mode {
+                continue;
+            }
+            if used[source] > 0 && !options.copies {
+                continue;
+            }
+            let score =
+                usize::from(used[source] == 0) + usize::from(same_file_name(&old.path, &new.path));
+            if best.map_or(true, |(_, best_score)| score > best_score) {
+                best = Some((source, score));
+                if score == 2 {
+                    break;
+                }
+            }
+        }
+        if let Some((source, _)) = best {
+            used[source] += 1;
+            found.insert(destination, (source, MAX_SCORE));
+        }
+    }
+
+    let mut similarity = Similarity {
+        pairs: &pairs,
+        chunks: vec![None; pairs.len()],
+        min_score: options.min_score,
+    };
+    if !options.copies {
+        sources.retain(|&source| used[source] == 0);
+        // a file name found once among the sources and once among the destinations most
+        // likely moved, so it needs less similarity than the rest
+        let min_score = options.min_score + (MAX_SCORE - options.min_score) / 2;
+        let mut source_names: HashMap<&[u8], Option<usize>> = HashMap::new();
+        for &source in &sources {
+            let name = file_name(&pairs[source].old.as_ref().expect("source").path);
+            source_names
+                .entry(name)
+                .and_modify(|unique| *unique = None)
+                .or_insert(Some(source));
+        }
+        let mut destination_names: HashMap<&[u8], Option<usize>> = HashMap::new();
+        for &destination in destinations.iter().filter(|d| !found.contains_key(d)) {
+            let name = file_name(&pairs[destination].new.as_ref().expect("destination").path);
+            destination_names
+                .entry(name)
+                .and_modify(|unique| *unique = None)
+                .or_insert(Some(destination));
+        }
+        for &source in &sources {
+            let name = file_name(&pairs[source].old.as_ref().expect("source").path);
+            let (Some(Some(source)), Some(Some(destination))) =
+                (source_names.get(name), destination_names.get(name))
+            else {
+                continue;
+            };
+            if found.contains_key(destination) {
+                continue;
+            }
+            let score = similarity.score(*source, *destination);
+            if score >= min_score {
+                used[*source] += 1;
+                found.insert(*destination, (*source, score));
+            }
+        }
+        sources.retain(|&source| used[source] == 0);
+    }
+
+    let destinations: Vec<usize> = destinations
+        .into_iter()
+        .filter(|destination| !found.contains_key(destination))
+        .collect();
+    let mut needed = None;
+    if !destinations.is_empty() && !sources.is_empty() {
+        if options.limit > 0 && destinations.len() * sources.len() > options.limit * options.limit {
+            needed = Some(destinations.len().max(sources.len()));
+        } else {
+            let mut matrix = Vec::with_capacity(destinations.len() * CANDIDATES);
+            for &destination in &destinations {
+                let mut best: [Option<Candidate>; CANDIDATES] = [None; CANDIDATES];
+                for &source in &sources {
+                    let candidate = Some(Candidate {
+                        score: similarity.score(source, destination),
+                        same_name: same_file_name(
+                            &similarity.file(source).path,
+                            &similarity.file(destination).path,
+                        ),
+                        destination,
+                        source,
+                    });
+                    let mut worst = 0;
+                    for i in 1..CANDIDATES {
+                        if compare(&best[i], &best[worst]) == Ordering::Greater {
+                            worst = i;
+                        }
+                    }
+                    if compare(&best[worst], &candidate) == Ordering::Greater {
+                        best[worst] = candidate;
+                    }
+                }
+                matrix.extend(best);
+            }
+            matrix.sort_by(compare);
+            // renames first, then what is left over as copies
+            for copies in [false, true] {
+                if copies && !options.copies {
+                    break;
+                }
+                for candidate in matrix.iter().map_while(|candidate| *candidate) {
+                    if candidate.score < options.min_score {
+                        break;
+                    }
+                    if found.contains_key(&candidate.destination)
+                        || (!copies && used[candidate.source] > 0)
+                    {
+                        continue;
+                    }
+                    used[candidate.source] += 1;
+                    found.insert(candidate.destination, (candidate.source, candidate.score));
+                }
+            }
+        }
+    }
+
+    // each use of a source but the last is a copy; a changed path that stays counts as the
+    // last use
+    let mut uses_left = used.clone();
+    let mut renamed = Vec::with_capacity(pairs.len());
+    for (i, pair) in pairs.iter().enumerate() {
+        if let Some(&(source, score)) = found.get(&i) {
+            uses_left[source] -= 1;
+            renamed.push(FilePair {
+                old: pairs[source].old.clone(),
+                new: pair.new.clone(),
+                rename: Some(Rename {
+                    score,
+                    copy: uses_left[source] > 0,
+                }),
+            });
+        } else if (pair.new.is_some() || used[i] == 0) && !is_unmodified(pair) {
+            renamed.push(pair.clone());
+        }
+    }
+    (renamed, needed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::HashAlgorithm;
+
+    fn file(path: &str, data: &str) -> Option<DiffFile> {
+        Some(DiffFile {
+            path: path.as_bytes().to_vec(),
+            mode: 0o100644,
+            id: HashAlgorithm::Sha1.hash(data.as_bytes()),
+            data: data.as_bytes().to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_detect_renames() -> anyhow::Result<()> {
+        assert_eq!(parse_score("5")?, 30000);
+        assert_eq!(parse_score("90%")?, 54000);
+        assert_eq!(parse_score(".875")?, 52500);
+        assert!(parse_score("5x").is_err());
+
+        let lines: String = (1..=40).map(|i| format!("line {i}\n")).collect();
+        let pairs = vec![
+            FilePair::new(file("a.txt", &lines), None),
+            FilePair::new(None, file("b.txt", &format!("{lines}line 41\n"))),
+            FilePair::new(file("same", "moved\n"), None),
+            FilePair::new(None, file("sub/same", "moved\n")),
+            FilePair::new(None, file("z.txt", "unrelated\n")),
+        ];
+        let (renamed, needed) = detect_renames(pairs.clone(), &RenameOptions::default());
+        assert_eq!(needed, None);
+        let statuses: Vec<String> = renamed
+            .iter()
+            .map(|pair| format!("{} {}", pair.status(), pair.paths()))
+            .collect();
+        assert_eq!(
+            statuses,
+            ["R097 a.txt\tb.txt", "R100 same\tsub/same", "A z.txt"]
+        );
+        assert_eq!(renamed[1].display_name(), "same => sub/same");
+
+        // too many files to compare them all, though exact renames are still found
+        let options = RenameOptions {
+            limit: 1,
+            ..RenameOptions::default()
+        };
+        let (renamed, needed) = detect_renames(pairs, &options);
+        assert_eq!(needed, Some(2));
+        assert_eq!(renamed.len(), 4);
+
+        // `-C -C` copies from a file that stayed the same, which isn't shown itself
+        let pairs = vec![
+            FilePair::new(None, file("copy", &lines)),
+            FilePair::new(file("kept", &lines), file("kept", &lines)),
+            FilePair::new(file("other", "x\n"), file("other", "x\n")),
+        ];
+        let options = RenameOptions {
+            copies: true,
+            harder: true,
+            ..RenameOptions::default()
+        };
+        let (renamed, _) = detect_renames(pairs, &options);
+        let statuses: Vec<String> = renamed
+            .iter()
+            .map(|pair| format!("{} {}", pair.status(), pair.paths()))
+            .collect();
+        assert_eq!(statuses, ["C100 kept\tcopy"]);
+        Ok(())
+    }
+}
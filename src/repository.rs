@@ -0,0 +1,740 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::{self, Debug},
+    fs,
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    commit::Commit,
+    config::{Fsync, GitConfig},
+    hash::{HashAlgorithm, ObjectId},
+    index::Index,
+    object::{Object, ObjectType},
+    store::{CompositeObjectStore, MemoryObjectStore, ObjectStore},
+    tag::Tag,
+};
+
+/// The error for an abbreviated object id more than one object starts with.
+#[derive(Debug)]
+pub struct AmbiguousName(pub String);
+
+impl fmt::Display for AmbiguousName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "short object id '{}' is ambiguous", self.0)
+    }
+}
+
+impl std::error::Error for AmbiguousName {}
+
+/// Everything under `.git` that isn't an object: `HEAD`, refs, `packed-refs`, `index`, `config`.
+/// Paths are relative to the `.git` directory and always use `/`.
+pub trait RepositoryFiles: Debug {
+    /// Sets up an empty repository layout.
+    fn init(&self) -> anyhow::Result<()>;
+
+    fn read(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    fn write(&self, path: &str, contents: &[u8]) -> anyhow::Result<()>;
+
+    /// Removing a file that doesn't exist is not an error.
+    fn remove(&self, path: &str) -> anyhow::Result<()>;
+
+    /// Every file below `dir`, recursively.
+    fn list(&self, dir: &str) -> anyhow::Result<Vec<String>>;
+}
+
+#[derive(Debug)]
+pub struct DiskFiles {
+    dot_git_path: PathBuf,
+    fsync: Fsync,
+}
+
+impl DiskFiles {
+    pub fn new(dot_git_path: impl Into<PathBuf>) -> Self {
+        Self {
+            dot_git_path: dot_git_path.into(),
+            fsync: Fsync::default(),
+        }
+    }
+
+    /// Which of the files written are flushed to disk before being moved into place.
+    pub fn with_fsync(mut self, fsync: Fsync) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    fn needs_fsync(&self, path: &str) -> bool {
+        match path {
+            "index" => self.fsync.index,
+            "HEAD" | "packed-refs" => self.fsync.refs,
+            _ => path.starts_with("refs/") && self.fsync.refs,
+        }
+    }
+
+    fn list_into(&self, dir: &Path, prefix: &str, files: &mut Vec<String>) -> anyhow::Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("list {}", dir.display())),
+        };
+        for entry in entries {
+            let entry =
+                entry.with_context(|| format!("bad directory entry in {}", dir.display()))?;
+            let name = format!("{prefix}/{}", entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                self.list_into(&entry.path(), &name, files)?;
+            } else {
+                files.push(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RepositoryFiles for DiskFiles {
+    fn init(&self) -> anyhow::Result<()> {
+        fs::create_dir(&self.dot_git_path)?;
+        fs::create_dir(self.dot_git_path.join("objects"))?;
+        fs::create_dir(self.dot_git_path.join("refs"))?;
+        Ok(())
+    }
+
+    fn read(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let file = self.dot_git_path.join(path);
+        match fs::read(&file) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            // like `refs/heads/topic` when there are branches below it
+            Err(_) if file.is_dir() => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("read .git/{path}")),
+        }
+    }
+
+    /// Writes to a temporary file beside `path` and renames it over `path`, so readers never
+    /// see a partly written file. The file gets the usual `0666` less the umask, rather than
+    /// the private mode temporary files are made with.
+    fn write(&self, path: &str, contents: &[u8]) -> anyhow::Result<()> {
+        let fsync = self.needs_fsync(path);
+        let path = self.dot_git_path.join(path);
+        let parent = path.parent().context("file has a directory")?;
+        fs::create_dir_all(parent)
+            .with_context(|| format!("create directory {}", parent.display()))?;
+        let mut tempfile = tempfile::Builder::new()
+            .permissions(fs::Permissions::from_mode(0o666))
+            .tempfile_in(parent)
+            .with_context(|| format!("create temporary file in {}", parent.display()))?;
+        tempfile
+            .write_all(contents)
+            .with_context(|| format!("write {}", path.display()))?;
+        if fsync {
+            tempfile
+                .as_file()
+                .sync_all()
+                .with_context(|| format!("fsync {}", path.display()))?;
+        }
+        tempfile
+            .persist(&path)
+            .with_context(|| format!("move {} into place", path.display()))?;
+        Ok(())
+    }
+
+    /// Directories left empty go too, so that `refs/heads/topic/a` going makes room for a
+    /// `refs/heads/topic`, down to `refs/<kind>` and `logs/refs/<kind>`, which stay.
+    fn remove(&self, path: &str) -> anyhow::Result<()> {
+        match fs::remove_file(self.dot_git_path.join(path)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("remove .git/{path}")),
+        }
+        let kept = if path.starts_with("logs/") { 3 } else { 2 };
+        let mut dir = path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            if parent.split('/').count() <= kept
+                || fs::remove_dir(self.dot_git_path.join(parent)).is_err()
+            {
+                break;
+            }
+            dir = parent;
+        }
+        Ok(())
+    }
+
+    fn list(&self, dir: &str) -> anyhow::Result<Vec<String>> {
+        let mut files = Vec::new();
+        self.list_into(&self.dot_git_path.join(dir), dir, &mut files)?;
+        files.sort();
+        Ok(files)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryFiles {
+    files: RefCell<BTreeMap<String, Vec<u8>>>,
+}
+
+impl RepositoryFiles for MemoryFiles {
+    fn init(&self) -> anyhow::Result<()> {
+        if !self.files.borrow().is_empty() {
+            bail!("repository already exists");
+        }
+        Ok(())
+    }
+
+    fn read(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.files.borrow().get(path).cloned())
+    }
+
+    fn write(&self, path: &str, contents: &[u8]) -> anyhow::Result<()> {
+        self.files
+            .borrow_mut()
+            .insert(path.to_string(), contents.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> anyhow::Result<()> {
+        self.files.borrow_mut().remove(path);
+        Ok(())
+    }
+
+    fn list(&self, dir: &str) -> anyhow::Result<Vec<String>> {
+        let prefix = format!("{dir}/");
+        Ok(self
+            .files
+            .borrow()
+            .keys()
+            .filter(|path| path.starts_with(&prefix))
+            .cloned()
+            .collect())
+    }
+}
+
+fn object_format(config: &GitConfig) -> anyhow::Result<HashAlgorithm> {
+    let version = config.get("core.repositoryformatversion").unwrap_or("0");
+    match version {
+        "0" => Ok(HashAlgorithm::Sha1),
+        "1" => config
+            .get("extensions.objectformat")
+            .map_or(Ok(HashAlgorithm::Sha1), str::parse),
+        _ => bail!("unsupported repository format version {version}"),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Head {
+    /// `HEAD` points at a branch, which may not have any commits yet.
+    Branch(String),
+    Detached(String),
+}
+
+/// A git repository: its object database plus the rest of the `.git` directory.
+#[derive(Debug)]
+pub struct Repository {
+    pub objects: Box<dyn ObjectStore>,
+    pub files: Box<dyn RepositoryFiles>,
+}
+
+impl Repository {
+    pub fn new(objects: Box<dyn ObjectStore>, files: Box<dyn RepositoryFiles>) -> Self {
+        Self { objects, files }
+    }
+
+    /// The repository stored on disk at `dot_git_path`, using the object format from its config.
+    /// A repository that doesn't exist yet is opened as SHA-1.
+    pub fn open(dot_git_path: &Path) -> anyhow::Result<Self> {
+        let files = DiskFiles::new(dot_git_path);
+        let config = match files.read("config")? {
+            Some(contents) => {
+                let contents = String::from_utf8(contents).context("config isn't valid UTF-8")?;
+                GitConfig::parse(&contents).context("parse config")?
+            }
+            None => GitConfig::default(),
+        };
+        let hash = object_format(&config)?;
+        let fsync = config.fsync()?;
+        Ok(Self::new(
+            Box::new(CompositeObjectStore::open_with_fsync(
+                dot_git_path,
+                hash,
+                fsync.loose_objects,
+            )),
+            Box::new(files.with_fsync(fsync)),
+        ))
+    }
+
+    /// The repository on disk at `dot_git_path`, naming objects with `hash`.
+    pub fn on_disk(dot_git_path: &Path, hash: HashAlgorithm) -> Self {
+        Self::new(
+            Box::new(CompositeObjectStore::open(dot_git_path, hash)),
+            Box::new(DiskFiles::new(dot_git_path)),
+        )
+    }
+
+    /// A repository that never touches the filesystem.
+    pub fn in_memory() -> Self {
+        Self::in_memory_with(HashAlgorithm::default())
+    }
+
+    pub fn in_memory_with(hash: HashAlgorithm) -> Self {
+        Self::new(
+            Box::new(MemoryObjectStore::new(hash)),
+            Box::<MemoryFiles>::default(),
+        )
+    }
+
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.objects.hash_algorithm()
+    }
+
+    /// Creates an empty repository whose object format is that of the object store.
+    pub fn init(&self) -> anyhow::Result<()> {
+        self.files.init().context("create repository layout")?;
+        self.files
+            .write("HEAD", b"ref: refs/heads/master\n")
+            .context("write HEAD")?;
+        let hash = self.hash_algorithm();
+        let mut config = GitConfig::default();
+        if hash == HashAlgorithm::Sha1 {
+            config.set("core.repositoryformatversion", "0");
+        } else {
+            // extensions are only honored by repository format version 1
+            config.set("core.repositoryformatversion", "1");
+        }
+        config.set("core.filemode", "true");
+        config.set("core.bare", "false");
+        if hash != HashAlgorithm::Sha1 {
+            config.set("extensions.objectformat", &hash.to_string());
+        }
+        self.write_config(&config)
+    }
+
+    pub fn config(&self) -> anyhow::Result<GitConfig> {
+        let Some(contents) = self.files.read("config")? else {
+            return Ok(GitConfig::default());
+        };
+        let contents = String::from_utf8(contents).context("config isn't valid UTF-8")?;
+        GitConfig::parse(&contents).context("parse config")
+    }
+
+    pub fn write_config(&self, config: &GitConfig) -> anyhow::Result<()> {
+        self.files
+            .write("config", config.to_string().as_bytes())
+            .context("write config")
+    }
+
+    /// The staging area, empty if there is no `index` file yet. A split index is combined
+    /// with the shared index it refers to.
+    pub fn index(&self) -> anyhow::Result<Index> {
+        let hash = self.hash_algorithm();
+        let Some(contents) = self.files.read("index")? else {
+            return Ok(Index::default());
+        };
+        let mut index = Index::parse(&contents, hash).context("parse index")?;
+        if let Some(link) = &index.link {
+            let name = format!("sharedindex.{}", link.shared_index);
+            let shared = self
+                .files
+                .read(&name)?
+                .with_context(|| format!("{name} is missing"))?;
+            let shared = Index::parse(&shared, hash).with_context(|| format!("parse {name}"))?;
+            index.merge_shared(shared)?;
+        }
+        Ok(index)
+    }
+
+    pub fn write_index(&self, index: &Index) -> anyhow::Result<()> {
+        self.files
+            .write("index", &index.to_bytes(self.hash_algorithm())?)
+            .context("write index")
+    }
+
+    pub fn head(&self) -> anyhow::Result<Head> {
+        let head = self.files.read("HEAD")?.context("HEAD is missing")?;
+        let head = String::from_utf8(head).context("HEAD isn't valid UTF-8")?;
+        match head.strip_prefix("ref: ") {
+            Some(target) => Ok(Head::Branch(target.trim().to_string())),
+            None => Ok(Head::Detached(head.trim().to_string())),
+        }
+    }
+
+    /// The hash a ref points at, following symbolic refs such as `HEAD`.
+    /// Returns `None` for refs that don't exist yet, like the branch of a fresh repository.
+    pub fn resolve_ref(&self, name: &str) -> anyhow::Result<Option<String>> {
+        let mut name = name.to_string();
+        for _ in 0..5 {
+            let Some(value) = self.read_ref(&name)? else {
+                return Ok(None);
+            };
+            match value.strip_prefix("ref: ") {
+                Some(target) => name = target.to_string(),
+                None => return Ok(Some(value)),
+            }
+        }
+        bail!("too many levels of symbolic refs resolving '{name}'")
+    }
+
+    /// The raw contents of a loose or packed ref, without following symbolic refs.
+    pub fn read_ref(&self, name: &str) -> anyhow::Result<Option<String>> {
+        if let Some(value) = self.files.read(name)? {
+            let value = String::from_utf8(value)
+                .with_context(|| format!("ref '{name}' isn't valid UTF-8"))?;
+            return Ok(Some(value.trim().to_string()));
+        }
+        Ok(self
+            .packed_refs()?
+            .into_iter()
+            .find(|(ref_name, _)| ref_name == name)
+            .map(|(_, hash)| hash))
+    }
+
+    pub fn update_ref(&self, name: &str, hash: &str) -> anyhow::Result<()> {
+        self.files
+            .write(name, format!("{hash}\n").as_bytes())
+            .with_context(|| format!("update ref '{name}'"))
+    }
+
+    /// Removes the ref `name`, whether it is loose or packed.
+    pub fn delete_ref(&self, name: &str) -> anyhow::Result<()> {
+        self.files.remove(name)?;
+        let Some(contents) = self.files.read("packed-refs")? else {
+            return Ok(());
+        };
+        let contents = String::from_utf8(contents).context("packed-refs isn't valid UTF-8")?;
+        let mut kept = String::new();
+        let mut dropping = false;
+        for line in contents.lines() {
+            // a `^` line holds what the ref before it peels to
+            if !line.starts_with('^') {
+                dropping = line
+                    .split_once(' ')
+                    .is_some_and(|(_, ref_name)| ref_name == name);
+            }
+            if !dropping {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+        if kept != contents {
+            self.files
+                .write("packed-refs", kept.as_bytes())
+                .context("write packed-refs")?;
+        }
+        Ok(())
+    }
+
+    /// Every ref under `refs/` with the hash it points at, sorted by name.
+    pub fn refs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let mut refs: BTreeMap<String, String> = self.packed_refs()?.into_iter().collect();
+        for name in self.files.list("refs")? {
+            if let Some(hash) = self.resolve_ref(&name)? {
+                refs.insert(name, hash);
+            }
+        }
+        Ok(refs.into_iter().collect())
+    }
+
+    /// The ref `name` stands for, `HEAD` or one under `refs/` which may be shortened the way
+    /// git allows (`master`, `tags/v1`, `origin/master`), with the object it points at.
+    pub fn expand_ref(&self, name: &str) -> anyhow::Result<Option<(String, ObjectId)>> {
+        for candidate in [
+            name.to_string(),
+            format!("refs/{name}"),
+            format!("refs/tags/{name}"),
+            format!("refs/heads/{name}"),
+            format!("refs/remotes/{name}"),
+            format!("refs/remotes/{name}/HEAD"),
+        ] {
+            if candidate != "HEAD" && !candidate.starts_with("refs/") {
+                continue;
+            }
+            if let Some(target) = self.resolve_ref(&candidate)? {
+                let id = ObjectId::from_hex(&target)
+                    .with_context(|| format!("ref '{candidate}' is not an object id"))?;
+                return Ok(Some((candidate, id)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The object `name` refers to: a full or abbreviated object id, `HEAD`, or a ref name,
+    /// which may be shortened the way git allows (`master`, `tags/v1`, `origin/master`).
+    /// Returns `None` if nothing matches and errors if an abbreviation is ambiguous.
+    pub fn resolve_name(&self, name: &str) -> anyhow::Result<Option<ObjectId>> {
+        let hash = self.hash_algorithm();
+        if name.len() == hash.hex_len() {
+            if let Ok(id) = ObjectId::from_hex(name) {
+                return Ok(self.objects.contains(name)?.then_some(id));
+            }
+        }
+        if let Some((_, id)) = self.expand_ref(name)? {
+            return Ok(Some(id));
+        }
+        if name.len() < 4 || !name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        let prefix = name.to_ascii_lowercase();
+        let mut matches = self
+            .objects
+            .iter()?
+            .filter(|id| id.to_string().starts_with(&prefix));
+        let found = matches.next();
+        if found.is_some() && matches.any(|id| Some(id) != found) {
+            return Err(AmbiguousName(name.to_string()).into());
+        }
+        Ok(found)
+    }
+
+    /// Like [`Repository::resolve_name`], also following `~<n>` and `^<n>` suffixes to
+    /// ancestors: `HEAD~2` is the first parent's first parent, `HEAD^2` the second parent.
+    pub fn resolve_revision(&self, revision: &str) -> anyhow::Result<Option<ObjectId>> {
+        let split = revision.find(['~', '^']).unwrap_or(revision.len());
+        let (name, mut suffix) = revision.split_at(split);
+        let name = if name == "@" { "HEAD" } else { name };
+        let Some(mut id) = self.resolve_name(name)? else {
+            return Ok(None);
+        };
+        while let Some(operator) = suffix.chars().next() {
+            let digits = suffix[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(suffix.len(), |end| end + 1);
+            let count = match &suffix[1..digits] {
+                "" => 1,
+                count => count
+                    .parse()
+                    .with_context(|| format!("bad revision '{revision}'"))?,
+            };
+            suffix = &suffix[digits..];
+            // tags name the commits they point to
+            while Object::read(self.objects.as_ref(), &id.to_string())?.object_type
+                == ObjectType::Tag
+            {
+                id = Tag::read(self.objects.as_ref(), &id.to_string())?
+                    .object
+                    .parse()?;
+            }
+            let parent = |id: ObjectId, n: usize| -> anyhow::Result<Option<ObjectId>> {
+                let commit = Commit::read(self.objects.as_ref(), &id.to_string())?;
+                commit
+                    .parents
+                    .get(n - 1)
+                    .map(|parent| parent.parse())
+                    .transpose()
+            };
+            match (operator, count) {
+                ('^', 0) => {}
+                ('^', n) => match parent(id, n)? {
+                    Some(parent) => id = parent,
+                    None => return Ok(None),
+                },
+                (_, n) => {
+                    for _ in 0..n {
+                        match parent(id, 1)? {
+                            Some(parent) => id = parent,
+                            None => return Ok(None),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Some(id))
+    }
+
+    /// Moves every loose ref under `refs/` into `packed-refs`, recording what annotated tags
+    /// peel to. Symbolic refs stay loose.
+    pub fn pack_refs(&self) -> anyhow::Result<()> {
+        let mut refs: BTreeMap<String, String> = self.packed_refs()?.into_iter().collect();
+        let mut loose = Vec::new();
+        for name in self.files.list("refs")? {
+            let Some(value) = self.read_ref(&name)? else {
+                continue;
+            };
+            if value.starts_with("ref: ") {
+                continue;
+            }
+            refs.insert(name.clone(), value);
+            loose.push(name);
+        }
+
+        let mut contents = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+        for (name, hash) in &refs {
+            contents.push_str(&format!("{hash} {name}\n"));
+            if let Some(peeled) = self.peel_tag(hash)? {
+                contents.push_str(&format!("^{peeled}\n"));
+            }
+        }
+        self.files
+            .write("packed-refs", contents.as_bytes())
+            .context("write packed-refs")?;
+        for name in loose {
+            self.files.remove(&name)?;
+        }
+        Ok(())
+    }
+
+    /// The object an annotated tag ultimately points at, or `None` if `hash` isn't a tag.
+    fn peel_tag(&self, hash: &str) -> anyhow::Result<Option<String>> {
+        let mut peeled = None;
+        let mut hash = hash.to_string();
+        while self.objects.contains(&hash)?
+            && Object::read(self.objects.as_ref(), &hash)?.object_type == ObjectType::Tag
+        {
+            hash = Tag::read(self.objects.as_ref(), &hash)?.object;
+            peeled = Some(hash.clone());
+        }
+        Ok(peeled)
+    }
+
+    fn packed_refs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let Some(contents) = self.files.read("packed-refs")? else {
+            return Ok(Vec::new());
+        };
+        let contents = String::from_utf8(contents).context("packed-refs isn't valid UTF-8")?;
+        let mut refs = Vec::new();
+        for line in contents.lines() {
+            if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+                continue;
+            }
+            let Some((hash, name)) = line.split_once(' ') else {
+                bail!("invalid packed-refs line '{line}'");
+            };
+            refs.push((name.to_string(), hash.to_string()));
+        }
+        Ok(refs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_memory_refs() -> anyhow::Result<()> {
+        let repo = Repository::in_memory();
+        repo.init()?;
+        assert_eq!(repo.head()?, Head::Branch("refs/heads/master".to_string()));
+        assert_eq!(repo.resolve_ref("HEAD")?, None);
+        repo.update_ref(
+            "refs/heads/master",
+            "388f38c3f0448af4063b9b9b727ae38ef73a23e2",
+        )?;
+        assert_eq!(
+            repo.resolve_ref("HEAD")?.as_deref(),
+            Some("388f38c3f0448af4063b9b9b727ae38ef73a23e2")
+        );
+        assert!(repo.init().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_refs() -> anyhow::Result<()> {
+        let repo = Repository::open(Path::new("tests/fixtures/packed-app/dot-git"))?;
+        assert_eq!(
+            repo.refs()?,
+            vec![
+                (
+                    "refs/heads/master".to_string(),
+                    "388f38c3f0448af4063b9b9b727ae38ef73a23e2".to_string()
+                ),
+                (
+                    "refs/tags/v1".to_string(),
+                    "3844e8f7fa93d6aac63576776d275dead317af0a".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            repo.resolve_ref("HEAD")?.as_deref(),
+            Some("388f38c3f0448af4063b9b9b727ae38ef73a23e2")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_writes_are_atomic() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dot_git_path = temp_dir.path().join(".git");
+        Repository::on_disk(&dot_git_path, HashAlgorithm::Sha1).init()?;
+        let repo = Repository::open(&dot_git_path)?;
+        let mut config = repo.config()?;
+        config.set("core.fsync", "all");
+        repo.write_config(&config)?;
+
+        let repo = Repository::open(&dot_git_path)?;
+        let hash = Object {
+            object_type: ObjectType::Blob,
+            expected_size: 2,
+            reader: "hi".as_bytes(),
+        }
+        .write_to_objects(repo.objects.as_ref())?
+        .to_string();
+        repo.update_ref("refs/heads/master", &hash)?;
+        assert_eq!(repo.resolve_ref("HEAD")?, Some(hash.clone()));
+        assert_eq!(
+            fs::read_dir(dot_git_path.join("refs/heads"))?.count(),
+            1,
+            "no temporary files are left behind"
+        );
+        assert_eq!(
+            fs::read_dir(dot_git_path.join("objects").join(&hash[..2]))?.count(),
+            1
+        );
+        // the same mode as any other new file, not the temporary file's 0600
+        fs::write(temp_dir.path().join("plain"), "")?;
+        let mode = |path: &Path| -> anyhow::Result<u32> {
+            Ok(fs::metadata(path)?.permissions().mode() & 0o777)
+        };
+        assert_eq!(
+            mode(&dot_git_path.join("refs/heads/master"))?,
+            mode(&temp_dir.path().join("plain"))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_name() -> anyhow::Result<()> {
+        let repo = Repository::open(Path::new("tests/fixtures/packed-app/dot-git"))?;
+        let master = ObjectId::from_hex("388f38c3f0448af4063b9b9b727ae38ef73a23e2")?;
+        let tag = ObjectId::from_hex("3844e8f7fa93d6aac63576776d275dead317af0a")?;
+        assert_eq!(repo.resolve_name("HEAD")?, Some(master));
+        assert_eq!(repo.resolve_name("master")?, Some(master));
+        assert_eq!(repo.resolve_name("refs/heads/master")?, Some(master));
+        assert_eq!(repo.resolve_name("v1")?, Some(tag));
+        assert_eq!(repo.resolve_name("388f38c")?, Some(master));
+        assert_eq!(repo.resolve_name(&master.to_string())?, Some(master));
+        assert_eq!(repo.resolve_name("nope")?, None);
+        assert_eq!(
+            repo.resolve_name("ea8c4bf7f35f6f77f75d92ad8ce8349f6e81ddba")?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pack_refs() -> anyhow::Result<()> {
+        let repo = Repository::in_memory();
+        repo.init()?;
+        let commit = "388f38c3f0448af4063b9b9b727ae38ef73a23e2";
+        repo.update_ref("refs/heads/master", commit)?;
+        repo.update_ref("refs/heads/topic", commit)?;
+        repo.files
+            .write("refs/remotes/origin/HEAD", b"ref: refs/heads/master\n")?;
+        let refs = repo.refs()?;
+
+        repo.pack_refs()?;
+        assert_eq!(repo.refs()?, refs);
+        assert_eq!(repo.files.read("refs/heads/master")?, None);
+        assert!(repo.files.read("refs/remotes/origin/HEAD")?.is_some());
+        assert_eq!(
+            String::from_utf8(repo.files.read("packed-refs")?.unwrap())?,
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n\
+                 {commit} refs/heads/master\n{commit} refs/heads/topic\n"
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! `add` and `rm`: recording worktree changes in the index.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::bail;
+
+use crate::{
+    commit::Commit,
+    hash::ObjectId,
+    ignore::IgnoreRules,
+    index::{Index, IndexEntry, StatData},
+    pathspec::{Pathspec, PathspecMatch},
+    repository::Repository,
+    tree::tree_files,
+    worktree::{disk_path, hash_file, remove_file, worktree_files, ModeConfig},
+};
+
+/// Stages the files matching `pathspec`: new and modified files, and the removal of tracked
+/// files that are gone. With `update_only` files the index doesn't know about are left alone,
+/// and unless `force` is set so are ignored ones. Naming only ignored files is an error, after
+/// the rest has been staged.
+pub(crate) fn add(
+    repo: &Repository,
+    worktree: &Path,
+    pathspec: &Pathspec,
+    update_only: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    let mut index = repo.index()?;
+    let files = worktree_files(worktree)?;
+    let modes = ModeConfig::load(repo)?;
+    let ignore = IgnoreRules::load(repo, worktree)?;
+    let skipped = |index: &Index, path: &[u8]| {
+        index.stages(path).is_empty() && (update_only || (!force && ignore.is_ignored(path, false)))
+    };
+
+    let mut ignored = Vec::new();
+    for i in 0..pathspec.len() {
+        let matches_entry = index
+            .entries
+            .iter()
+            .any(|entry| pathspec.match_item(i, &entry.path).is_some());
+        let mut matched_files = files
+            .iter()
+            .filter(|file| pathspec.match_item(i, &file.path).is_some());
+        let matches_file = !update_only && matched_files.clone().next().is_some();
+        if !matches_file && !matches_entry {
+            bail!(
+                "pathspec '{}' did not match any files",
+                pathspec.original(i)
+            );
+        }
+        if !update_only && !matches_entry && matched_files.all(|file| skipped(&index, &file.path)) {
+            ignored.push(pathspec.original(i).to_string());
+        }
+    }
+
+    for file in files.iter().filter(|file| pathspec.matches(&file.path)) {
+        if skipped(&index, &file.path) {
+            continue;
+        }
+        let existing = index.get(&file.path);
+        let mode = modes.mode_of(&file.metadata, existing.map(|existing| existing.mode));
+        if existing.is_some_and(|existing| {
+            existing.mode == mode && existing.stat == StatData::from_metadata(&file.metadata)
+        }) {
+            continue;
+        }
+        let hash = hash_file(repo, worktree, &file.path, &file.metadata, mode, true)?;
+        index.add(IndexEntry::new(
+            file.path.clone(),
+            mode,
+            hash,
+            &file.metadata,
+        ));
+    }
+
+    let gone: Vec<Vec<u8>> = index
+        .entries
+        .iter()
+        .filter(|entry| pathspec.matches(&entry.path))
+        .filter(|entry| {
+            files
+                .binary_search_by(|file| file.path.cmp(&entry.path))
+                .is_err()
+        })
+        // submodules that aren't checked out are still there
+        .filter(|entry| entry.mode != 0o160000 || !disk_path(worktree, &entry.path).is_dir())
+        .map(|entry| entry.path.clone())
+        .collect();
+    for path in gone {
+        index.remove(&path);
+    }
+    repo.write_index(&index)?;
+    if !ignored.is_empty() {
+        bail!(
+            "The following paths are ignored by one of your .gitignore files:\n{}\n\
+             hint: Use -f if you really want to add them.",
+            ignored.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Unstages the tracked paths matching `pathspec`, also deleting them from the worktree
+/// unless `cached` is set, and returns them. Without `force` paths whose staged or worktree
+/// contents would be lost are refused.
+pub(crate) fn rm(
+    repo: &Repository,
+    worktree: &Path,
+    pathspec: &Pathspec,
+    cached: bool,
+    recursive: bool,
+    force: bool,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut index = repo.index()?;
+    let mut paths: Vec<Vec<u8>> = Vec::new();
+    for i in 0..pathspec.len() {
+        let mut matched = false;
+        for entry in &index.entries {
+            let Some(how) = pathspec.match_item(i, &entry.path) else {
+                continue;
+            };
+            if how == PathspecMatch::Directory && !recursive {
+                bail!(
+                    "not removing '{}' recursively without -r",
+                    pathspec.original(i)
+                );
+            }
+            matched = true;
+            paths.push(entry.path.clone());
+        }
+        if !matched {
+            bail!(
+                "pathspec '{}' did not match any files",
+                pathspec.original(i)
+            );
+        }
+    }
+    paths.sort();
+    paths.dedup();
+
+    if !force {
+        let head = head_files(repo)?;
+        let modes = ModeConfig::load(repo)?;
+        for path in &paths {
+            // unmerged paths have nothing of their own to lose
+            let Some(entry) = index.get(path) else {
+                continue;
+            };
+            let staged = head.get(path) != Some(&(entry.mode, entry.hash));
+            let modified = worktree_differs(repo, worktree, entry, modes)?;
+            let path = String::from_utf8_lossy(path);
+            if cached {
+                if staged && modified {
+                    bail!(
+                        "the following file has staged content different from both the\n\
+                         file and the HEAD:\n    {path}\n(use -f to force removal)"
+                    );
+                }
+            } else if staged {
+                bail!(
+                    "the following file has changes staged in the index:\n    {path}\n\
+                     (use --cached to keep the file, or -f to force removal)"
+                );
+            } else if modified {
+                bail!(
+                    "the following file has local modifications:\n    {path}\n\
+                     (use --cached to keep the file, or -f to force removal)"
+                );
+            }
+        }
+    }
+
+    for path in &paths {
+        index.remove(path);
+        if !cached {
+            remove_file(worktree, path)?;
+        }
+    }
+    repo.write_index(&index)?;
+    Ok(paths)
+}
+
+/// The files of the commit HEAD points to, nothing before the first commit.
+pub(crate) fn head_files(repo: &Repository) -> anyhow::Result<BTreeMap<Vec<u8>, (u32, ObjectId)>> {
+    let Some(head) = repo.resolve_name("HEAD")? else {
+        return Ok(BTreeMap::new());
+    };
+    let commit = Commit::read(repo.objects.as_ref(), &head.to_string())?;
+    tree_files(repo.objects.as_ref(), &commit.tree)
+}
+
+/// Whether the worktree file of `entry` has contents or a mode other than what's staged. A
+/// missing file doesn't count as different.
+fn worktree_differs(
+    repo: &Repository,
+    worktree: &Path,
+    entry: &IndexEntry,
+    modes: ModeConfig,
+) -> anyhow::Result<bool> {
+    let Ok(metadata) = fs::symlink_metadata(disk_path(worktree, &entry.path)) else {
+        return Ok(false);
+    };
+    if metadata.is_dir() {
+        return Ok(false);
+    }
+    let mode = modes.mode_of(&metadata, Some(entry.mode));
+    if mode != entry.mode {
+        return Ok(true);
+    }
+    Ok(hash_file(repo, worktree, &entry.path, &metadata, mode, false)? != entry.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{commit::commit, hash::HashAlgorithm};
+
+    use super::*;
+
+    fn staged(repo: &Repository) -> anyhow::Result<Vec<String>> {
+        Ok(repo
+            .index()?
+            .entries
+            .iter()
+            .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+            .collect())
+    }
+
+    #[test]
+    fn test_add_and_rm() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let repo = Repository::on_disk(&worktree.join(".git"), HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::create_dir_all(worktree.join("src/bin"))?;
+        fs::write(worktree.join("README"), "hello\n")?;
+        fs::write(worktree.join("src/lib.rs"), "lib\n")?;
+        fs::write(worktree.join("src/bin/main.rs"), "main\n")?;
+        let pathspec = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            Pathspec::new(worktree, &args)
+        };
+
+        add(&repo, worktree, &pathspec(&["src"])?, false, false)?;
+        assert_eq!(staged(&repo)?, ["src/bin/main.rs", "src/lib.rs"]);
+        assert!(add(&repo, worktree, &pathspec(&["missing"])?, false, false).is_err());
+        // -u only knows about tracked files
+        assert!(add(&repo, worktree, &pathspec(&["README"])?, true, false).is_err());
+
+        fs::write(worktree.join("src/lib.rs"), "changed\n")?;
+        fs::remove_file(worktree.join("src/bin/main.rs"))?;
+        add(&repo, worktree, &Pathspec::default(), true, false)?;
+        let index = repo.index()?;
+        assert_eq!(staged(&repo)?, ["src/lib.rs"]);
+        assert_eq!(
+            index.get(b"src/lib.rs").map(|entry| entry.hash.to_string()),
+            Some("5ea2ed416fbd4a4cbe227b75fe255dd7fa6bd4d6".to_string())
+        );
+        fs::write(worktree.join("debug.log"), "log\n")?;
+        fs::create_dir_all(worktree.join(".git/info"))?;
+        fs::write(worktree.join(".git/info/exclude"), "*.log\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        assert_eq!(staged(&repo)?, ["README", "src/lib.rs"]);
+        assert!(add(&repo, worktree, &pathspec(&["debug.log"])?, false, false).is_err());
+        add(&repo, worktree, &pathspec(&["debug.log"])?, false, true)?;
+        assert_eq!(staged(&repo)?, ["README", "debug.log", "src/lib.rs"]);
+        rm(
+            &repo,
+            worktree,
+            &pathspec(&["debug.log"])?,
+            true,
+            false,
+            true,
+        )?;
+        commit(&repo, "initial")?;
+
+        // removing needs -r for directories and refuses to lose changes
+        assert!(rm(&repo, worktree, &pathspec(&["src"])?, false, false, false).is_err());
+        fs::write(worktree.join("src/lib.rs"), "edited\n")?;
+        assert!(rm(&repo, worktree, &pathspec(&["src"])?, false, true, false).is_err());
+        let removed = rm(&repo, worktree, &pathspec(&["src"])?, true, true, false)?;
+        assert_eq!(removed, [b"src/lib.rs".to_vec()]);
+        assert!(worktree.join("src/lib.rs").exists());
+        assert_eq!(staged(&repo)?, ["README"]);
+
+        rm(
+            &repo,
+            worktree,
+            &pathspec(&["README"])?,
+            false,
+            false,
+            false,
+        )?;
+        assert!(!worktree.join("README").exists());
+        assert!(staged(&repo)?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,1014 @@
+//! `status`: how the HEAD commit, the index and the worktree differ.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    branch::{upstream, Upstream},
+    commit::Commit,
+    hash::{HashAlgorithm, ObjectId},
+    ignore::IgnoreRules,
+    index::{index_time, Index, IndexEntry, StatData},
+    reflog::describe_detached,
+    repository::{Head, Repository},
+    tree::tree_files,
+    worktree::{
+        disk_path, file_mode, hash_file, is_nested_repo, nested_repo_head, worktree_files,
+        ModeConfig,
+    },
+};
+
+/// How `status` prints what it found.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum StatusFormat {
+    #[default]
+    Long,
+    Short,
+    PorcelainV1,
+    PorcelainV2,
+}
+
+/// Which untracked files `status` lists.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum UntrackedFiles {
+    No,
+    /// Directories without any tracked files are shown as a whole.
+    #[default]
+    Normal,
+    All,
+}
+
+impl FromStr for UntrackedFiles {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no" | "false" => Ok(UntrackedFiles::No),
+            "normal" | "true" => Ok(UntrackedFiles::Normal),
+            "all" => Ok(UntrackedFiles::All),
+            _ => bail!("invalid untracked files mode '{s}'"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StatusOptions {
+    pub format: StatusFormat,
+    /// Show the branch and its upstream in the short and porcelain formats.
+    pub branch: bool,
+    /// Terminate entries with NUL and leave paths unquoted.
+    pub nul: bool,
+    pub untracked: UntrackedFiles,
+    pub ignored: bool,
+}
+
+/// A mode and object, as recorded in a tree or the index.
+type Version = (u32, ObjectId);
+
+type Paths = Vec<Vec<u8>>;
+
+/// How a path changed from one side of a comparison to the other.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Change {
+    Unchanged,
+    Modified,
+    /// Became a symlink instead of a file, or the other way around.
+    TypeChanged,
+    Added,
+    Deleted,
+}
+
+impl Change {
+    fn between(old: Option<Version>, new: Option<Version>) -> Self {
+        match (old, new) {
+            (None, None) => Change::Unchanged,
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Deleted,
+            (Some((old_mode, _)), Some((new_mode, _))) if !same_type(old_mode, new_mode) => {
+                Change::TypeChanged
+            }
+            (Some(old), Some(new)) if old != new => Change::Modified,
+            _ => Change::Unchanged,
+        }
+    }
+
+    fn code(self) -> char {
+        match self {
+            Change::Unchanged => ' ',
+            Change::Modified => 'M',
+            Change::TypeChanged => 'T',
+            Change::Added => 'A',
+            Change::Deleted => 'D',
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Change::Unchanged => "",
+            Change::Modified => "modified:",
+            Change::TypeChanged => "typechange:",
+            Change::Added => "new file:",
+            Change::Deleted => "deleted:",
+        }
+    }
+}
+
+fn same_type(a: u32, b: u32) -> bool {
+    a & 0o170000 == b & 0o170000
+}
+
+/// A tracked path that differs somewhere.
+#[derive(Debug)]
+pub(crate) struct StatusEntry {
+    pub(crate) path: Vec<u8>,
+    pub(crate) head: Option<Version>,
+    /// `None` when the index doesn't have it, or only records the intent to add it.
+    pub(crate) index: Option<Version>,
+    /// `None` when the file is missing from the worktree.
+    pub(crate) worktree_mode: Option<u32>,
+    /// HEAD compared to the index.
+    pub(crate) staged: Change,
+    /// The index compared to the worktree.
+    pub(crate) unstaged: Change,
+}
+
+impl StatusEntry {
+    fn is_submodule(&self) -> bool {
+        self.index
+            .or(self.head)
+            .is_some_and(|(mode, _)| mode == 0o160000)
+    }
+}
+
+/// A path with conflicting stages in the index.
+#[derive(Debug)]
+pub(crate) struct UnmergedEntry {
+    pub(crate) path: Vec<u8>,
+    /// The base, ours and theirs.
+    pub(crate) stages: [Option<Version>; 3],
+    pub(crate) worktree_mode: Option<u32>,
+}
+
+impl UnmergedEntry {
+    fn code(&self) -> &'static str {
+        match self.stages.map(|stage| stage.is_some()) {
+            [true, false, false] => "DD",
+            [false, true, false] => "AU",
+            [true, true, false] => "UD",
+            [false, false, true] => "UA",
+            [true, false, true] => "DU",
+            [false, true, true] => "AA",
+            _ => "UU",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self.code() {
+            "DD" => "both deleted:",
+            "AU" => "added by us:",
+            "UD" => "deleted by them:",
+            "UA" => "added by them:",
+            "DU" => "deleted by us:",
+            "AA" => "both added:",
+            _ => "both modified:",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct BranchStatus {
+    /// `None` when HEAD is detached.
+    pub(crate) name: Option<String>,
+    /// `None` before the first commit.
+    pub(crate) commit: Option<ObjectId>,
+    /// How a detached HEAD got where it is, like `at v1.0` or `from 1a2b3c4`.
+    pub(crate) detached: Option<String>,
+    pub(crate) upstream: Option<Upstream>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Status {
+    pub(crate) hash_algorithm: HashAlgorithm,
+    pub(crate) branch: BranchStatus,
+    /// A merge stopped to let conflicts be resolved.
+    pub(crate) merging: bool,
+    /// Sorted by path.
+    pub(crate) entries: Vec<StatusEntry>,
+    pub(crate) unmerged: Vec<UnmergedEntry>,
+    /// Directories end in `/`.
+    pub(crate) untracked: Paths,
+    pub(crate) ignored: Paths,
+}
+
+/// Compares HEAD with the index and the index with the worktree, and finds the untracked and
+/// ignored files. Files whose stat data changed but whose contents didn't get their index
+/// entries refreshed, so they aren't hashed again next time.
+pub(crate) fn status(
+    repo: &Repository,
+    dot_git_path: &Path,
+    options: &StatusOptions,
+) -> anyhow::Result<Status> {
+    let worktree = dot_git_path
+        .parent()
+        .context("repository has no working tree")?;
+    let branch = branch_status(repo)?;
+    let head = match &branch.commit {
+        Some(hash) => {
+            let commit = Commit::read(repo.objects.as_ref(), &hash.to_string())?;
+            tree_files(repo.objects.as_ref(), &commit.tree)?
+        }
+        None => BTreeMap::new(),
+    };
+    let mut index = repo.index()?;
+    // entries modified at or after the index was written might have changed again since
+    // without their stat data showing it
+    let index_time = index_time(dot_git_path);
+    let modes = ModeConfig::load(repo)?;
+
+    let mut entries = Vec::new();
+    let mut unmerged = Vec::new();
+    let mut refreshed = Vec::new();
+    let mut i = 0;
+    while i < index.entries.len() {
+        let path = &index.entries[i].path;
+        let stages = index.stages(path);
+        i += stages.len();
+        // a directory only stands for a submodule
+        let is_gitlink = stages.iter().any(|entry| entry.mode == 0o160000);
+        let worktree_metadata = fs::symlink_metadata(disk_path(worktree, path))
+            .ok()
+            .filter(|metadata| !metadata.is_dir() || is_gitlink);
+        if stages[0].stage != 0 {
+            let mut versions = [None; 3];
+            for entry in stages {
+                versions[entry.stage as usize - 1] = Some((entry.mode, entry.hash));
+            }
+            unmerged.push(UnmergedEntry {
+                path: path.clone(),
+                stages: versions,
+                worktree_mode: worktree_metadata.as_ref().map(file_mode),
+            });
+            continue;
+        }
+
+        let entry = &stages[0];
+        let head_version = head.get(path).copied();
+        let (staged, unstaged) = if entry.intent_to_add {
+            (Change::Unchanged, Change::Added)
+        } else {
+            let staged = Change::between(head_version, Some((entry.mode, entry.hash)));
+            let unstaged = match &worktree_metadata {
+                None => Change::Deleted,
+                Some(metadata) => {
+                    let (change, stat) =
+                        worktree_change(repo, worktree, entry, metadata, modes, index_time)?;
+                    if let Some(stat) = stat {
+                        refreshed.push((entry.path.clone(), stat));
+                    }
+                    change
+                }
+            };
+            (staged, unstaged)
+        };
+        if staged != Change::Unchanged || unstaged != Change::Unchanged {
+            entries.push(StatusEntry {
+                path: path.clone(),
+                head: head_version,
+                index: (!entry.intent_to_add).then_some((entry.mode, entry.hash)),
+                worktree_mode: worktree_metadata
+                    .as_ref()
+                    .map(|metadata| modes.mode_of(metadata, Some(entry.mode))),
+                staged,
+                unstaged,
+            });
+        }
+    }
+    for (path, version) in &head {
+        if index.stages(path).is_empty() {
+            entries.push(StatusEntry {
+                path: path.clone(),
+                head: Some(*version),
+                index: None,
+                worktree_mode: None,
+                staged: Change::Deleted,
+                unstaged: Change::Unchanged,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if !refreshed.is_empty() {
+        for (path, stat) in refreshed {
+            if let Ok(i) = index
+                .entries
+                .binary_search_by(|entry| (&entry.path[..], entry.stage).cmp(&(&path[..], 0)))
+            {
+                index.entries[i].stat = stat;
+            }
+        }
+        repo.write_index(&index)?;
+    }
+
+    let (untracked, ignored) =
+        untracked_files(repo, worktree, &index, options.untracked, options.ignored)?;
+    Ok(Status {
+        hash_algorithm: repo.hash_algorithm(),
+        branch,
+        merging: repo.files.read("MERGE_HEAD")?.is_some(),
+        entries,
+        unmerged,
+        untracked,
+        ignored,
+    })
+}
+
+/// How the worktree file of `entry` differs from it, and the new stat data to record when
+/// only that changed.
+pub(crate) fn worktree_change(
+    repo: &Repository,
+    worktree: &Path,
+    entry: &IndexEntry,
+    metadata: &fs::Metadata,
+    modes: ModeConfig,
+    index_time: Option<(u32, u32)>,
+) -> anyhow::Result<(Change, Option<StatData>)> {
+    let mode = modes.mode_of(metadata, Some(entry.mode));
+    if !same_type(mode, entry.mode) {
+        return Ok((Change::TypeChanged, None));
+    }
+    if mode == 0o160000 {
+        // a submodule that isn't checked out doesn't count as changed
+        let path = disk_path(worktree, &entry.path);
+        let head = if is_nested_repo(&path) {
+            nested_repo_head(&path)?
+        } else {
+            None
+        };
+        let change = match head {
+            Some(head) if head != entry.hash => Change::Modified,
+            _ => Change::Unchanged,
+        };
+        return Ok((change, None));
+    }
+    let mode_changed = mode != entry.mode;
+    let stat = StatData::from_metadata(metadata);
+    if entry.is_stat_clean(&stat, index_time) {
+        let change = if mode_changed {
+            Change::Modified
+        } else {
+            Change::Unchanged
+        };
+        return Ok((change, None));
+    }
+    if mode_changed || hash_file(repo, worktree, &entry.path, metadata, mode, false)? != entry.hash
+    {
+        return Ok((Change::Modified, None));
+    }
+    Ok((Change::Unchanged, (stat != entry.stat).then_some(stat)))
+}
+
+fn branch_status(repo: &Repository) -> anyhow::Result<BranchStatus> {
+    let name = match repo.head()? {
+        Head::Branch(name) => name,
+        Head::Detached(hash) => {
+            let hash = hash.parse()?;
+            return Ok(BranchStatus {
+                name: None,
+                commit: Some(hash),
+                detached: Some(describe_detached(repo, &hash)?),
+                upstream: None,
+            });
+        }
+    };
+    let commit = match repo.resolve_ref(&name)? {
+        Some(hash) => Some(hash.parse::<ObjectId>()?),
+        None => None,
+    };
+    let short = name
+        .strip_prefix("refs/heads/")
+        .unwrap_or(&name)
+        .to_string();
+
+    let upstream = match &commit {
+        Some(commit) => upstream(repo, &repo.config()?, &short, commit)?,
+        None => None,
+    };
+    Ok(BranchStatus {
+        name: Some(short),
+        commit,
+        detached: None,
+        upstream,
+    })
+}
+
+/// Whether any index entry is inside `dir`, which ends in `/`.
+pub(crate) fn has_tracked_files(index: &Index, dir: &[u8]) -> bool {
+    let start = index.entries.partition_point(|entry| &entry.path[..] < dir);
+    index
+        .entries
+        .get(start)
+        .is_some_and(|entry| entry.path.starts_with(dir))
+}
+
+/// The untracked files and, if `show_ignored` is set, the ignored ones. Directories that hold
+/// nothing tracked are listed once as a whole, ending in `/`, unless `untracked` is `All`.
+pub(crate) fn untracked_files(
+    repo: &Repository,
+    worktree: &Path,
+    index: &Index,
+    untracked: UntrackedFiles,
+    show_ignored: bool,
+) -> anyhow::Result<(Paths, Paths)> {
+    if untracked == UntrackedFiles::No && !show_ignored {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let rules = IgnoreRules::load(repo, worktree)?;
+    let mut candidates = Vec::new();
+    // directories holding untracked files that aren't ignored
+    let mut visible_dirs = BTreeSet::new();
+    for file in worktree_files(worktree)? {
+        if !index.stages(&file.path).is_empty() {
+            continue;
+        }
+        let is_dir = file.metadata.is_dir();
+        let ignored = rules.is_ignored(&file.path, is_dir);
+        if !ignored {
+            for (i, _) in file.path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+                visible_dirs.insert(file.path[..=i].to_vec());
+            }
+        }
+        let mut path = file.path;
+        // nested repositories are shown like directories
+        if is_dir {
+            path.push(b'/');
+        }
+        candidates.push((path, ignored));
+    }
+
+    let collapse = |path: &[u8], ignored: bool| -> Vec<u8> {
+        if untracked != UntrackedFiles::All {
+            for (i, _) in path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+                let dir = &path[..=i];
+                if !has_tracked_files(index, dir) && (!ignored || !visible_dirs.contains(dir)) {
+                    return dir.to_vec();
+                }
+            }
+        }
+        path.to_vec()
+    };
+    let mut untracked_paths = BTreeSet::new();
+    let mut ignored_paths = BTreeSet::new();
+    for (path, is_ignored) in candidates {
+        if is_ignored && show_ignored {
+            ignored_paths.insert(collapse(&path, true));
+        } else if !is_ignored && untracked != UntrackedFiles::No {
+            untracked_paths.insert(collapse(&path, false));
+        }
+    }
+    Ok((
+        untracked_paths.into_iter().collect(),
+        ignored_paths.into_iter().collect(),
+    ))
+}
+
+/// Quotes a path the way git does when it has control characters, quotes, backslashes or
+/// non-ASCII bytes in it.
+pub(crate) fn quote_path(path: &[u8]) -> String {
+    quote(path, false)
+}
+
+/// Quotes `path` like [`quote_path`], and with `spaces` also when it has a space in it, as
+/// formats that separate fields with spaces need.
+fn quote(path: &[u8], spaces: bool) -> String {
+    if !path
+        .iter()
+        .any(|&b| b < 0x20 || b == b'"' || b == b'\\' || b >= 0x7f || (spaces && b == b' '))
+    {
+        return String::from_utf8_lossy(path).to_string();
+    }
+    let mut quoted = String::from("\"");
+    for &b in path {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{b:03o}")),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// Writes the status in the chosen format.
+pub(crate) fn write_status(
+    out: &mut impl Write,
+    status: &Status,
+    options: &StatusOptions,
+) -> anyhow::Result<()> {
+    match options.format {
+        StatusFormat::Long => write_long(out, status, options),
+        StatusFormat::Short | StatusFormat::PorcelainV1 => write_short(out, status, options),
+        StatusFormat::PorcelainV2 => write_porcelain_v2(out, status, options),
+    }
+}
+
+/// Writes how the branch compares to its upstream, with hints on what to do about it.
+pub(crate) fn write_tracking(out: &mut impl Write, upstream: &Upstream) -> anyhow::Result<()> {
+    let name = &upstream.name;
+    match upstream.ahead_behind {
+        None => {
+            writeln!(
+                out,
+                "Your branch is based on '{name}', but the upstream is gone."
+            )?;
+            writeln!(out, "  (use \"git branch --unset-upstream\" to fixup)")?;
+        }
+        Some((0, 0)) => writeln!(out, "Your branch is up to date with '{name}'.")?,
+        Some((ahead, 0)) => {
+            writeln!(
+                out,
+                "Your branch is ahead of '{name}' by {ahead} commit{}.",
+                plural(ahead)
+            )?;
+            writeln!(out, "  (use \"git push\" to publish your local commits)")?;
+        }
+        Some((0, behind)) => {
+            writeln!(
+                out,
+                "Your branch is behind '{name}' by {behind} commit{}, and can be fast-forwarded.",
+                plural(behind)
+            )?;
+            writeln!(out, "  (use \"git pull\" to update your local branch)")?;
+        }
+        Some((ahead, behind)) => {
+            writeln!(out, "Your branch and '{name}' have diverged,")?;
+            writeln!(
+                out,
+                "and have {ahead} and {behind} different commits each, respectively."
+            )?;
+            writeln!(
+                out,
+                "  (use \"git pull\" to merge the remote branch into yours)"
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_long(
+    out: &mut impl Write,
+    status: &Status,
+    options: &StatusOptions,
+) -> anyhow::Result<()> {
+    let branch = &status.branch;
+    match (&branch.name, &branch.detached) {
+        (Some(name), _) => writeln!(out, "On branch {name}")?,
+        (None, Some(detached)) => writeln!(out, "HEAD detached {detached}")?,
+        (None, None) => writeln!(out, "Not currently on any branch.")?,
+    }
+    if let Some(upstream) = &branch.upstream {
+        write_tracking(out, upstream)?;
+        writeln!(out)?;
+    }
+    if branch.commit.is_none() {
+        writeln!(out, "\nNo commits yet\n")?;
+    }
+
+    if status.merging {
+        if status.unmerged.is_empty() {
+            writeln!(out, "All conflicts fixed but you are still merging.")?;
+            writeln!(out, "  (use \"git commit\" to conclude merge)")?;
+        } else {
+            writeln!(out, "You have unmerged paths.")?;
+            writeln!(out, "  (fix conflicts and run \"git commit\")")?;
+            writeln!(out, "  (use \"git merge --abort\" to abort the merge)")?;
+        }
+        writeln!(out)?;
+    }
+
+    let staged: Vec<&StatusEntry> = status
+        .entries
+        .iter()
+        .filter(|entry| entry.staged != Change::Unchanged)
+        .collect();
+    if !staged.is_empty() {
+        writeln!(out, "Changes to be committed:")?;
+        // while merging the hint is left out, as git does
+        if !status.merging {
+            if branch.commit.is_some() {
+                writeln!(out, "  (use \"git restore --staged <file>...\" to unstage)")?;
+            } else {
+                writeln!(out, "  (use \"git rm --cached <file>...\" to unstage)")?;
+            }
+        }
+        for entry in &staged {
+            writeln!(
+                out,
+                "\t{:<12}{}",
+                entry.staged.label(),
+                quote_path(&entry.path)
+            )?;
+        }
+        writeln!(out)?;
+    }
+
+    if !status.unmerged.is_empty() {
+        writeln!(out, "Unmerged paths:")?;
+        if status
+            .unmerged
+            .iter()
+            .any(|entry| entry.code().contains('D'))
+        {
+            writeln!(
+                out,
+                "  (use \"git add/rm <file>...\" as appropriate to mark resolution)"
+            )?;
+        } else {
+            writeln!(out, "  (use \"git add <file>...\" to mark resolution)")?;
+        }
+        for entry in &status.unmerged {
+            writeln!(out, "\t{:<17}{}", entry.label(), quote_path(&entry.path))?;
+        }
+        writeln!(out)?;
+    }
+
+    let unstaged: Vec<&StatusEntry> = status
+        .entries
+        .iter()
+        .filter(|entry| entry.unstaged != Change::Unchanged)
+        .collect();
+    if !unstaged.is_empty() {
+        writeln!(out, "Changes not staged for commit:")?;
+        if unstaged
+            .iter()
+            .any(|entry| entry.unstaged == Change::Deleted)
+        {
+            writeln!(
+                out,
+                "  (use \"git add/rm <file>...\" to update what will be committed)"
+            )?;
+        } else {
+            writeln!(
+                out,
+                "  (use \"git add <file>...\" to update what will be committed)"
+            )?;
+        }
+        writeln!(
+            out,
+            "  (use \"git restore <file>...\" to discard changes in working directory)"
+        )?;
+        for entry in &unstaged {
+            let new_commits = if entry.is_submodule() && entry.unstaged == Change::Modified {
+                " (new commits)"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "\t{:<12}{}{new_commits}",
+                entry.unstaged.label(),
+                quote_path(&entry.path)
+            )?;
+        }
+        writeln!(out)?;
+    }
+
+    if !status.untracked.is_empty() {
+        writeln!(out, "Untracked files:")?;
+        writeln!(
+            out,
+            "  (use \"git add <file>...\" to include in what will be committed)"
+        )?;
+        for path in &status.untracked {
+            writeln!(out, "\t{}", quote_path(path))?;
+        }
+        writeln!(out)?;
+    }
+    if !status.ignored.is_empty() {
+        writeln!(out, "Ignored files:")?;
+        writeln!(
+            out,
+            "  (use \"git add -f <file>...\" to include in what will be committed)"
+        )?;
+        for path in &status.ignored {
+            writeln!(out, "\t{}", quote_path(path))?;
+        }
+        writeln!(out)?;
+    }
+
+    if !staged.is_empty() {
+        if options.untracked == UntrackedFiles::No {
+            writeln!(
+                out,
+                "Untracked files not listed (use -u option to show untracked files)"
+            )?;
+        }
+        return Ok(());
+    }
+    if !unstaged.is_empty() || !status.unmerged.is_empty() {
+        writeln!(
+            out,
+            "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+        )?;
+    } else if !status.untracked.is_empty() {
+        writeln!(
+            out,
+            "nothing added to commit but untracked files present (use \"git add\" to track)"
+        )?;
+    } else if branch.commit.is_none() {
+        writeln!(
+            out,
+            "nothing to commit (create/copy files and use \"git add\" to track)"
+        )?;
+    } else if options.untracked == UntrackedFiles::No {
+        writeln!(out, "nothing to commit (use -u to show untracked files)")?;
+    } else {
+        writeln!(out, "nothing to commit, working tree clean")?;
+    }
+    Ok(())
+}
+
+/// Writes `path` as the short formats do: quoted unless entries are NUL terminated. With
+/// `spaces` a space also calls for quotes, as the short format and porcelain v1 separate
+/// fields with them.
+fn short_path(path: &[u8], options: &StatusOptions, spaces: bool) -> Vec<u8> {
+    if options.nul {
+        path.to_vec()
+    } else {
+        quote(path, spaces).into_bytes()
+    }
+}
+
+fn write_short(
+    out: &mut impl Write,
+    status: &Status,
+    options: &StatusOptions,
+) -> anyhow::Result<()> {
+    let terminator = if options.nul { b'\0' } else { b'\n' };
+    if options.branch {
+        let branch = &status.branch;
+        let mut line = match (&branch.name, &branch.commit) {
+            (Some(name), None) => format!("## No commits yet on {name}"),
+            (Some(name), Some(_)) => format!("## {name}"),
+            (None, _) => "## HEAD (no branch)".to_string(),
+        };
+        if let Some(upstream) = &branch.upstream {
+            line.push_str(&format!("...{}", upstream.name));
+            match upstream.ahead_behind {
+                None => line.push_str(" [gone]"),
+                Some((0, 0)) => {}
+                Some((ahead, 0)) => line.push_str(&format!(" [ahead {ahead}]")),
+                Some((0, behind)) => line.push_str(&format!(" [behind {behind}]")),
+                Some((ahead, behind)) => {
+                    line.push_str(&format!(" [ahead {ahead}, behind {behind}]"))
+                }
+            }
+        }
+        out.write_all(line.as_bytes())?;
+        out.write_all(&[terminator])?;
+    }
+
+    let mut lines: Vec<(&[u8], String)> = Vec::new();
+    for entry in &status.entries {
+        lines.push((
+            &entry.path,
+            format!("{}{}", entry.staged.code(), entry.unstaged.code()),
+        ));
+    }
+    for entry in &status.unmerged {
+        lines.push((&entry.path, entry.code().to_string()));
+    }
+    lines.sort_by(|a, b| a.0.cmp(b.0));
+    let untracked = status.untracked.iter().map(|path| (&path[..], "??".into()));
+    let ignored = status.ignored.iter().map(|path| (&path[..], "!!".into()));
+    for (path, code) in lines.into_iter().chain(untracked).chain(ignored) {
+        out.write_all(code.as_bytes())?;
+        out.write_all(b" ")?;
+        out.write_all(&short_path(path, options, true))?;
+        out.write_all(&[terminator])?;
+    }
+    Ok(())
+}
+
+fn write_porcelain_v2(
+    out: &mut impl Write,
+    status: &Status,
+    options: &StatusOptions,
+) -> anyhow::Result<()> {
+    let terminator = if options.nul { b'\0' } else { b'\n' };
+    let mut lines: Vec<(&[u8], Vec<u8>)> = Vec::new();
+    if options.branch {
+        let branch = &status.branch;
+        let mut headers = vec![
+            match &branch.commit {
+                Some(commit) => format!("# branch.oid {commit}"),
+                None => "# branch.oid (initial)".to_string(),
+            },
+            format!(
+                "# branch.head {}",
+                branch.name.as_deref().unwrap_or("(detached)")
+            ),
+        ];
+        if let Some(upstream) = &branch.upstream {
+            headers.push(format!("# branch.upstream {}", upstream.name));
+            if let Some((ahead, behind)) = upstream.ahead_behind {
+                headers.push(format!("# branch.ab +{ahead} -{behind}"));
+            }
+        }
+        for header in headers {
+            out.write_all(header.as_bytes())?;
+            out.write_all(&[terminator])?;
+        }
+    }
+
+    let mode = |mode: Option<u32>| format!("{:06o}", mode.unwrap_or(0));
+    let null = status.hash_algorithm.null_id();
+    let hash = |version: Option<Version>| version.map_or(null, |(_, hash)| hash).to_string();
+    let code = |change: Change| match change {
+        Change::Unchanged => '.',
+        change => change.code(),
+    };
+    for entry in &status.entries {
+        // only whether a submodule has new commits is known, not what changed inside it
+        let submodule = match (entry.is_submodule(), entry.unstaged) {
+            (false, _) => "N...",
+            (true, Change::Modified) => "SC..",
+            (true, _) => "S...",
+        };
+        let mut line = format!(
+            "1 {}{} {submodule} {} {} {} {} {} ",
+            code(entry.staged),
+            code(entry.unstaged),
+            mode(entry.head.map(|(mode, _)| mode)),
+            mode(entry.index.map(|(mode, _)| mode)),
+            mode(entry.worktree_mode),
+            hash(entry.head),
+            hash(entry.index),
+        )
+        .into_bytes();
+        line.extend(short_path(&entry.path, options, false));
+        lines.push((&entry.path, line));
+    }
+    for entry in &status.unmerged {
+        let [base, ours, theirs] = entry.stages;
+        let mut line = format!(
+            "u {} N... {} {} {} {} {} {} {} ",
+            entry.code(),
+            mode(base.map(|(mode, _)| mode)),
+            mode(ours.map(|(mode, _)| mode)),
+            mode(theirs.map(|(mode, _)| mode)),
+            mode(entry.worktree_mode),
+            hash(base),
+            hash(ours),
+            hash(theirs),
+        )
+        .into_bytes();
+        line.extend(short_path(&entry.path, options, false));
+        lines.push((&entry.path, line));
+    }
+    lines.sort_by(|a, b| a.0.cmp(b.0));
+    for (_, line) in lines {
+        out.write_all(&line)?;
+        out.write_all(&[terminator])?;
+    }
+    for (prefix, paths) in [("? ", &status.untracked), ("! ", &status.ignored)] {
+        for path in paths {
+            out.write_all(prefix.as_bytes())?;
+            out.write_all(&short_path(path, options, false))?;
+            out.write_all(&[terminator])?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        commit::commit,
+        object::{Object, ObjectType},
+        pathspec::Pathspec,
+        staging::add,
+    };
+
+    use super::*;
+
+    fn short(
+        repo: &Repository,
+        dot_git_path: &Path,
+        options: StatusOptions,
+    ) -> anyhow::Result<String> {
+        let options = StatusOptions {
+            format: StatusFormat::Short,
+            ..options
+        };
+        let mut out = Vec::new();
+        write_status(&mut out, &status(repo, dot_git_path, &options)?, &options)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn test_status() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let dot_git_path = worktree.join(".git");
+        let repo = Repository::on_disk(&dot_git_path, HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::create_dir_all(worktree.join("src"))?;
+        fs::create_dir_all(worktree.join("build"))?;
+        fs::write(worktree.join("README"), "hello\n")?;
+        fs::write(worktree.join("src/lib.rs"), "lib\n")?;
+        fs::write(worktree.join("src/gone.rs"), "gone\n")?;
+        fs::write(worktree.join(".gitignore"), "build/\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        commit(&repo, "initial")?;
+        assert_eq!(short(&repo, &dot_git_path, StatusOptions::default())?, "");
+
+        fs::write(worktree.join("README"), "changed\n")?;
+        fs::write(worktree.join("src/lib.rs"), "staged\n")?;
+        add(
+            &repo,
+            worktree,
+            &Pathspec::new(worktree, &["src".into()])?,
+            false,
+            false,
+        )?;
+        fs::remove_file(worktree.join("src/gone.rs"))?;
+        fs::create_dir_all(worktree.join("new/dir"))?;
+        fs::write(worktree.join("new/dir/file"), "new\n")?;
+        fs::write(worktree.join("build/out"), "out\n")?;
+        fs::write(worktree.join("tab\there"), "tab\n")?;
+        fs::write(worktree.join("sp ace.txt"), "space\n")?;
+        assert_eq!(
+            short(&repo, &dot_git_path, StatusOptions::default())?,
+            " M README\n D src/gone.rs\nM  src/lib.rs\n?? new/\n?? \"sp ace.txt\"\n\
+             ?? \"tab\\there\"\n"
+        );
+        let options = StatusOptions {
+            untracked: UntrackedFiles::All,
+            ignored: true,
+            nul: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            short(&repo, &dot_git_path, options)?,
+            " M README\0 D src/gone.rs\0M  src/lib.rs\0?? new/dir/file\0?? sp ace.txt\0\
+             ?? tab\there\0!! build/out\0"
+        );
+
+        // a file rewritten with the same contents only gets its stat data refreshed
+        let before = repo.index()?.get(b"src/lib.rs").unwrap().stat;
+        fs::remove_file(worktree.join("src/lib.rs"))?;
+        fs::write(worktree.join("src/lib.rs"), "staged\n")?;
+        let status = status(&repo, &dot_git_path, &StatusOptions::default())?;
+        let entry = status
+            .entries
+            .iter()
+            .find(|entry| entry.path == b"src/lib.rs");
+        assert_eq!(entry.map(|entry| entry.unstaged), Some(Change::Unchanged));
+        assert_ne!(repo.index()?.get(b"src/lib.rs").unwrap().stat, before);
+
+        // an intent-to-add entry has no mode or object in the index yet
+        fs::write(worktree.join("later"), "later\n")?;
+        let mut index = repo.index()?;
+        let empty = Object::hash_of(ObjectType::Blob, b"", HashAlgorithm::Sha1);
+        let metadata = fs::metadata(worktree.join("later"))?;
+        index.add(IndexEntry {
+            intent_to_add: true,
+            ..IndexEntry::new(b"later".to_vec(), 0o100644, empty, &metadata)
+        });
+        repo.write_index(&index)?;
+        let options = StatusOptions {
+            format: StatusFormat::PorcelainV2,
+            ..Default::default()
+        };
+        let status = super::status(&repo, &dot_git_path, &options)?;
+        let mut out = Vec::new();
+        write_status(&mut out, &status, &options)?;
+        let null = HashAlgorithm::Sha1.null_id();
+        let line = format!("1 .A N... 000000 000000 100644 {null} {null} later");
+        assert!(String::from_utf8(out)?.lines().any(|l| l == line));
+        Ok(())
+    }
+}
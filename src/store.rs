@@ -0,0 +1,603 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    fs,
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{
+    hash::{HashAlgorithm, ObjectId},
+    object::{Object, ObjectType},
+    pack::Pack,
+};
+
+/// An object database: somewhere git objects can be read from and written to.
+pub trait ObjectStore: Debug {
+    /// Looks up an object by its hex hash, returning `None` when this store doesn't have it.
+    fn read(&self, hash: &str) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>>;
+
+    /// Stores an object and returns its hash.
+    fn write(&self, object: Object<&mut dyn Read>) -> anyhow::Result<ObjectId>;
+
+    /// Like [`ObjectStore::write`], but leaves the object out if `stored` says an object with
+    /// its hash is already kept elsewhere.
+    fn write_unless(
+        &self,
+        object: Object<&mut dyn Read>,
+        _stored: &dyn Fn(&str) -> anyhow::Result<bool>,
+    ) -> anyhow::Result<ObjectId> {
+        self.write(object)
+    }
+
+    fn contains(&self, hash: &str) -> anyhow::Result<bool>;
+
+    /// Hashes of every object in this store, in no particular order.
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectId> + '_>>;
+
+    /// The hash function objects are named with.
+    fn hash_algorithm(&self) -> HashAlgorithm;
+}
+
+fn parse_hash(hash: &str, algorithm: HashAlgorithm) -> anyhow::Result<ObjectId> {
+    let id = ObjectId::from_hex(hash)?;
+    anyhow::ensure!(
+        id.algorithm() == algorithm,
+        "object hash '{hash}' is not a {algorithm} hash"
+    );
+    Ok(id)
+}
+
+/// Objects up to this size are spooled in memory before being compressed, bigger ones to a
+/// temporary file.
+const SPOOL_LIMIT: usize = 1 << 20;
+
+/// Objects stored one per file under `objects/xx/yyyy...`, zlib compressed.
+#[derive(Debug)]
+pub struct LooseObjectStore {
+    objects_dir: PathBuf,
+    hash: HashAlgorithm,
+    fsync: bool,
+}
+
+impl LooseObjectStore {
+    pub fn new(objects_dir: impl Into<PathBuf>, hash: HashAlgorithm) -> Self {
+        Self {
+            objects_dir: objects_dir.into(),
+            hash,
+            fsync: false,
+        }
+    }
+
+    /// Whether object files are flushed to disk before being moved into place.
+    pub fn with_fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.objects_dir.join(&hash[..2]).join(&hash[2..])
+    }
+}
+
+impl ObjectStore for LooseObjectStore {
+    fn read(&self, hash: &str) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>> {
+        parse_hash(hash, self.hash)?;
+        let f = match fs::File::open(self.object_path(hash)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("open in .git/objects"),
+        };
+        let z = ZlibDecoder::new(f);
+        let mut z = BufReader::new(z);
+        let (object_type, size) =
+            Object::read_header(&mut z).context("read header from .git/objects")?;
+
+        Ok(Some(Object {
+            object_type,
+            expected_size: size,
+            reader: Box::new(z.take(size)),
+        }))
+    }
+
+    fn write(&self, object: Object<&mut dyn Read>) -> anyhow::Result<ObjectId> {
+        self.write_unless(object, &|_| Ok(false))
+    }
+
+    /// The object is hashed as it is spooled, uncompressed, to memory or a temporary file, so
+    /// it never has to be in memory as a whole. Only objects that aren't stored yet are then
+    /// compressed into the objects directory; those already on disk are left alone.
+    fn write_unless(
+        &self,
+        object: Object<&mut dyn Read>,
+        stored: &dyn Fn(&str) -> anyhow::Result<bool>,
+    ) -> anyhow::Result<ObjectId> {
+        let mut spool = tempfile::spooled_tempfile(SPOOL_LIMIT);
+        let hash = object
+            .write_uncompressed(&mut spool, self.hash)
+            .context("spool object")?;
+        let hash_hex = hash.to_string();
+        if self.object_path(&hash_hex).is_file() || stored(&hash_hex)? {
+            return Ok(hash);
+        }
+
+        // the temporary file lives in the objects directory so the rename can't cross
+        // filesystems
+        fs::create_dir_all(&self.objects_dir).context("create .git/objects")?;
+        let mut tempfile = tempfile::Builder::new()
+            .prefix("tmp_obj_")
+            .tempfile_in(&self.objects_dir)
+            .context("create temporary object file")?;
+        spool
+            .seek(SeekFrom::Start(0))
+            .context("rewind spooled object")?;
+        let mut z = ZlibEncoder::new(&mut tempfile, Compression::default());
+        std::io::copy(&mut spool, &mut z).context("compress object into object file")?;
+        z.finish().context("compress object into object file")?;
+
+        if self.fsync {
+            tempfile.as_file().sync_all().context("fsync object file")?;
+        }
+        // objects never change once written
+        fs::set_permissions(tempfile.path(), fs::Permissions::from_mode(0o444))
+            .context("make object file read-only")?;
+        fs::create_dir_all(self.objects_dir.join(&hash_hex[..2]))
+            .context("create subdir of .git/objects")?;
+        tempfile
+            .persist(self.object_path(&hash_hex))
+            .context("move object file into .git/objects")?;
+        Ok(hash)
+    }
+
+    fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        parse_hash(hash, self.hash)?;
+        Ok(self.object_path(hash).is_file())
+    }
+
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectId> + '_>> {
+        let mut hashes = Vec::new();
+        let dirs = match fs::read_dir(&self.objects_dir) {
+            Ok(dirs) => dirs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Box::new(hashes.into_iter()))
+            }
+            Err(e) => return Err(e).context("list .git/objects"),
+        };
+        for dir in dirs {
+            let dir = dir.context("bad entry in .git/objects")?;
+            let prefix = dir.file_name().to_string_lossy().to_string();
+            if prefix.len() != 2 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+                continue;
+            }
+            for file in fs::read_dir(dir.path()).context("list .git/objects subdir")? {
+                let file = file.context("bad entry in .git/objects subdir")?;
+                let rest = file.file_name().to_string_lossy().to_string();
+                if let Ok(hash) = parse_hash(&format!("{prefix}{rest}"), self.hash) {
+                    hashes.push(hash);
+                }
+            }
+        }
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash
+    }
+}
+
+/// Read-only access to the pack files in `objects/pack`.
+#[derive(Debug)]
+pub struct PackedObjectStore {
+    pack_dir: PathBuf,
+    hash: HashAlgorithm,
+    packs: RefCell<Option<Vec<Pack>>>,
+}
+
+impl PackedObjectStore {
+    pub fn new(pack_dir: impl Into<PathBuf>, hash: HashAlgorithm) -> Self {
+        Self {
+            pack_dir: pack_dir.into(),
+            hash,
+            packs: RefCell::new(None),
+        }
+    }
+
+    fn load_packs(&self) -> anyhow::Result<Vec<Pack>> {
+        let mut packs = Vec::new();
+        let entries = match fs::read_dir(&self.pack_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(packs),
+            Err(e) => return Err(e).context("list .git/objects/pack"),
+        };
+        for entry in entries {
+            let path = entry.context("bad entry in .git/objects/pack")?.path();
+            if path.extension().is_some_and(|ext| ext == "idx") {
+                packs.push(Pack::open(&path, self.hash)?);
+            }
+        }
+        Ok(packs)
+    }
+
+    /// Runs `f` against the known packs, rescanning the pack directory once if it
+    /// comes back empty handed in case a new pack appeared since the last scan.
+    fn with_packs<T>(
+        &self,
+        f: impl Fn(&[Pack]) -> anyhow::Result<Option<T>>,
+    ) -> anyhow::Result<Option<T>> {
+        if let Some(packs) = self.packs.borrow().as_ref() {
+            if let Some(found) = f(packs)? {
+                return Ok(Some(found));
+            }
+        }
+        let packs = self.load_packs()?;
+        let found = f(&packs);
+        *self.packs.borrow_mut() = Some(packs);
+        found
+    }
+}
+
+impl ObjectStore for PackedObjectStore {
+    fn read(&self, hash: &str) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>> {
+        let hash = parse_hash(hash, self.hash)?;
+        let found = self.with_packs(|packs| {
+            for pack in packs {
+                if let Some(found) = pack.read(&hash)? {
+                    return Ok(Some(found));
+                }
+            }
+            Ok(None)
+        })?;
+        Ok(found.map(|(object_type, data)| Object {
+            object_type,
+            expected_size: data.len() as u64,
+            reader: Box::new(Cursor::new(data)) as Box<dyn BufRead>,
+        }))
+    }
+
+    fn write(&self, _object: Object<&mut dyn Read>) -> anyhow::Result<ObjectId> {
+        anyhow::bail!("cannot write individual objects into a pack")
+    }
+
+    fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        let hash = parse_hash(hash, self.hash)?;
+        let found = self.with_packs(|packs| Ok(packs.iter().find_map(|pack| pack.find(&hash))))?;
+        Ok(found.is_some())
+    }
+
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectId> + '_>> {
+        let packs = self.load_packs()?;
+        let hashes: Vec<_> = packs
+            .iter()
+            .flat_map(|pack| pack.hashes().iter().copied())
+            .collect();
+        *self.packs.borrow_mut() = Some(packs);
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash
+    }
+}
+
+type StoredObject = (ObjectType, Vec<u8>);
+
+/// Objects kept in memory for the lifetime of the store.
+#[derive(Debug, Default)]
+pub struct MemoryObjectStore {
+    hash: HashAlgorithm,
+    objects: RefCell<BTreeMap<ObjectId, StoredObject>>,
+}
+
+impl MemoryObjectStore {
+    pub fn new(hash: HashAlgorithm) -> Self {
+        Self {
+            hash,
+            objects: RefCell::default(),
+        }
+    }
+}
+
+impl ObjectStore for MemoryObjectStore {
+    fn read(&self, hash: &str) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>> {
+        let hash = parse_hash(hash, self.hash)?;
+        let objects = self.objects.borrow();
+        Ok(objects.get(&hash).map(|(object_type, data)| Object {
+            object_type: *object_type,
+            expected_size: data.len() as u64,
+            reader: Box::new(Cursor::new(data.clone())) as Box<dyn BufRead>,
+        }))
+    }
+
+    fn write(&self, object: Object<&mut dyn Read>) -> anyhow::Result<ObjectId> {
+        let object_type = object.object_type;
+        let data = object.into_bytes()?;
+        let hash = Object::hash_of(object_type, &data, self.hash);
+        self.objects
+            .borrow_mut()
+            .entry(hash)
+            .or_insert((object_type, data));
+        Ok(hash)
+    }
+
+    fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        let hash = parse_hash(hash, self.hash)?;
+        Ok(self.objects.borrow().contains_key(&hash))
+    }
+
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectId> + '_>> {
+        let hashes: Vec<_> = self.objects.borrow().keys().copied().collect();
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash
+    }
+}
+
+/// Checks each store in turn; new objects go into the first one.
+#[derive(Debug)]
+pub struct CompositeObjectStore {
+    stores: Vec<Box<dyn ObjectStore>>,
+}
+
+impl CompositeObjectStore {
+    pub fn new(stores: Vec<Box<dyn ObjectStore>>) -> Self {
+        Self { stores }
+    }
+
+    /// The loose objects and packs of the repository at `dot_git_path`, followed by those of
+    /// every object directory listed in `objects/info/alternates`.
+    pub fn open(dot_git_path: &Path, hash: HashAlgorithm) -> Self {
+        Self::open_with_fsync(dot_git_path, hash, false)
+    }
+
+    /// Like [`CompositeObjectStore::open`], fsyncing new loose objects when `fsync` is set.
+    pub fn open_with_fsync(dot_git_path: &Path, hash: HashAlgorithm, fsync: bool) -> Self {
+        let mut stores: Vec<Box<dyn ObjectStore>> = Vec::new();
+        let mut seen = BTreeSet::new();
+        let objects_dir = dot_git_path.join("objects");
+        add_object_dir(&objects_dir, hash, fsync, 0, &mut seen, &mut stores);
+        Self::new(stores)
+    }
+}
+
+/// Alternates may themselves have alternates, up to this many levels deep.
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+fn add_object_dir(
+    objects_dir: &Path,
+    hash: HashAlgorithm,
+    fsync: bool,
+    depth: usize,
+    seen: &mut BTreeSet<PathBuf>,
+    stores: &mut Vec<Box<dyn ObjectStore>>,
+) {
+    let key = objects_dir
+        .canonicalize()
+        .unwrap_or_else(|_| objects_dir.to_path_buf());
+    if !seen.insert(key) {
+        return;
+    }
+    stores.push(Box::new(
+        LooseObjectStore::new(objects_dir, hash).with_fsync(fsync),
+    ));
+    stores.push(Box::new(PackedObjectStore::new(
+        objects_dir.join("pack"),
+        hash,
+    )));
+    if depth == MAX_ALTERNATE_DEPTH {
+        return;
+    }
+    for alternate in alternates(objects_dir) {
+        if alternate.is_dir() {
+            add_object_dir(&alternate, hash, false, depth + 1, seen, stores);
+        }
+    }
+}
+
+/// The object directories listed in `objects_dir/info/alternates`, relative entries being
+/// resolved against `objects_dir`.
+pub(crate) fn alternates(objects_dir: &Path) -> Vec<PathBuf> {
+    let Ok(contents) = fs::read(objects_dir.join("info/alternates")) else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&contents)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| objects_dir.join(line))
+        .collect()
+}
+
+impl ObjectStore for CompositeObjectStore {
+    fn read(&self, hash: &str) -> anyhow::Result<Option<Object<Box<dyn BufRead + '_>>>> {
+        for store in &self.stores {
+            if let Some(object) = store.read(hash)? {
+                return Ok(Some(object));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Objects already in any of the stores, alternates included, aren't written again.
+    fn write(&self, object: Object<&mut dyn Read>) -> anyhow::Result<ObjectId> {
+        let store = self
+            .stores
+            .first()
+            .context("composite object store has no stores to write to")?;
+        // only the first store takes new objects, but one any of them has needn't be added
+        store.write_unless(object, &|hash| self.contains(hash))
+    }
+
+    fn contains(&self, hash: &str) -> anyhow::Result<bool> {
+        for store in &self.stores {
+            if store.contains(hash)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn iter(&self) -> anyhow::Result<Box<dyn Iterator<Item = ObjectId> + '_>> {
+        let mut hashes = BTreeSet::new();
+        for store in &self.stores {
+            hashes.extend(store.iter()?);
+        }
+        Ok(Box::new(hashes.into_iter()))
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.stores
+            .first()
+            .map_or(HashAlgorithm::default(), |store| store.hash_algorithm())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_to_string(store: &dyn ObjectStore, hash: &str) -> anyhow::Result<(ObjectType, String)> {
+        let object = Object::read(store, hash)?;
+        let object_type = object.object_type;
+        Ok((object_type, String::from_utf8(object.into_bytes()?)?))
+    }
+
+    #[test]
+    fn test_memory_store_round_trip() -> anyhow::Result<()> {
+        let store = MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let hash = Object {
+            object_type: ObjectType::Blob,
+            expected_size: 11,
+            reader: "hello world".as_bytes(),
+        }
+        .write_to_objects(&store)?;
+        let hash = hash.to_string();
+        assert_eq!(hash, "95d09f2b10159347eece71399a7e2e907ea3df4f");
+        assert!(store.contains(&hash)?);
+        assert!(!store.contains("ea8c4bf7f35f6f77f75d92ad8ce8349f6e81ddba")?);
+        assert_eq!(
+            read_to_string(&store, &hash)?,
+            (ObjectType::Blob, String::from("hello world"))
+        );
+        assert_eq!(store.iter()?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_store_reads_deltified_objects() -> anyhow::Result<()> {
+        let store = PackedObjectStore::new(
+            "tests/fixtures/packed-app/dot-git/objects/pack",
+            HashAlgorithm::Sha1,
+        );
+        assert_eq!(store.iter()?.count(), 10);
+        let expected: String = (1..=201).map(|n| format!("{n}\n")).collect();
+        assert_eq!(
+            read_to_string(&store, "56361596f1b65a93f739052bec31dfaa09809989")?,
+            (ObjectType::Blob, expected)
+        );
+        let (object_type, tag) =
+            read_to_string(&store, "3844e8f7fa93d6aac63576776d275dead317af0a")?;
+        assert_eq!(object_type, ObjectType::Tag);
+        assert!(tag.starts_with("object 388f38c3f0448af4063b9b9b727ae38ef73a23e2\ntype commit\n"));
+        assert!(store
+            .read("ea8c4bf7f35f6f77f75d92ad8ce8349f6e81ddba")?
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_composite_store_checks_each_store() -> anyhow::Result<()> {
+        let store = CompositeObjectStore::new(vec![
+            Box::new(MemoryObjectStore::new(HashAlgorithm::Sha1)),
+            Box::new(LooseObjectStore::new(
+                "tests/fixtures/simple-app/dot-git/objects",
+                HashAlgorithm::Sha1,
+            )),
+        ]);
+        assert!(store.contains("ea8c4bf7f35f6f77f75d92ad8ce8349f6e81ddba")?);
+        let hash = Object {
+            object_type: ObjectType::Blob,
+            expected_size: 3,
+            reader: "new".as_bytes(),
+        }
+        .write_to_objects(&store)?;
+        assert!(store.contains(&hash.to_string())?);
+        assert_eq!(store.iter()?.count(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_loose_store_skips_existing_objects() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let store = LooseObjectStore::new(temp_dir.path(), HashAlgorithm::Sha1);
+        let write = || {
+            Object {
+                object_type: ObjectType::Blob,
+                expected_size: 11,
+                reader: "hello world".as_bytes(),
+            }
+            .write_to_objects(&store)
+        };
+        let hash = write()?.to_string();
+        let path = store.object_path(&hash);
+        assert!(fs::metadata(&path)?.permissions().readonly());
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+        fs::write(&path, "already here")?;
+        let before = fs::metadata(temp_dir.path())?.modified()?;
+
+        assert_eq!(write()?.to_string(), hash);
+        assert_eq!(fs::read_to_string(&path)?, "already here");
+        // no temporary file was created, so nothing was compressed into one either
+        assert_eq!(fs::metadata(temp_dir.path())?.modified()?, before);
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_alternates() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let shared = temp_dir.path().join("shared/.git");
+        let middle = temp_dir.path().join("middle/.git");
+        let checkout = temp_dir.path().join("checkout/.git");
+        let shared_store = LooseObjectStore::new(shared.join("objects"), HashAlgorithm::Sha1);
+        let hash = Object {
+            object_type: ObjectType::Blob,
+            expected_size: 7,
+            reader: "shared\n".as_bytes(),
+        }
+        .write_to_objects(&shared_store)?;
+
+        fs::create_dir_all(middle.join("objects/info"))?;
+        fs::write(
+            middle.join("objects/info/alternates"),
+            "# shared store\n../../../shared/.git/objects\n",
+        )?;
+        fs::create_dir_all(checkout.join("objects/info"))?;
+        fs::write(
+            checkout.join("objects/info/alternates"),
+            format!("{}\n", middle.join("objects").display()),
+        )?;
+
+        let store = CompositeObjectStore::open(&checkout, HashAlgorithm::Sha1);
+        assert_eq!(
+            read_to_string(&store, &hash.to_string())?,
+            (ObjectType::Blob, String::from("shared\n"))
+        );
+        let rewritten = Object {
+            object_type: ObjectType::Blob,
+            expected_size: 7,
+            reader: "shared\n".as_bytes(),
+        }
+        .write_to_objects(&store)?;
+        assert_eq!(rewritten, hash);
+        let local = LooseObjectStore::new(checkout.join("objects"), HashAlgorithm::Sha1);
+        assert!(!local.contains(&hash.to_string())?);
+        Ok(())
+    }
+}
@@ -0,0 +1,381 @@
+//! Submodules: repositories checked out inside the worktree at the commits their gitlinks
+//! name, as listed in `.gitmodules`.
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    clone::{clone, git_dir},
+    commit::Commit,
+    config::GitConfig,
+    hash::ObjectId,
+    index::{Index, IndexEntry},
+    object::{Object, ObjectType},
+    pathspec::Pathspec,
+    repository::Repository,
+    tag::Tag,
+    tree::{checkout_tree, tree_files},
+    walk::{ahead_behind, ancestors},
+    worktree::{disk_path, is_nested_repo, nested_repo_head},
+};
+
+/// A submodule as `.gitmodules` describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submodule {
+    pub name: String,
+    /// Where it is checked out, relative to the top of the worktree.
+    pub path: String,
+    pub url: Option<String>,
+}
+
+/// A submodule checked out by [`update`].
+#[derive(Debug)]
+pub struct UpdatedSubmodule {
+    pub path: String,
+    pub commit: ObjectId,
+    /// Whether it had to be cloned first.
+    pub cloned: bool,
+}
+
+/// One line of `submodule status`.
+#[derive(Debug)]
+pub struct SubmoduleStatus {
+    /// `-` when not checked out, `+` when at another commit than the gitlink's, `U` when the
+    /// gitlink is unmerged and a space otherwise.
+    pub state: char,
+    pub commit: ObjectId,
+    pub path: String,
+    /// The checked out commit named after a nearby ref, like `heads/master`.
+    pub describe: Option<String>,
+}
+
+/// The submodules listed in the `.gitmodules` of `worktree`.
+pub(crate) fn submodules(worktree: &Path) -> anyhow::Result<Vec<Submodule>> {
+    let contents = match fs::read_to_string(worktree.join(".gitmodules")) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("read .gitmodules"),
+    };
+    let config = GitConfig::parse(&contents).context("parse .gitmodules")?;
+    Ok(config
+        .subsections("submodule")
+        .into_iter()
+        .filter_map(|name| {
+            let path = config.get(&format!("submodule.{name}.path"))?;
+            Some(Submodule {
+                name: name.to_string(),
+                path: path.to_string(),
+                url: config
+                    .get(&format!("submodule.{name}.url"))
+                    .map(str::to_string),
+            })
+        })
+        .collect())
+}
+
+/// The gitlinks in the index matching `pathspec`, with their submodules and stage 0 commits,
+/// `None` for unmerged ones.
+fn gitlinks(
+    repo: &Repository,
+    worktree: &Path,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<(Submodule, Option<ObjectId>)>> {
+    let index = repo.index()?;
+    let modules = submodules(worktree)?;
+    let mut gitlinks: Vec<(Submodule, Option<ObjectId>)> = Vec::new();
+    for entry in &index.entries {
+        if entry.mode != 0o160000 || !pathspec.matches(&entry.path) {
+            continue;
+        }
+        let path = String::from_utf8_lossy(&entry.path);
+        if gitlinks.last().is_some_and(|(last, _)| last.path == path) {
+            continue;
+        }
+        let submodule = modules
+            .iter()
+            .find(|submodule| submodule.path == path)
+            .with_context(|| {
+                format!("no submodule mapping found in .gitmodules for path '{path}'")
+            })?;
+        let commit = index.get(&entry.path).map(|entry| entry.hash);
+        gitlinks.push((submodule.clone(), commit));
+    }
+    Ok(gitlinks)
+}
+
+/// Records the URLs of the submodules matching `pathspec` in the config, which marks them to
+/// be checked out by `update`, and returns the ones that weren't yet. Relative URLs are taken
+/// relative to the `origin` remote, or to the worktree without one.
+pub(crate) fn init(
+    repo: &Repository,
+    worktree: &Path,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<Submodule>> {
+    let mut config = repo.config()?;
+    let mut registered = Vec::new();
+    for (submodule, _) in gitlinks(repo, worktree, pathspec)? {
+        let key = format!("submodule.{}.url", submodule.name);
+        if config.get(&key).is_some() {
+            continue;
+        }
+        let url = submodule.url.as_deref().with_context(|| {
+            format!(
+                "No url found for submodule path '{}' in .gitmodules",
+                submodule.path
+            )
+        })?;
+        let url = resolve_url(&config, worktree, url);
+        config.set(&format!("submodule.{}.active", submodule.name), "true");
+        config.set(&key, &url);
+        registered.push(Submodule {
+            url: Some(url),
+            ..submodule
+        });
+    }
+    repo.write_config(&config)?;
+    Ok(registered)
+}
+
+/// A `./` or `../` URL relative to the superproject's own origin.
+fn resolve_url(config: &GitConfig, worktree: &Path, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_string();
+    }
+    let base = match config.get("remote.origin.url") {
+        Some(origin) => origin
+            .strip_suffix("/.git")
+            .unwrap_or(origin)
+            .trim_end_matches('/')
+            .to_string(),
+        None => worktree.display().to_string(),
+    };
+    let mut parts: Vec<&str> = base.split('/').collect();
+    for part in url.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Clones the initialized submodules matching `pathspec` that aren't checked out yet, and
+/// detaches each at the commit its gitlink names. Returns the ones that were checked out.
+pub(crate) fn update(
+    repo: &Repository,
+    worktree: &Path,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<UpdatedSubmodule>> {
+    let config = repo.config()?;
+    let mut updated = Vec::new();
+    for (submodule, commit) in gitlinks(repo, worktree, pathspec)? {
+        let Some(commit) = commit else {
+            bail!("Skipping unmerged submodule {}", submodule.path);
+        };
+        let Some(url) = config.get(&format!("submodule.{}.url", submodule.name)) else {
+            // not initialized
+            continue;
+        };
+        let dir = disk_path(worktree, submodule.path.as_bytes());
+        let cloned = !is_nested_repo(&dir);
+        if cloned {
+            clone(Path::new(url), &dir, None).with_context(|| {
+                format!(
+                    "clone of '{url}' into submodule path '{}' failed",
+                    dir.display()
+                )
+            })?;
+        }
+        if nested_repo_head(&dir)? == Some(commit) && !cloned {
+            continue;
+        }
+        checkout_detached(&dir, &commit).with_context(|| {
+            format!(
+                "Unable to checkout '{commit}' in submodule path '{}'",
+                submodule.path
+            )
+        })?;
+        updated.push(UpdatedSubmodule {
+            path: submodule.path,
+            commit,
+            cloned,
+        });
+    }
+    Ok(updated)
+}
+
+/// Replaces the files of the commit checked out in the repository at `dir` with those of
+/// `commit`, stages them, and detaches HEAD there.
+fn checkout_detached(dir: &Path, commit: &ObjectId) -> anyhow::Result<()> {
+    let repo = Repository::open(&git_dir(dir)?)?;
+    let hex = commit.to_string();
+    if !repo.objects.contains(&hex)? {
+        bail!("the submodule doesn't have commit {hex}");
+    }
+    let tree = Commit::read(repo.objects.as_ref(), &hex)?.tree;
+    if let Some(head) = repo.resolve_name("HEAD")? {
+        let old_tree = Commit::read(repo.objects.as_ref(), &head.to_string())?.tree;
+        for path in tree_files(repo.objects.as_ref(), &old_tree)?.keys() {
+            let file = disk_path(dir, path);
+            if fs::symlink_metadata(&file).is_ok_and(|metadata| !metadata.is_dir()) {
+                fs::remove_file(&file).with_context(|| format!("remove {}", file.display()))?;
+            }
+        }
+    }
+    let symlinks = repo.config()?.get_bool("core.symlinks")?.unwrap_or(true);
+    checkout_tree(repo.objects.as_ref(), &tree, dir, symlinks)?;
+    let mut index = Index::default();
+    for (path, (mode, hash)) in tree_files(repo.objects.as_ref(), &tree)? {
+        let file = disk_path(dir, &path);
+        let metadata =
+            fs::symlink_metadata(&file).with_context(|| format!("stat {}", file.display()))?;
+        index.add(IndexEntry::new(path, mode, hash, &metadata));
+    }
+    repo.write_index(&index)?;
+    repo.files.write("HEAD", format!("{hex}\n").as_bytes())
+}
+
+/// The state of each submodule matching `pathspec`.
+pub(crate) fn status(
+    repo: &Repository,
+    worktree: &Path,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<SubmoduleStatus>> {
+    let mut statuses = Vec::new();
+    for (submodule, commit) in gitlinks(repo, worktree, pathspec)? {
+        let Some(commit) = commit else {
+            statuses.push(SubmoduleStatus {
+                state: 'U',
+                commit: repo.hash_algorithm().null_id(),
+                path: submodule.path,
+                describe: None,
+            });
+            continue;
+        };
+        let dir = disk_path(worktree, submodule.path.as_bytes());
+        let head = if is_nested_repo(&dir) {
+            nested_repo_head(&dir)?
+        } else {
+            None
+        };
+        let status = match head {
+            None => SubmoduleStatus {
+                state: '-',
+                commit,
+                path: submodule.path,
+                describe: None,
+            },
+            Some(head) => SubmoduleStatus {
+                state: if head == commit { ' ' } else { '+' },
+                commit: head,
+                path: submodule.path,
+                describe: Some(describe(&Repository::open(&git_dir(&dir)?)?, &head)?),
+            },
+        };
+        statuses.push(status);
+    }
+    Ok(statuses)
+}
+
+/// Names `commit` after the closest tag it descends from, or else the closest ref, as
+/// `name-<distance>-g<abbreviated id>` when it isn't the ref's commit itself.
+fn describe(repo: &Repository, commit: &ObjectId) -> anyhow::Result<String> {
+    let history = ancestors(repo, commit)?;
+    let mut candidates = Vec::new();
+    for (name, hash) in repo.refs()? {
+        let mut target: ObjectId = hash.parse()?;
+        // annotated tags name the commit they point to
+        if Object::read(repo.objects.as_ref(), &hash)?.object_type == ObjectType::Tag {
+            target = Tag::read(repo.objects.as_ref(), &hash)?.object.parse()?;
+        }
+        if !history.contains(&target) {
+            continue;
+        }
+        let (short, is_tag) = match name.strip_prefix("refs/tags/") {
+            Some(tag) => (tag.to_string(), true),
+            None => (
+                name.strip_prefix("refs/").unwrap_or(&name).to_string(),
+                false,
+            ),
+        };
+        let (distance, _) = ahead_behind(repo, commit, &target)?;
+        candidates.push((!is_tag, distance, short));
+    }
+    let abbreviated = &commit.to_string()[..7];
+    Ok(match candidates.into_iter().min() {
+        Some((_, 0, name)) => name,
+        Some((_, distance, name)) => format!("{name}-{distance}-g{abbreviated}"),
+        None => abbreviated.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{commit::commit, hash::HashAlgorithm, staging::add, tree::write_tree_for};
+
+    use super::*;
+
+    fn repo_with_file(dir: &Path, name: &str, contents: &str) -> anyhow::Result<Repository> {
+        let repo = Repository::on_disk(&dir.join(".git"), HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::write(dir.join(name), contents)?;
+        add(&repo, dir, &Pathspec::default(), false, false)?;
+        commit(&repo, "initial")?;
+        Ok(repo)
+    }
+
+    #[test]
+    fn test_submodules() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&library)?;
+        let library_repo = repo_with_file(&library, "lib.rs", "lib\n")?;
+        let library_head = library_repo.resolve_name("HEAD")?.unwrap();
+
+        // a nested repository is staged as a gitlink to its HEAD
+        let app = temp_dir.path().join("app");
+        fs::create_dir_all(&app)?;
+        clone(&library, &app.join("lib"), None)?;
+        fs::write(
+            app.join(".gitmodules"),
+            "[submodule \"lib\"]\n\tpath = lib\n\turl = ../library\n",
+        )?;
+        let app_repo = repo_with_file(&app, "main.rs", "main\n")?;
+        let entry = app_repo
+            .index()?
+            .get(b"lib")
+            .map(|entry| (entry.mode, entry.hash));
+        assert_eq!(entry, Some((0o160000, library_head)));
+        let objects = crate::store::MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let tree = write_tree_for(&objects, &app.join(".git"), &app)?.unwrap();
+        let files = tree_files(&objects, &tree.to_string())?;
+        assert_eq!(files.get(&b"lib"[..]), Some(&(0o160000, library_head)));
+
+        let everything = Pathspec::default();
+        let statuses = status(&app_repo, &app, &everything)?;
+        assert_eq!(statuses[0].state, ' ');
+        assert_eq!(statuses[0].describe.as_deref(), Some("heads/master"));
+
+        // a fresh copy of the superproject has to init and update it
+        let copy = temp_dir.path().join("copy");
+        clone(&app, &copy, None)?;
+        let copy_repo = Repository::open(&copy.join(".git"))?;
+        copy_repo.write_index(&app_repo.index()?)?;
+        assert_eq!(status(&copy_repo, &copy, &everything)?[0].state, '-');
+        let registered = init(&copy_repo, &copy, &everything)?;
+        let expected_url = PathBuf::from(&library).display().to_string();
+        assert_eq!(registered[0].url.as_deref(), Some(expected_url.as_str()));
+        let updated = update(&copy_repo, &copy, &everything)?;
+        assert!(updated[0].cloned);
+        assert_eq!(fs::read_to_string(copy.join("lib/lib.rs"))?, "lib\n");
+        assert_eq!(status(&copy_repo, &copy, &everything)?[0].state, ' ');
+        assert!(update(&copy_repo, &copy, &everything)?.is_empty());
+        Ok(())
+    }
+}
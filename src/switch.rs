@@ -0,0 +1,923 @@
+//! `switch`: carrying the index and the worktree over from the tree of one commit to that of
+//! another as `HEAD` moves, keeping the local changes along the way.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    io::{ErrorKind, Write},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    branch::{check_name, create, resolve_commit},
+    commit::Commit,
+    diff::{is_binary, worktree_changes},
+    diff_tree::Version,
+    hash::ObjectId,
+    ignore::IgnoreRules,
+    index::{index_time, Index, IndexEntry, StatData},
+    merge::merge_lines,
+    object::{Object, ObjectType},
+    pathspec::Pathspec,
+    reflog::{self, previous_checkout},
+    repository::{Head, Repository},
+    status::{worktree_change, Change},
+    tree::tree_files,
+    worktree::{disk_path, hash_file, remove_file, verify_path, write_file, ModeConfig},
+};
+
+/// What stands in the way of a switch, by path.
+#[derive(Debug, Default)]
+pub(crate) struct Refusal {
+    /// Tracked files whose local changes would be overwritten.
+    pub(crate) local_changes: Vec<Vec<u8>>,
+    /// Directories with untracked files in them where a file would go.
+    pub(crate) untracked_dirs: Vec<Vec<u8>>,
+    /// Untracked files where a tracked file would be removed.
+    pub(crate) untracked_removed: Vec<Vec<u8>>,
+    /// Untracked files that would be overwritten.
+    pub(crate) untracked_overwritten: Vec<Vec<u8>>,
+    /// Set when it came up while merging local changes, where git gives up at the first path.
+    pub(crate) merging: bool,
+}
+
+impl Refusal {
+    fn is_empty(&self) -> bool {
+        self.local_changes.is_empty()
+            && self.untracked_dirs.is_empty()
+            && self.untracked_removed.is_empty()
+            && self.untracked_overwritten.is_empty()
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |path: &Vec<u8>| String::from_utf8_lossy(path).to_string();
+        if self.merging {
+            let mut first: Vec<(&Vec<u8>, bool)> = self
+                .untracked_dirs
+                .iter()
+                .map(|path| (path, true))
+                .chain(self.untracked_overwritten.iter().map(|path| (path, false)))
+                .collect();
+            first.sort();
+            return match first.first() {
+                Some((path, true)) => writeln!(
+                    f,
+                    "error: Updating '{}' would lose untracked files in it",
+                    show(path)
+                ),
+                Some((path, false)) => writeln!(
+                    f,
+                    "error: Untracked working tree file '{}' would be overwritten by merge.",
+                    show(path)
+                ),
+                None => Ok(()),
+            };
+        }
+        let list = |paths: &[Vec<u8>]| -> String {
+            paths
+                .iter()
+                .map(|path| format!("\t{}\n", show(path)))
+                .collect()
+        };
+        if !self.local_changes.is_empty() {
+            write!(
+                f,
+                "error: Your local changes to the following files would be overwritten by \
+                 checkout:\n{}Please commit your changes or stash them before you switch \
+                 branches.\n",
+                list(&self.local_changes)
+            )?;
+        }
+        if !self.untracked_dirs.is_empty() {
+            write!(
+                f,
+                "error: Updating the following directories would lose untracked files in \
+                 them:\n{}\n",
+                list(&self.untracked_dirs)
+            )?;
+        }
+        if !self.untracked_removed.is_empty() {
+            write!(
+                f,
+                "error: The following untracked working tree files would be removed by \
+                 checkout:\n{}Please move or remove them before you switch branches.\n",
+                list(&self.untracked_removed)
+            )?;
+        }
+        if !self.untracked_overwritten.is_empty() {
+            write!(
+                f,
+                "error: The following untracked working tree files would be overwritten by \
+                 checkout:\n{}Please move or remove them before you switch branches.\n",
+                list(&self.untracked_overwritten)
+            )?;
+        }
+        writeln!(f, "Aborting")
+    }
+}
+
+/// An untracked file or directory in the way of a path.
+enum Obstruction {
+    File(Vec<u8>),
+    Dir(Vec<u8>),
+}
+
+/// What the switch looks at in the worktree.
+struct Worktree<'a> {
+    repo: &'a Repository,
+    path: &'a Path,
+    rules: IgnoreRules,
+    modes: ModeConfig,
+    index_time: Option<(u32, u32)>,
+}
+
+impl Worktree<'_> {
+    /// Whether the worktree file of `entry` still has what it does, or is gone.
+    fn uptodate(&self, entry: &IndexEntry) -> anyhow::Result<bool> {
+        // a submodule is left to itself
+        if entry.mode == 0o160000 {
+            return Ok(true);
+        }
+        match fs::symlink_metadata(disk_path(self.path, &entry.path)) {
+            Ok(metadata) => {
+                let (change, _) = worktree_change(
+                    self.repo,
+                    self.path,
+                    entry,
+                    &metadata,
+                    self.modes,
+                    self.index_time,
+                )?;
+                Ok(change == Change::Unchanged)
+            }
+            // a file where one of its directories should be counts as a change
+            Err(e) => Ok(e.kind() == ErrorKind::NotFound),
+        }
+    }
+
+    /// The untracked file or directory of untracked files that putting a file at `path`, or
+    /// with `removing` taking the one there away, would lose. Ignored files don't count.
+    fn obstruction(
+        &self,
+        index: &Index,
+        path: &[u8],
+        removing: bool,
+    ) -> anyhow::Result<Option<Obstruction>> {
+        if !removing {
+            // a file where one of the directories goes
+            for (i, _) in path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+                let dir = &path[..i];
+                match fs::symlink_metadata(disk_path(self.path, dir)) {
+                    Ok(metadata) if metadata.is_dir() => continue,
+                    Ok(_) => {
+                        let expendable =
+                            !index.stages(dir).is_empty() || self.rules.is_ignored(dir, false);
+                        return Ok((!expendable).then(|| Obstruction::File(dir.to_vec())));
+                    }
+                    Err(_) => return Ok(None),
+                }
+            }
+        }
+        let Ok(metadata) = fs::symlink_metadata(disk_path(self.path, path)) else {
+            return Ok(None);
+        };
+        if self.rules.is_ignored(path, metadata.is_dir()) {
+            return Ok(None);
+        }
+        if metadata.is_dir() {
+            if removing || !self.has_untracked(index, path)? {
+                return Ok(None);
+            }
+            return Ok(Some(Obstruction::Dir(path.to_vec())));
+        }
+        Ok(Some(Obstruction::File(path.to_vec())))
+    }
+
+    /// Whether the directory `dir` has files in it that are neither tracked nor ignored.
+    fn has_untracked(&self, index: &Index, dir: &[u8]) -> anyhow::Result<bool> {
+        let disk_dir = disk_path(self.path, dir);
+        for entry in
+            fs::read_dir(&disk_dir).with_context(|| format!("read {}", disk_dir.display()))?
+        {
+            let entry = entry?;
+            let mut path = dir.to_vec();
+            path.push(b'/');
+            path.extend(entry.file_name().as_bytes());
+            let is_dir = entry.file_type()?.is_dir();
+            if self.rules.is_ignored(&path, is_dir) || !index.stages(&path).is_empty() {
+                continue;
+            }
+            if !is_dir || entry.path().join(".git").exists() || self.has_untracked(index, &path)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// What the worktree has at the tracked path of `entry`: its version when unchanged, the
+    /// blob of what it became, written to the object store, or `None` when it is gone.
+    fn version(&self, entry: &IndexEntry) -> anyhow::Result<Option<Version>> {
+        let Ok(metadata) = fs::symlink_metadata(disk_path(self.path, &entry.path)) else {
+            return Ok(None);
+        };
+        if metadata.is_dir() && entry.mode != 0o160000 {
+            return Ok(None);
+        }
+        if self.uptodate(entry)? {
+            return Ok(Some((entry.mode, entry.hash)));
+        }
+        let mode = self.modes.mode_of(&metadata, Some(entry.mode));
+        let id = hash_file(self.repo, self.path, &entry.path, &metadata, mode, true)?;
+        Ok(Some((mode, id)))
+    }
+}
+
+/// An index entry for `version` without stat data, so that the worktree file gets looked at.
+fn unstatted(path: &[u8], (mode, hash): Version, stage: u8) -> IndexEntry {
+    IndexEntry {
+        stat: StatData::default(),
+        mode,
+        hash,
+        stage,
+        assume_valid: false,
+        skip_worktree: false,
+        intent_to_add: false,
+        path: path.to_vec(),
+    }
+}
+
+/// Moves the index and the worktree from the tree `old`, or nothing on an unborn branch, to the
+/// tree `new` the way git's two-tree checkout does. Paths the two trees agree on keep their
+/// local changes, and the others only change where the index and the worktree still have
+/// what `old` does; untracked files are only overwritten when they are ignored.
+///
+/// When something stands in the way nothing changes and what it was is returned, unless
+/// `merge` names the new side: then the local changes to tracked files are merged into what
+/// `new` has, conflicts being left in the files and in the stages of the index, and binary
+/// files that can't be merged are reported to `err`.
+pub(crate) fn switch_trees(
+    err: &mut impl Write,
+    repo: &Repository,
+    dot_git_path: &Path,
+    old: Option<&str>,
+    new: &str,
+    merge: Option<&str>,
+) -> anyhow::Result<Option<Refusal>> {
+    let worktree = dot_git_path
+        .parent()
+        .context("repository has no working tree")?;
+    let objects = repo.objects.as_ref();
+    let old_files = match old {
+        Some(tree) => tree_files(objects, tree)?,
+        None => BTreeMap::new(),
+    };
+    let new_files = tree_files(objects, new)?;
+    for path in new_files.keys() {
+        verify_path(path)?;
+    }
+    let mut index = repo.index()?;
+    if index.has_conflicts() {
+        bail!("you need to resolve your current index first");
+    }
+    let disk = Worktree {
+        repo,
+        path: worktree,
+        rules: IgnoreRules::load(repo, worktree)?,
+        modes: ModeConfig::load(repo)?,
+        index_time: index_time(dot_git_path),
+    };
+
+    let mut paths: BTreeSet<Vec<u8>> = old_files.keys().chain(new_files.keys()).cloned().collect();
+    paths.extend(index.entries.iter().map(|entry| entry.path.clone()));
+    let mut refusal = Refusal::default();
+    let mut removals = Vec::new();
+    let mut updates = Vec::new();
+    for path in &paths {
+        let old = old_files.get(path).copied();
+        let new = new_files.get(path).copied();
+        let Some(entry) = index.get(path) else {
+            let obstruction = match (old, new) {
+                // the deletion of the path was staged
+                (Some(old), Some(new)) => {
+                    if old != new {
+                        refusal.local_changes.push(path.clone());
+                    }
+                    continue;
+                }
+                (None, Some(new)) => {
+                    updates.push((path.clone(), new));
+                    disk.obstruction(&index, path, false)?
+                }
+                (Some(_), None) => disk.obstruction(&index, path, true)?,
+                (None, None) => continue,
+            };
+            match obstruction {
+                Some(Obstruction::Dir(dir)) => refusal.untracked_dirs.push(dir),
+                Some(Obstruction::File(file)) if new.is_none() => {
+                    refusal.untracked_removed.push(file)
+                }
+                Some(Obstruction::File(file)) => refusal.untracked_overwritten.push(file),
+                None => {}
+            }
+            continue;
+        };
+        let current = Some((entry.mode, entry.hash));
+        let keep = (old.is_none() && new.is_none())
+            || (new.is_some() && current == new)
+            || (old.is_some() && old == new);
+        if keep {
+            continue;
+        }
+        if old.is_some() && current == old && disk.uptodate(entry)? {
+            match new {
+                Some(new) => updates.push((path.clone(), new)),
+                None => removals.push(path.clone()),
+            }
+        } else {
+            refusal.local_changes.push(path.clone());
+        }
+    }
+    if !refusal.is_empty() {
+        return match (merge, old.is_some()) {
+            (Some(label), true) => {
+                merge_local_changes(err, &disk, index, &old_files, &new_files, label, refusal)
+            }
+            _ => Ok(Some(refusal)),
+        };
+    }
+
+    // files go first to make way for directories
+    for path in removals {
+        index.remove(&path);
+        remove_file(worktree, &path)?;
+    }
+    for (path, (mode, id)) in updates {
+        let metadata = write_file(repo, worktree, &path, mode, &id, disk.modes.symlinks)?;
+        index.add(IndexEntry::new(path, mode, id, &metadata));
+    }
+    repo.write_index(&index)?;
+    Ok(None)
+}
+
+/// Switches from `old_files` to `new_files` taking the local changes along: each tracked path
+/// is merged three ways, from what `old_files` has to what `new_files` and the worktree have.
+/// The index ends up with what `new_files` has, apart from the conflicts. Only untracked files
+/// in the way from `refusal` stop it.
+fn merge_local_changes(
+    err: &mut impl Write,
+    disk: &Worktree,
+    mut index: Index,
+    old_files: &BTreeMap<Vec<u8>, Version>,
+    new_files: &BTreeMap<Vec<u8>, Version>,
+    label: &str,
+    refusal: Refusal,
+) -> anyhow::Result<Option<Refusal>> {
+    let repo = disk.repo;
+    let staged: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|entry| old_files.get(&entry.path) != Some(&(entry.mode, entry.hash)))
+        .map(|entry| &entry.path)
+        .chain(old_files.keys().filter(|path| index.get(path).is_none()))
+        .map(|path| String::from_utf8_lossy(path).to_string())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    if !staged.is_empty() {
+        bail!(
+            "cannot continue with staged changes in the following files:\n{}",
+            staged.join(" ")
+        );
+    }
+    if !refusal.untracked_dirs.is_empty() || !refusal.untracked_overwritten.is_empty() {
+        return Ok(Some(Refusal {
+            merging: true,
+            ..refusal
+        }));
+    }
+
+    let paths: BTreeSet<&Vec<u8>> = old_files.keys().chain(new_files.keys()).collect();
+    let mut removals = Vec::new();
+    let mut updates = Vec::new();
+    for path in paths {
+        let base = old_files.get(path).copied();
+        let ours = new_files.get(path).copied();
+        let theirs = match index.get(path) {
+            Some(entry) => disk.version(entry)?,
+            None => None,
+        };
+        if theirs == ours {
+            match (ours, theirs == base) {
+                (Some(_), false) => {
+                    // the worktree has it already, so its stat data can go in
+                    let file = disk_path(disk.path, path);
+                    let metadata = fs::symlink_metadata(&file)
+                        .with_context(|| format!("stat {}", file.display()))?;
+                    if let Some(entry) = index.get_mut(path) {
+                        entry.stat = StatData::from_metadata(&metadata);
+                        (entry.mode, entry.hash) = ours.expect("present");
+                    }
+                }
+                (None, false) => {
+                    index.remove(path);
+                }
+                (_, true) => {}
+            }
+            continue;
+        }
+        if theirs == base {
+            match ours {
+                Some(ours) => updates.push((path.clone(), ours, None)),
+                None => removals.push(path.clone()),
+            }
+            continue;
+        }
+        if ours == base {
+            // only changed here, which stays as it is
+            continue;
+        }
+
+        let stages = [base, ours, theirs];
+        let regular = |version: &Option<Version>| {
+            version.is_some_and(|(mode, _)| mode & 0o170000 == 0o100000)
+        };
+        let (Some(base), Some(ours), Some(theirs)) = (base, ours, theirs) else {
+            // changed on one side and deleted on the other: what is left stays
+            if let (Some(ours), None) = (ours, theirs) {
+                updates.push((path.clone(), ours, None));
+            }
+            index.remove(path);
+            for (stage, version) in (1..).zip(stages) {
+                if let Some(version) = version {
+                    index.add(unstatted(path, version, stage));
+                }
+            }
+            continue;
+        };
+        if !stages.iter().all(regular) {
+            index.remove(path);
+            for (stage, version) in (1..).zip(stages) {
+                index.add(unstatted(path, version.expect("present"), stage));
+            }
+            continue;
+        }
+        let read = |id: &ObjectId| -> anyhow::Result<Vec<u8>> {
+            Object::read(repo.objects.as_ref(), &id.to_string())?.into_bytes()
+        };
+        let (base_data, ours_data, theirs_data) =
+            (read(&base.1)?, read(&ours.1)?, read(&theirs.1)?);
+        let (merged, conflicted) = if [&base_data, &ours_data, &theirs_data]
+            .iter()
+            .any(|data| is_binary(data))
+        {
+            writeln!(
+                err,
+                "warning: Cannot merge binary files: {} ({label} vs. local)",
+                String::from_utf8_lossy(path)
+            )?;
+            (ours_data, true)
+        } else {
+            merge_lines(&base_data, &ours_data, &theirs_data, label, "local")
+        };
+        let mode = if base.0 == ours.0 { theirs.0 } else { ours.0 };
+        let id = Object {
+            object_type: ObjectType::Blob,
+            expected_size: merged.len() as u64,
+            reader: &merged[..],
+        }
+        .write_to_objects(repo.objects.as_ref())
+        .context("write merged blob")?;
+        updates.push((path.clone(), (mode, id), Some(ours)));
+        if conflicted {
+            index.remove(path);
+            for (stage, version) in (1..).zip(stages) {
+                index.add(unstatted(path, version.expect("present"), stage));
+            }
+        }
+    }
+
+    for path in removals {
+        index.remove(&path);
+        remove_file(disk.path, &path)?;
+    }
+    for (path, (mode, id), staged) in updates {
+        let metadata = write_file(repo, disk.path, &path, mode, &id, disk.modes.symlinks)?;
+        // a merged file goes in with what `new_files` has, which the worktree no longer does
+        let entry = match staged {
+            Some(ours) => unstatted(&path, ours, 0),
+            None => IndexEntry::new(path.clone(), mode, id, &metadata),
+        };
+        if index.stages(&path).iter().all(|entry| entry.stage == 0) {
+            index.add(entry);
+        }
+    }
+    repo.write_index(&index)?;
+    Ok(None)
+}
+
+/// Where `HEAD` is when a switch starts.
+#[derive(Debug)]
+pub(crate) struct Origin {
+    /// The short name of the branch checked out, `None` when `HEAD` is detached.
+    pub(crate) branch: Option<String>,
+    /// `None` on a branch yet to be born.
+    pub(crate) commit: Option<ObjectId>,
+}
+
+impl Origin {
+    pub(crate) fn read(repo: &Repository) -> anyhow::Result<Self> {
+        let commit = match repo.resolve_ref("HEAD")? {
+            Some(hash) => Some(hash.parse()?),
+            None => None,
+        };
+        let branch = match repo.head()? {
+            Head::Branch(name) => Some(
+                name.strip_prefix("refs/heads/")
+                    .unwrap_or(&name)
+                    .to_string(),
+            ),
+            Head::Detached(_) => None,
+        };
+        Ok(Self { branch, commit })
+    }
+}
+
+/// Where a switch goes.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Destination {
+    /// The branch `name`, created at `start` when that is given. Only a branch created on one
+    /// yet to be born has no commit.
+    Branch {
+        name: String,
+        start: Option<String>,
+        commit: Option<ObjectId>,
+    },
+    /// `HEAD` detached at the commit `revision` names.
+    Detached { revision: String, commit: ObjectId },
+}
+
+impl Destination {
+    /// What the reflog and conflict markers call it: the branch, or the revision as given.
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Destination::Branch { name, .. } => name,
+            Destination::Detached { revision, .. } => revision,
+        }
+    }
+
+    pub(crate) fn commit(&self) -> Option<&ObjectId> {
+        match self {
+            Destination::Branch { commit, .. } => commit.as_ref(),
+            Destination::Detached { commit, .. } => Some(commit),
+        }
+    }
+}
+
+/// The commit `revision` names, for `switch`.
+fn switch_commit(repo: &Repository, revision: &str) -> anyhow::Result<ObjectId> {
+    if repo.resolve_revision(revision)?.is_none() {
+        bail!("invalid reference: {revision}");
+    }
+    resolve_commit(repo, revision)
+}
+
+/// Works out where `switch` goes from `origin`: to the branch `target`, to a new branch
+/// `create` starting at `target` or `HEAD`, or with `detach` to the commit `target` names. `-`
+/// stands for what was checked out before, and a branch only one remote has is created from
+/// that remote's.
+pub(crate) fn destination(
+    repo: &Repository,
+    origin: &Origin,
+    target: Option<&str>,
+    create: Option<&str>,
+    detach: bool,
+) -> anyhow::Result<Destination> {
+    let previous;
+    let target = match target {
+        Some("-") => {
+            previous = previous_checkout(repo)?.context("invalid reference: @{-1}")?;
+            Some(previous.as_str())
+        }
+        target => target,
+    };
+
+    if let Some(create) = create {
+        check_name(create)?;
+        if repo.resolve_ref(&format!("refs/heads/{create}"))?.is_some() {
+            bail!("a branch named '{create}' already exists");
+        }
+        let commit = match target {
+            Some(start) => Some(switch_commit(repo, start)?),
+            None => origin.commit,
+        };
+        return Ok(Destination::Branch {
+            name: create.to_string(),
+            start: Some(target.unwrap_or("HEAD").to_string()),
+            commit,
+        });
+    }
+    if detach {
+        let target = target.unwrap_or("HEAD");
+        if target == "HEAD" && origin.commit.is_none() {
+            bail!("You are on a branch yet to be born");
+        }
+        return Ok(Destination::Detached {
+            revision: target.to_string(),
+            commit: switch_commit(repo, target)?,
+        });
+    }
+
+    let target = target.context("missing branch or commit argument")?;
+    if let Some(hash) = repo.resolve_ref(&format!("refs/heads/{target}"))? {
+        return Ok(Destination::Branch {
+            name: target.to_string(),
+            start: None,
+            commit: Some(hash.parse()?),
+        });
+    }
+    let remote_branches: Vec<String> = repo
+        .refs()?
+        .into_iter()
+        .filter_map(|(reference, _)| {
+            let remote_branch = reference.strip_prefix("refs/remotes/")?;
+            let (_, branch) = remote_branch.split_once('/')?;
+            (branch == target).then(|| remote_branch.to_string())
+        })
+        .collect();
+    if let [remote_branch] = &remote_branches[..] {
+        let start = format!("refs/remotes/{remote_branch}");
+        return Ok(Destination::Branch {
+            name: target.to_string(),
+            commit: Some(switch_commit(repo, &start)?),
+            start: Some(start),
+        });
+    }
+    if repo.resolve_revision(target)?.is_none() {
+        bail!("invalid reference: {target}");
+    }
+    let kind = match repo.expand_ref(target)? {
+        Some((reference, _)) if reference.starts_with("refs/tags/") => "tag",
+        Some((reference, _)) if reference.starts_with("refs/remotes/") => "remote branch",
+        _ => "commit",
+    };
+    bail!(
+        "a branch is expected, got {kind} '{target}'\n\
+         hint: If you want to detach HEAD at the commit, try again with the --detach option."
+    );
+}
+
+/// The paths with more than one stage in `index`, each once.
+pub(crate) fn unmerged_paths(index: &Index) -> Vec<&[u8]> {
+    let mut unmerged: Vec<&[u8]> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage != 0)
+        .map(|entry| &entry.path[..])
+        .collect();
+    unmerged.dedup();
+    unmerged
+}
+
+/// What is left of the local changes once the switch to `commit` is done, by path, with the
+/// letter `switch` lists each under. Conflicts count as modified, or as added where `commit`
+/// doesn't have the path and as deleted where the worktree doesn't.
+pub(crate) fn local_changes(
+    repo: &Repository,
+    dot_git_path: &Path,
+    commit: &ObjectId,
+) -> anyhow::Result<BTreeMap<Vec<u8>, char>> {
+    let worktree = dot_git_path
+        .parent()
+        .context("repository has no working tree")?;
+    let tree = Commit::read(repo.objects.as_ref(), &commit.to_string())?.tree;
+    let mut changes: BTreeMap<Vec<u8>, char> =
+        worktree_changes(repo, dot_git_path, Some(&tree), &Pathspec::default())?
+            .iter()
+            .map(|pair| (pair.path().to_vec(), pair.kind().letter()))
+            .collect();
+    let index = repo.index()?;
+    for path in unmerged_paths(&index) {
+        let letter = if !index.stages(path).iter().any(|entry| entry.stage == 2) {
+            'A'
+        } else if fs::symlink_metadata(disk_path(worktree, path)).is_err() {
+            'D'
+        } else {
+            'M'
+        };
+        changes.insert(path.to_vec(), letter);
+    }
+    Ok(changes)
+}
+
+/// Points `HEAD` at `destination`, creating the branch first when it is new, and records the
+/// move from `origin` in its reflog. The upstream a new branch was set up to track is
+/// returned.
+pub(crate) fn move_head(
+    repo: &Repository,
+    worktree: &Path,
+    origin: &Origin,
+    destination: &Destination,
+) -> anyhow::Result<Option<String>> {
+    let mut upstream = None;
+    match destination {
+        Destination::Branch {
+            name,
+            start,
+            commit,
+        } => {
+            if let (Some(start), Some(_)) = (start, commit) {
+                upstream = create(repo, worktree, name, Some(start), false)?;
+            }
+            repo.files
+                .write("HEAD", format!("ref: refs/heads/{name}\n").as_bytes())?;
+        }
+        Destination::Detached { commit, .. } => repo.update_ref("HEAD", &commit.to_string())?,
+    }
+    if let Some(commit) = destination.commit() {
+        let old = origin.commit.map(|id| id.to_string());
+        let from = match (&origin.branch, &old) {
+            (Some(branch), _) => branch.as_str(),
+            (None, old) => old.as_deref().unwrap_or_default(),
+        };
+        reflog::append(
+            repo,
+            "HEAD",
+            old.as_deref(),
+            Some(&commit.to_string()),
+            &format!("checkout: moving from {from} to {}", destination.name()),
+        )?;
+    }
+    Ok(upstream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commit::{commit, Commit},
+        hash::HashAlgorithm,
+        pathspec::Pathspec,
+        staging::add,
+    };
+
+    #[test]
+    fn test_switch_trees() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let dot_git = worktree.join(".git");
+        let repo = Repository::on_disk(&dot_git, HashAlgorithm::Sha1);
+        repo.init()?;
+        let tree_of_head = |repo: &Repository| -> anyhow::Result<String> {
+            let head = repo.resolve_name("HEAD")?.expect("a commit");
+            Ok(Commit::read(repo.objects.as_ref(), &head.to_string())?.tree)
+        };
+        fs::write(worktree.join("a"), "1\n2\n3\n4\n5\n6\n")?;
+        fs::write(worktree.join("b"), "b\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        commit(&repo, "first")?;
+        let first = tree_of_head(&repo)?;
+        fs::write(worktree.join("a"), "1\ntwo\n3\n4\n5\n6\n")?;
+        fs::remove_file(worktree.join("b"))?;
+        fs::write(worktree.join("n"), "n\n")?;
+        add(&repo, worktree, &Pathspec::default(), true, false)?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        commit(&repo, "second")?;
+        let second = tree_of_head(&repo)?;
+
+        assert!(switch_trees(
+            &mut Vec::new(),
+            &repo,
+            &dot_git,
+            Some(&second),
+            &first,
+            None
+        )?
+        .is_none());
+        assert_eq!(
+            fs::read_to_string(worktree.join("a"))?,
+            "1\n2\n3\n4\n5\n6\n"
+        );
+        assert!(worktree.join("b").exists());
+        assert!(!worktree.join("n").exists());
+
+        // an untracked file and a local change in the way
+        fs::write(worktree.join("n"), "n\n")?;
+        fs::write(worktree.join("a"), "1\n2\n3\n4\n5\nsix\n")?;
+        let refusal = switch_trees(
+            &mut Vec::new(),
+            &repo,
+            &dot_git,
+            Some(&first),
+            &second,
+            None,
+        )?;
+        let refusal = refusal.expect("refused");
+        assert_eq!(refusal.local_changes, [b"a".to_vec()]);
+        assert_eq!(refusal.untracked_overwritten, [b"n".to_vec()]);
+
+        // carried along when merging
+        fs::remove_file(worktree.join("n"))?;
+        assert!(switch_trees(
+            &mut Vec::new(),
+            &repo,
+            &dot_git,
+            Some(&first),
+            &second,
+            Some("second")
+        )?
+        .is_none());
+        assert_eq!(
+            fs::read_to_string(worktree.join("a"))?,
+            "1\ntwo\n3\n4\n5\nsix\n"
+        );
+        let index = repo.index()?;
+        assert!(!index.has_conflicts());
+        assert!(index.get(b"n").is_some() && index.get(b"b").is_none());
+
+        // a tree reaching into .git is refused before anything is written
+        let write = |object_type, data: &[u8]| -> anyhow::Result<ObjectId> {
+            Object {
+                object_type,
+                expected_size: data.len() as u64,
+                reader: data,
+            }
+            .write_to_objects(repo.objects.as_ref())
+        };
+        let mut tree = b"100755 pre-commit\0".to_vec();
+        tree.extend(write(ObjectType::Blob, b"#!/bin/sh\n")?.as_bytes());
+        let hooks = write(ObjectType::Tree, &tree)?;
+        let mut tree = b"40000 hooks\0".to_vec();
+        tree.extend(hooks.as_bytes());
+        let dot_git_tree = write(ObjectType::Tree, &tree)?;
+        let mut tree = b"40000 .git\0".to_vec();
+        tree.extend(dot_git_tree.as_bytes());
+        let evil = write(ObjectType::Tree, &tree)?.to_string();
+        let err =
+            switch_trees(&mut Vec::new(), &repo, &dot_git, Some(&second), &evil, None).unwrap_err();
+        assert_eq!(err.to_string(), "invalid path '.git/hooks/pre-commit'");
+        assert!(!dot_git.join("hooks/pre-commit").exists());
+        assert!(worktree.join("a").exists());
+        assert_eq!(repo.index()?.entries.len(), index.entries.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_destination() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let worktree = temp_dir.path();
+        let repo = Repository::on_disk(&worktree.join(".git"), HashAlgorithm::Sha1);
+        repo.init()?;
+        fs::write(worktree.join("a"), "a\n")?;
+        add(&repo, worktree, &Pathspec::default(), false, false)?;
+        let head = commit(&repo, "first")?.expect("a commit");
+        repo.update_ref("refs/remotes/origin/feature", &head.to_string())?;
+        let origin = Origin::read(&repo)?;
+        let resolve = |target: Option<&str>, create: Option<&str>, detach: bool| {
+            destination(&repo, &origin, target, create, detach)
+        };
+
+        assert_eq!(
+            resolve(None, Some("topic"), false)?,
+            Destination::Branch {
+                name: "topic".to_string(),
+                start: Some("HEAD".to_string()),
+                commit: Some(head),
+            }
+        );
+        assert_eq!(
+            resolve(Some("master"), None, false)?,
+            Destination::Branch {
+                name: "master".to_string(),
+                start: None,
+                commit: Some(head),
+            }
+        );
+        assert_eq!(
+            resolve(Some("feature"), None, false)?,
+            Destination::Branch {
+                name: "feature".to_string(),
+                start: Some("refs/remotes/origin/feature".to_string()),
+                commit: Some(head),
+            }
+        );
+        assert_eq!(
+            resolve(None, None, true)?,
+            Destination::Detached {
+                revision: "HEAD".to_string(),
+                commit: head,
+            }
+        );
+        let error = resolve(Some(&head.to_string()), None, false).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("a branch is expected, got commit"));
+        let error = resolve(Some("nope"), None, false).unwrap_err();
+        assert_eq!(error.to_string(), "invalid reference: nope");
+        Ok(())
+    }
+}
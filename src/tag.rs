@@ -0,0 +1,62 @@
+use anyhow::{bail, ensure, Context};
+
+use crate::{
+    commit::{parse_headers, Signature},
+    hash::ObjectId,
+    object::{Object, ObjectType},
+    store::ObjectStore,
+};
+
+/// An annotated tag object.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Tag {
+    pub object: String,
+    pub object_type: ObjectType,
+    pub name: String,
+    pub tagger: Option<Signature>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let data = String::from_utf8_lossy(data);
+        let (headers, message) = parse_headers(&data);
+        let mut headers = headers.into_iter();
+
+        let Some(("object", object)) = headers.next() else {
+            bail!("tag does not start with an object header");
+        };
+        ObjectId::from_hex(object).context("bad object id in tag")?;
+        let Some(("type", object_type)) = headers.next() else {
+            bail!("tag is missing a type header");
+        };
+        let Some(("tag", name)) = headers.next() else {
+            bail!("tag is missing a tag header");
+        };
+        ensure!(!name.is_empty(), "tag has an empty name");
+        let tagger = match headers.next() {
+            Some(("tagger", tagger)) => {
+                Some(Signature::parse(tagger).context("bad tagger in tag")?)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            object: object.to_string(),
+            object_type: object_type.parse()?,
+            name: name.to_string(),
+            tagger,
+            message: message.to_string(),
+        })
+    }
+
+    pub fn read(objects: &dyn ObjectStore, hash: &str) -> anyhow::Result<Self> {
+        let object = Object::read(objects, hash)?;
+        ensure!(
+            object.object_type == ObjectType::Tag,
+            "object {hash} is a {}, not a tag",
+            object.object_type
+        );
+        Self::parse(&object.into_bytes()?).with_context(|| format!("parse tag {hash}"))
+    }
+}
@@ -1,6 +1,6 @@
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use flate2::{write::ZlibEncoder, Compression};
@@ -9,6 +9,7 @@ use tempfile::tempdir;
 
 use crate::config::Config;
 use crate::git::Git;
+use crate::repository::Repository;
 
 pub(crate) fn build_test_git() -> anyhow::Result<TestGit> {
     let temp_dir = tempdir()?;
@@ -19,7 +20,17 @@ pub(crate) fn build_test_git() -> anyhow::Result<TestGit> {
         error_writer,
         dot_git_path: temp_dir.path().to_path_buf().join(".git"),
     };
-    Ok(Git { config })
+    Git::new(config)
+}
+
+/// A git whose repository lives entirely in memory.
+pub(crate) fn build_memory_git() -> anyhow::Result<TestGit> {
+    let config = Config {
+        writer: Vec::new(),
+        error_writer: Vec::new(),
+        dot_git_path: PathBuf::from("in-memory/.git"),
+    };
+    Ok(Git::with_repository(config, Repository::in_memory()))
 }
 
 pub(crate) fn build_simple_app_git() -> anyhow::Result<TestGit> {
@@ -34,7 +45,7 @@ pub(crate) fn build_git_from_fixture(fixture: &str) -> anyhow::Result<TestGit> {
         error_writer,
         dot_git_path: PathBuf::from(format!("tests/fixtures/{}/dot-git", fixture)),
     };
-    Ok(Git { config })
+    Git::new(config)
 }
 
 pub type TestGit = Git<Vec<u8>, Vec<u8>>;
@@ -70,3 +81,16 @@ pub(crate) fn write_to_git_objects(
 
     Ok((hash, file_path))
 }
+
+pub(crate) fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
@@ -1,23 +1,34 @@
 use std::fmt::Write;
 use std::{
     cmp::Ordering,
-    ffi::CStr,
+    collections::BTreeMap,
+    ffi::OsStr,
     fmt::{self, Display},
     fs::{self, Metadata},
     io::{BufRead, Cursor, Read},
-    os::unix::fs::PermissionsExt,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
     path::{Path, PathBuf},
 };
 
 use anyhow::{bail, Context};
 
-use crate::object::{Object, ObjectType};
+use crate::{
+    commit::Signature,
+    hash::ObjectId,
+    ignore::IgnoreRules,
+    index::{CacheTree, Index, IndexEntry},
+    object::{Object, ObjectType},
+    store::ObjectStore,
+    worktree::{is_nested_repo, nested_repo_head, verify_path},
+};
 
 #[derive(Debug, PartialEq, Eq, Default)]
 pub enum TreeEntryType {
     Blob,
     #[default]
     Tree,
+    /// A commit of a submodule, which lives in that repository rather than this one.
+    Commit,
 }
 
 impl Display for TreeEntryType {
@@ -25,6 +36,7 @@ impl Display for TreeEntryType {
         match self {
             TreeEntryType::Blob => write!(f, "blob"),
             TreeEntryType::Tree => write!(f, "tree"),
+            TreeEntryType::Commit => write!(f, "commit"),
         }
     }
 }
@@ -37,7 +49,8 @@ pub struct Tree {
 #[derive(Debug, PartialEq, Eq, Default)]
 pub struct TreeEntry {
     pub mode: TreeEntryMode,
-    pub name: String,
+    /// The name as stored, which needn't be UTF-8.
+    pub name: Vec<u8>,
     pub sha: String,
 }
 
@@ -45,6 +58,7 @@ impl TreeEntry {
     pub fn tree_entry_type(&self) -> TreeEntryType {
         match self.mode {
             TreeEntryMode::Directory => TreeEntryType::Tree,
+            TreeEntryMode::Gitlink => TreeEntryType::Commit,
             _ => TreeEntryType::Blob,
         }
     }
@@ -57,20 +71,46 @@ pub enum TreeEntryMode {
     ExecutableFile,
     SymbolicLink,
     Directory,
+    /// A submodule, checked out at the commit the entry names.
+    Gitlink,
 }
 
-impl From<&str> for TreeEntryMode {
-    fn from(value: &str) -> Self {
-        match value {
-            "100644" => TreeEntryMode::RegularFile,
-            "100755" => TreeEntryMode::ExecutableFile,
-            "120000" => TreeEntryMode::SymbolicLink,
-            "040000" | "40000" => TreeEntryMode::Directory,
-            _ => panic!("unknown tree entry mode `{value}`"),
+impl TreeEntryMode {
+    /// The mode as the number the index records.
+    pub fn bits(self) -> u32 {
+        match self {
+            TreeEntryMode::RegularFile => 0o100644,
+            TreeEntryMode::ExecutableFile => 0o100755,
+            TreeEntryMode::SymbolicLink => 0o120000,
+            TreeEntryMode::Directory => 0o40000,
+            TreeEntryMode::Gitlink => 0o160000,
         }
     }
 }
 
+impl TryFrom<&str> for TreeEntryMode {
+    type Error = anyhow::Error;
+
+    /// Like git, legacy modes such as `100664` are read by their file type alone, a regular
+    /// file being executable when its owner may execute it.
+    fn try_from(value: &str) -> anyhow::Result<Self> {
+        let mode = value
+            .bytes()
+            .all(|b| (b'0'..=b'7').contains(&b))
+            .then(|| u32::from_str_radix(value, 8).ok())
+            .flatten()
+            .with_context(|| format!("malformed tree entry mode `{value}`"))?;
+        Ok(match mode & 0o170000 {
+            0o100000 if mode & 0o100 != 0 => TreeEntryMode::ExecutableFile,
+            0o100000 => TreeEntryMode::RegularFile,
+            0o120000 => TreeEntryMode::SymbolicLink,
+            0o040000 => TreeEntryMode::Directory,
+            0o160000 => TreeEntryMode::Gitlink,
+            _ => bail!("unknown tree entry mode `{value}`"),
+        })
+    }
+}
+
 impl From<&Metadata> for TreeEntryMode {
     fn from(meta: &Metadata) -> Self {
         if meta.is_dir() {
@@ -92,6 +132,7 @@ impl Display for TreeEntryMode {
             TreeEntryMode::ExecutableFile => write!(f, "100755"),
             TreeEntryMode::SymbolicLink => write!(f, "120000"),
             TreeEntryMode::Directory => write!(f, "040000"),
+            TreeEntryMode::Gitlink => write!(f, "160000"),
         }
     }
 }
@@ -99,7 +140,7 @@ impl Display for TreeEntryMode {
 #[derive(Debug, Default)]
 struct TreeEntryBytesBuilder {
     mode: Option<TreeEntryMode>,
-    name: Option<String>,
+    name: Option<Vec<u8>>,
     path: Option<PathBuf>,
     is_dir: Option<bool>,
 }
@@ -107,10 +148,16 @@ struct TreeEntryBytesBuilder {
 impl TreeEntryBytesBuilder {
     fn dir_entry(mut self, entry: &fs::DirEntry) -> Self {
         let meta = entry.metadata().expect("metadata for directory entry");
-        self.mode = Some(TreeEntryMode::from(&meta));
-        self.name = Some(entry.file_name().to_string_lossy().to_string());
+        // a repository of its own is recorded as the commit it has checked out
+        let is_gitlink = meta.is_dir() && is_nested_repo(&entry.path());
+        self.mode = Some(if is_gitlink {
+            TreeEntryMode::Gitlink
+        } else {
+            TreeEntryMode::from(&meta)
+        });
+        self.name = Some(entry.file_name().as_bytes().to_vec());
         self.path = Some(entry.path());
-        self.is_dir = Some(meta.is_dir());
+        self.is_dir = Some(meta.is_dir() && !is_gitlink);
         self
     }
 
@@ -123,15 +170,14 @@ impl TreeEntryBytesBuilder {
     }
 
     fn is_dot_git_entry(&self, dot_git_path: &Path) -> bool {
-        self.name.as_ref().map_or(false, |name| {
+        self.name.as_ref().is_some_and(|name| {
             dot_git_path
                 .file_name()
-                .and_then(|file_name| file_name.to_str())
-                .map_or(false, |file_name_str| name == file_name_str)
+                .is_some_and(|file_name| name == file_name.as_bytes())
         })
     }
 
-    fn to_raw_bytes(&self, hash: [u8; 20]) -> anyhow::Result<Vec<u8>> {
+    fn to_raw_bytes(&self, hash: ObjectId) -> anyhow::Result<Vec<u8>> {
         let mode = self.mode.context("mode is required")?;
         let mode_string = match mode {
             TreeEntryMode::Directory => "40000".to_string(), // git doesn't use 040000
@@ -141,9 +187,9 @@ impl TreeEntryBytesBuilder {
         let mut raw_bytes = Vec::new();
         raw_bytes.extend(mode_string.as_bytes());
         raw_bytes.push(b' ');
-        raw_bytes.extend(file_name.as_bytes());
+        raw_bytes.extend(file_name);
         raw_bytes.push(0);
-        raw_bytes.extend(hash);
+        raw_bytes.extend(hash.as_bytes());
         Ok(raw_bytes)
     }
 }
@@ -153,27 +199,32 @@ fn compare_tree_entry_bytes_builder(
     b: &TreeEntryBytesBuilder,
 ) -> Ordering {
     let afn = a.name.as_ref().expect("name is required");
-    let afn = afn.as_bytes();
     let bfn = b.name.as_ref().expect("name is required");
-    let bfn = bfn.as_bytes();
+    compare_tree_entry_names(afn, a.is_dir(), bfn, b.is_dir())
+}
+
+/// Git sorts tree entries by name, comparing directories as if their name ended in `/`.
+pub(crate) fn compare_tree_entry_names(
+    afn: &[u8],
+    a_is_dir: bool,
+    bfn: &[u8],
+    b_is_dir: bool,
+) -> Ordering {
     let common_len = std::cmp::min(afn.len(), bfn.len());
     match afn[..common_len].cmp(&bfn[..common_len]) {
         Ordering::Equal => {}
         o => return o,
     }
-    if afn.len() == bfn.len() {
-        return Ordering::Equal;
-    }
     let c1 = if let Some(c) = afn.get(common_len).copied() {
         Some(c)
-    } else if a.is_dir() {
+    } else if a_is_dir {
         Some(b'/')
     } else {
         None
     };
     let c2 = if let Some(c) = bfn.get(common_len).copied() {
         Some(c)
-    } else if b.is_dir() {
+    } else if b_is_dir {
         Some(b'/')
     } else {
         None
@@ -182,13 +233,13 @@ fn compare_tree_entry_bytes_builder(
     c1.cmp(&c2)
 }
 
-pub(crate) fn build_tree(dot_git_path: &Path, tree_hash: &str) -> anyhow::Result<Tree> {
+pub(crate) fn build_tree(objects: &dyn ObjectStore, tree_hash: &str) -> anyhow::Result<Tree> {
     let mut tree_entries = vec![];
-    let mut object = Object::read(dot_git_path, tree_hash).context("parse out tree object file")?;
+    let mut object = Object::read(objects, tree_hash).context("parse out tree object file")?;
     match object.object_type {
         ObjectType::Tree => {
             let mut buffer = Vec::new();
-            let mut sha_buffer = [0; 20];
+            let mut sha_buffer = vec![0; objects.hash_algorithm().raw_len()];
 
             loop {
                 buffer.clear();
@@ -204,19 +255,20 @@ pub(crate) fn build_tree(dot_git_path: &Path, tree_hash: &str) -> anyhow::Result
                     .read_exact(&mut sha_buffer[..])
                     .context("failed to read sha entry")?;
 
-                let header = CStr::from_bytes_with_nul(&buffer)
-                    .expect("only one nul at the end")
-                    .to_str()
-                    .context("tree entry line is no valid UTF-8")?;
-
-                let Some((mode, name)) = header.split_once(' ') else {
-                    bail!("invalid tree entry line `{header}`");
+                let header = buffer.strip_suffix(b"\0").unwrap_or(&buffer);
+                let Some(space) = header.iter().position(|&b| b == b' ') else {
+                    bail!(
+                        "invalid tree entry line `{}`",
+                        String::from_utf8_lossy(header)
+                    );
                 };
+                let mode = std::str::from_utf8(&header[..space])
+                    .context("tree entry mode is no valid UTF-8")?;
 
-                let sha = hex::encode(sha_buffer);
+                let sha = hex::encode(&sha_buffer);
                 tree_entries.push(TreeEntry {
-                    mode: mode.into(),
-                    name: name.to_string(),
+                    mode: mode.try_into()?,
+                    name: header[space + 1..].to_vec(),
                     sha,
                 });
             }
@@ -228,7 +280,25 @@ pub(crate) fn build_tree(dot_git_path: &Path, tree_hash: &str) -> anyhow::Result
     }
 }
 
-pub(crate) fn write_tree_for(dot_git_path: &Path, path: &Path) -> anyhow::Result<Option<[u8; 20]>> {
+/// Snapshots the directory `path` as a tree, whatever the index says, skipping `.git` and
+/// the files its ignore rules leave out. Nested repositories become gitlinks to their HEAD.
+pub fn write_tree_for(
+    objects: &dyn ObjectStore,
+    dot_git_path: &Path,
+    path: &Path,
+) -> anyhow::Result<Option<ObjectId>> {
+    let ignore = IgnoreRules::open(dot_git_path, path)?;
+    write_dir_tree(objects, dot_git_path, path, &[], &ignore)
+}
+
+/// Writes the tree for the directory `path`, which is at `prefix` in the worktree.
+fn write_dir_tree(
+    objects: &dyn ObjectStore,
+    dot_git_path: &Path,
+    path: &Path,
+    prefix: &[u8],
+    ignore: &IgnoreRules,
+) -> anyhow::Result<Option<ObjectId>> {
     let dir = fs::read_dir(path).with_context(|| format!("open directory {}", path.display()))?;
 
     let mut entries = Vec::new();
@@ -245,17 +315,43 @@ pub(crate) fn write_tree_for(dot_git_path: &Path, path: &Path) -> anyhow::Result
         if builder.is_dot_git_entry(dot_git_path) {
             continue;
         }
+        let mut entry_path = prefix.to_vec();
+        entry_path.extend(builder.name.as_deref().unwrap_or_default());
+        if ignore.is_ignored(&entry_path, builder.is_dir()) {
+            continue;
+        }
 
-        let hash = if builder.is_dir() {
-            let Some(hash) = write_tree_for(dot_git_path, builder.path())? else {
+        let hash = if builder.mode == Some(TreeEntryMode::Gitlink) {
+            let Some(hash) = nested_repo_head(builder.path())? else {
+                // nothing committed there yet
+                continue;
+            };
+            hash
+        } else if builder.is_dir() {
+            entry_path.push(b'/');
+            let Some(hash) =
+                write_dir_tree(objects, dot_git_path, builder.path(), &entry_path, ignore)?
+            else {
                 // empty directory, so don't include in parent
                 continue;
             };
             hash
+        } else if builder.mode == Some(TreeEntryMode::SymbolicLink) {
+            // the link itself is stored, as the path it points to
+            let target = fs::read_link(builder.path())
+                .with_context(|| format!("read link {}", builder.path().display()))?;
+            let target = target.as_os_str().as_bytes();
+            Object {
+                object_type: ObjectType::Blob,
+                expected_size: target.len() as u64,
+                reader: target,
+            }
+            .write_to_objects(objects)
+            .context("writing to objects")?
         } else {
-            Object::blob_from_file(&builder.path())
+            Object::blob_from_file(builder.path())
                 .context("open blob input file")?
-                .write_to_objects(dot_git_path)
+                .write_to_objects(objects)
                 .context("writing to objects")?
         };
         tree_object.extend(builder.to_raw_bytes(hash)?);
@@ -270,37 +366,200 @@ pub(crate) fn write_tree_for(dot_git_path: &Path, path: &Path) -> anyhow::Result
                 expected_size: tree_object.len() as u64,
                 reader: Cursor::new(tree_object),
             }
-            .write_to_objects(dot_git_path)
+            .write_to_objects(objects)
             .context("write tree object")?,
         ))
     }
 }
 
+/// Writes the trees for the entries of `index`, reusing the trees its `TREE` extension still
+/// has and recording the new ones there. Entries only marked as intended to be added are
+/// left out.
+pub(crate) fn write_tree_from_index(
+    objects: &dyn ObjectStore,
+    index: &mut Index,
+) -> anyhow::Result<ObjectId> {
+    if let Some(entry) = index.entries.iter().find(|entry| entry.stage != 0) {
+        bail!(
+            "path '{}' is unmerged",
+            String::from_utf8_lossy(&entry.path)
+        );
+    }
+    let entries: Vec<&IndexEntry> = index
+        .entries
+        .iter()
+        .filter(|entry| !entry.intent_to_add)
+        .collect();
+    let cache_tree = write_index_tree(objects, &entries, 0, Vec::new(), index.cache_tree.as_ref())?;
+    let hash = cache_tree.hash.context("root tree was written")?;
+    index.cache_tree = Some(cache_tree);
+    Ok(hash)
+}
+
+/// Writes the tree for `entries`, which all start with the same `prefix_len` bytes of path.
+fn write_index_tree(
+    objects: &dyn ObjectStore,
+    entries: &[&IndexEntry],
+    prefix_len: usize,
+    name: Vec<u8>,
+    cached: Option<&CacheTree>,
+) -> anyhow::Result<CacheTree> {
+    if let Some(CacheTree {
+        entry_count,
+        hash: Some(hash),
+        subtrees,
+        ..
+    }) = cached
+    {
+        if *entry_count == entries.len() as i32 && objects.contains(&hash.to_string())? {
+            return Ok(CacheTree {
+                name,
+                entry_count: *entry_count,
+                hash: Some(*hash),
+                subtrees: subtrees.clone(),
+            });
+        }
+    }
+
+    let mut items: Vec<(Vec<u8>, bool, u32, ObjectId)> = Vec::new();
+    let mut subtrees = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let relative = &entries[i].path[prefix_len..];
+        let Some(slash) = relative.iter().position(|&b| b == b'/') else {
+            items.push((relative.to_vec(), false, entries[i].mode, entries[i].hash));
+            i += 1;
+            continue;
+        };
+        // sorting by path keeps everything in a directory together
+        let dir = &relative[..=slash];
+        let end = i + entries[i..]
+            .iter()
+            .take_while(|entry| entry.path[prefix_len..].starts_with(dir))
+            .count();
+        let dir_name = relative[..slash].to_vec();
+        let subtree = write_index_tree(
+            objects,
+            &entries[i..end],
+            prefix_len + slash + 1,
+            dir_name.clone(),
+            cached.and_then(|cached| cached.subtrees.iter().find(|sub| sub.name == dir_name)),
+        )?;
+        let hash = subtree.hash.context("subtree was written")?;
+        items.push((dir_name, true, TreeEntryMode::Directory.bits(), hash));
+        subtrees.push(subtree);
+        i = end;
+    }
+
+    items.sort_by(|a, b| compare_tree_entry_names(&a.0, a.1, &b.0, b.1));
+    let mut data = Vec::new();
+    for (name, _, mode, hash) in items {
+        data.extend(format!("{mode:o} ").as_bytes());
+        data.extend(name);
+        data.push(0);
+        data.extend(hash.as_bytes());
+    }
+    let hash = Object {
+        object_type: ObjectType::Tree,
+        expected_size: data.len() as u64,
+        reader: Cursor::new(data),
+    }
+    .write_to_objects(objects)
+    .context("write tree object")?;
+    Ok(CacheTree {
+        name,
+        entry_count: entries.len() as i32,
+        hash: Some(hash),
+        subtrees,
+    })
+}
+
+/// Every non-tree entry below the tree `tree_hash` by path, with its mode and object.
+pub(crate) fn tree_files(
+    objects: &dyn ObjectStore,
+    tree_hash: &str,
+) -> anyhow::Result<BTreeMap<Vec<u8>, (u32, ObjectId)>> {
+    let mut files = BTreeMap::new();
+    collect_tree_files(objects, tree_hash, &[], &mut files)?;
+    Ok(files)
+}
+
+fn collect_tree_files(
+    objects: &dyn ObjectStore,
+    tree_hash: &str,
+    prefix: &[u8],
+    files: &mut BTreeMap<Vec<u8>, (u32, ObjectId)>,
+) -> anyhow::Result<()> {
+    for entry in build_tree(objects, tree_hash)?.entries {
+        let mut path = prefix.to_vec();
+        path.extend(&entry.name);
+        if entry.mode == TreeEntryMode::Directory {
+            path.push(b'/');
+            collect_tree_files(objects, &entry.sha, &path, files)?;
+        } else {
+            files.insert(path, (entry.mode.bits(), ObjectId::from_hex(&entry.sha)?));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the files of a tree into `dir`, creating directories as needed. Symlinks are
+/// created as such, or without `symlinks` as plain files holding the path they point to, and
+/// submodules as empty directories.
+pub(crate) fn checkout_tree(
+    objects: &dyn ObjectStore,
+    tree_hash: &str,
+    dir: &Path,
+    symlinks: bool,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("create directory {}", dir.display()))?;
+    for entry in build_tree(objects, tree_hash)?.entries {
+        if entry.name.contains(&b'/') {
+            bail!("invalid path '{}'", String::from_utf8_lossy(&entry.name));
+        }
+        verify_path(&entry.name)?;
+        let path = dir.join(OsStr::from_bytes(&entry.name));
+        if entry.mode == TreeEntryMode::Directory {
+            checkout_tree(objects, &entry.sha, &path, symlinks)?;
+            continue;
+        }
+        if entry.mode == TreeEntryMode::Gitlink {
+            // submodules are left empty until `submodule update`
+            fs::create_dir_all(&path)
+                .with_context(|| format!("create directory {}", path.display()))?;
+            continue;
+        }
+        let contents = Object::read(objects, &entry.sha)?.into_bytes()?;
+        match entry.mode {
+            TreeEntryMode::SymbolicLink if symlinks => {
+                std::os::unix::fs::symlink(OsStr::from_bytes(&contents), &path)
+            }
+            _ => fs::write(&path, contents),
+        }
+        .with_context(|| format!("write {}", path.display()))?;
+        if entry.mode == TreeEntryMode::ExecutableFile {
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+                .with_context(|| format!("make {} executable", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn commit_tree(
-    dot_git_path: &Path,
+    objects: &dyn ObjectStore,
     message: &str,
     tree_hash: &str,
     parent_hash: Option<&str>,
-) -> anyhow::Result<Option<[u8; 20]>> {
+) -> anyhow::Result<Option<ObjectId>> {
     let mut commit = String::new();
     writeln!(commit, "tree {tree_hash}")?;
     if let Some(parent_hash) = parent_hash {
         writeln!(commit, "parent {parent_hash}")?;
     }
-    let time = std::time::SystemTime::now()
-        .duration_since(std::time::SystemTime::UNIX_EPOCH)
-        .context("current system time is before UNIX epoch")?;
-    writeln!(
-        commit,
-        "author Perry Hertler <perry@hertler.org> {} +0000",
-        time.as_secs()
-    )?;
-    writeln!(
-        commit,
-        "committer Perry Hertler <perry@hertler.org> {} +0000",
-        time.as_secs()
-    )?;
-    writeln!(commit, "")?;
+    let signature = Signature::now()?;
+    writeln!(commit, "author {signature}")?;
+    writeln!(commit, "committer {signature}")?;
+    writeln!(commit)?;
     writeln!(commit, "{message}")?;
     Ok(Some(
         Object {
@@ -308,7 +567,7 @@ pub(crate) fn commit_tree(
             expected_size: commit.len() as u64,
             reader: Cursor::new(commit),
         }
-        .write_to_objects(dot_git_path)
+        .write_to_objects(objects)
         .context("write commit object")?,
     ))
 }
@@ -319,7 +578,11 @@ mod tests {
 
     use tempfile::tempdir;
 
-    use crate::test::build_simple_app_git;
+    use crate::{
+        hash::HashAlgorithm,
+        store::{CompositeObjectStore, MemoryObjectStore},
+        test::{build_simple_app_git, copy_dir},
+    };
 
     use super::*;
 
@@ -327,13 +590,13 @@ mod tests {
     fn test_build_tree() -> anyhow::Result<()> {
         let git = build_simple_app_git()?;
         let tree_sha = String::from("825ad6339808aa69dd0b2d487586a32fe4b6be17");
-        let tree = build_tree(&git.config.dot_git_path, &tree_sha)?;
+        let tree = build_tree(git.repo.objects.as_ref(), &tree_sha)?;
         assert_eq!(tree.entries.len(), 3);
         assert_eq!(
             tree.entries[0],
             TreeEntry {
                 mode: TreeEntryMode::RegularFile,
-                name: String::from(".gitignore"),
+                name: b".gitignore".to_vec(),
                 sha: String::from("ea8c4bf7f35f6f77f75d92ad8ce8349f6e81ddba"),
             }
         );
@@ -341,7 +604,7 @@ mod tests {
             tree.entries[1],
             TreeEntry {
                 mode: TreeEntryMode::RegularFile,
-                name: String::from("Cargo.toml"),
+                name: b"Cargo.toml".to_vec(),
                 sha: String::from("f195397afef8ad7a138507d1cf1c118d6e0d6dfc"),
             }
         );
@@ -349,76 +612,199 @@ mod tests {
             tree.entries[2],
             TreeEntry {
                 mode: TreeEntryMode::Directory,
-                name: String::from("src"),
+                name: b"src".to_vec(),
                 sha: String::from("305157a396c6858705a9cb625bab219053264ee4"),
             }
         );
         Ok(())
     }
 
+    #[test]
+    fn test_build_tree_legacy_modes() -> anyhow::Result<()> {
+        let objects = MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let blob = HashAlgorithm::Sha1.hash(b"blob 0\0");
+        let mut data = Vec::new();
+        for (mode, name) in [("100664", "group-writable"), ("100775", "script")] {
+            data.extend(format!("{mode} {name}\0").as_bytes());
+            data.extend(blob.as_bytes());
+        }
+        let tree = Object {
+            object_type: ObjectType::Tree,
+            expected_size: data.len() as u64,
+            reader: &data[..],
+        }
+        .write_to_objects(&objects)?;
+        let tree = build_tree(&objects, &tree.to_string())?;
+        let modes: Vec<_> = tree.entries.iter().map(|entry| entry.mode).collect();
+        assert_eq!(
+            modes,
+            [TreeEntryMode::RegularFile, TreeEntryMode::ExecutableFile]
+        );
+        assert!(TreeEntryMode::try_from("170000").is_err());
+        assert!(TreeEntryMode::try_from("10064x").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_write_tree_with_one_file() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
         let dot_git = tmp_dir.path().join("dot-git");
         fs::create_dir_all(dot_git.join("objects")).context("create subdir of .git/objects")?;
-        let staging_git_dir = PathBuf::from(format!("tests/fixtures/one-file-app"));
-        let result = write_tree_for(&dot_git, staging_git_dir.as_path());
+        let objects = CompositeObjectStore::open(&dot_git, HashAlgorithm::Sha1);
+        let staging_git_dir = PathBuf::from("tests/fixtures/one-file-app");
+        let result = write_tree_for(&objects, &dot_git, staging_git_dir.as_path());
         assert!(&result.is_ok());
         assert!(result.as_ref().unwrap().is_some());
         let actual_sha = result?;
         let actual_sha = actual_sha.as_ref().expect("SHA should be present");
-        let actual_sha = hex::encode(actual_sha);
+        let actual_sha = actual_sha.to_string();
         assert_eq!(actual_sha, "5da554cc6d31c65185d6d63ae707cc1328eeb8c2");
         assert_eq!(fs::read_dir(tmp_dir.path().join("dot-git"))?.count(), 1);
         assert_eq!(fs::read_dir(dot_git.join("objects/"))?.count(), 2);
-        let tree = build_tree(dot_git.as_path(), &actual_sha)?;
+        let tree = build_tree(&objects, &actual_sha)?;
         assert_eq!(tree.entries.len(), 1);
         assert_eq!(
             tree.entries[0],
             TreeEntry {
                 mode: TreeEntryMode::RegularFile,
-                name: String::from("foo.rs"),
+                name: b"foo.rs".to_vec(),
                 sha: String::from("3524658cc82dda8611f51bd132493e711d50bb81"),
             }
         );
         Ok(())
     }
 
+    #[test]
+    fn test_write_tree_skips_ignored() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let worktree = tmp_dir.path().join("simple-app");
+        copy_dir(Path::new("tests/fixtures/simple-app"), &worktree)?;
+        let dot_git = worktree.join("dot-git");
+        fs::create_dir_all(dot_git.join("info"))?;
+        fs::write(dot_git.join("info/exclude"), "*.swp\n")?;
+        fs::create_dir_all(worktree.join("target/debug"))?;
+        fs::write(worktree.join("target/debug/app"), "binary")?;
+        fs::write(worktree.join("src/.main.rs.swp"), "swap")?;
+        let objects = MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let tree_sha = write_tree_for(&objects, &dot_git, &worktree)?
+            .expect("SHA should be present")
+            .to_string();
+        // the tree committed in the fixture
+        assert_eq!(tree_sha, "825ad6339808aa69dd0b2d487586a32fe4b6be17");
+        Ok(())
+    }
+
+    #[test]
+    fn test_symlinks() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let worktree = tmp_dir.path().join("links");
+        fs::create_dir_all(worktree.join("src"))?;
+        fs::write(worktree.join("src/a.txt"), "hello\n")?;
+        std::os::unix::fs::symlink("src/a.txt", worktree.join("link"))?;
+        std::os::unix::fs::symlink("src", worktree.join("dirlink"))?;
+        std::os::unix::fs::symlink("missing", worktree.join("dangling"))?;
+        let objects = MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let tree_sha = write_tree_for(&objects, &worktree.join(".git"), &worktree)?
+            .expect("SHA should be present")
+            .to_string();
+        assert_eq!(tree_sha, "9e4af12f42429e7fe8dd7023d2dc3ac8278e5e5f");
+
+        let checkout = tmp_dir.path().join("checkout");
+        checkout_tree(&objects, &tree_sha, &checkout, true)?;
+        assert_eq!(fs::read_link(checkout.join("dirlink"))?, Path::new("src"));
+        assert_eq!(fs::read_to_string(checkout.join("link"))?, "hello\n");
+        let plain = tmp_dir.path().join("plain");
+        checkout_tree(&objects, &tree_sha, &plain, false)?;
+        assert!(!fs::symlink_metadata(plain.join("link"))?.is_symlink());
+        assert_eq!(fs::read_to_string(plain.join("link"))?, "src/a.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_utf8_names() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let worktree = tmp_dir.path().join("latin1");
+        let dir = worktree.join(OsStr::from_bytes(b"d\xfc"));
+        fs::create_dir_all(&dir)?;
+        fs::write(worktree.join(OsStr::from_bytes(b"caf\xe9.txt")), "café\n")?;
+        fs::write(dir.join("x"), "x\n")?;
+        let objects = MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let tree_sha = write_tree_for(&objects, &worktree.join(".git"), &worktree)?
+            .expect("SHA should be present")
+            .to_string();
+        let tree = build_tree(&objects, &tree_sha)?;
+        let names: Vec<&[u8]> = tree.entries.iter().map(|entry| &entry.name[..]).collect();
+        assert_eq!(names, [&b"caf\xe9.txt"[..], b"d\xfc"]);
+
+        let checkout = tmp_dir.path().join("checkout");
+        checkout_tree(&objects, &tree_sha, &checkout, true)?;
+        assert_eq!(
+            fs::read_to_string(checkout.join(OsStr::from_bytes(b"d\xfc/x")))?,
+            "x\n"
+        );
+        let copy = write_tree_for(&objects, &checkout.join(".git"), &checkout)?;
+        assert_eq!(copy.map(|hash| hash.to_string()), Some(tree_sha));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_tree_sha256() -> anyhow::Result<()> {
+        let objects = MemoryObjectStore::new(HashAlgorithm::Sha256);
+        let staging_git_dir = PathBuf::from("tests/fixtures/one-file-app");
+        let tree_sha = write_tree_for(&objects, Path::new(".git"), &staging_git_dir)?
+            .expect("SHA should be present")
+            .to_string();
+        assert_eq!(
+            tree_sha,
+            "4a08e10a444feb72fee0ffc370f723212447bfdcf46234d439ca328563ae1b58"
+        );
+        let tree = build_tree(&objects, &tree_sha)?;
+        assert_eq!(
+            tree.entries,
+            vec![TreeEntry {
+                mode: TreeEntryMode::RegularFile,
+                name: b"foo.rs".to_vec(),
+                sha: String::from(
+                    "bb62fab281c256653b10e422f06df72aa6a04b6ebbee397cfae2d8d9ac17522d"
+                ),
+            }]
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_write_tree_complex() -> anyhow::Result<()> {
         let tmp_dir = tempdir()?;
         let dot_git = tmp_dir.path().join("dot-git");
         fs::create_dir_all(dot_git.join("objects")).context("create subdir of .git/objects")?;
-        let staging_git_dir = PathBuf::from(format!("tests/fixtures/complex-app"));
-        let result = write_tree_for(&dot_git, staging_git_dir.as_path());
+        let objects = CompositeObjectStore::open(&dot_git, HashAlgorithm::Sha1);
+        let staging_git_dir = PathBuf::from("tests/fixtures/complex-app");
+        let result = write_tree_for(&objects, &dot_git, staging_git_dir.as_path());
         assert!(&result.is_ok());
         assert!(result.as_ref().unwrap().is_some());
         let actual_sha = result?;
         let actual_sha = actual_sha.as_ref().expect("SHA should be present");
-        let actual_sha = hex::encode(actual_sha);
+        let actual_sha = actual_sha.to_string();
         assert_eq!(actual_sha, "f33421767929a06951899aa91cc699df29c3893b");
         assert_eq!(fs::read_dir(tmp_dir.path().join("dot-git"))?.count(), 1);
         assert_eq!(fs::read_dir(dot_git.join("objects/"))?.count(), 6);
-        let tree = build_tree(dot_git.as_path(), &actual_sha)?;
+        let tree = build_tree(&objects, &actual_sha)?;
         assert_eq!(tree.entries.len(), 1);
         assert_eq!(
             tree.entries[0],
             TreeEntry {
                 mode: TreeEntryMode::Directory,
-                name: String::from("src"),
+                name: b"src".to_vec(),
                 sha: String::from("32692fb2462bbe82c8b88e54ec5f0fec3badbe88"),
             }
         );
-        let tree = build_tree(
-            dot_git.as_path(),
-            "32692fb2462bbe82c8b88e54ec5f0fec3badbe88",
-        )?;
+        let tree = build_tree(&objects, "32692fb2462bbe82c8b88e54ec5f0fec3badbe88")?;
         assert_eq!(tree.entries.len(), 2);
         assert_eq!(
             tree.entries[0],
             TreeEntry {
                 mode: TreeEntryMode::RegularFile,
-                name: String::from("foo.txt"),
+                name: b"foo.txt".to_vec(),
                 sha: String::from("1657a67183cbc4719b4818685a2f5635bf481094"),
             }
         );
@@ -426,20 +812,17 @@ mod tests {
             tree.entries[1],
             TreeEntry {
                 mode: TreeEntryMode::Directory,
-                name: String::from("foo"),
+                name: b"foo".to_vec(),
                 sha: String::from("68ab77c490a5cbdcf70a2094ce43780c5780ab4b"),
             }
         );
-        let tree = build_tree(
-            dot_git.as_path(),
-            "68ab77c490a5cbdcf70a2094ce43780c5780ab4b",
-        )?;
+        let tree = build_tree(&objects, "68ab77c490a5cbdcf70a2094ce43780c5780ab4b")?;
         assert_eq!(tree.entries.len(), 2);
         assert_eq!(
             tree.entries[0],
             TreeEntry {
                 mode: TreeEntryMode::RegularFile,
-                name: String::from("ab.txt"),
+                name: b"ab.txt".to_vec(),
                 sha: String::from("4a3055de6ce49aa356dd07a1e7feeff79bd18fb8"),
             }
         );
@@ -447,7 +830,7 @@ mod tests {
             tree.entries[1],
             TreeEntry {
                 mode: TreeEntryMode::RegularFile,
-                name: String::from("bc.txt"),
+                name: b"bc.txt".to_vec(),
                 sha: String::from("2ce489f987a7d6dbfa73a54df760cdc90e841794"),
             }
         );
@@ -459,27 +842,78 @@ mod tests {
         let tmp_dir = tempdir()?;
         let dot_git = tmp_dir.path().join("dot-git");
         fs::create_dir_all(dot_git.join("objects")).context("create subdir of .git/objects")?;
-        let staging_git_dir = PathBuf::from(format!("tests/fixtures/complex-app"));
-        let result = write_tree_for(&dot_git, staging_git_dir.as_path());
+        let objects = CompositeObjectStore::open(&dot_git, HashAlgorithm::Sha1);
+        let staging_git_dir = PathBuf::from("tests/fixtures/complex-app");
+        let result = write_tree_for(&objects, &dot_git, staging_git_dir.as_path());
         assert!(&result.is_ok());
-        let tree_sha = hex::encode(result.unwrap().unwrap());
+        let tree_sha = result.unwrap().unwrap().to_string();
         assert_eq!(tree_sha, "f33421767929a06951899aa91cc699df29c3893b");
         assert_eq!(fs::read_dir(tmp_dir.path().join("dot-git"))?.count(), 1);
-        let result = commit_tree(&dot_git, "initial commit", &tree_sha, None)?;
-        let commit_sha = hex::encode(result.unwrap());
+        let result = commit_tree(&objects, "initial commit", &tree_sha, None)?;
+        let commit_sha = result.unwrap().to_string();
         assert_eq!(commit_sha.len(), 40);
 
-        let tree = build_tree(dot_git.as_path(), &tree_sha)?;
+        let tree = build_tree(&objects, &tree_sha)?;
         assert_eq!(tree.entries.len(), 1);
         assert_eq!(
             tree.entries[0],
             TreeEntry {
                 mode: TreeEntryMode::Directory,
-                name: String::from("src"),
+                name: b"src".to_vec(),
                 sha: String::from("32692fb2462bbe82c8b88e54ec5f0fec3badbe88"),
             }
         );
 
         Ok(())
     }
+
+    #[test]
+    fn test_write_tree_from_index() -> anyhow::Result<()> {
+        let objects = MemoryObjectStore::new(HashAlgorithm::Sha1);
+        let blob = |contents: &str| {
+            Object {
+                object_type: ObjectType::Blob,
+                expected_size: contents.len() as u64,
+                reader: contents.as_bytes(),
+            }
+            .write_to_objects(&objects)
+        };
+        let metadata = fs::metadata("tests/fixtures/one-file-app/foo.rs")?;
+        let mut index = Index::default();
+        for (path, contents) in [
+            ("src/foo.txt", "foomanchoo"),
+            ("src/foo/ab.txt", "four score"),
+            ("src/foo/bc.txt", "and seven years ago"),
+        ] {
+            index.add(IndexEntry::new(
+                path.as_bytes().to_vec(),
+                0o100644,
+                blob(contents)?,
+                &metadata,
+            ));
+        }
+
+        let hash = write_tree_from_index(&objects, &mut index)?;
+        // the same tree the complex-app fixture gives, which has these files
+        assert_eq!(hash.to_string(), "f33421767929a06951899aa91cc699df29c3893b");
+        let cache_tree = index.cache_tree.clone().expect("cache tree is recorded");
+        assert_eq!(cache_tree.entry_count, 3);
+        assert_eq!(cache_tree.subtrees[0].name, b"src");
+        assert_eq!(cache_tree.subtrees[0].subtrees[0].entry_count, 2);
+
+        // reusing what's still cached gives the same trees as starting over
+        index.add(IndexEntry::new(
+            b"top.txt".to_vec(),
+            0o100755,
+            blob("top\n")?,
+            &metadata,
+        ));
+        let hash = write_tree_from_index(&objects, &mut index)?;
+        index.cache_tree = None;
+        assert_eq!(write_tree_from_index(&objects, &mut index)?, hash);
+
+        index.entries[0].stage = 2;
+        assert!(write_tree_from_index(&objects, &mut index).is_err());
+        Ok(())
+    }
 }
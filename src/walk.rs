@@ -0,0 +1,152 @@
+use std::collections::BTreeSet;
+
+use anyhow::Context;
+
+use crate::{
+    commit::Commit,
+    hash::ObjectId,
+    index::CacheTree,
+    object::{Object, ObjectType},
+    reflog::logged_objects,
+    repository::Repository,
+    tag::Tag,
+    tree::{build_tree, TreeEntryType},
+};
+
+/// The objects every ref and `HEAD` point at.
+pub(crate) fn ref_roots(repo: &Repository) -> anyhow::Result<Vec<ObjectId>> {
+    let mut roots = Vec::new();
+    for (name, hash) in repo.refs()? {
+        roots.push(ObjectId::from_hex(&hash).with_context(|| format!("resolve ref '{name}'"))?);
+    }
+    if let Some(hash) = repo.resolve_ref("HEAD")? {
+        roots.push(ObjectId::from_hex(&hash).context("resolve HEAD")?);
+    }
+    Ok(roots)
+}
+
+/// Everything that keeps objects alive, with the type of object each should be and where it
+/// was found: refs and `HEAD`, the ids the reflogs recorded, and what the index stages, its
+/// resolve-undo records and its cache tree name.
+pub(crate) fn roots(repo: &Repository) -> anyhow::Result<Vec<(ObjectType, ObjectId, String)>> {
+    let mut roots = Vec::new();
+    for (name, hash) in repo.refs()? {
+        let id = ObjectId::from_hex(&hash).with_context(|| format!("resolve ref '{name}'"))?;
+        roots.push((ObjectType::Commit, id, name));
+    }
+    if let Some(hash) = repo.resolve_ref("HEAD")? {
+        let id = ObjectId::from_hex(&hash).context("resolve HEAD")?;
+        roots.push((ObjectType::Commit, id, "HEAD".to_string()));
+    }
+    for (log, id) in logged_objects(repo)? {
+        roots.push((ObjectType::Commit, id, log));
+    }
+
+    let index = repo.index()?;
+    // gitlinks point at commits in another repository, and an intent to add has no blob yet
+    for entry in &index.entries {
+        if entry.mode != 0o160000 && !entry.intent_to_add {
+            roots.push((ObjectType::Blob, entry.hash, "index".to_string()));
+        }
+    }
+    for record in &index.resolve_undo {
+        for (mode, hash) in record.modes.iter().zip(&record.hashes) {
+            if let Some(hash) = hash.filter(|_| *mode != 0o160000) {
+                roots.push((ObjectType::Blob, hash, "resolve-undo".to_string()));
+            }
+        }
+    }
+    let mut trees: Vec<&CacheTree> = index.cache_tree.iter().collect();
+    while let Some(tree) = trees.pop() {
+        if let Some(hash) = tree.hash {
+            roots.push((ObjectType::Tree, hash, "cache-tree".to_string()));
+        }
+        trees.extend(&tree.subtrees);
+    }
+    Ok(roots)
+}
+
+/// Every object reachable from `roots`, each listed once in the order it was found.
+pub(crate) fn reachable_objects(
+    repo: &Repository,
+    roots: &[ObjectId],
+) -> anyhow::Result<Vec<(ObjectType, ObjectId)>> {
+    let objects = repo.objects.as_ref();
+    let mut seen = BTreeSet::new();
+    let mut found = Vec::new();
+    let mut pending: Vec<(Option<ObjectType>, ObjectId)> =
+        roots.iter().rev().map(|root| (None, *root)).collect();
+
+    while let Some((object_type, hash)) = pending.pop() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        let hex = hash.to_string();
+        let object_type = match object_type {
+            Some(object_type) => object_type,
+            None => {
+                Object::read(objects, &hex)
+                    .with_context(|| format!("read {hex}"))?
+                    .object_type
+            }
+        };
+        found.push((object_type, hash));
+        match object_type {
+            ObjectType::Commit => {
+                let commit = Commit::read(objects, &hex)?;
+                for parent in commit.parents.iter().rev() {
+                    pending.push((Some(ObjectType::Commit), parent.parse()?));
+                }
+                pending.push((Some(ObjectType::Tree), commit.tree.parse()?));
+            }
+            ObjectType::Tree => {
+                let tree = build_tree(objects, &hex)?;
+                for entry in tree.entries.iter().rev() {
+                    let entry_type = match entry.tree_entry_type() {
+                        TreeEntryType::Tree => ObjectType::Tree,
+                        TreeEntryType::Blob => ObjectType::Blob,
+                        // submodule commits aren't in this repository
+                        TreeEntryType::Commit => continue,
+                    };
+                    pending.push((Some(entry_type), entry.sha.parse()?));
+                }
+            }
+            ObjectType::Tag => {
+                let tag = Tag::read(objects, &hex)?;
+                pending.push((Some(tag.object_type), tag.object.parse()?));
+            }
+            ObjectType::Blob => {}
+        }
+    }
+    Ok(found)
+}
+
+/// Every commit reachable from `start`, including `start` itself.
+pub(crate) fn ancestors(repo: &Repository, start: &ObjectId) -> anyhow::Result<BTreeSet<ObjectId>> {
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![*start];
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash) {
+            continue;
+        }
+        let commit = Commit::read(repo.objects.as_ref(), &hash.to_string())?;
+        for parent in &commit.parents {
+            pending.push(parent.parse()?);
+        }
+    }
+    Ok(seen)
+}
+
+/// How many commits are reachable from `ours` but not `theirs`, and the other way around.
+pub(crate) fn ahead_behind(
+    repo: &Repository,
+    ours: &ObjectId,
+    theirs: &ObjectId,
+) -> anyhow::Result<(usize, usize)> {
+    let ours = ancestors(repo, ours)?;
+    let theirs = ancestors(repo, theirs)?;
+    Ok((
+        ours.difference(&theirs).count(),
+        theirs.difference(&ours).count(),
+    ))
+}
@@ -0,0 +1,284 @@
+//! Shell-style pattern matching the way git matches pathspecs, `.gitignore` and
+//! `.gitattributes` patterns.
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Match {
+    Matched,
+    Mismatch,
+    /// Nothing later in the text can match either, so every enclosing `*` can give up.
+    AbortAll,
+    /// Only an enclosing `**` could still match, by consuming a `/`.
+    AbortToStarStar,
+}
+
+/// Whether `text` matches `pattern`. With `pathname` set, `*` and `?` don't match `/` and
+/// `**` between slashes matches any number of directories.
+pub(crate) fn wildmatch(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    dowild(pattern, text, pathname) == Match::Matched
+}
+
+/// The pattern byte at `i`, or 0 past its end.
+fn at(pattern: &[u8], i: usize) -> u8 {
+    pattern.get(i).copied().unwrap_or(0)
+}
+
+fn dowild(pattern: &[u8], text: &[u8], pathname: bool) -> Match {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        let p_ch = pattern[p];
+        if t == text.len() && p_ch != b'*' {
+            return Match::AbortAll;
+        }
+        let t_ch = at(text, t);
+        match p_ch {
+            b'\\' => {
+                p += 1;
+                if p == pattern.len() || t_ch != pattern[p] {
+                    return Match::Mismatch;
+                }
+            }
+            b'?' => {
+                if pathname && t_ch == b'/' {
+                    return Match::Mismatch;
+                }
+            }
+            b'*' => {
+                p += 1;
+                let match_slash = if at(pattern, p) == b'*' {
+                    let starts_component = p == 1 || pattern[p - 2] == b'/';
+                    while at(pattern, p) == b'*' {
+                        p += 1;
+                    }
+                    let next = at(pattern, p);
+                    if !pathname {
+                        true
+                    } else if starts_component
+                        && (next == 0
+                            || next == b'/'
+                            || (next == b'\\' && at(pattern, p + 1) == b'/'))
+                    {
+                        // "**/" may match no directories at all
+                        if next == b'/'
+                            && dowild(&pattern[p + 1..], &text[t..], pathname) == Match::Matched
+                        {
+                            return Match::Matched;
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    !pathname
+                };
+
+                if p == pattern.len() {
+                    // a trailing "**" matches everything, a trailing "*" only what's left of
+                    // the current path component
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Match::AbortToStarStar;
+                    }
+                    return Match::Matched;
+                }
+                if !match_slash && pattern[p] == b'/' {
+                    let Some(slash) = text[t..].iter().position(|&c| c == b'/') else {
+                        return Match::AbortAll;
+                    };
+                    // the slashes themselves are consumed below
+                    t += slash;
+                } else {
+                    while t < text.len() {
+                        let matched = dowild(&pattern[p..], &text[t..], pathname);
+                        if matched != Match::Mismatch {
+                            if !match_slash || matched != Match::AbortToStarStar {
+                                return matched;
+                            }
+                        } else if !match_slash && text[t] == b'/' {
+                            return Match::AbortToStarStar;
+                        }
+                        t += 1;
+                    }
+                    return Match::AbortAll;
+                }
+            }
+            b'[' => {
+                p += 1;
+                let mut p_ch = at(pattern, p);
+                if p_ch == b'!' {
+                    p_ch = b'^';
+                }
+                let negated = p_ch == b'^';
+                if negated {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                let mut prev_ch = 0;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return Match::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == 0 {
+                            return Match::AbortAll;
+                        }
+                        matched |= t_ch == p_ch;
+                    } else if p_ch == b'-'
+                        && prev_ch != 0
+                        && at(pattern, p + 1) != 0
+                        && at(pattern, p + 1) != b']'
+                    {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == b'\\' {
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == 0 {
+                                return Match::AbortAll;
+                            }
+                        }
+                        matched |= prev_ch <= t_ch && t_ch <= p_ch;
+                        // a range can't be the start of another range
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(pattern, p + 1) == b':' {
+                        let start = p + 2;
+                        let Some(len) = pattern[start..].iter().position(|&c| c == b']') else {
+                            return Match::AbortAll;
+                        };
+                        let end = start + len;
+                        if len == 0 || pattern[end - 1] != b':' {
+                            // no ":]", so the '[' is just a character in the set
+                            matched |= t_ch == b'[';
+                        } else {
+                            let Some(is_class) = character_class(&pattern[start..end - 1]) else {
+                                return Match::AbortAll;
+                            };
+                            matched |= is_class(t_ch);
+                            p = end;
+                            p_ch = 0;
+                        }
+                    } else {
+                        matched |= t_ch == p_ch;
+                    }
+                    prev_ch = p_ch;
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || (pathname && t_ch == b'/') {
+                    return Match::Mismatch;
+                }
+            }
+            _ => {
+                if t_ch != p_ch {
+                    return Match::Mismatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+    if t == text.len() {
+        Match::Matched
+    } else {
+        Match::Mismatch
+    }
+}
+
+fn character_class(name: &[u8]) -> Option<fn(u8) -> bool> {
+    Some(match name {
+        b"alnum" => |c: u8| c.is_ascii_alphanumeric(),
+        b"alpha" => |c: u8| c.is_ascii_alphabetic(),
+        b"blank" => |c: u8| c == b' ' || c == b'\t',
+        b"cntrl" => |c: u8| c.is_ascii_control(),
+        b"digit" => |c: u8| c.is_ascii_digit(),
+        b"graph" => |c: u8| c.is_ascii_graphic(),
+        b"lower" => |c: u8| c.is_ascii_lowercase(),
+        b"print" => |c: u8| c.is_ascii_graphic() || c == b' ',
+        b"punct" => |c: u8| c.is_ascii_punctuation(),
+        b"space" => |c: u8| c.is_ascii_whitespace() || c == 0x0b,
+        b"upper" => |c: u8| c.is_ascii_uppercase(),
+        b"xdigit" => |c: u8| c.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildmatch() {
+        for (pattern, text, pathname, expected) in [
+            ("foo", "foo", true, true),
+            ("foo", "bar", true, false),
+            ("", "", true, true),
+            ("???", "foo", true, true),
+            ("??", "foo", true, false),
+            ("*", "foo", true, true),
+            ("f*", "foo", true, true),
+            ("*f", "foo", true, false),
+            ("*foo*", "foo", true, true),
+            ("*ob*a*r*", "foobar", true, true),
+            ("*ab", "aaaaaaabababab", true, true),
+            ("foo\\*", "foo*", true, true),
+            ("foo\\*bar", "foobar", true, false),
+            ("f\\\\oo", "f\\oo", true, true),
+            ("*[al]?", "ball", true, true),
+            ("[ten]", "ten", true, false),
+            ("**[!te]", "ten", true, true),
+            ("**[!ten]", "ten", true, false),
+            ("t[a-g]n", "ten", true, true),
+            ("t[!a-g]n", "ten", true, false),
+            ("t[^a-g]n", "ton", true, true),
+            ("a[]]b", "a]b", true, true),
+            ("a[]-]b", "a-b", true, true),
+            ("a[]a-]b", "aab", true, true),
+            ("]", "]", true, true),
+            ("foo*bar", "foo/baz/bar", true, false),
+            ("foo*bar", "foo/baz/bar", false, true),
+            ("foo**bar", "foo/baz/bar", true, false),
+            ("foo/**/bar", "foo/baz/bar", true, true),
+            ("foo/**/bar", "foo/b/a/z/bar", true, true),
+            ("foo/**/bar", "foo/bar", true, true),
+            ("foo/**", "foo/bar/baz", true, true),
+            ("**/foo", "foo", true, true),
+            ("**/foo", "a/b/foo", true, true),
+            ("**/bar*", "deep/foo/bar/baz/", true, false),
+            ("**/bar/*", "deep/foo/bar/baz", true, true),
+            ("**/bar/*", "deep/foo/bar/baz/", true, false),
+            ("**/bar/**", "deep/foo/bar/baz/", true, true),
+            ("*/bar/**", "deep/foo/bar/baz/x", true, false),
+            ("foo?bar", "foo/bar", true, false),
+            ("foo[/]bar", "foo/bar", true, false),
+            ("*.c", "src/main.c", true, false),
+            ("*.c", "main.c", true, true),
+            ("[[:digit:][:upper:][:space:]]", "A", true, true),
+            ("[[:digit:][:upper:][:space:]]", "a", true, false),
+            ("[[:digit:]]", "5", true, true),
+            ("[[:bogus:]]", "a", true, false),
+            ("[a-", "a", true, false),
+            (
+                "XXX/*/*/*/*/*/*/12/*/*/*/m/*/*/*",
+                "XXX/adobe/courier/bold/o/normal//12/120/75/75/m/70/iso8859/1",
+                true,
+                true,
+            ),
+            (
+                "-*-*-*-*-*-*-12-*-*-*-m-*-*-*",
+                "-adobe-courier-bold-o-normal--12-120-75-75-X-70-iso8859-1",
+                true,
+                false,
+            ),
+        ] {
+            assert_eq!(
+                wildmatch(pattern.as_bytes(), text.as_bytes(), pathname),
+                expected,
+                "{pattern} against {text}"
+            );
+        }
+    }
+}
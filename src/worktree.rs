@@ -0,0 +1,272 @@
+//! The checked out files next to `.git`.
+
+use std::{
+    ffi::OsStr,
+    fs::{self, Metadata},
+    io::Write,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{OpenOptionsExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context};
+
+use crate::{
+    attributes::clean,
+    clone::git_dir,
+    hash::ObjectId,
+    object::{Object, ObjectType},
+    repository::Repository,
+};
+
+/// A file, symlink or nested repository in the worktree.
+#[derive(Debug)]
+pub(crate) struct WorktreeFile {
+    /// Relative to the top of the worktree, separated by `/`.
+    pub(crate) path: Vec<u8>,
+    /// Of the symlink itself rather than its target.
+    pub(crate) metadata: Metadata,
+}
+
+/// Where the worktree path `path` is on disk.
+pub(crate) fn disk_path(worktree: &Path, path: &[u8]) -> PathBuf {
+    worktree.join(OsStr::from_bytes(path))
+}
+
+/// Errors unless `path` can be checked out without leaving the worktree or landing in
+/// `.git`: no empty, `.`, `..` or `.git` (in any case) components and no NUL bytes, as git's
+/// `verify_path` has it.
+pub(crate) fn verify_path(path: &[u8]) -> anyhow::Result<()> {
+    let valid = !path.contains(&0)
+        && path.split(|&b| b == b'/').all(|component| {
+            !component.is_empty()
+                && component != b"."
+                && component != b".."
+                && !component.eq_ignore_ascii_case(b".git")
+        });
+    ensure!(valid, "invalid path '{}'", String::from_utf8_lossy(path));
+    Ok(())
+}
+
+/// Every file below `worktree`, sorted by path, leaving out `.git`. Nested repositories are
+/// listed as a whole, like files.
+pub(crate) fn worktree_files(worktree: &Path) -> anyhow::Result<Vec<WorktreeFile>> {
+    let mut files = Vec::new();
+    collect_files(worktree, &[], &mut files)?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+fn collect_files(dir: &Path, prefix: &[u8], files: &mut Vec<WorktreeFile>) -> anyhow::Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("open directory {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("bad directory entry in {}", dir.display()))?;
+        let name = entry.file_name();
+        if name == ".git" {
+            continue;
+        }
+        let mut path = prefix.to_vec();
+        path.extend(name.as_bytes());
+        let metadata = fs::symlink_metadata(entry.path())
+            .with_context(|| format!("stat {}", entry.path().display()))?;
+        if metadata.is_dir() && !is_nested_repo(&entry.path()) {
+            path.push(b'/');
+            collect_files(&entry.path(), &path, files)?;
+        } else {
+            files.push(WorktreeFile { path, metadata });
+        }
+    }
+    Ok(())
+}
+
+/// Whether the directory `dir` is a repository of its own, to be recorded as a gitlink.
+pub(crate) fn is_nested_repo(dir: &Path) -> bool {
+    fs::symlink_metadata(dir.join(".git")).is_ok()
+}
+
+/// The commit checked out in the nested repository at `dir`, `None` before its first commit.
+pub(crate) fn nested_repo_head(dir: &Path) -> anyhow::Result<Option<ObjectId>> {
+    let repo = Repository::open(&git_dir(dir)?)
+        .with_context(|| format!("open repository {}", dir.display()))?;
+    repo.resolve_name("HEAD")
+}
+
+/// The mode git records for a file with `metadata`, a directory being a nested repository.
+pub(crate) fn file_mode(metadata: &Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o160000
+    } else if metadata.is_symlink() {
+        0o120000
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// How far the modes of worktree files can be trusted, from `core.filemode` and
+/// `core.symlinks`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ModeConfig {
+    pub(crate) filemode: bool,
+    pub(crate) symlinks: bool,
+}
+
+impl ModeConfig {
+    pub(crate) fn load(repo: &Repository) -> anyhow::Result<Self> {
+        let config = repo.config()?;
+        Ok(Self {
+            filemode: config.get_bool("core.filemode")?.unwrap_or(true),
+            symlinks: config.get_bool("core.symlinks")?.unwrap_or(true),
+        })
+    }
+
+    /// The mode to record for a worktree file whose index entry, if any, has `staged_mode`.
+    /// Where the file system can't be trusted the index keeps its say: on the executable bit
+    /// without `core.filemode`, and on symlinks checked out as plain files without
+    /// `core.symlinks`.
+    pub(crate) fn mode_of(&self, metadata: &Metadata, staged_mode: Option<u32>) -> u32 {
+        let mode = file_mode(metadata);
+        match staged_mode {
+            Some(0o120000) if !self.symlinks && metadata.is_file() => 0o120000,
+            Some(staged) if !self.filemode && metadata.is_file() && staged != 0o120000 => staged,
+            _ => mode,
+        }
+    }
+}
+
+/// The contents of the worktree file at `path` as they would be stored with `mode`, after the
+/// clean filters; for symlinks, and the plain files standing in for them, the target.
+pub(crate) fn read_file(
+    repo: &Repository,
+    worktree: &Path,
+    path: &[u8],
+    metadata: &Metadata,
+    mode: u32,
+) -> anyhow::Result<Vec<u8>> {
+    let file = disk_path(worktree, path);
+    if metadata.is_symlink() {
+        Ok(fs::read_link(&file)
+            .with_context(|| format!("read link {}", file.display()))?
+            .as_os_str()
+            .as_bytes()
+            .to_vec())
+    } else if mode == 0o120000 {
+        fs::read(&file).with_context(|| format!("read {}", file.display()))
+    } else {
+        let data = fs::read(&file).with_context(|| format!("read {}", file.display()))?;
+        clean(repo, worktree, &String::from_utf8_lossy(path), data).context("apply filters")
+    }
+}
+
+/// Hashes the worktree file at `path` as the blob it would be stored as with `mode`, after the
+/// clean filters, writing the blob when `write` is set. Symlinks are stored as their target,
+/// and so are the plain files standing in for them without `core.symlinks`. For a nested
+/// repository it is the commit checked out there.
+pub(crate) fn hash_file(
+    repo: &Repository,
+    worktree: &Path,
+    path: &[u8],
+    metadata: &Metadata,
+    mode: u32,
+    write: bool,
+) -> anyhow::Result<ObjectId> {
+    let file = disk_path(worktree, path);
+    if metadata.is_dir() {
+        return nested_repo_head(&file)?.with_context(|| {
+            format!(
+                "'{}' does not have a commit checked out",
+                String::from_utf8_lossy(path)
+            )
+        });
+    }
+    let data = read_file(repo, worktree, path, metadata, mode)?;
+    if !write {
+        return Ok(Object::hash_of(
+            ObjectType::Blob,
+            &data,
+            repo.hash_algorithm(),
+        ));
+    }
+    Object {
+        object_type: ObjectType::Blob,
+        expected_size: data.len() as u64,
+        reader: &data[..],
+    }
+    .write_to_objects(repo.objects.as_ref())
+    .with_context(|| format!("write blob for {}", file.display()))
+}
+
+/// Deletes a file from the worktree along with the directories it leaves empty.
+pub(crate) fn remove_file(worktree: &Path, path: &[u8]) -> anyhow::Result<()> {
+    let file = disk_path(worktree, path);
+    match fs::remove_file(&file) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("remove {}", file.display())),
+    }
+    let mut dir = file.parent();
+    while let Some(current) = dir {
+        if current == worktree || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+/// Writes the blob `id` to the worktree at `path` as a file with `mode`, replacing whatever is
+/// there and whatever files stand where its directories go, and returns the new file's
+/// metadata. Symlinks are created as such, or without `symlinks` as plain files holding the
+/// path they point to, and submodules as empty directories if they aren't there yet.
+pub(crate) fn write_file(
+    repo: &Repository,
+    worktree: &Path,
+    path: &[u8],
+    mode: u32,
+    id: &ObjectId,
+    symlinks: bool,
+) -> anyhow::Result<Metadata> {
+    verify_path(path)?;
+    let file = disk_path(worktree, path);
+    let mut components: Vec<&[u8]> = path.split(|&b| b == b'/').collect();
+    components.pop();
+    let mut dir = worktree.to_path_buf();
+    for component in components {
+        dir.push(OsStr::from_bytes(component));
+        if fs::symlink_metadata(&dir).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(&dir).with_context(|| format!("remove {}", dir.display()))?;
+        }
+    }
+    fs::create_dir_all(&dir).with_context(|| format!("create directory {}", dir.display()))?;
+    if mode == 0o160000 {
+        if !file.is_dir() {
+            let _ = fs::remove_file(&file);
+            fs::create_dir_all(&file)
+                .with_context(|| format!("create directory {}", file.display()))?;
+        }
+        return fs::symlink_metadata(&file).with_context(|| format!("stat {}", file.display()));
+    }
+    match fs::symlink_metadata(&file) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&file),
+        Ok(_) => fs::remove_file(&file),
+        Err(_) => Ok(()),
+    }
+    .with_context(|| format!("remove {}", file.display()))?;
+    let contents = Object::read(repo.objects.as_ref(), &id.to_string())?.into_bytes()?;
+    if mode == 0o120000 && symlinks {
+        std::os::unix::fs::symlink(OsStr::from_bytes(&contents), &file)
+    } else {
+        // the umask decides who else gets to read and run it
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(if mode == 0o100755 { 0o777 } else { 0o666 })
+            .open(&file)
+            .and_then(|mut out| out.write_all(&contents))
+    }
+    .with_context(|| format!("write {}", file.display()))?;
+    fs::symlink_metadata(&file).with_context(|| format!("stat {}", file.display()))
+}
@@ -0,0 +1,3 @@
+fn something() {
+    println!("Hello, world!");
+}more